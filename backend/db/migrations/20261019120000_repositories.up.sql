@@ -0,0 +1,30 @@
+-- Add up migration script here
+create table session (
+    id_hash bytea primary key,          -- sha256 of the session id given to the client
+    account_id integer not null references account (id) on delete cascade,
+    created_at timestamptz not null default now(),
+    expires_at timestamptz not null,
+    revoked_at timestamptz              -- set on logout or password change
+);
+
+create index session_account_id_idx on session (account_id);
+
+create table item (
+    id uuid primary key default gen_random_uuid(),
+    account_id integer not null references account (id) on delete cascade,
+    ciphertext bytea not null,          -- encrypted by the client, opaque to the server
+    created_at timestamptz not null default now(),
+    updated_at timestamptz not null default now()
+);
+
+create index item_account_id_idx on item (account_id);
+
+create table audit_event (
+    id bigserial primary key,
+    account_id integer references account (id) on delete set null,
+    action text not null,
+    detail jsonb not null default '{}',
+    created_at timestamptz not null default now()
+);
+
+create index audit_event_account_id_idx on audit_event (account_id);
@@ -0,0 +1,9 @@
+-- Add up migration script here
+create table key_log_entry (
+    leaf_index bigint primary key,      -- position in the Merkle tree, from 0
+    username text not null,             -- not a foreign key, the log outlives accounts
+    leaf bytea not null,                -- exact bytes hashed into the tree
+    created_at timestamptz not null default now()
+);
+
+create index key_log_entry_username_idx on key_log_entry (username, leaf_index);
@@ -0,0 +1,8 @@
+-- Add down migration script here
+drop index if exists audit_event_created_at_idx;
+
+alter table audit_event
+    drop constraint if exists audit_event_prev_hash_key,
+    drop column if exists hash,
+    drop column if exists prev_hash,
+    drop column if exists username;
@@ -0,0 +1,24 @@
+-- Add up migration script here
+alter table audit_event
+    add column username text,   -- kept after the account is deleted
+    add column prev_hash bytea, -- hash of the previous event, zeros for the first
+    add column hash bytea;      -- see models::audit::chain_hash
+
+update audit_event
+set username = account.username
+from account
+where account.id = audit_event.account_id;
+
+-- Events recorded before the chain existed get an empty hash, and the
+-- server chains them when it starts, see Models::chain_legacy_audit_events.
+-- Their placeholder prev_hash is unique until then.
+update audit_event set prev_hash = int8send(id), hash = '';
+
+alter table audit_event
+    alter column prev_hash set not null,
+    alter column hash set not null;
+
+-- Two events chained to the same predecessor would fork the chain
+alter table audit_event add constraint audit_event_prev_hash_key unique (prev_hash);
+
+create index audit_event_created_at_idx on audit_event (created_at);
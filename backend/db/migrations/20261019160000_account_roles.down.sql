@@ -0,0 +1,4 @@
+-- Add down migration script here
+alter table account
+    drop column if exists locked_at,
+    drop column if exists role;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+alter table account
+    add column role text not null default 'user'
+        constraint account_role_check check (role in ('user', 'admin')),
+    add column locked_at timestamptz;   -- set while an admin has locked the account
@@ -0,0 +1,3 @@
+-- Add down migration script here
+drop index if exists account_opaque_key_id_idx;
+alter table account drop column if exists opaque_key_id;
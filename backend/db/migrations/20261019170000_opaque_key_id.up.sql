@@ -0,0 +1,5 @@
+-- Add up migration script here
+alter table account
+    add column opaque_key_id integer not null default 1;   -- OPAQUE server setup generation
+
+create index account_opaque_key_id_idx on account (opaque_key_id);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+drop index if exists account_kek_id_idx;
+alter table account drop column if exists wrapped_dek;
+alter table account drop column if exists kek_id;
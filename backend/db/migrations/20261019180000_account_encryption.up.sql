@@ -0,0 +1,6 @@
+-- Add up migration script here
+alter table account
+    add column kek_id integer,          -- KEK wrapping the data key, null while the row is plaintext
+    add column wrapped_dek bytea;       -- per-account data key encrypting the sensitive columns
+
+create index account_kek_id_idx on account (kek_id);
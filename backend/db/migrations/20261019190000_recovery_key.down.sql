@@ -0,0 +1,6 @@
+-- Add down migration script here
+alter table account drop constraint if exists account_recovery_key_check;
+alter table account drop column if exists wrapped_master_key;
+alter table account drop column if exists recovery_public_key;
+alter table account drop column if exists recovery_opaque_key_id;
+alter table account drop column if exists recovery_record;
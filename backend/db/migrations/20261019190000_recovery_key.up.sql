@@ -0,0 +1,11 @@
+-- Add up migration script here
+alter table account
+    add column recovery_record bytea,             -- OPAQUE record of the recovery code, encrypted like registration_record
+    add column recovery_opaque_key_id integer,    -- OPAQUE server setup generation of recovery_record
+    add column recovery_public_key bytea,         -- X25519 key the client wraps its master key to
+    add column wrapped_master_key bytea,          -- opened only with the recovery code
+    add constraint account_recovery_key_check check (
+        (recovery_record is null) = (recovery_opaque_key_id is null)
+        and (recovery_record is null) = (recovery_public_key is null)
+        and (recovery_record is null) = (wrapped_master_key is null)
+    );
@@ -0,0 +1,2 @@
+-- Add down migration script here
+alter table account drop column if exists password_wrapped_master_key;
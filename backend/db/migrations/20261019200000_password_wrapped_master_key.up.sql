@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- The client's master key wrapped with a key derived from the password's
+-- export key. Accounts without one derive their vault keys from the export
+-- key itself, as every account did before
+alter table account add column password_wrapped_master_key bytea;
@@ -36,7 +36,13 @@ CREATE TABLE public.account (
     username text NOT NULL,
     credential_id bytea NOT NULL,
     client_identity bytea NOT NULL,
-    registration_record bytea NOT NULL
+    registration_record bytea NOT NULL,
+    role text DEFAULT 'user'::text NOT NULL,
+    locked_at timestamp with time zone,
+    opaque_key_id integer DEFAULT 1 NOT NULL,
+    kek_id integer,
+    wrapped_dek bytea,
+    CONSTRAINT account_role_check CHECK ((role = ANY (ARRAY['user'::text, 'admin'::text])))
 );
 
 
@@ -60,6 +66,66 @@ CREATE SEQUENCE public.account_id_seq
 ALTER SEQUENCE public.account_id_seq OWNED BY public.account.id;
 
 
+--
+-- Name: audit_event; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.audit_event (
+    id bigint NOT NULL,
+    account_id integer,
+    action text NOT NULL,
+    detail jsonb DEFAULT '{}'::jsonb NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    username text,
+    prev_hash bytea NOT NULL,
+    hash bytea NOT NULL
+);
+
+
+--
+-- Name: audit_event_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.audit_event_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: audit_event_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: -
+--
+
+ALTER SEQUENCE public.audit_event_id_seq OWNED BY public.audit_event.id;
+
+
+--
+-- Name: item; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.item (
+    id uuid DEFAULT gen_random_uuid() NOT NULL,
+    account_id integer NOT NULL,
+    ciphertext bytea NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    updated_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+--
+-- Name: key_log_entry; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.key_log_entry (
+    leaf_index bigint NOT NULL,
+    username text NOT NULL,
+    leaf bytea NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
 --
 -- Name: schema_migrations; Type: TABLE; Schema: public; Owner: -
 --
@@ -69,6 +135,19 @@ CREATE TABLE public.schema_migrations (
 );
 
 
+--
+-- Name: session; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.session (
+    id_hash bytea NOT NULL,
+    account_id integer NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    expires_at timestamp with time zone NOT NULL,
+    revoked_at timestamp with time zone
+);
+
+
 --
 -- Name: account id; Type: DEFAULT; Schema: public; Owner: -
 --
@@ -76,6 +155,13 @@ CREATE TABLE public.schema_migrations (
 ALTER TABLE ONLY public.account ALTER COLUMN id SET DEFAULT nextval('public.account_id_seq'::regclass);
 
 
+--
+-- Name: audit_event id; Type: DEFAULT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.audit_event ALTER COLUMN id SET DEFAULT nextval('public.audit_event_id_seq'::regclass);
+
+
 --
 -- Name: _sqlx_migrations _sqlx_migrations_pkey; Type: CONSTRAINT; Schema: public; Owner: -
 --
@@ -100,6 +186,38 @@ ALTER TABLE ONLY public.account
     ADD CONSTRAINT account_username_key UNIQUE (username);
 
 
+--
+-- Name: audit_event audit_event_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.audit_event
+    ADD CONSTRAINT audit_event_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: audit_event audit_event_prev_hash_key; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.audit_event
+    ADD CONSTRAINT audit_event_prev_hash_key UNIQUE (prev_hash);
+
+
+--
+-- Name: item item_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.item
+    ADD CONSTRAINT item_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: key_log_entry key_log_entry_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.key_log_entry
+    ADD CONSTRAINT key_log_entry_pkey PRIMARY KEY (leaf_index);
+
+
 --
 -- Name: schema_migrations schema_migrations_pkey; Type: CONSTRAINT; Schema: public; Owner: -
 --
@@ -108,6 +226,87 @@ ALTER TABLE ONLY public.schema_migrations
     ADD CONSTRAINT schema_migrations_pkey PRIMARY KEY (version);
 
 
+--
+-- Name: session session_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.session
+    ADD CONSTRAINT session_pkey PRIMARY KEY (id_hash);
+
+
+--
+-- Name: account_kek_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX account_kek_id_idx ON public.account USING btree (kek_id);
+
+
+--
+-- Name: account_opaque_key_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX account_opaque_key_id_idx ON public.account USING btree (opaque_key_id);
+
+
+--
+-- Name: audit_event_account_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX audit_event_account_id_idx ON public.audit_event USING btree (account_id);
+
+
+--
+-- Name: audit_event_created_at_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX audit_event_created_at_idx ON public.audit_event USING btree (created_at);
+
+
+--
+-- Name: item_account_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX item_account_id_idx ON public.item USING btree (account_id);
+
+
+--
+-- Name: key_log_entry_username_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX key_log_entry_username_idx ON public.key_log_entry USING btree (username, leaf_index);
+
+
+--
+-- Name: session_account_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX session_account_id_idx ON public.session USING btree (account_id);
+
+
+--
+-- Name: audit_event audit_event_account_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.audit_event
+    ADD CONSTRAINT audit_event_account_id_fkey FOREIGN KEY (account_id) REFERENCES public.account(id) ON DELETE SET NULL;
+
+
+--
+-- Name: item item_account_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.item
+    ADD CONSTRAINT item_account_id_fkey FOREIGN KEY (account_id) REFERENCES public.account(id) ON DELETE CASCADE;
+
+
+--
+-- Name: session session_account_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.session
+    ADD CONSTRAINT session_account_id_fkey FOREIGN KEY (account_id) REFERENCES public.account(id) ON DELETE CASCADE;
+
+
 --
 -- PostgreSQL database dump complete
 --
@@ -0,0 +1,4 @@
+-- Add down migration script here
+drop table if exists item;
+drop table if exists session;
+drop table if exists account;
@@ -0,0 +1,30 @@
+-- Add up migration script here
+-- Mirrors the Postgres schema. Timestamps are unix seconds so that they
+-- compare correctly, and item ids are generated by the application.
+create table account (
+    id integer primary key autoincrement,
+    username text unique not null,
+    credential_id blob not null,
+    client_identity blob not null,
+    registration_record blob not null
+);
+
+create table session (
+    id_hash blob primary key,
+    account_id integer not null references account (id) on delete cascade,
+    created_at integer not null default (unixepoch()),
+    expires_at integer not null,
+    revoked_at integer
+);
+
+create index session_account_id_idx on session (account_id);
+
+create table item (
+    id blob primary key,
+    account_id integer not null references account (id) on delete cascade,
+    ciphertext blob not null,
+    created_at integer not null default (unixepoch()),
+    updated_at integer not null default (unixepoch())
+);
+
+create index item_account_id_idx on item (account_id);
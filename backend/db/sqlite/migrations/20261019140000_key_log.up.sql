@@ -0,0 +1,9 @@
+-- Add up migration script here
+create table key_log_entry (
+    leaf_index integer primary key,
+    username text not null,
+    leaf blob not null,
+    created_at integer not null default (unixepoch())
+);
+
+create index key_log_entry_username_idx on key_log_entry (username, leaf_index);
@@ -0,0 +1,14 @@
+-- Add up migration script here
+create table audit_event (
+    id integer primary key autoincrement,
+    account_id integer references account (id) on delete set null,
+    username text,
+    action text not null,
+    detail text not null default '{}',
+    created_at integer not null,
+    prev_hash blob unique not null,
+    hash blob not null
+);
+
+create index audit_event_account_id_idx on audit_event (account_id);
+create index audit_event_created_at_idx on audit_event (created_at);
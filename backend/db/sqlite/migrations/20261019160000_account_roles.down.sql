@@ -0,0 +1,3 @@
+-- Add down migration script here
+alter table account drop column locked_at;
+alter table account drop column role;
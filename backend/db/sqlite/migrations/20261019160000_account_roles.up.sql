@@ -0,0 +1,4 @@
+-- Add up migration script here
+alter table account
+    add column role text not null default 'user' check (role in ('user', 'admin'));
+alter table account add column locked_at integer;
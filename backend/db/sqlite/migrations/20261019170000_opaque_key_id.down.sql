@@ -0,0 +1,3 @@
+-- Add down migration script here
+drop index account_opaque_key_id_idx;
+alter table account drop column opaque_key_id;
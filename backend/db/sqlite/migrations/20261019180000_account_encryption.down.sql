@@ -0,0 +1,4 @@
+-- Add down migration script here
+drop index account_kek_id_idx;
+alter table account drop column wrapped_dek;
+alter table account drop column kek_id;
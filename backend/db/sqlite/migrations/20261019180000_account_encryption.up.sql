@@ -0,0 +1,5 @@
+-- Add up migration script here
+alter table account add column kek_id integer;
+alter table account add column wrapped_dek blob;
+
+create index account_kek_id_idx on account (kek_id);
@@ -0,0 +1,5 @@
+-- Add down migration script here
+alter table account drop column wrapped_master_key;
+alter table account drop column recovery_public_key;
+alter table account drop column recovery_opaque_key_id;
+alter table account drop column recovery_record;
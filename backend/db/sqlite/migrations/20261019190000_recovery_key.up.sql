@@ -0,0 +1,5 @@
+-- Add up migration script here
+alter table account add column recovery_record blob;
+alter table account add column recovery_opaque_key_id integer;
+alter table account add column recovery_public_key blob;
+alter table account add column wrapped_master_key blob;
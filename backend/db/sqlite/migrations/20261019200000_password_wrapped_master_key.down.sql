@@ -0,0 +1,2 @@
+-- Add down migration script here
+alter table account drop column password_wrapped_master_key;
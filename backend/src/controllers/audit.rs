@@ -0,0 +1,281 @@
+use std::fmt::{Display, Formatter};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::storage::{AuditEvent, Storage, StorageError};
+
+/// Security-relevant actions recorded in the audit log. The strings are
+/// stored, so existing ones must never change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    AccountRegistered,
+    LoginSucceeded,
+    LoginFailed,
+    PasswordChanged,
+    PublicKeysPublished,
+    DeviceKeyAdded,
+    DeviceKeyRemoved,
+    ShareGranted,
+    ItemDeleted,
+    RoleChanged,
+    AccountsSearched,
+    AccountLocked,
+    AccountUnlocked,
+    SessionsRevoked,
+    RateLimitsViewed,
+    SigningKeyRotated,
+    OpaqueKeysViewed,
+    OpaqueKeyRotated,
+    OpaqueKeyRetired,
+    AuditLogViewed,
+    AuditLogVerified,
+    RecoveryKeySet,
+    RecoveryKeyRemoved,
+}
+
+impl AuditAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::AccountRegistered => "account_registered",
+            AuditAction::LoginSucceeded => "login_succeeded",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::PasswordChanged => "password_changed",
+            AuditAction::PublicKeysPublished => "public_keys_published",
+            AuditAction::DeviceKeyAdded => "device_key_added",
+            AuditAction::DeviceKeyRemoved => "device_key_removed",
+            AuditAction::ShareGranted => "share_granted",
+            AuditAction::ItemDeleted => "item_deleted",
+            AuditAction::RoleChanged => "role_changed",
+            AuditAction::AccountsSearched => "accounts_searched",
+            AuditAction::AccountLocked => "account_locked",
+            AuditAction::AccountUnlocked => "account_unlocked",
+            AuditAction::SessionsRevoked => "sessions_revoked",
+            AuditAction::RateLimitsViewed => "rate_limits_viewed",
+            AuditAction::SigningKeyRotated => "signing_key_rotated",
+            AuditAction::OpaqueKeysViewed => "opaque_keys_viewed",
+            AuditAction::OpaqueKeyRotated => "opaque_key_rotated",
+            AuditAction::OpaqueKeyRetired => "opaque_key_retired",
+            AuditAction::AuditLogViewed => "audit_log_viewed",
+            AuditAction::AuditLogVerified => "audit_log_verified",
+            AuditAction::RecoveryKeySet => "recovery_key_set",
+            AuditAction::RecoveryKeyRemoved => "recovery_key_removed",
+        }
+    }
+}
+
+impl Display for AuditAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub enum AuditChainError {
+    /// The event predates the chain and has not been chained yet
+    Unchained {
+        id: i64,
+    },
+    /// The stored hash does not match the event's fields
+    HashMismatch {
+        id: i64,
+    },
+    /// The event does not point at the hash of the event before it
+    BrokenLink {
+        id: i64,
+    },
+    Storage(StorageError),
+}
+
+impl Display for AuditChainError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditChainError::Unchained { id } => write!(
+                f,
+                "Audit event {} predates the chain, start the server once to chain it",
+                id
+            ),
+            AuditChainError::HashMismatch { id } => {
+                write!(f, "Audit event {} was modified", id)
+            }
+            AuditChainError::BrokenLink { id } => {
+                write!(f, "Audit events before {} were removed or reordered", id)
+            }
+            AuditChainError::Storage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for AuditChainError {}
+
+impl From<StorageError> for AuditChainError {
+    fn from(err: StorageError) -> Self {
+        AuditChainError::Storage(err)
+    }
+}
+
+/// Checks events one at a time in chain order. The first event may point at
+/// any hash, since retention removes the events before it.
+#[derive(Default)]
+pub struct ChainVerifier {
+    last_hash: Option<Vec<u8>>,
+    events_checked: u64,
+}
+
+impl ChainVerifier {
+    pub fn check(&mut self, event: &AuditEvent) -> Result<(), AuditChainError> {
+        if event.hash.is_empty() {
+            return Err(AuditChainError::Unchained { id: event.id });
+        }
+        if event.hash != event.expected_hash() {
+            return Err(AuditChainError::HashMismatch { id: event.id });
+        }
+        if self
+            .last_hash
+            .as_ref()
+            .is_some_and(|last_hash| *last_hash != event.prev_hash)
+        {
+            return Err(AuditChainError::BrokenLink { id: event.id });
+        }
+        self.last_hash = Some(event.hash.clone());
+        self.events_checked += 1;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq, Eq)]
+pub struct ChainSummary {
+    pub events_checked: u64,
+    /// Hex encoded hash of the newest event. Keeping a copy elsewhere makes
+    /// removing the newest events detectable too.
+    pub head_hash: Option<String>,
+}
+
+const VERIFY_PAGE_SIZE: u32 = 500;
+
+/// Walks the whole audit chain in pages.
+pub async fn verify_chain(storage: &dyn Storage) -> Result<ChainSummary, AuditChainError> {
+    let mut verifier = ChainVerifier::default();
+    let mut after = 0;
+    loop {
+        let events = storage
+            .list_audit_events_after(after, VERIFY_PAGE_SIZE)
+            .await?;
+        let Some(last) = events.last() else {
+            break;
+        };
+        after = last.id;
+        for event in &events {
+            verifier.check(event)?;
+        }
+    }
+
+    Ok(ChainSummary {
+        events_checked: verifier.events_checked,
+        head_hash: verifier.last_hash.map(hex::encode),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+    use crate::storage::{memory::MemoryStorage, AuditStore, NewAuditEvent};
+    use serde_json::json;
+    use sqlx::types::chrono::Utc;
+    use std::time::Duration;
+
+    async fn storage_with_events(count: usize) -> MemoryStorage {
+        let storage = MemoryStorage::default();
+        for i in 0..count {
+            storage
+                .record_audit_event(NewAuditEvent {
+                    account_id: None,
+                    username: Some("alice@example.com"),
+                    action: AuditAction::LoginSucceeded.as_str(),
+                    detail: &json!({ "attempt": i }),
+                })
+                .await
+                .unwrap();
+        }
+        storage
+    }
+
+    async fn all_events(storage: &MemoryStorage) -> Vec<AuditEvent> {
+        storage.list_audit_events_after(0, 100).await.unwrap()
+    }
+
+    fn check_all(events: &[AuditEvent]) -> Result<(), AuditChainError> {
+        let mut verifier = ChainVerifier::default();
+        events.iter().try_for_each(|event| verifier.check(event))
+    }
+
+    #[tokio::test]
+    async fn intact_chain_verifies() {
+        let storage = storage_with_events(3).await;
+        let summary = verify_chain(&storage).await.unwrap();
+
+        let events = all_events(&storage).await;
+        assert_eq!(summary.events_checked, 3);
+        assert_eq!(summary.head_hash, Some(hex::encode(&events[2].hash)));
+        assert_eq!(events[1].prev_hash, events[0].hash);
+    }
+
+    #[tokio::test]
+    async fn modified_event_is_detected() {
+        let mut events = all_events(&storage_with_events(3).await).await;
+        events[1].detail = json!({ "attempt": 99 });
+
+        assert!(matches!(
+            check_all(&events),
+            Err(AuditChainError::HashMismatch { id }) if id == events[1].id
+        ));
+    }
+
+    #[tokio::test]
+    async fn unchained_event_is_reported() {
+        let mut events = all_events(&storage_with_events(2).await).await;
+        events[0].hash.clear();
+
+        assert!(matches!(
+            check_all(&events),
+            Err(AuditChainError::Unchained { id }) if id == events[0].id
+        ));
+    }
+
+    #[tokio::test]
+    async fn removed_event_is_detected() {
+        let mut events = all_events(&storage_with_events(3).await).await;
+        let removed = events.remove(1);
+
+        assert!(matches!(
+            check_all(&events),
+            Err(AuditChainError::BrokenLink { id }) if id == removed.id + 1
+        ));
+    }
+
+    #[tokio::test]
+    async fn chain_verifies_after_retention() {
+        let storage = storage_with_events(2).await;
+        let deleted = storage
+            .delete_audit_events_before(Utc::now() + Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(deleted, 2);
+
+        for _ in 0..2 {
+            storage
+                .record_audit_event(NewAuditEvent {
+                    account_id: None,
+                    username: None,
+                    action: AuditAction::PasswordChanged.as_str(),
+                    detail: &json!({}),
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(verify_chain(&storage).await.unwrap().events_checked, 2);
+    }
+}
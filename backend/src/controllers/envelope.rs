@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::Arc;
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use opaque_ke::rand::{rngs::OsRng, CryptoRng, RngCore};
+
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use super::key_provider::{kek_name, KeyProvider, KeyProviderError, WrappingKey};
+use crate::utils::secret::SecretBytes;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug)]
+pub enum EnvelopeError {
+    Provider(KeyProviderError),
+    UnknownKekId(i32),
+    /// The ciphertext was altered or belongs to another row or column
+    Decrypt,
+}
+
+impl Display for EnvelopeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnvelopeError::Provider(err) => write!(f, "{}", err),
+            EnvelopeError::UnknownKekId(kek_id) => write!(f, "No KEK with id {}", kek_id),
+            EnvelopeError::Decrypt => write!(f, "Failed to decrypt data at rest"),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+impl From<KeyProviderError> for EnvelopeError {
+    fn from(err: KeyProviderError) -> Self {
+        match err {
+            KeyProviderError::Decrypt => EnvelopeError::Decrypt,
+            err => EnvelopeError::Provider(err),
+        }
+    }
+}
+
+/// Encrypts `plaintext` under `key` with a random nonce, which is prepended
+/// to the ciphertext. `aad` binds the ciphertext to where it is stored.
+fn seal<R: RngCore + CryptoRng>(
+    key: &[u8; KEY_LEN],
+    aad: &[u8],
+    plaintext: &[u8],
+    rng: &mut R,
+) -> Vec<u8> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill_bytes(&mut nonce);
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let mut sealed = nonce.to_vec();
+    // Only fails for plaintexts beyond 256 GiB
+    sealed.extend(
+        cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .unwrap_or_default(),
+    );
+    sealed
+}
+
+fn open(key: &[u8; KEY_LEN], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, EnvelopeError> {
+    if sealed.len() < NONCE_LEN {
+        return Err(EnvelopeError::Decrypt);
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| EnvelopeError::Decrypt)
+}
+
+/// A row's data key in the clear. Only ever held in memory and zeroized on
+/// drop.
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct DataKey([u8; KEY_LEN]);
+
+impl DataKey {
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let mut key = Self([0u8; KEY_LEN]);
+        rng.fill_bytes(&mut key.0);
+        key
+    }
+
+    pub fn seal<R: RngCore + CryptoRng>(
+        &self,
+        aad: &[u8],
+        plaintext: &[u8],
+        rng: &mut R,
+    ) -> Vec<u8> {
+        seal(&self.0, aad, plaintext, rng)
+    }
+
+    pub fn open(&self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, EnvelopeError> {
+        open(&self.0, aad, sealed)
+    }
+}
+
+/// A wrapping key held in process memory, for key providers that only store
+/// secrets. Zeroized on drop.
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct LocalWrappingKey([u8; KEY_LEN]);
+
+impl LocalWrappingKey {
+    pub fn new(key: &[u8]) -> Result<Self, KeyProviderError> {
+        if key.len() != KEY_LEN {
+            return Err(KeyProviderError::Invalid(format!(
+                "wrapping keys are {} bytes",
+                KEY_LEN
+            )));
+        }
+        let mut wrapping_key = Self([0u8; KEY_LEN]);
+        wrapping_key.0.copy_from_slice(key);
+        Ok(wrapping_key)
+    }
+}
+
+impl WrappingKey for LocalWrappingKey {
+    fn wrap(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, KeyProviderError> {
+        Ok(seal(&self.0, aad, plaintext, &mut OsRng))
+    }
+
+    fn unwrap(&self, aad: &[u8], wrapped: &[u8]) -> Result<SecretBytes, KeyProviderError> {
+        open(&self.0, aad, wrapped)
+            .map(SecretBytes::new)
+            .map_err(|_| KeyProviderError::Decrypt)
+    }
+}
+
+/// The key-encryption keys (KEKs). They live outside the database and wrap
+/// the per-row data keys stored in it, so a database dump alone decrypts
+/// nothing. New data keys are wrapped with the current KEK, older ones are
+/// kept until no row is wrapped with them any more.
+#[derive(Clone)]
+pub struct KekRing {
+    current: i32,
+    keks: BTreeMap<i32, Arc<dyn WrappingKey>>,
+}
+
+impl KekRing {
+    /// Every KEK `provider` holds, creating the first on first start. The
+    /// KEK with the highest id is current.
+    pub fn load(provider: &dyn KeyProvider) -> Result<Self, EnvelopeError> {
+        let mut keks = BTreeMap::new();
+        let mut kek_id = 1;
+        while let Some(kek) = provider.wrapping_key(&kek_name(kek_id))? {
+            keks.insert(kek_id, kek);
+            kek_id += 1;
+        }
+        let mut ring = Self {
+            current: keks.keys().last().copied().unwrap_or(0),
+            keks,
+        };
+        if ring.keks.is_empty() {
+            ring.rotate(provider)?;
+            tracing::info!(kind = provider.kind(), "created the first KEK");
+        }
+        Ok(ring)
+    }
+
+    pub fn current_kek_id(&self) -> i32 {
+        self.current
+    }
+
+    /// Adds a new KEK to `provider` and makes it current. Returns its id.
+    pub fn rotate(&mut self, provider: &dyn KeyProvider) -> Result<i32, EnvelopeError> {
+        let kek_id = self.current + 1;
+        let kek = provider.generate_wrapping_key(&kek_name(kek_id))?;
+        self.keks.insert(kek_id, kek);
+        self.current = kek_id;
+        Ok(kek_id)
+    }
+
+    /// Wraps `data_key` with the current KEK. Returns the KEK id to store
+    /// next to the wrapped key.
+    pub fn wrap(&self, aad: &[u8], data_key: &DataKey) -> Result<(i32, Vec<u8>), EnvelopeError> {
+        let kek = self
+            .keks
+            .get(&self.current)
+            .ok_or(EnvelopeError::UnknownKekId(self.current))?;
+        Ok((self.current, kek.wrap(aad, &data_key.0)?))
+    }
+
+    pub fn unwrap(
+        &self,
+        kek_id: i32,
+        aad: &[u8],
+        wrapped: &[u8],
+    ) -> Result<DataKey, EnvelopeError> {
+        let kek = self
+            .keks
+            .get(&kek_id)
+            .ok_or(EnvelopeError::UnknownKekId(kek_id))?;
+        let key = kek.unwrap(aad, wrapped)?;
+        if key.expose().len() != KEY_LEN {
+            return Err(EnvelopeError::Decrypt);
+        }
+        let mut data_key = DataKey([0u8; KEY_LEN]);
+        data_key.0.copy_from_slice(key.expose());
+        Ok(data_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+    use crate::controllers::key_provider::memory::MemoryKeyProvider;
+
+    #[test]
+    fn data_keys_unwrap_after_rotation() {
+        let provider = MemoryKeyProvider::default();
+        let mut ring = KekRing::load(&provider).unwrap();
+        let data_key = DataKey::generate(&mut OsRng);
+        let (kek_id, wrapped) = ring.wrap(b"row 1", &data_key).unwrap();
+        let sealed = data_key.seal(b"row 1 column", b"secret", &mut OsRng);
+
+        assert_eq!(ring.rotate(&provider).unwrap(), 2);
+        let unwrapped = ring.unwrap(kek_id, b"row 1", &wrapped).unwrap();
+        assert_eq!(unwrapped.open(b"row 1 column", &sealed).unwrap(), b"secret");
+        assert_eq!(ring.wrap(b"row 1", &unwrapped).unwrap().0, 2);
+    }
+
+    #[test]
+    fn ciphertexts_are_bound_to_their_place() {
+        let ring = KekRing::load(&MemoryKeyProvider::default()).unwrap();
+        let data_key = DataKey::generate(&mut OsRng);
+        let (kek_id, wrapped) = ring.wrap(b"row 1", &data_key).unwrap();
+        let sealed = data_key.seal(b"row 1 column", b"secret", &mut OsRng);
+
+        assert!(matches!(
+            ring.unwrap(kek_id, b"row 2", &wrapped),
+            Err(EnvelopeError::Decrypt)
+        ));
+        assert!(matches!(
+            ring.unwrap(7, b"row 1", &wrapped),
+            Err(EnvelopeError::UnknownKekId(7))
+        ));
+        assert!(matches!(
+            data_key.open(b"row 1 other column", &sealed),
+            Err(EnvelopeError::Decrypt)
+        ));
+        assert!(matches!(
+            data_key.open(b"row 1 column", &sealed[..10]),
+            Err(EnvelopeError::Decrypt)
+        ));
+    }
+
+    #[test]
+    fn ring_reloads_from_provider() {
+        let provider = MemoryKeyProvider::default();
+        let mut ring = KekRing::load(&provider).unwrap();
+        let data_key = DataKey::generate(&mut OsRng);
+        let (kek_id, wrapped) = ring.wrap(b"row", &data_key).unwrap();
+        ring.rotate(&provider).unwrap();
+
+        let loaded = KekRing::load(&provider).unwrap();
+        assert_eq!(loaded.current_kek_id(), 2);
+        let unwrapped = loaded.unwrap(kek_id, b"row", &wrapped).unwrap();
+        assert_eq!(unwrapped.0, data_key.0);
+    }
+}
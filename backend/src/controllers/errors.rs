@@ -1,13 +1,43 @@
 use std::fmt::{Display, Formatter};
 
 use opaque_ke::errors::ProtocolError;
-use serde::Serialize;
 
-#[derive(Serialize, Debug)]
+use crate::storage::StorageError;
+
+/// Not `Serialize`: the detail of [`ServiceError::InternalError`] is only
+/// logged, never sent to clients. It must not contain key material.
+#[derive(Debug)]
 pub enum ServiceError {
     InternalError(String),
     InvalidCredentials,
     LoginSessionMissingOrExpired,
+    RegistrationTicketMissingOrExpired,
+    TooManyPendingRegistrations,
+    SessionMissingOrExpired,
+    InvalidRequestSignature,
+    RequestTimestampOutOfWindow,
+    ReplayedNonce,
+    TooManyNonces,
+    InvalidSealedMessage,
+    SealedSequenceMismatch { expected: u64, actual: u64 },
+    SealedChannelRequired,
+    UsernameTaken,
+    AccountLocked,
+}
+
+impl From<sqlx::Error> for ServiceError {
+    fn from(err: sqlx::Error) -> Self {
+        ServiceError::InternalError(err.to_string())
+    }
+}
+
+impl From<StorageError> for ServiceError {
+    fn from(err: StorageError) -> Self {
+        match err {
+            StorageError::Conflict => ServiceError::UsernameTaken,
+            err => ServiceError::InternalError(err.to_string()),
+        }
+    }
 }
 
 impl From<opaque_ke::errors::ProtocolError> for ServiceError {
@@ -30,6 +60,32 @@ impl Display for ServiceError {
             ServiceError::LoginSessionMissingOrExpired => {
                 write!(f, "Login session is missing or expired")
             }
+            ServiceError::RegistrationTicketMissingOrExpired => {
+                write!(f, "Registration ticket is missing, used or expired")
+            }
+            ServiceError::TooManyPendingRegistrations => {
+                write!(f, "Too many registrations are waiting to be finished")
+            }
+            ServiceError::SessionMissingOrExpired => write!(f, "Session is missing or expired"),
+            ServiceError::InvalidRequestSignature => write!(f, "Invalid request signature"),
+            ServiceError::RequestTimestampOutOfWindow => {
+                write!(f, "Request timestamp is outside the allowed window")
+            }
+            ServiceError::ReplayedNonce => write!(f, "Request nonce has already been used"),
+            ServiceError::TooManyNonces => {
+                write!(f, "Too many signed requests inside the timestamp window")
+            }
+            ServiceError::InvalidSealedMessage => write!(f, "Sealed message could not be opened"),
+            ServiceError::SealedSequenceMismatch { expected, actual } => write!(
+                f,
+                "Sealed message out of sequence: expected {} but got {}",
+                expected, actual
+            ),
+            ServiceError::SealedChannelRequired => {
+                write!(f, "Request body must be sealed with the session key")
+            }
+            ServiceError::UsernameTaken => write!(f, "Username is already registered"),
+            ServiceError::AccountLocked => write!(f, "Account is locked by an administrator"),
         }
     }
 }
@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use opaque_ke::rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use super::{KeyProvider, KeyProviderError};
+use crate::utils::base64::Base64String;
+use crate::utils::fs::write_private;
+use crate::utils::secret::{Secret, SecretBytes};
+
+const VERSION: u32 = 1;
+const AAD: &[u8] = b"salauskilke keystore v1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+#[derive(Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u32,
+    kdf: KdfParams,
+    nonce: Base64String,
+    /// The secrets as a JSON object of URL-safe base64 strings
+    ciphertext: Base64String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct KdfParams {
+    salt: Base64String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl KdfParams {
+    fn derive_key(&self, passphrase: &str) -> Result<Secret<[u8; 32]>, KeyProviderError> {
+        let invalid = |e: argon2::Error| KeyProviderError::Invalid(format!("keystore KDF: {}", e));
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(invalid)?;
+        let salt = self
+            .salt
+            .decode_bytes()
+            .map_err(|e| KeyProviderError::Invalid(e.to_string()))?;
+        let mut key = Secret::new([0u8; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, key.expose_mut())
+            .map_err(invalid)?;
+        Ok(key)
+    }
+}
+
+/// Keeps every secret in one file, encrypted with a key derived from a
+/// passphrase with Argon2id. The passphrase is only needed at startup, so the
+/// file can sit next to the database without giving anything away.
+pub struct KeystoreProvider {
+    path: PathBuf,
+    kdf: KdfParams,
+    key: Secret<[u8; 32]>,
+    secrets: Mutex<BTreeMap<String, SecretBytes>>,
+}
+
+impl KeystoreProvider {
+    /// Opens the keystore at `path`, creating an empty one on first start.
+    pub fn open(path: &str, passphrase: &str) -> Result<Self, KeyProviderError> {
+        Self::open_with_params(Path::new(path), passphrase, Params::DEFAULT)
+    }
+
+    /// Like [`Self::open`]. `params` are only used for a new keystore, an
+    /// existing one keeps its own.
+    pub fn open_with_params(
+        path: &Path,
+        passphrase: &str,
+        params: Params,
+    ) -> Result<Self, KeyProviderError> {
+        if !path.exists() {
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            let kdf = KdfParams {
+                salt: Base64String::encode_bytes(&salt),
+                memory_kib: params.m_cost(),
+                iterations: params.t_cost(),
+                parallelism: params.p_cost(),
+            };
+            let keystore = Self {
+                path: path.to_path_buf(),
+                key: kdf.derive_key(passphrase)?,
+                kdf,
+                secrets: Mutex::default(),
+            };
+            keystore.save(&BTreeMap::new())?;
+            tracing::info!(path = %path.display(), "created a new keystore");
+            return Ok(keystore);
+        }
+
+        let contents = fs::read(path).map_err(|e| KeyProviderError::Io(path.to_path_buf(), e))?;
+        let file: KeystoreFile = serde_json::from_slice(&contents)
+            .map_err(|e| KeyProviderError::Invalid(format!("keystore: {}", e)))?;
+        if file.version != VERSION {
+            return Err(KeyProviderError::Invalid(format!(
+                "unsupported keystore version {}",
+                file.version
+            )));
+        }
+        let key = file.kdf.derive_key(passphrase)?;
+        let decode = |value: &Base64String| {
+            value
+                .decode_bytes()
+                .map_err(|e| KeyProviderError::Invalid(format!("keystore: {}", e)))
+        };
+        let nonce = decode(&file.nonce)?;
+        if nonce.len() != NONCE_LEN {
+            return Err(KeyProviderError::Invalid(
+                "keystore nonce length".to_string(),
+            ));
+        }
+        let plaintext = XChaCha20Poly1305::new(key.expose().into())
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &decode(&file.ciphertext)?,
+                    aad: AAD,
+                },
+            )
+            .map(SecretBytes::new)
+            .map_err(|_| KeyProviderError::Locked)?;
+        // serde_json errors can quote the input, which is all secrets here
+        let mut encoded: BTreeMap<String, String> = serde_json::from_slice(plaintext.expose())
+            .map_err(|e| {
+                KeyProviderError::Invalid(format!(
+                    "keystore contents are malformed at line {} column {}",
+                    e.line(),
+                    e.column()
+                ))
+            })?;
+        let secrets = encoded
+            .iter()
+            .map(|(name, secret)| {
+                // The decode error would show the offending character
+                let secret = base64::engine::general_purpose::URL_SAFE
+                    .decode(secret)
+                    .map_err(|_| {
+                        KeyProviderError::Invalid(format!("keystore secret {} is not base64", name))
+                    })?;
+                Ok((name.clone(), SecretBytes::new(secret)))
+            })
+            .collect::<Result<_, KeyProviderError>>();
+        encoded.values_mut().for_each(Zeroize::zeroize);
+        let secrets = secrets?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            kdf: file.kdf,
+            key,
+            secrets: Mutex::new(secrets),
+        })
+    }
+
+    fn secrets(&self) -> Result<MutexGuard<'_, BTreeMap<String, SecretBytes>>, KeyProviderError> {
+        self.secrets
+            .lock()
+            .map_err(|e| KeyProviderError::Backend(e.to_string()))
+    }
+
+    /// Encrypts `secrets` under a fresh nonce and replaces the file
+    /// atomically. The file is only readable by its owner.
+    fn save(&self, secrets: &BTreeMap<String, SecretBytes>) -> Result<(), KeyProviderError> {
+        let mut encoded: BTreeMap<&str, String> = secrets
+            .iter()
+            .map(|(name, secret)| {
+                (
+                    name.as_str(),
+                    base64::engine::general_purpose::URL_SAFE.encode(secret.expose()),
+                )
+            })
+            .collect();
+        let plaintext = serde_json::to_vec(&encoded)
+            .map(SecretBytes::new)
+            .map_err(|e| KeyProviderError::Invalid(format!("keystore: {}", e)));
+        encoded.values_mut().for_each(Zeroize::zeroize);
+        let plaintext = plaintext?;
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = XChaCha20Poly1305::new(self.key.expose().into())
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext.expose(),
+                    aad: AAD,
+                },
+            )
+            .map_err(|_| KeyProviderError::Backend("keystore encryption failed".to_string()))?;
+        let contents = serde_json::to_vec_pretty(&KeystoreFile {
+            version: VERSION,
+            kdf: self.kdf.clone(),
+            nonce: Base64String::encode_bytes(&nonce),
+            ciphertext: Base64String::encode_bytes(&ciphertext),
+        })
+        .map_err(|e| KeyProviderError::Invalid(format!("keystore: {}", e)))?;
+
+        write_private(&self.path, &contents).map_err(|e| KeyProviderError::Io(self.path.clone(), e))
+    }
+}
+
+impl KeyProvider for KeystoreProvider {
+    fn kind(&self) -> &'static str {
+        "keystore"
+    }
+
+    fn secret(&self, name: &str) -> Result<Option<SecretBytes>, KeyProviderError> {
+        Ok(self.secrets()?.get(name).cloned())
+    }
+
+    fn store_secret(&self, name: &str, secret: &[u8]) -> Result<(), KeyProviderError> {
+        let mut secrets = self.secrets()?;
+        let mut updated = secrets.clone();
+        updated.insert(name.to_string(), SecretBytes::new(secret.to_vec()));
+        self.save(&updated)?;
+        *secrets = updated;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+
+    fn fast_params() -> Params {
+        Params::new(Params::MIN_M_COST, 1, 1, None).unwrap()
+    }
+
+    #[test]
+    fn secrets_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keystore.json");
+        let keystore =
+            KeystoreProvider::open_with_params(&path, "correct horse", fast_params()).unwrap();
+        assert!(keystore.secret("kek-1").unwrap().is_none());
+        keystore.store_secret("kek-1", b"key material").unwrap();
+        let signing_key = keystore.generate_signing_key("signing-key").unwrap();
+
+        let reopened =
+            KeystoreProvider::open_with_params(&path, "correct horse", fast_params()).unwrap();
+        assert_eq!(
+            reopened.secret("kek-1").unwrap().unwrap().expose(),
+            b"key material"
+        );
+        assert_eq!(
+            reopened
+                .signing_key("signing-key")
+                .unwrap()
+                .unwrap()
+                .public_key(),
+            signing_key.public_key()
+        );
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains(&Base64String::encode_bytes(b"key material").to_string()));
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keystore.json");
+        KeystoreProvider::open_with_params(&path, "correct horse", fast_params()).unwrap();
+
+        assert!(matches!(
+            KeystoreProvider::open_with_params(&path, "battery staple", fast_params()),
+            Err(KeyProviderError::Locked)
+        ));
+    }
+}
@@ -0,0 +1,35 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use super::{KeyProvider, KeyProviderError};
+use crate::utils::secret::SecretBytes;
+
+/// Keeps secrets in process memory. Meant for tests and throwaway
+/// development servers.
+#[derive(Default)]
+pub struct MemoryKeyProvider {
+    secrets: Mutex<BTreeMap<String, SecretBytes>>,
+}
+
+impl KeyProvider for MemoryKeyProvider {
+    fn kind(&self) -> &'static str {
+        "memory"
+    }
+
+    fn secret(&self, name: &str) -> Result<Option<SecretBytes>, KeyProviderError> {
+        let secrets = self
+            .secrets
+            .lock()
+            .map_err(|e| KeyProviderError::Backend(e.to_string()))?;
+        Ok(secrets.get(name).cloned())
+    }
+
+    fn store_secret(&self, name: &str, secret: &[u8]) -> Result<(), KeyProviderError> {
+        let mut secrets = self
+            .secrets
+            .lock()
+            .map_err(|e| KeyProviderError::Backend(e.to_string()))?;
+        secrets.insert(name.to_string(), SecretBytes::new(secret.to_vec()));
+        Ok(())
+    }
+}
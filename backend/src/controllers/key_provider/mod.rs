@@ -0,0 +1,189 @@
+use std::fmt::Display;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use opaque_ke::rand::{rngs::OsRng, RngCore};
+
+use super::envelope::LocalWrappingKey;
+use super::signing::{SigningKey, SigningKeyError};
+use crate::utils::config::{Config, KeyProviderKind};
+use crate::utils::secret::{Secret, SecretBytes};
+
+pub mod keystore;
+pub mod memory;
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+pub mod secrets;
+
+/// The OPAQUE server setup generations, see
+/// [`crate::controllers::server_setup::ServerSetupKeyring`].
+pub const OPAQUE_KEYRING: &str = "opaque-keyring";
+
+/// The Ed25519 key signing the well-known document.
+pub const SIGNING_KEY: &str = "signing-key";
+
+/// KEKs are named `kek-1`, `kek-2` and so on, see
+/// [`crate::controllers::envelope::KekRing`].
+pub fn kek_name(kek_id: i32) -> String {
+    format!("kek-{}", kek_id)
+}
+
+#[derive(Debug)]
+pub enum KeyProviderError {
+    Io(PathBuf, io::Error),
+    /// A stored secret or the keystore itself is malformed
+    Invalid(String),
+    /// The keystore passphrase is wrong or the keystore was tampered with
+    Locked,
+    /// The provider cannot store new secrets
+    ReadOnly(&'static str),
+    /// A wrapped key was altered or is bound to other data
+    Decrypt,
+    Backend(String),
+}
+
+impl Display for KeyProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyProviderError::Io(path, err) => {
+                write!(f, "Failed to access {}: {}", path.display(), err)
+            }
+            KeyProviderError::Invalid(err) => write!(f, "Invalid key material: {}", err),
+            KeyProviderError::Locked => {
+                write!(f, "Wrong keystore passphrase or tampered keystore")
+            }
+            KeyProviderError::ReadOnly(kind) => {
+                write!(f, "The {} key provider cannot store new secrets", kind)
+            }
+            KeyProviderError::Decrypt => write!(f, "Failed to unwrap key"),
+            KeyProviderError::Backend(err) => write!(f, "Key provider error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for KeyProviderError {}
+
+impl From<SigningKeyError> for KeyProviderError {
+    fn from(err: SigningKeyError) -> Self {
+        KeyProviderError::Invalid(err.to_string())
+    }
+}
+
+/// An Ed25519 private key. Provider backed keys never leave the provider.
+pub trait Signer: Send + Sync {
+    fn public_key(&self) -> &[u8];
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, KeyProviderError>;
+}
+
+/// A symmetric key that wraps other keys, such as the per-account data keys.
+pub trait WrappingKey: Send + Sync {
+    /// `aad` is authenticated but not encrypted, unwrapping needs the same.
+    fn wrap(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, KeyProviderError>;
+
+    /// Fails with [`KeyProviderError::Decrypt`] if `wrapped` was altered or
+    /// `aad` differs.
+    fn unwrap(&self, aad: &[u8], wrapped: &[u8]) -> Result<SecretBytes, KeyProviderError>;
+}
+
+/// Where the server's long-term secrets live. Every secret has a name, such
+/// as [`OPAQUE_KEYRING`]. Secrets the server has to hold in memory, like the
+/// OPAQUE server setup, are plain byte strings. Signing and wrapping keys are
+/// handed out as handles, so providers that can keep them, like PKCS#11
+/// tokens, never reveal them.
+pub trait KeyProvider: Send + Sync {
+    /// Short name for logs and errors, such as `keystore`.
+    fn kind(&self) -> &'static str;
+
+    fn secret(&self, name: &str) -> Result<Option<SecretBytes>, KeyProviderError>;
+
+    /// Stores `secret` under `name`, replacing any earlier one.
+    fn store_secret(&self, name: &str, secret: &[u8]) -> Result<(), KeyProviderError>;
+
+    /// Kept as a PKCS#8 secret unless the provider can hold keys itself.
+    fn signing_key(&self, name: &str) -> Result<Option<SigningKey>, KeyProviderError> {
+        self.secret(name)?
+            .map(|pkcs8| Ok(SigningKey::from_pkcs8(pkcs8.expose())?))
+            .transpose()
+    }
+
+    /// Stores an existing key under `name`, such as one read from
+    /// `signing_key_path` before the provider was configured.
+    fn import_signing_key(&self, name: &str, pkcs8: &[u8]) -> Result<SigningKey, KeyProviderError> {
+        let key = SigningKey::from_pkcs8(pkcs8)?;
+        self.store_secret(name, pkcs8)?;
+        Ok(key)
+    }
+
+    /// Replaces the key under `name` with a new one. Handles to the old key
+    /// keep working until they are dropped.
+    fn generate_signing_key(&self, name: &str) -> Result<SigningKey, KeyProviderError> {
+        let key = SigningKey::generate()?;
+        match key.pkcs8() {
+            Some(pkcs8) => self.import_signing_key(name, pkcs8),
+            None => Err(KeyProviderError::Invalid(
+                "generated signing key has no PKCS#8 encoding".to_string(),
+            )),
+        }
+    }
+
+    /// Kept as a 32 byte secret unless the provider can hold keys itself.
+    fn wrapping_key(&self, name: &str) -> Result<Option<Arc<dyn WrappingKey>>, KeyProviderError> {
+        self.secret(name)?
+            .map(|key| Ok(Arc::new(LocalWrappingKey::new(key.expose())?) as Arc<dyn WrappingKey>))
+            .transpose()
+    }
+
+    fn generate_wrapping_key(&self, name: &str) -> Result<Arc<dyn WrappingKey>, KeyProviderError> {
+        let mut key = Secret::new([0u8; 32]);
+        OsRng.fill_bytes(key.expose_mut());
+        self.store_secret(name, key.expose())?;
+        Ok(Arc::new(LocalWrappingKey::new(key.expose())?))
+    }
+}
+
+/// Opens the provider named by `config.key_provider`, or returns `None` if
+/// none is configured.
+pub fn connect(config: &Config) -> Result<Option<Arc<dyn KeyProvider>>, KeyProviderError> {
+    let missing = |key: &str| KeyProviderError::Invalid(format!("{} is not configured", key));
+    let provider: Arc<dyn KeyProvider> = match config.key_provider {
+        None => return Ok(None),
+        Some(KeyProviderKind::Keystore) => Arc::new(keystore::KeystoreProvider::open(
+            config
+                .keystore_path
+                .as_deref()
+                .ok_or_else(|| missing("keystore_path"))?,
+            config
+                .keystore_passphrase
+                .as_deref()
+                .ok_or_else(|| missing("keystore_passphrase"))?,
+        )?),
+        Some(KeyProviderKind::Secrets) => Arc::new(secrets::SecretsProvider::new(
+            config.secrets_dir.as_deref().map(PathBuf::from),
+        )),
+        #[cfg(feature = "pkcs11")]
+        Some(KeyProviderKind::Pkcs11) => Arc::new(pkcs11::Pkcs11Provider::open(
+            config
+                .pkcs11_module
+                .as_deref()
+                .ok_or_else(|| missing("pkcs11_module"))?,
+            config
+                .pkcs11_token_label
+                .as_deref()
+                .ok_or_else(|| missing("pkcs11_token_label"))?,
+            config
+                .pkcs11_pin
+                .as_deref()
+                .ok_or_else(|| missing("pkcs11_pin"))?,
+        )?),
+        #[cfg(not(feature = "pkcs11"))]
+        Some(KeyProviderKind::Pkcs11) => {
+            return Err(KeyProviderError::Invalid(
+                "the server was built without the pkcs11 feature".to_string(),
+            ))
+        }
+    };
+    tracing::info!(kind = provider.kind(), "opened key provider");
+    Ok(Some(provider))
+}
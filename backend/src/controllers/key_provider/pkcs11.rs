@@ -0,0 +1,416 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use cryptoki::context::{CInitializeArgs, CInitializeFlags, Pkcs11};
+use cryptoki::error::{Error as CryptokiError, RvError};
+use cryptoki::mechanism::aead::GcmParams;
+use cryptoki::mechanism::eddsa::{EddsaParams, EddsaSignatureScheme};
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::{AuthPin, Ulong};
+use opaque_ke::rand::{rngs::OsRng, RngCore};
+
+use super::{KeyProvider, KeyProviderError, Signer, WrappingKey};
+use crate::controllers::signing::SigningKey;
+use crate::utils::secret::SecretBytes;
+
+/// DER encoded OID of Ed25519, the curve of `CKK_EC_EDWARDS` keys
+const ED25519_OID: &[u8] = &[0x06, 0x03, 0x2b, 0x65, 0x70];
+/// An Ed25519 public key is a 32 byte DER OCTET STRING in `CKA_EC_POINT`
+const EC_POINT_PREFIX: &[u8] = &[0x04, 0x20];
+/// What comes before the 32 byte seed in an Ed25519 PKCS#8 document
+const PKCS8_SEED_PREFIX: &[u8] = &[
+    0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+const GCM_IV_LEN: usize = 12;
+const GCM_TAG_LEN: usize = 16;
+
+fn backend(err: CryptokiError) -> KeyProviderError {
+    KeyProviderError::Backend(err.to_string())
+}
+
+/// A loaded module and the context it was initialized in. Dropped after the
+/// session, which must be closed before the module is finalized.
+struct Module {
+    pkcs11: Pkcs11,
+    /// Whether this token initialized the module, and so must finalize it.
+    /// A module someone else in the process initialized stays initialized
+    initialized: bool,
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        if self.initialized {
+            let _ = self.pkcs11.clone().finalize();
+        }
+    }
+}
+
+/// A loaded module with one logged-in session. PKCS#11 sessions must not be
+/// used by two threads at once, so every call goes through `session`.
+struct Token {
+    session: Mutex<Session>,
+    _module: Module,
+}
+
+impl Token {
+    fn open(module: &str, token_label: &str, pin: &str) -> Result<Self, KeyProviderError> {
+        let pkcs11 = Pkcs11::new(module)
+            .map_err(|e| KeyProviderError::Backend(format!("{}: {}", module, e)))?;
+        let initialized =
+            match pkcs11.initialize(CInitializeArgs::new(CInitializeFlags::OS_LOCKING_OK)) {
+                Ok(()) => true,
+                Err(CryptokiError::Pkcs11(RvError::CryptokiAlreadyInitialized, _)) => false,
+                Err(err) => return Err(backend(err)),
+            };
+        let module = Module {
+            pkcs11,
+            initialized,
+        };
+
+        let slot = module
+            .pkcs11
+            .get_slots_with_token()
+            .map_err(backend)?
+            .into_iter()
+            .find(|slot| {
+                module
+                    .pkcs11
+                    .get_token_info(*slot)
+                    .is_ok_and(|info| info.label() == token_label)
+            })
+            .ok_or_else(|| {
+                KeyProviderError::Invalid(format!("no PKCS#11 token labelled {:?}", token_label))
+            })?;
+        let session = module.pkcs11.open_rw_session(slot).map_err(backend)?;
+        match session.login(UserType::User, Some(&AuthPin::from(pin))) {
+            Ok(()) | Err(CryptokiError::Pkcs11(RvError::UserAlreadyLoggedIn, _)) => {}
+            Err(err) => return Err(backend(err)),
+        }
+        Ok(Token {
+            session: Mutex::new(session),
+            _module: module,
+        })
+    }
+
+    fn session(&self) -> Result<MutexGuard<'_, Session>, KeyProviderError> {
+        self.session
+            .lock()
+            .map_err(|e| KeyProviderError::Backend(e.to_string()))
+    }
+
+    fn find(&self, template: &[Attribute]) -> Result<Vec<ObjectHandle>, KeyProviderError> {
+        self.session()?.find_objects(template).map_err(backend)
+    }
+
+    fn attribute(
+        &self,
+        object: ObjectHandle,
+        kind: AttributeType,
+    ) -> Result<Vec<u8>, KeyProviderError> {
+        let attributes = self
+            .session()?
+            .get_attributes(object, &[kind])
+            .map_err(backend)?;
+        attributes
+            .into_iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Value(value) | Attribute::Id(value) | Attribute::EcPoint(value) => {
+                    Some(value)
+                }
+                _ => None,
+            })
+            .ok_or_else(|| KeyProviderError::Backend(format!("the token has no {}", kind)))
+    }
+
+    fn create(&self, template: &[Attribute]) -> Result<ObjectHandle, KeyProviderError> {
+        self.session()?.create_object(template).map_err(backend)
+    }
+
+    fn destroy(&self, object: ObjectHandle) -> Result<(), KeyProviderError> {
+        self.session()?.destroy_object(object).map_err(backend)
+    }
+}
+
+/// An Ed25519 private key that never leaves the token.
+struct Pkcs11Signer {
+    token: Arc<Token>,
+    private_key: ObjectHandle,
+    public_key: Vec<u8>,
+}
+
+impl Signer for Pkcs11Signer {
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, KeyProviderError> {
+        let mechanism = Mechanism::Eddsa(EddsaParams::new(EddsaSignatureScheme::Pure));
+        self.token
+            .session()?
+            .sign(&mechanism, self.private_key, message)
+            .map_err(backend)
+    }
+}
+
+/// An AES-256 key that never leaves the token. Wrapped keys are the GCM IV,
+/// ciphertext and tag.
+struct Pkcs11WrappingKey {
+    token: Arc<Token>,
+    key: ObjectHandle,
+}
+
+impl Pkcs11WrappingKey {
+    fn mechanism<'a>(iv: &'a mut [u8], aad: &'a [u8]) -> Result<Mechanism<'a>, CryptokiError> {
+        let tag_bits = Ulong::try_from(GCM_TAG_LEN * 8)?;
+        GcmParams::new(iv, aad, tag_bits).map(Mechanism::AesGcm)
+    }
+}
+
+impl WrappingKey for Pkcs11WrappingKey {
+    fn wrap(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, KeyProviderError> {
+        let mut iv = [0u8; GCM_IV_LEN];
+        OsRng.fill_bytes(&mut iv);
+        let mechanism = Self::mechanism(&mut iv, aad).map_err(backend)?;
+        let ciphertext = self
+            .token
+            .session()?
+            .encrypt(&mechanism, self.key, plaintext)
+            .map_err(backend)?;
+        Ok([iv.as_slice(), &ciphertext].concat())
+    }
+
+    fn unwrap(&self, aad: &[u8], wrapped: &[u8]) -> Result<SecretBytes, KeyProviderError> {
+        if wrapped.len() < GCM_IV_LEN + GCM_TAG_LEN {
+            return Err(KeyProviderError::Decrypt);
+        }
+        let (iv, ciphertext) = wrapped.split_at(GCM_IV_LEN);
+        let mut iv = iv.to_vec();
+        let mechanism = Self::mechanism(&mut iv, aad).map_err(|_| KeyProviderError::Decrypt)?;
+        self.token
+            .session()?
+            .decrypt(&mechanism, self.key, ciphertext)
+            .map(SecretBytes::new)
+            .map_err(|_| KeyProviderError::Decrypt)
+    }
+}
+
+/// Keeps secrets on a PKCS#11 token, such as an HSM or SoftHSM. Plain
+/// secrets are data objects. Signing keys are Ed25519 key pairs and wrapping
+/// keys are AES keys, both created non-extractable so they never leave the
+/// token.
+pub struct Pkcs11Provider {
+    token: Arc<Token>,
+}
+
+impl Pkcs11Provider {
+    /// Loads `module` and logs in to the token labelled `token_label`.
+    pub fn open(module: &str, token_label: &str, pin: &str) -> Result<Self, KeyProviderError> {
+        Ok(Self {
+            token: Arc::new(Token::open(module, token_label, pin)?),
+        })
+    }
+
+    fn signer(
+        &self,
+        public_key: ObjectHandle,
+        private_key: ObjectHandle,
+    ) -> Result<SigningKey, KeyProviderError> {
+        let point = self.token.attribute(public_key, AttributeType::EcPoint)?;
+        // Some tokens return the raw point instead of a DER OCTET STRING
+        let public_key = point
+            .strip_prefix(EC_POINT_PREFIX)
+            .filter(|key| key.len() == 32)
+            .unwrap_or(&point)
+            .to_vec();
+        Ok(SigningKey::from_signer(Box::new(Pkcs11Signer {
+            token: Arc::clone(&self.token),
+            private_key,
+            public_key,
+        })))
+    }
+
+    /// Key pairs are never destroyed, so old handles keep working. Every
+    /// pair gets its creation time in milliseconds as `CKA_ID` and the
+    /// newest one is used.
+    fn key_pair_id() -> [u8; 8] {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        millis.to_be_bytes()
+    }
+}
+
+impl KeyProvider for Pkcs11Provider {
+    fn kind(&self) -> &'static str {
+        "pkcs11"
+    }
+
+    fn secret(&self, name: &str) -> Result<Option<SecretBytes>, KeyProviderError> {
+        let objects = self.token.find(&[
+            Attribute::Class(ObjectClass::DATA),
+            Attribute::Label(name.as_bytes().to_vec()),
+        ])?;
+        objects
+            .first()
+            .map(|object| {
+                self.token
+                    .attribute(*object, AttributeType::Value)
+                    .map(SecretBytes::new)
+            })
+            .transpose()
+    }
+
+    fn store_secret(&self, name: &str, secret: &[u8]) -> Result<(), KeyProviderError> {
+        let existing = self.token.find(&[
+            Attribute::Class(ObjectClass::DATA),
+            Attribute::Label(name.as_bytes().to_vec()),
+        ])?;
+        self.token.create(&[
+            Attribute::Class(ObjectClass::DATA),
+            Attribute::Token(true),
+            Attribute::Private(true),
+            Attribute::Label(name.as_bytes().to_vec()),
+            Attribute::Value(secret.to_vec()),
+        ])?;
+        for object in existing {
+            self.token.destroy(object)?;
+        }
+        Ok(())
+    }
+
+    fn signing_key(&self, name: &str) -> Result<Option<SigningKey>, KeyProviderError> {
+        let mut newest = None;
+        for private_key in self.token.find(&[
+            Attribute::Class(ObjectClass::PRIVATE_KEY),
+            Attribute::KeyType(KeyType::EC_EDWARDS),
+            Attribute::Label(name.as_bytes().to_vec()),
+        ])? {
+            let id = self.token.attribute(private_key, AttributeType::Id)?;
+            if newest.as_ref().is_none_or(|(newest_id, _)| id > *newest_id) {
+                newest = Some((id, private_key));
+            }
+        }
+        let Some((id, private_key)) = newest else {
+            return Ok(None);
+        };
+        let public_key = self
+            .token
+            .find(&[
+                Attribute::Class(ObjectClass::PUBLIC_KEY),
+                Attribute::Label(name.as_bytes().to_vec()),
+                Attribute::Id(id),
+            ])?
+            .first()
+            .copied()
+            .ok_or_else(|| {
+                KeyProviderError::Invalid(format!("signing key {} has no public key", name))
+            })?;
+        self.signer(public_key, private_key).map(Some)
+    }
+
+    fn import_signing_key(&self, name: &str, pkcs8: &[u8]) -> Result<SigningKey, KeyProviderError> {
+        let public_key = SigningKey::from_pkcs8(pkcs8)?.public_key().to_vec();
+        let seed = pkcs8
+            .get(5..)
+            .and_then(|rest| rest.strip_prefix(PKCS8_SEED_PREFIX))
+            .and_then(|rest| rest.get(..32))
+            .ok_or_else(|| KeyProviderError::Invalid("not an Ed25519 PKCS#8 key".to_string()))?;
+        let id = Self::key_pair_id();
+        let point = [EC_POINT_PREFIX, &public_key].concat();
+
+        let public_key = self.token.create(&[
+            Attribute::Class(ObjectClass::PUBLIC_KEY),
+            Attribute::KeyType(KeyType::EC_EDWARDS),
+            Attribute::Token(true),
+            Attribute::Verify(true),
+            Attribute::Label(name.as_bytes().to_vec()),
+            Attribute::Id(id.to_vec()),
+            Attribute::EcParams(ED25519_OID.to_vec()),
+            Attribute::EcPoint(point),
+        ])?;
+        let private_key = self.token.create(&[
+            Attribute::Class(ObjectClass::PRIVATE_KEY),
+            Attribute::KeyType(KeyType::EC_EDWARDS),
+            Attribute::Token(true),
+            Attribute::Private(true),
+            Attribute::Sensitive(true),
+            Attribute::Extractable(false),
+            Attribute::Sign(true),
+            Attribute::Label(name.as_bytes().to_vec()),
+            Attribute::Id(id.to_vec()),
+            Attribute::EcParams(ED25519_OID.to_vec()),
+            Attribute::Value(seed.to_vec()),
+        ])?;
+        self.signer(public_key, private_key)
+    }
+
+    fn generate_signing_key(&self, name: &str) -> Result<SigningKey, KeyProviderError> {
+        let id = Self::key_pair_id();
+        let (public_key, private_key) = self
+            .token
+            .session()?
+            .generate_key_pair(
+                &Mechanism::EccEdwardsKeyPairGen,
+                &[
+                    Attribute::Token(true),
+                    Attribute::Verify(true),
+                    Attribute::Label(name.as_bytes().to_vec()),
+                    Attribute::Id(id.to_vec()),
+                    Attribute::EcParams(ED25519_OID.to_vec()),
+                ],
+                &[
+                    Attribute::Token(true),
+                    Attribute::Private(true),
+                    Attribute::Sensitive(true),
+                    Attribute::Extractable(false),
+                    Attribute::Sign(true),
+                    Attribute::Label(name.as_bytes().to_vec()),
+                    Attribute::Id(id.to_vec()),
+                ],
+            )
+            .map_err(backend)?;
+        self.signer(public_key, private_key)
+    }
+
+    fn wrapping_key(&self, name: &str) -> Result<Option<Arc<dyn WrappingKey>>, KeyProviderError> {
+        let keys = self.token.find(&[
+            Attribute::Class(ObjectClass::SECRET_KEY),
+            Attribute::KeyType(KeyType::AES),
+            Attribute::Label(name.as_bytes().to_vec()),
+        ])?;
+        Ok(keys.first().map(|key| {
+            Arc::new(Pkcs11WrappingKey {
+                token: Arc::clone(&self.token),
+                key: *key,
+            }) as Arc<dyn WrappingKey>
+        }))
+    }
+
+    fn generate_wrapping_key(&self, name: &str) -> Result<Arc<dyn WrappingKey>, KeyProviderError> {
+        let key = self
+            .token
+            .session()?
+            .generate_key(
+                &Mechanism::AesKeyGen,
+                &[
+                    Attribute::Class(ObjectClass::SECRET_KEY),
+                    Attribute::KeyType(KeyType::AES),
+                    Attribute::Token(true),
+                    Attribute::Private(true),
+                    Attribute::Sensitive(true),
+                    Attribute::Extractable(false),
+                    Attribute::Encrypt(true),
+                    Attribute::Decrypt(true),
+                    Attribute::ValueLen(32.into()),
+                    Attribute::Label(name.as_bytes().to_vec()),
+                ],
+            )
+            .map_err(backend)?;
+        Ok(Arc::new(Pkcs11WrappingKey {
+            token: Arc::clone(&self.token),
+            key,
+        }))
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use base64::Engine;
+
+use super::{KeyProvider, KeyProviderError};
+use crate::utils::fs::write_private;
+use crate::utils::secret::SecretBytes;
+
+/// Environment variables holding secrets start with this, followed by the
+/// upper-cased secret name with dashes as underscores.
+pub const ENV_PREFIX: &str = "SALAUSKILKE_SECRET_";
+
+/// Reads secrets the deployment hands to the server: a file named after the
+/// secret in `dir`, such as systemd's `$CREDENTIALS_DIRECTORY` or a mounted
+/// Kubernetes secret, or else a base64 encoded environment variable like
+/// `SALAUSKILKE_SECRET_OPAQUE_KEYRING`. New secrets are written to `dir`,
+/// without it the provider is read-only.
+pub struct SecretsProvider {
+    dir: Option<PathBuf>,
+}
+
+impl SecretsProvider {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self { dir }
+    }
+
+    pub fn env_var(name: &str) -> String {
+        format!(
+            "{}{}",
+            ENV_PREFIX,
+            name.to_ascii_uppercase().replace('-', "_")
+        )
+    }
+}
+
+/// Secret names become file names, so they must not name another directory.
+fn check_name(name: &str) -> Result<(), KeyProviderError> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(KeyProviderError::Invalid(format!(
+            "{:?} is not a valid secret name",
+            name
+        )));
+    }
+    Ok(())
+}
+
+impl KeyProvider for SecretsProvider {
+    fn kind(&self) -> &'static str {
+        "secrets"
+    }
+
+    fn secret(&self, name: &str) -> Result<Option<SecretBytes>, KeyProviderError> {
+        check_name(name)?;
+        if let Some(dir) = &self.dir {
+            let path = dir.join(name);
+            match fs::read(&path) {
+                Ok(secret) => return Ok(Some(SecretBytes::new(secret))),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(KeyProviderError::Io(path, err)),
+            }
+        }
+
+        let var = Self::env_var(name);
+        match std::env::var(&var) {
+            Ok(encoded) => {
+                let encoded = SecretBytes::new(encoded.into_bytes());
+                // The decode error would show the offending character
+                base64::engine::general_purpose::STANDARD
+                    .decode(encoded.expose().trim_ascii())
+                    .map(|secret| Some(SecretBytes::new(secret)))
+                    .map_err(|_| KeyProviderError::Invalid(format!("{} is not base64", var)))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn store_secret(&self, name: &str, secret: &[u8]) -> Result<(), KeyProviderError> {
+        check_name(name)?;
+        let dir = self
+            .dir
+            .as_ref()
+            .ok_or(KeyProviderError::ReadOnly("secrets"))?;
+
+        let path = dir.join(name);
+        write_private(&path, secret).map_err(|e| KeyProviderError::Io(path.clone(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+
+    #[test]
+    fn secrets_are_read_from_files_then_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = SecretsProvider::new(Some(dir.path().to_path_buf()));
+        assert!(provider.secret("unit-test-missing").unwrap().is_none());
+
+        provider
+            .store_secret("unit-test-file", b"from file")
+            .unwrap();
+        assert_eq!(
+            provider.secret("unit-test-file").unwrap().unwrap().expose(),
+            b"from file"
+        );
+
+        // Only this test sets the variable
+        std::env::set_var("SALAUSKILKE_SECRET_UNIT_TEST_ENV", "ZnJvbSBlbnY=");
+        assert_eq!(
+            SecretsProvider::new(None)
+                .secret("unit-test-env")
+                .unwrap()
+                .unwrap()
+                .expose(),
+            b"from env"
+        );
+    }
+
+    #[test]
+    fn without_directory_secrets_cannot_be_stored() {
+        assert!(matches!(
+            SecretsProvider::new(None).store_secret("kek-1", b"key"),
+            Err(KeyProviderError::ReadOnly("secrets"))
+        ));
+    }
+
+    #[test]
+    fn names_cannot_leave_the_directory() {
+        let provider = SecretsProvider::new(Some(PathBuf::from("/run/credentials")));
+        assert!(matches!(
+            provider.secret("../etc/passwd"),
+            Err(KeyProviderError::Invalid(_))
+        ));
+    }
+}
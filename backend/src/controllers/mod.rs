@@ -1,2 +1,11 @@
+pub mod audit;
+pub mod envelope;
 pub mod errors;
+pub mod key_provider;
 pub mod opaque;
+pub mod rate_limit;
+pub mod sealed_channel;
+pub mod server_setup;
+pub mod session;
+pub mod signing;
+pub mod transparency;
@@ -6,16 +6,23 @@ use opaque_ke::rand::rngs::OsRng;
 use opaque_ke::rand::{CryptoRng, RngCore};
 use opaque_ke::{
     CredentialFinalization, CredentialFinalizationLen, CredentialRequest, CredentialRequestLen,
-    CredentialResponseLen, RegistrationRequest, RegistrationRequestLen, RegistrationResponseLen,
-    RegistrationUpload, RegistrationUploadLen, ServerLogin, ServerLoginStartParameters,
-    ServerLoginStartResult, ServerRegistration, ServerRegistrationLen, ServerSetup,
+    CredentialResponseLen, Identifiers, RegistrationRequest, RegistrationRequestLen,
+    RegistrationResponseLen, RegistrationUpload, RegistrationUploadLen, ServerLogin,
+    ServerLoginStartParameters, ServerRegistration, ServerRegistrationLen,
 };
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 use super::errors::ServiceError;
+use super::server_setup::ServerSetupKeyring;
+use crate::utils::secret::SecretBytes;
 
 pub struct CS;
 
+/// Identifies [`CS`] to clients.
+pub const CIPHERSUITE_ID: &str = "opaque-3dh-ristretto255-sha512-argon2id";
+
 impl CipherSuite for CS {
     type OprfCs = opaque_ke::Ristretto255;
     type KeGroup = opaque_ke::Ristretto255;
@@ -24,10 +31,79 @@ impl CipherSuite for CS {
 }
 
 pub struct OpaqueController<R: RngCore + CryptoRng> {
-    opaque_server_setup: ServerSetup<CS>,
-    users: HashMap<String, GenericArray<u8, ServerRegistrationLen<CS>>>,
+    keyring: ServerSetupKeyring,
     rng: R,
-    login_sessions: HashMap<String, ServerLoginStartResult<CS>>,
+    login_sessions: HashMap<String, PendingLogin>,
+    /// Keyed by the SHA-256 of the ticket so that the tickets themselves are
+    /// never kept
+    registrations: HashMap<[u8; 32], PendingRegistration>,
+    registration_ticket_ttl: Duration,
+    max_pending_registrations: usize,
+    login_session_ttl: Duration,
+    server_identity: Option<String>,
+}
+
+struct PendingLogin {
+    started: Instant,
+    opaque_key_id: i32,
+    /// Zeroized on drop. Boxed so that growing the map moves the pointer,
+    /// not copies of the key exchange state
+    state: Box<ServerLogin<CS>>,
+}
+
+struct PendingRegistration {
+    started: Instant,
+    username: String,
+    purpose: RegistrationPurpose,
+    request_hash: [u8; 32],
+    opaque_key_id: i32,
+}
+
+/// What a registration ticket may be redeemed for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegistrationPurpose {
+    Register,
+    PasswordChange,
+    RecoveryCode,
+}
+
+/// A finished OPAQUE login.
+pub struct LoginOutcome {
+    pub session_key: SecretBytes,
+    /// Set when the account's record belongs to an older server setup
+    /// generation, so the client should register again to move it to the
+    /// current one
+    pub reregistration_required: bool,
+}
+
+/// Length of the tickets returned by [`OpaqueController::register_init`].
+pub const REGISTRATION_TICKET_LEN: usize = 32;
+
+pub type RegistrationTicket = [u8; REGISTRATION_TICKET_LEN];
+
+pub const DEFAULT_REGISTRATION_TICKET_TTL: Duration = Duration::from_secs(300);
+
+/// Registrations need no session to start, so the tickets waiting for
+/// their finish are capped.
+pub const DEFAULT_MAX_PENDING_REGISTRATIONS: usize = 10_000;
+
+pub const DEFAULT_LOGIN_SESSION_TTL: Duration = Duration::from_secs(120);
+
+/// The credential identifier of the recovery code of `username`, passed to
+/// the controller in place of the username. It keeps recovery registrations
+/// and logins apart from password ones, and gives the code an OPRF key of
+/// its own. Usernames may not contain control characters, so none of them
+/// equals the identifier of another user's code.
+pub fn recovery_credential_id(username: &str) -> String {
+    format!("recovery\0{}", username)
+}
+
+/// Records how long a server-side OPAQUE operation took. The Argon2 key
+/// stretching itself runs on the client, so this is the server's share of the
+/// work per request.
+fn record_operation(operation: &'static str, started: Instant) {
+    metrics::histogram!("opaque_server_operation_duration_seconds", "operation" => operation)
+        .record(started.elapsed().as_secs_f64());
 }
 
 impl Default for OpaqueController<OsRng> {
@@ -38,88 +114,265 @@ impl Default for OpaqueController<OsRng> {
 
 impl<R: RngCore + CryptoRng> OpaqueController<R> {
     pub fn new(mut rng: R) -> Self {
+        let keyring = ServerSetupKeyring::generate(&mut rng);
+        Self::with_keyring(rng, keyring)
+    }
+
+    pub fn with_keyring(rng: R, keyring: ServerSetupKeyring) -> Self {
         Self {
-            opaque_server_setup: ServerSetup::<CS>::new(&mut rng),
-            users: HashMap::new(),
+            keyring,
             rng,
             login_sessions: HashMap::new(),
+            registrations: HashMap::new(),
+            registration_ticket_ttl: DEFAULT_REGISTRATION_TICKET_TTL,
+            max_pending_registrations: DEFAULT_MAX_PENDING_REGISTRATIONS,
+            login_session_ttl: DEFAULT_LOGIN_SESSION_TTL,
+            server_identity: None,
         }
     }
 
+    pub fn with_registration_ticket_ttl(mut self, ttl: Duration) -> Self {
+        self.registration_ticket_ttl = ttl;
+        self
+    }
+
+    pub fn with_max_pending_registrations(mut self, max: usize) -> Self {
+        self.max_pending_registrations = max;
+        self
+    }
+
+    /// Binds logins to `identity`. Clients put the same identity into their
+    /// registration envelopes, so changing it stops every existing account
+    /// from logging in.
+    pub fn with_server_identity(mut self, identity: String) -> Self {
+        self.server_identity = Some(identity);
+        self
+    }
+
+    /// How long a login may take between [`Self::login_start`] and
+    /// [`Self::login_finish`].
+    pub fn with_login_session_ttl(mut self, ttl: Duration) -> Self {
+        self.login_session_ttl = ttl;
+        self
+    }
+
+    /// The server's current static OPAQUE public key, which clients see
+    /// during registration and login.
+    pub fn server_public_key(&self) -> Vec<u8> {
+        self.keyring.current().public_key()
+    }
+
+    pub fn keyring(&self) -> &ServerSetupKeyring {
+        &self.keyring
+    }
+
+    /// Takes effect for registrations and logins started afterwards.
+    pub fn replace_keyring(&mut self, keyring: ServerSetupKeyring) {
+        self.keyring = keyring;
+    }
+
+    /// Returns the registration response together with a single-use ticket
+    /// that [`Self::register_finish`] requires for the same `username`,
+    /// `purpose` and `registration_request`. Fails while too many tickets
+    /// are waiting, after expired ones are dropped.
     pub fn register_init(
         &mut self,
         username: String,
-        registration_request: GenericArray<u8, RegistrationRequestLen<CS>>,
-    ) -> Result<GenericArray<u8, RegistrationResponseLen<CS>>, ServiceError> {
+        purpose: RegistrationPurpose,
+        registration_request: &GenericArray<u8, RegistrationRequestLen<CS>>,
+    ) -> Result<
+        (
+            GenericArray<u8, RegistrationResponseLen<CS>>,
+            RegistrationTicket,
+        ),
+        ServiceError,
+    > {
+        if self.registrations.len() >= self.max_pending_registrations {
+            self.sweep_expired_registrations();
+            if self.registrations.len() >= self.max_pending_registrations {
+                return Err(ServiceError::TooManyPendingRegistrations);
+            }
+        }
+
+        let started = Instant::now();
+        let current = self.keyring.current();
         let server_registration_start_result = ServerRegistration::<CS>::start(
-            &self.opaque_server_setup,
-            RegistrationRequest::deserialize(&registration_request)?,
+            &current.setup,
+            RegistrationRequest::deserialize(registration_request)?,
             username.as_bytes(),
         )?;
+        record_operation("register_init", started);
+
+        let mut ticket = [0u8; REGISTRATION_TICKET_LEN];
+        self.rng.fill_bytes(&mut ticket);
+        self.registrations.insert(
+            Sha256::digest(ticket).into(),
+            PendingRegistration {
+                started: Instant::now(),
+                username,
+                purpose,
+                request_hash: Sha256::digest(registration_request).into(),
+                opaque_key_id: current.key_id,
+            },
+        );
+        self.record_pending_registrations();
 
-        Ok(server_registration_start_result.message.serialize())
+        Ok((server_registration_start_result.message.serialize(), ticket))
     }
 
+    /// Consumes `ticket` and returns the password file the caller must store
+    /// for `username`, along with the key id of the server setup it was made
+    /// under. Unknown, used or expired tickets and tickets issued for another
+    /// username, purpose or registration request are rejected.
     pub fn register_finish(
         &mut self,
         username: String,
-        registration_finish: GenericArray<u8, RegistrationUploadLen<CS>>,
-    ) -> Result<(), ServiceError> {
-        let password_file = ServerRegistration::finish(RegistrationUpload::<CS>::deserialize(
-            &registration_finish,
-        )?);
-        let serialize_password_file = password_file.serialize();
-
-        self.users.insert(username, serialize_password_file);
-
-        Ok(())
+        purpose: RegistrationPurpose,
+        ticket: &[u8],
+        registration_request: &GenericArray<u8, RegistrationRequestLen<CS>>,
+        registration_finish: &GenericArray<u8, RegistrationUploadLen<CS>>,
+    ) -> Result<(GenericArray<u8, ServerRegistrationLen<CS>>, i32), ServiceError> {
+        let key: [u8; 32] = Sha256::digest(ticket).into();
+        let pending = self.registrations.remove(&key);
+        self.record_pending_registrations();
+        let opaque_key_id = match pending {
+            // The generation may have been retired since the ticket was issued
+            Some(pending)
+                if pending.username == username
+                    && pending.purpose == purpose
+                    && pending.request_hash
+                        == <[u8; 32]>::from(Sha256::digest(registration_request))
+                    && pending.started.elapsed() <= self.registration_ticket_ttl
+                    && self.keyring.get(pending.opaque_key_id).is_some() =>
+            {
+                pending.opaque_key_id
+            }
+            _ => return Err(ServiceError::RegistrationTicketMissingOrExpired),
+        };
+
+        let password_file =
+            ServerRegistration::finish(RegistrationUpload::<CS>::deserialize(registration_finish)?);
+
+        Ok((password_file.serialize(), opaque_key_id))
     }
 
+    /// `record` is the stored password file of `username` and the key id of
+    /// the server setup it was registered under, or `None` for an unknown
+    /// user, in which case a fake response is returned so that clients cannot
+    /// tell which usernames are registered.
     pub fn login_start(
         &mut self,
         username: String,
-        credential_request: GenericArray<u8, CredentialRequestLen<CS>>,
+        record: Option<(&[u8], i32)>,
+        credential_request: &GenericArray<u8, CredentialRequestLen<CS>>,
     ) -> Result<GenericArray<u8, CredentialResponseLen<CS>>, ServiceError> {
-        let record = self
-            .users
-            .get(&username)
-            .map(|p| ServerRegistration::<CS>::deserialize(p))
-            .transpose()?;
+        let started = Instant::now();
+        // A record of a retired generation cannot be used, which the client
+        // sees as wrong credentials
+        let (generation, record) =
+            match record.and_then(|(record, key_id)| Some((self.keyring.get(key_id)?, record))) {
+                Some((generation, record)) => (
+                    generation,
+                    Some(ServerRegistration::<CS>::deserialize(record)?),
+                ),
+                None => (self.keyring.current(), None),
+            };
 
         let server_login_start_result = ServerLogin::start(
             &mut self.rng,
-            &self.opaque_server_setup,
+            &generation.setup,
             record,
-            CredentialRequest::deserialize(&credential_request)?,
+            CredentialRequest::deserialize(credential_request)?,
             username.as_bytes(),
-            ServerLoginStartParameters::default(),
+            ServerLoginStartParameters {
+                context: None,
+                identifiers: Identifiers {
+                    client: None,
+                    server: self.server_identity.as_deref().map(str::as_bytes),
+                },
+            },
         )?;
+        record_operation("login_start", started);
+
+        let credential_response = server_login_start_result.message.serialize();
+        self.login_sessions.insert(
+            username.clone(),
+            PendingLogin {
+                started: Instant::now(),
+                opaque_key_id: generation.key_id,
+                state: Box::new(server_login_start_result.state),
+            },
+        );
+        self.record_pending_login_sessions();
 
-        self.login_sessions
-            .insert(username.clone(), server_login_start_result.clone());
-
-        Ok(server_login_start_result.message.serialize())
+        Ok(credential_response)
     }
 
     pub fn login_finish(
         &mut self,
         username: String,
-        credential_finalization_bytes: GenericArray<u8, CredentialFinalizationLen<CS>>,
-    ) -> Result<(), ServiceError> {
-        let server_login_start_result = self
+        credential_finalization_bytes: &GenericArray<u8, CredentialFinalizationLen<CS>>,
+    ) -> Result<LoginOutcome, ServiceError> {
+        let pending = self
             .login_sessions
-            .get(&username)
-            .cloned()
-            .ok_or(ServiceError::LoginSessionMissingOrExpired)?;
+            .remove(&username)
+            .filter(|pending| pending.started.elapsed() <= self.login_session_ttl)
+            .ok_or(ServiceError::LoginSessionMissingOrExpired);
+        self.record_pending_login_sessions();
+
+        let started = Instant::now();
+        let current_key_id = self.keyring.current_key_id();
+        let result = pending.and_then(|pending| {
+            let credential_finalization =
+                CredentialFinalization::deserialize(credential_finalization_bytes)?;
+            let finish = pending.state.finish(credential_finalization)?;
+            Ok(LoginOutcome {
+                session_key: SecretBytes::new(finish.session_key.to_vec()),
+                reregistration_required: pending.opaque_key_id != current_key_id,
+            })
+        });
+        record_operation("login_finish", started);
+
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+        metrics::counter!("login_attempts_total", "outcome" => outcome).increment(1);
+
+        result
+    }
 
-        let credential_finalization =
-            CredentialFinalization::deserialize(&credential_finalization_bytes)?;
+    pub fn pending_login_sessions(&self) -> usize {
+        self.login_sessions.len()
+    }
 
-        server_login_start_result
-            .state
-            .finish(credential_finalization)?;
+    /// Drops login sessions that were started but never finished in time.
+    pub fn sweep_expired_login_sessions(&mut self) -> usize {
+        let before = self.login_sessions.len();
+        let ttl = self.login_session_ttl;
+        self.login_sessions
+            .retain(|_, pending| pending.started.elapsed() <= ttl);
+        self.record_pending_login_sessions();
+        before - self.login_sessions.len()
+    }
 
-        Ok(())
+    pub fn pending_registrations(&self) -> usize {
+        self.registrations.len()
+    }
+
+    /// Drops registration tickets that were never redeemed in time.
+    pub fn sweep_expired_registrations(&mut self) -> usize {
+        let before = self.registrations.len();
+        let ttl = self.registration_ticket_ttl;
+        self.registrations
+            .retain(|_, pending| pending.started.elapsed() <= ttl);
+        self.record_pending_registrations();
+        before - self.registrations.len()
+    }
+
+    fn record_pending_registrations(&self) {
+        metrics::gauge!("opaque_pending_registrations").set(self.registrations.len() as f64);
+    }
+
+    fn record_pending_login_sessions(&self) {
+        metrics::gauge!("opaque_pending_login_sessions").set(self.login_sessions.len() as f64);
     }
 }
 
@@ -134,7 +387,7 @@ mod tests {
     use opaque_ke::rand::SeedableRng;
     use opaque_ke::{
         ClientLogin, ClientLoginFinishParameters, ClientRegistration,
-        ClientRegistrationFinishParameters, CredentialResponse, RegistrationResponse,
+        ClientRegistrationFinishParameters, CredentialResponse, RegistrationResponse, ServerSetup,
     };
 
     const PASSWORD: &str = "salasana123";
@@ -160,8 +413,12 @@ mod tests {
         let registration_request = client_registration_start.message.serialize();
 
         // Server inits registration
-        let registration_response = opaque_controller
-            .register_init(username.clone(), registration_request)
+        let (registration_response, ticket) = opaque_controller
+            .register_init(
+                username.clone(),
+                RegistrationPurpose::Register,
+                &registration_request,
+            )
             .unwrap();
 
         // Client finalizes registration
@@ -178,10 +435,15 @@ mod tests {
 
         // Server finalizes registration
         opaque_controller
-            .register_finish(username, password_file)
-            .unwrap();
-
-        password_file
+            .register_finish(
+                username,
+                RegistrationPurpose::Register,
+                &ticket,
+                &registration_request,
+                &password_file,
+            )
+            .unwrap()
+            .0
     }
 
     fn login<R: RngCore + CryptoRng>(
@@ -192,9 +454,6 @@ mod tests {
         server_rng: R,
     ) {
         let mut opaque_controller = OpaqueController::new(server_rng);
-        opaque_controller
-            .users
-            .insert(username.clone(), password_file_bytes);
 
         // Client start login
         let client_login_start =
@@ -203,7 +462,11 @@ mod tests {
 
         // Server start login
         let credential_response = opaque_controller
-            .login_start(username.clone(), credential_request)
+            .login_start(
+                username.clone(),
+                Some((&password_file_bytes, 1)),
+                &credential_request,
+            )
             .unwrap();
 
         // Client finish login
@@ -221,7 +484,7 @@ mod tests {
         // Client sends credential_finalization_bytes to server
 
         opaque_controller
-            .login_finish(username, credential_finalization_bytes)
+            .login_finish(username, &credential_finalization_bytes)
             .unwrap();
 
         assert_eq!(
@@ -394,4 +657,338 @@ mod tests {
             server_rng,
         ));
     }
+
+    /// The messages of a registration started by [`start_registration`].
+    struct Started {
+        ticket: RegistrationTicket,
+        request: GenericArray<u8, RegistrationRequestLen<CS>>,
+        upload: GenericArray<u8, RegistrationUploadLen<CS>>,
+    }
+
+    impl Started {
+        fn finish(
+            &self,
+            opaque_controller: &mut OpaqueController<StdRng>,
+            username: &str,
+        ) -> Result<(GenericArray<u8, ServerRegistrationLen<CS>>, i32), ServiceError> {
+            opaque_controller.register_finish(
+                username.to_string(),
+                RegistrationPurpose::Register,
+                &self.ticket,
+                &self.request,
+                &self.upload,
+            )
+        }
+    }
+
+    /// Runs the client side of registration up to the upload and returns the
+    /// server's ticket alongside it.
+    fn start_registration(
+        opaque_controller: &mut OpaqueController<StdRng>,
+        username: &str,
+    ) -> Started {
+        let mut client_rng = StdRng::from_seed([1u8; 32]);
+        let start = ClientRegistration::<CS>::start(&mut client_rng, PASSWORD.as_bytes()).unwrap();
+        let request = start.message.serialize();
+        let (response, ticket) = opaque_controller
+            .register_init(
+                username.to_string(),
+                RegistrationPurpose::Register,
+                &request,
+            )
+            .unwrap();
+        let finish = start
+            .state
+            .finish(
+                &mut client_rng,
+                PASSWORD.as_bytes(),
+                RegistrationResponse::deserialize(&response).unwrap(),
+                ClientRegistrationFinishParameters::default(),
+            )
+            .unwrap();
+        Started {
+            ticket,
+            request,
+            upload: finish.message.serialize(),
+        }
+    }
+
+    #[test]
+    fn register_finish_rejects_unknown_ticket() {
+        let mut opaque_controller = OpaqueController::new(StdRng::from_seed([0u8; 32]));
+        let started = Started {
+            ticket: [7u8; 32],
+            ..start_registration(&mut opaque_controller, USERNAME)
+        };
+
+        let result = started.finish(&mut opaque_controller, USERNAME);
+        assert!(matches!(
+            result,
+            Err(ServiceError::RegistrationTicketMissingOrExpired)
+        ));
+    }
+
+    #[test]
+    fn register_finish_consumes_ticket() {
+        let mut opaque_controller = OpaqueController::new(StdRng::from_seed([0u8; 32]));
+        let started = start_registration(&mut opaque_controller, USERNAME);
+
+        started.finish(&mut opaque_controller, USERNAME).unwrap();
+        assert_eq!(opaque_controller.pending_registrations(), 0);
+        let result = started.finish(&mut opaque_controller, USERNAME);
+        assert!(matches!(
+            result,
+            Err(ServiceError::RegistrationTicketMissingOrExpired)
+        ));
+    }
+
+    #[test]
+    fn register_finish_rejects_ticket_of_other_username() {
+        let mut opaque_controller = OpaqueController::new(StdRng::from_seed([0u8; 32]));
+        let started = start_registration(&mut opaque_controller, USERNAME);
+
+        let result = started.finish(&mut opaque_controller, "mallory");
+        assert!(matches!(
+            result,
+            Err(ServiceError::RegistrationTicketMissingOrExpired)
+        ));
+    }
+
+    #[test]
+    fn register_finish_rejects_expired_ticket() {
+        let mut opaque_controller = OpaqueController::new(StdRng::from_seed([0u8; 32]))
+            .with_registration_ticket_ttl(Duration::ZERO);
+        let started = start_registration(&mut opaque_controller, USERNAME);
+        std::thread::sleep(Duration::from_millis(5));
+
+        let result = started.finish(&mut opaque_controller, USERNAME);
+        assert!(matches!(
+            result,
+            Err(ServiceError::RegistrationTicketMissingOrExpired)
+        ));
+    }
+
+    #[test]
+    fn sweep_drops_expired_registrations() {
+        let mut opaque_controller = OpaqueController::new(StdRng::from_seed([0u8; 32]))
+            .with_registration_ticket_ttl(Duration::ZERO);
+        start_registration(&mut opaque_controller, USERNAME);
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(opaque_controller.sweep_expired_registrations(), 1);
+        assert_eq!(opaque_controller.pending_registrations(), 0);
+    }
+
+    #[test]
+    fn register_finish_rejects_other_purpose_or_request() {
+        let mut opaque_controller = OpaqueController::new(StdRng::from_seed([0u8; 32]));
+        let started = start_registration(&mut opaque_controller, USERNAME);
+        let result = opaque_controller.register_finish(
+            USERNAME.to_string(),
+            RegistrationPurpose::PasswordChange,
+            &started.ticket,
+            &started.request,
+            &started.upload,
+        );
+        assert!(matches!(
+            result,
+            Err(ServiceError::RegistrationTicketMissingOrExpired)
+        ));
+
+        let started = start_registration(&mut opaque_controller, USERNAME);
+        let mut request = started.request;
+        request[0] ^= 1;
+        let result = opaque_controller.register_finish(
+            USERNAME.to_string(),
+            RegistrationPurpose::Register,
+            &started.ticket,
+            &request,
+            &started.upload,
+        );
+        assert!(matches!(
+            result,
+            Err(ServiceError::RegistrationTicketMissingOrExpired)
+        ));
+    }
+
+    #[test]
+    fn register_init_is_capped() {
+        let mut opaque_controller =
+            OpaqueController::new(StdRng::from_seed([0u8; 32])).with_max_pending_registrations(2);
+        start_registration(&mut opaque_controller, USERNAME);
+        start_registration(&mut opaque_controller, USERNAME);
+
+        let mut client_rng = StdRng::from_seed([1u8; 32]);
+        let start = ClientRegistration::<CS>::start(&mut client_rng, PASSWORD.as_bytes()).unwrap();
+        assert!(matches!(
+            opaque_controller.register_init(
+                USERNAME.to_string(),
+                RegistrationPurpose::Register,
+                &start.message.serialize()
+            ),
+            Err(ServiceError::TooManyPendingRegistrations)
+        ));
+
+        // Expired tickets make room
+        let mut opaque_controller = opaque_controller.with_registration_ticket_ttl(Duration::ZERO);
+        start_registration(&mut opaque_controller, USERNAME);
+        assert_eq!(opaque_controller.pending_registrations(), 1);
+    }
+
+    fn finish_login(
+        opaque_controller: &mut OpaqueController<StdRng>,
+        record: &[u8],
+        key_id: i32,
+    ) -> Result<LoginOutcome, ServiceError> {
+        let mut client_rng = StdRng::from_seed([2u8; 32]);
+        let start = ClientLogin::<CS>::start(&mut client_rng, PASSWORD.as_bytes()).unwrap();
+        let response = opaque_controller.login_start(
+            USERNAME.to_string(),
+            Some((record, key_id)),
+            &start.message.serialize(),
+        )?;
+        let finish = start.state.finish(
+            PASSWORD.as_bytes(),
+            CredentialResponse::deserialize(&response).unwrap(),
+            ClientLoginFinishParameters::default(),
+        )?;
+        opaque_controller.login_finish(USERNAME.to_string(), &finish.message.serialize())
+    }
+
+    #[test]
+    fn login_under_old_generation_requires_reregistration() {
+        let mut opaque_controller = OpaqueController::new(StdRng::from_seed([0u8; 32]));
+        let started = start_registration(&mut opaque_controller, USERNAME);
+        let (record, key_id) = started.finish(&mut opaque_controller, USERNAME).unwrap();
+        assert_eq!(key_id, 1);
+        let outcome = finish_login(&mut opaque_controller, &record, key_id).unwrap();
+        assert!(!outcome.reregistration_required);
+
+        let mut keyring = opaque_controller.keyring().clone();
+        keyring.rotate(&mut StdRng::from_seed([3u8; 32]));
+        opaque_controller.replace_keyring(keyring);
+        let outcome = finish_login(&mut opaque_controller, &record, key_id).unwrap();
+        assert!(outcome.reregistration_required);
+
+        // New registrations move to the current generation
+        let started = start_registration(&mut opaque_controller, USERNAME);
+        let (record, key_id) = started.finish(&mut opaque_controller, USERNAME).unwrap();
+        assert_eq!(key_id, 2);
+        let outcome = finish_login(&mut opaque_controller, &record, key_id).unwrap();
+        assert!(!outcome.reregistration_required);
+    }
+
+    #[test]
+    fn login_under_retired_generation_fails() {
+        let mut opaque_controller = OpaqueController::new(StdRng::from_seed([0u8; 32]));
+        let started = start_registration(&mut opaque_controller, USERNAME);
+        let (record, key_id) = started.finish(&mut opaque_controller, USERNAME).unwrap();
+
+        let mut keyring = opaque_controller.keyring().clone();
+        keyring.rotate(&mut StdRng::from_seed([3u8; 32]));
+        keyring.retire(key_id).unwrap();
+        opaque_controller.replace_keyring(keyring);
+
+        assert!(matches!(
+            finish_login(&mut opaque_controller, &record, key_id),
+            Err(ServiceError::InvalidCredentials)
+        ));
+    }
+
+    #[test]
+    fn login_finish_rejects_expired_login() {
+        let mut opaque_controller = OpaqueController::new(StdRng::from_seed([0u8; 32]))
+            .with_login_session_ttl(Duration::ZERO);
+        let started = start_registration(&mut opaque_controller, USERNAME);
+        let (record, key_id) = started.finish(&mut opaque_controller, USERNAME).unwrap();
+
+        let mut client_rng = StdRng::from_seed([2u8; 32]);
+        let start = ClientLogin::<CS>::start(&mut client_rng, PASSWORD.as_bytes()).unwrap();
+        let response = opaque_controller
+            .login_start(
+                USERNAME.to_string(),
+                Some((&record, key_id)),
+                &start.message.serialize(),
+            )
+            .unwrap();
+        let finish = start
+            .state
+            .finish(
+                PASSWORD.as_bytes(),
+                CredentialResponse::deserialize(&response).unwrap(),
+                ClientLoginFinishParameters::default(),
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert!(matches!(
+            opaque_controller.login_finish(USERNAME.to_string(), &finish.message.serialize()),
+            Err(ServiceError::LoginSessionMissingOrExpired)
+        ));
+        assert_eq!(opaque_controller.pending_login_sessions(), 0);
+    }
+
+    #[test]
+    fn login_is_bound_to_the_server_identity() {
+        let mut opaque_controller = OpaqueController::new(StdRng::from_seed([0u8; 32]))
+            .with_server_identity("salauskilke.test".to_string());
+        let mut client_rng = StdRng::from_seed([1u8; 32]);
+        let start = ClientRegistration::<CS>::start(&mut client_rng, PASSWORD.as_bytes()).unwrap();
+        let request = start.message.serialize();
+        let (response, ticket) = opaque_controller
+            .register_init(
+                USERNAME.to_string(),
+                RegistrationPurpose::Register,
+                &request,
+            )
+            .unwrap();
+        let identifiers = Identifiers {
+            client: None,
+            server: Some(b"salauskilke.test"),
+        };
+        let upload = start
+            .state
+            .finish(
+                &mut client_rng,
+                PASSWORD.as_bytes(),
+                RegistrationResponse::deserialize(&response).unwrap(),
+                ClientRegistrationFinishParameters::new(identifiers, None),
+            )
+            .unwrap()
+            .message
+            .serialize();
+        let (record, key_id) = opaque_controller
+            .register_finish(
+                USERNAME.to_string(),
+                RegistrationPurpose::Register,
+                &ticket,
+                &request,
+                &upload,
+            )
+            .unwrap();
+
+        for (server, accepted) in [(&b"salauskilke.test"[..], true), (b"elsewhere", false)] {
+            let start = ClientLogin::<CS>::start(&mut client_rng, PASSWORD.as_bytes()).unwrap();
+            let response = opaque_controller
+                .login_start(
+                    USERNAME.to_string(),
+                    Some((&record, key_id)),
+                    &start.message.serialize(),
+                )
+                .unwrap();
+            let finish = start.state.finish(
+                PASSWORD.as_bytes(),
+                CredentialResponse::deserialize(&response).unwrap(),
+                ClientLoginFinishParameters::new(
+                    None,
+                    Identifiers {
+                        client: None,
+                        server: Some(server),
+                    },
+                    None,
+                ),
+            );
+            assert_eq!(finish.is_ok(), accepted);
+        }
+    }
 }
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket rate limiter keyed by client. Each client may make `burst`
+/// requests at once, refilled at `per_minute` tokens a minute.
+pub struct RateLimiter<K> {
+    buckets: HashMap<K, Bucket>,
+    per_minute: u32,
+    burst: u32,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            buckets: HashMap::new(),
+            per_minute,
+            burst,
+        }
+    }
+
+    fn refill_rate(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+
+    /// Takes a token for `client`. When none is left, returns how long until
+    /// the next one is available.
+    pub fn check(&mut self, client: K, now: Instant) -> Result<(), Duration> {
+        let rate = self.refill_rate();
+        let burst = f64::from(self.burst);
+
+        let bucket = self.buckets.entry(client).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if rate > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+
+    pub fn per_minute(&self) -> u32 {
+        self.per_minute
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    /// The tokens each tracked client has left at `now`. Clients without a
+    /// bucket have the full burst.
+    pub fn tokens(&self, now: Instant) -> impl Iterator<Item = (&K, f64)> {
+        let rate = self.refill_rate();
+        let burst = f64::from(self.burst);
+
+        self.buckets.iter().map(move |(client, bucket)| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            (client, (bucket.tokens + elapsed * rate).min(burst))
+        })
+    }
+
+    /// Drops buckets that have refilled completely, as a fresh bucket would
+    /// behave the same. Returns how many were dropped.
+    pub fn sweep(&mut self, now: Instant) -> usize {
+        let rate = self.refill_rate();
+        let burst = f64::from(self.burst);
+        let before = self.buckets.len();
+
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * rate < burst
+        });
+
+        before - self.buckets.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+
+    #[test]
+    fn allows_burst_then_limits() {
+        let mut limiter = RateLimiter::new(60, 3);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check("client", now).is_ok());
+        }
+        let retry_after = limiter.check("client", now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let mut limiter = RateLimiter::new(60, 1);
+        let now = Instant::now();
+
+        assert!(limiter.check("client", now).is_ok());
+        assert!(limiter.check("client", now).is_err());
+        assert!(limiter
+            .check("client", now + Duration::from_secs(1))
+            .is_ok());
+    }
+
+    #[test]
+    fn clients_are_limited_separately() {
+        let mut limiter = RateLimiter::new(60, 1);
+        let now = Instant::now();
+
+        assert!(limiter.check("a", now).is_ok());
+        assert!(limiter.check("b", now).is_ok());
+        assert!(limiter.check("a", now).is_err());
+    }
+
+    #[test]
+    fn sweeps_full_buckets() {
+        let mut limiter = RateLimiter::new(60, 2);
+        let now = Instant::now();
+
+        limiter.check("client", now).unwrap();
+        assert_eq!(limiter.sweep(now), 0);
+        assert_eq!(limiter.sweep(now + Duration::from_secs(1)), 1);
+    }
+
+    #[test]
+    fn reports_refilled_tokens() {
+        let mut limiter = RateLimiter::new(60, 3);
+        let now = Instant::now();
+
+        limiter.check("client", now).unwrap();
+        limiter.check("client", now).unwrap();
+        let tokens: Vec<_> = limiter.tokens(now + Duration::from_secs(1)).collect();
+        assert_eq!(tokens, vec![(&"client", 2.0)]);
+    }
+}
@@ -0,0 +1,164 @@
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use super::errors::ServiceError;
+
+pub const SEALED_CONTENT_TYPE: &str = "application/salauskilke-sealed";
+
+const CLIENT_TO_SERVER_INFO: &[u8] = b"salauskilke sealed channel client to server v1";
+const SERVER_TO_CLIENT_INFO: &[u8] = b"salauskilke sealed channel server to client v1";
+
+const SEQUENCE_LEN: usize = 8;
+
+#[derive(Clone, Copy)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+impl Direction {
+    fn aad(&self) -> &'static [u8] {
+        match self {
+            Direction::ClientToServer => b"c2s",
+            Direction::ServerToClient => b"s2c",
+        }
+    }
+}
+
+/// Separate AEAD keys for each direction, derived from the OPAQUE session key.
+/// Zeroized on drop.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct ChannelKeys {
+    pub client_to_server: [u8; 32],
+    pub server_to_client: [u8; 32],
+}
+
+impl ChannelKeys {
+    #[allow(clippy::expect_used)]
+    pub fn derive(session_key: &[u8]) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(None, session_key);
+        let mut client_to_server = [0u8; 32];
+        let mut server_to_client = [0u8; 32];
+        hkdf.expand(CLIENT_TO_SERVER_INFO, &mut client_to_server)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        hkdf.expand(SERVER_TO_CLIENT_INFO, &mut server_to_client)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+        Self {
+            client_to_server,
+            server_to_client,
+        }
+    }
+
+    fn key(&self, direction: Direction) -> &[u8; 32] {
+        match direction {
+            Direction::ClientToServer => &self.client_to_server,
+            Direction::ServerToClient => &self.server_to_client,
+        }
+    }
+
+    /// Encrypts `plaintext` into `sequence (8 bytes, big endian) || ciphertext`.
+    pub fn seal(
+        &self,
+        direction: Direction,
+        sequence: u64,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, ServiceError> {
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(self.key(direction)));
+        let ciphertext = cipher
+            .encrypt(
+                &nonce(sequence),
+                Payload {
+                    msg: plaintext,
+                    aad: direction.aad(),
+                },
+            )
+            .map_err(|_| ServiceError::InternalError("Failed to seal message".to_string()))?;
+
+        let mut sealed = Vec::with_capacity(SEQUENCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&sequence.to_be_bytes());
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypts a message produced by [`ChannelKeys::seal`] and returns its
+    /// sequence number and plaintext.
+    pub fn open(
+        &self,
+        direction: Direction,
+        sealed: &[u8],
+    ) -> Result<(u64, Vec<u8>), ServiceError> {
+        if sealed.len() < SEQUENCE_LEN {
+            return Err(ServiceError::InvalidSealedMessage);
+        }
+        let (sequence_bytes, ciphertext) = sealed.split_at(SEQUENCE_LEN);
+        let mut sequence = [0u8; SEQUENCE_LEN];
+        sequence.copy_from_slice(sequence_bytes);
+        let sequence = u64::from_be_bytes(sequence);
+
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(self.key(direction)));
+        let plaintext = cipher
+            .decrypt(
+                &nonce(sequence),
+                Payload {
+                    msg: ciphertext,
+                    aad: direction.aad(),
+                },
+            )
+            .map_err(|_| ServiceError::InvalidSealedMessage)?;
+
+        Ok((sequence, plaintext))
+    }
+}
+
+fn nonce(sequence: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&sequence.to_be_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+
+    const SESSION_KEY: [u8; 64] = [3u8; 64];
+
+    #[test]
+    fn seal_and_open_round_trip() {
+        let keys = ChannelKeys::derive(&SESSION_KEY);
+        let sealed = keys.seal(Direction::ClientToServer, 5, b"hello").unwrap();
+
+        let (sequence, plaintext) = keys.open(Direction::ClientToServer, &sealed).unwrap();
+        assert_eq!(sequence, 5);
+        assert_eq!(plaintext, b"hello");
+    }
+
+    #[test]
+    fn directions_use_separate_keys() {
+        let keys = ChannelKeys::derive(&SESSION_KEY);
+        let sealed = keys.seal(Direction::ClientToServer, 0, b"hello").unwrap();
+
+        assert!(matches!(
+            keys.open(Direction::ServerToClient, &sealed),
+            Err(ServiceError::InvalidSealedMessage)
+        ));
+    }
+
+    #[test]
+    fn tampered_sequence_fails_to_open() {
+        let keys = ChannelKeys::derive(&SESSION_KEY);
+        let mut sealed = keys.seal(Direction::ClientToServer, 0, b"hello").unwrap();
+        sealed[7] = 1;
+
+        assert!(matches!(
+            keys.open(Direction::ClientToServer, &sealed),
+            Err(ServiceError::InvalidSealedMessage)
+        ));
+    }
+}
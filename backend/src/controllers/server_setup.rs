@@ -0,0 +1,495 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use opaque_ke::rand::rngs::OsRng;
+use opaque_ke::rand::{CryptoRng, RngCore};
+use opaque_ke::{ClientRegistration, ServerRegistration, ServerSetup};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::key_provider::{KeyProvider, KeyProviderError, OPAQUE_KEYRING};
+use super::opaque::CS;
+use super::session::unix_timestamp;
+use crate::utils::base64::Base64String;
+use crate::utils::fs::write_private;
+use crate::utils::secret::{Secret, SecretBytes};
+
+#[derive(Debug)]
+pub enum ServerSetupError {
+    Io(PathBuf, io::Error),
+    /// `source` names the file or key provider the keyring came from
+    Invalid {
+        source: String,
+        reason: String,
+    },
+    Provider(KeyProviderError),
+    UnknownKeyId(i32),
+    /// The current generation cannot be retired, rotate first
+    CurrentKeyId(i32),
+}
+
+impl Display for ServerSetupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerSetupError::Io(path, err) => {
+                write!(f, "Failed to access {}: {}", path.display(), err)
+            }
+            ServerSetupError::Invalid { source, reason } => {
+                write!(f, "Invalid OPAQUE keyring {}: {}", source, reason)
+            }
+            ServerSetupError::Provider(err) => write!(f, "{}", err),
+            ServerSetupError::UnknownKeyId(key_id) => {
+                write!(f, "No OPAQUE server setup with key id {}", key_id)
+            }
+            ServerSetupError::CurrentKeyId(key_id) => {
+                write!(
+                    f,
+                    "OPAQUE key id {} is in use for new registrations",
+                    key_id
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ServerSetupError {}
+
+impl From<KeyProviderError> for ServerSetupError {
+    fn from(err: KeyProviderError) -> Self {
+        ServerSetupError::Provider(err)
+    }
+}
+
+/// One generation of the OPAQUE server setup. Every registration record was
+/// made under exactly one generation, and logins must use the same one.
+#[derive(Clone)]
+pub struct ServerSetupGeneration {
+    pub key_id: i32,
+    /// Unix seconds
+    pub created_at: u64,
+    pub setup: ServerSetup<CS>,
+}
+
+impl ServerSetupGeneration {
+    pub fn public_key(&self) -> Vec<u8> {
+        self.setup.keypair().public().serialize().to_vec()
+    }
+
+    /// Hex encoded SHA-256 of the public key.
+    pub fn fingerprint(&self) -> String {
+        hex::encode(Sha256::digest(self.public_key()))
+    }
+}
+
+/// The OPAQUE server setups the server can log users in with. New
+/// registrations always use the current generation, older ones are kept
+/// until no account depends on them.
+#[derive(Clone)]
+pub struct ServerSetupKeyring {
+    current: i32,
+    generations: BTreeMap<i32, ServerSetupGeneration>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyringFile {
+    current_key_id: i32,
+    generations: Vec<GenerationFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GenerationFile {
+    key_id: i32,
+    created_at: u64,
+    server_setup: Base64String,
+}
+
+impl ServerSetupKeyring {
+    /// A keyring with a single generation, key id 1.
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let generation = ServerSetupGeneration {
+            key_id: 1,
+            created_at: unix_timestamp(),
+            setup: ServerSetup::<CS>::new(rng),
+        };
+        Self {
+            current: generation.key_id,
+            generations: BTreeMap::from([(generation.key_id, generation)]),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ServerSetupError> {
+        let contents = fs::read(path)
+            .map(SecretBytes::new)
+            .map_err(|e| ServerSetupError::Io(path.to_path_buf(), e))?;
+        Self::decode(contents.expose()).map_err(|reason| ServerSetupError::Invalid {
+            source: path.display().to_string(),
+            reason,
+        })
+    }
+
+    fn decode(contents: &[u8]) -> Result<Self, String> {
+        // serde_json errors can quote the input, which holds the server setups
+        let file: KeyringFile = serde_json::from_slice(contents)
+            .map_err(|e| format!("malformed JSON at line {} column {}", e.line(), e.column()))?;
+
+        let mut generations = BTreeMap::new();
+        for generation in file.generations {
+            // The decode error would show the offending character
+            let bytes = generation
+                .server_setup
+                .decode_bytes()
+                .map(SecretBytes::new)
+                .map_err(|_| format!("key id {} is not base64", generation.key_id))?;
+            let setup = ServerSetup::<CS>::deserialize(bytes.expose())
+                .map_err(|e| format!("key id {}: {}", generation.key_id, e))?;
+            generations.insert(
+                generation.key_id,
+                ServerSetupGeneration {
+                    key_id: generation.key_id,
+                    created_at: generation.created_at,
+                    setup,
+                },
+            );
+        }
+        if !generations.contains_key(&file.current_key_id) {
+            return Err(format!(
+                "current key id {} has no server setup",
+                file.current_key_id
+            ));
+        }
+
+        Ok(Self {
+            current: file.current_key_id,
+            generations,
+        })
+    }
+
+    fn encode(&self) -> Result<SecretBytes, String> {
+        let file = KeyringFile {
+            current_key_id: self.current,
+            generations: self
+                .generations
+                .values()
+                .map(|generation| GenerationFile {
+                    key_id: generation.key_id,
+                    created_at: generation.created_at,
+                    server_setup: Base64String::encode(
+                        Secret::new(generation.setup.serialize()).expose(),
+                    ),
+                })
+                .collect(),
+        };
+        serde_json::to_vec_pretty(&file)
+            .map(SecretBytes::new)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Writes every generation to `path`, replacing the file atomically. The
+    /// file is only readable by its owner.
+    pub fn save(&self, path: &Path) -> Result<(), ServerSetupError> {
+        let contents = self.encode().map_err(|reason| ServerSetupError::Invalid {
+            source: path.display().to_string(),
+            reason,
+        })?;
+
+        write_private(path, contents.expose())
+            .map_err(|e| ServerSetupError::Io(path.to_path_buf(), e))
+    }
+
+    /// Loads the keyring held by `provider`. On first start the keyring file
+    /// at `import_path` or else the server setup file of earlier versions is
+    /// imported if it exists, otherwise a new keyring is generated.
+    pub fn load_from<R: RngCore + CryptoRng>(
+        provider: &dyn KeyProvider,
+        import_path: Option<&str>,
+        legacy_setup_path: Option<&str>,
+        rng: &mut R,
+    ) -> Result<Self, ServerSetupError> {
+        if let Some(contents) = provider.secret(OPAQUE_KEYRING)? {
+            return Self::decode(contents.expose()).map_err(|reason| ServerSetupError::Invalid {
+                source: format!("in the {} key provider", provider.kind()),
+                reason,
+            });
+        }
+
+        let legacy_setup_path = legacy_setup_path.filter(|legacy| Path::new(legacy).exists());
+        let keyring = match (import_path, legacy_setup_path) {
+            (Some(path), _) if Path::new(path).exists() => {
+                let keyring = Self::load(Path::new(path))?;
+                tracing::info!(path, "imported the OPAQUE keyring into the key provider");
+                keyring
+            }
+            (_, Some(legacy)) => {
+                let keyring = Self::from_legacy_setup(Path::new(legacy))?;
+                tracing::info!(legacy, "imported the OPAQUE server setup as key id 1");
+                keyring
+            }
+            _ => {
+                tracing::info!("created a new OPAQUE keyring");
+                Self::generate(rng)
+            }
+        };
+        keyring.store_in(provider)?;
+        Ok(keyring)
+    }
+
+    /// Replaces the keyring held by `provider`.
+    pub fn store_in(&self, provider: &dyn KeyProvider) -> Result<(), ServerSetupError> {
+        let contents = self.encode().map_err(|reason| ServerSetupError::Invalid {
+            source: format!("in the {} key provider", provider.kind()),
+            reason,
+        })?;
+        Ok(provider.store_secret(OPAQUE_KEYRING, contents.expose())?)
+    }
+
+    /// Loads the keyring at `path`, creating it on first start. A server setup
+    /// file from before keyrings existed is imported as key id 1 so the
+    /// accounts registered under it keep working. Without a path a throwaway
+    /// keyring is generated.
+    pub fn load_or_generate<R: RngCore + CryptoRng>(
+        path: Option<&str>,
+        legacy_setup_path: Option<&str>,
+        rng: &mut R,
+    ) -> Result<Self, ServerSetupError> {
+        if let Some(path) = path.filter(|path| Path::new(path).exists()) {
+            return Self::load(Path::new(path));
+        }
+
+        let legacy_setup_path = legacy_setup_path.filter(|legacy| Path::new(legacy).exists());
+        let keyring = match legacy_setup_path {
+            Some(legacy) => {
+                let keyring = Self::from_legacy_setup(Path::new(legacy))?;
+                tracing::info!(legacy, "imported the OPAQUE server setup as key id 1");
+                keyring
+            }
+            None => Self::generate(rng),
+        };
+        match path {
+            Some(path) => {
+                keyring.save(Path::new(path))?;
+                tracing::info!(path, "created a new OPAQUE keyring");
+            }
+            None if legacy_setup_path.is_none() => tracing::warn!(
+                "no opaque_keyring_path configured, every account becomes unusable after a restart"
+            ),
+            None => {}
+        }
+        Ok(keyring)
+    }
+
+    /// Reads the raw server setup written by earlier versions.
+    fn from_legacy_setup(path: &Path) -> Result<Self, ServerSetupError> {
+        let contents = fs::read(path).map_err(|e| ServerSetupError::Io(path.to_path_buf(), e))?;
+        let setup =
+            ServerSetup::<CS>::deserialize(&contents).map_err(|e| ServerSetupError::Invalid {
+                source: path.display().to_string(),
+                reason: e.to_string(),
+            })?;
+        let generation = ServerSetupGeneration {
+            key_id: 1,
+            created_at: unix_timestamp(),
+            setup,
+        };
+        Ok(Self {
+            current: generation.key_id,
+            generations: BTreeMap::from([(generation.key_id, generation)]),
+        })
+    }
+
+    pub fn current_key_id(&self) -> i32 {
+        self.current
+    }
+
+    pub fn current(&self) -> &ServerSetupGeneration {
+        &self.generations[&self.current]
+    }
+
+    pub fn get(&self, key_id: i32) -> Option<&ServerSetupGeneration> {
+        self.generations.get(&key_id)
+    }
+
+    /// Whether the current generation is loaded and answers the start of a
+    /// throwaway registration, which is what readiness checks ask.
+    pub fn current_is_usable(&self) -> bool {
+        let Some(generation) = self.generations.get(&self.current) else {
+            return false;
+        };
+        ClientRegistration::<CS>::start(&mut OsRng, b"readiness check")
+            .and_then(|start| {
+                ServerRegistration::<CS>::start(
+                    &generation.setup,
+                    start.message,
+                    b"readiness check",
+                )
+            })
+            .is_ok()
+    }
+
+    /// In key id order.
+    pub fn generations(&self) -> impl Iterator<Item = &ServerSetupGeneration> {
+        self.generations.values()
+    }
+
+    /// Adds a new generation and makes it current. Returns its key id.
+    pub fn rotate<R: RngCore + CryptoRng>(&mut self, rng: &mut R) -> i32 {
+        let key_id = self.generations.keys().last().map_or(1, |last| last + 1);
+        self.generations.insert(
+            key_id,
+            ServerSetupGeneration {
+                key_id,
+                created_at: unix_timestamp(),
+                setup: ServerSetup::<CS>::new(rng),
+            },
+        );
+        self.current = key_id;
+        key_id
+    }
+
+    /// Forgets an old generation. Accounts registered under it can no longer
+    /// log in, so callers check that none are left first.
+    pub fn retire(&mut self, key_id: i32) -> Result<(), ServerSetupError> {
+        if key_id == self.current {
+            return Err(ServerSetupError::CurrentKeyId(key_id));
+        }
+        self.generations
+            .remove(&key_id)
+            .map(|_| ())
+            .ok_or(ServerSetupError::UnknownKeyId(key_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+    use crate::controllers::key_provider::memory::MemoryKeyProvider;
+
+    #[test]
+    fn rotation_keeps_old_generations() {
+        let mut keyring = ServerSetupKeyring::generate(&mut OsRng);
+        let first = keyring.current().fingerprint();
+
+        assert_eq!(keyring.rotate(&mut OsRng), 2);
+        assert_eq!(keyring.current_key_id(), 2);
+        assert_ne!(keyring.current().fingerprint(), first);
+        assert_eq!(keyring.get(1).unwrap().fingerprint(), first);
+    }
+
+    #[test]
+    fn keyring_without_its_current_generation_is_unusable() {
+        let mut keyring = ServerSetupKeyring::generate(&mut OsRng);
+        assert!(keyring.current_is_usable());
+
+        keyring.generations.clear();
+        assert!(!keyring.current_is_usable());
+    }
+
+    #[test]
+    fn current_generation_cannot_be_retired() {
+        let mut keyring = ServerSetupKeyring::generate(&mut OsRng);
+        keyring.rotate(&mut OsRng);
+
+        assert!(matches!(
+            keyring.retire(2),
+            Err(ServerSetupError::CurrentKeyId(2))
+        ));
+        assert!(matches!(
+            keyring.retire(7),
+            Err(ServerSetupError::UnknownKeyId(7))
+        ));
+        keyring.retire(1).unwrap();
+        assert!(keyring.get(1).is_none());
+        // Key ids are never reused
+        assert_eq!(keyring.rotate(&mut OsRng), 3);
+    }
+
+    #[test]
+    fn saved_keyring_loads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("opaque-keyring.json");
+        let mut keyring = ServerSetupKeyring::generate(&mut OsRng);
+        keyring.rotate(&mut OsRng);
+        keyring.save(&path).unwrap();
+
+        let loaded = ServerSetupKeyring::load(&path).unwrap();
+        assert_eq!(loaded.current_key_id(), 2);
+        for (loaded, saved) in loaded.generations().zip(keyring.generations()) {
+            assert_eq!(loaded.key_id, saved.key_id);
+            assert_eq!(loaded.created_at, saved.created_at);
+            assert_eq!(loaded.fingerprint(), saved.fingerprint());
+        }
+
+        let created = ServerSetupKeyring::load_or_generate(
+            Some(dir.path().join("new.json").to_str().unwrap()),
+            None,
+            &mut OsRng,
+        )
+        .unwrap();
+        assert!(dir.path().join("new.json").exists());
+        assert_eq!(created.current_key_id(), 1);
+    }
+
+    #[test]
+    fn keyring_file_is_imported_into_the_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("opaque-keyring.json");
+        let keyring = ServerSetupKeyring::generate(&mut OsRng);
+        keyring.save(&path).unwrap();
+        let provider = MemoryKeyProvider::default();
+
+        let imported =
+            ServerSetupKeyring::load_from(&provider, path.to_str(), None, &mut OsRng).unwrap();
+        assert_eq!(
+            imported.current().fingerprint(),
+            keyring.current().fingerprint()
+        );
+
+        // Later starts use the provider's copy, not the file
+        fs::remove_file(&path).unwrap();
+        let loaded =
+            ServerSetupKeyring::load_from(&provider, path.to_str(), None, &mut OsRng).unwrap();
+        assert_eq!(
+            loaded.current().fingerprint(),
+            keyring.current().fingerprint()
+        );
+    }
+
+    #[test]
+    fn legacy_setup_is_imported_as_first_generation() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("opaque-server-setup.bin");
+        let setup = ServerSetup::<CS>::new(&mut OsRng);
+        fs::write(&legacy, setup.serialize()).unwrap();
+        let path = dir.path().join("opaque-keyring.json");
+
+        let keyring = ServerSetupKeyring::load_or_generate(
+            Some(path.to_str().unwrap()),
+            Some(legacy.to_str().unwrap()),
+            &mut OsRng,
+        )
+        .unwrap();
+        assert_eq!(keyring.current_key_id(), 1);
+        assert_eq!(
+            keyring.current().public_key(),
+            setup.keypair().public().serialize().to_vec()
+        );
+
+        // Once the keyring exists the legacy file is no longer read
+        fs::write(&legacy, b"not a server setup").unwrap();
+        let loaded = ServerSetupKeyring::load_or_generate(
+            Some(path.to_str().unwrap()),
+            Some(legacy.to_str().unwrap()),
+            &mut OsRng,
+        )
+        .unwrap();
+        assert_eq!(
+            loaded.current().fingerprint(),
+            keyring.current().fingerprint()
+        );
+    }
+}
@@ -0,0 +1,466 @@
+use generic_array::typenum::U32;
+use generic_array::GenericArray;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use opaque_ke::rand::rngs::OsRng;
+use opaque_ke::rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::errors::ServiceError;
+use super::sealed_channel::{ChannelKeys, Direction};
+use crate::utils::secret::Secret;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const MAC_KEY_INFO: &[u8] = b"salauskilke request mac v1";
+
+pub const MAC_KEY_LEN: usize = 32;
+
+/// Nonces remembered per session inside the MAC window. A client signing
+/// faster than this is refused until older nonces leave the window.
+const MAX_SEEN_NONCES: usize = 4096;
+
+pub type MacKey = Secret<[u8; MAC_KEY_LEN]>;
+
+/// Derives the request signing key from the OPAQUE session key. Both the
+/// client and the server run this after a successful login.
+#[allow(clippy::expect_used)]
+pub fn derive_mac_key(session_key: &[u8]) -> MacKey {
+    let mut mac_key = MacKey::default();
+    Hkdf::<Sha256>::new(None, session_key)
+        .expand(MAC_KEY_INFO, mac_key.expose_mut())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    mac_key
+}
+
+/// The parts of an HTTP request covered by the request MAC.
+pub struct RequestToSign<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub timestamp: u64,
+    pub nonce: &'a str,
+    pub body: &'a [u8],
+}
+
+impl RequestToSign<'_> {
+    /// `METHOD \n path \n timestamp \n nonce \n hex(sha256(body))`
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        format!(
+            "{}\n{}\n{}\n{}\n{}",
+            self.method,
+            self.path,
+            self.timestamp,
+            self.nonce,
+            hex::encode(Sha256::digest(self.body))
+        )
+        .into_bytes()
+    }
+
+    pub fn sign(&self, mac_key: &MacKey) -> GenericArray<u8, U32> {
+        let mut mac = new_mac(mac_key);
+        mac.update(&self.canonical_bytes());
+        mac.finalize().into_bytes()
+    }
+
+    fn verify(&self, mac_key: &MacKey, signature: &[u8]) -> bool {
+        let mut mac = new_mac(mac_key);
+        mac.update(&self.canonical_bytes());
+        mac.verify_slice(signature).is_ok()
+    }
+}
+
+#[allow(clippy::expect_used)]
+fn new_mac(mac_key: &MacKey) -> HmacSha256 {
+    HmacSha256::new_from_slice(mac_key.expose()).expect("HMAC accepts keys of any length")
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+struct Session {
+    username: String,
+    /// Unix time after which the session is no longer accepted
+    expires_at: u64,
+    mac_key: MacKey,
+    /// Nonces seen inside the timestamp window, mapped to their timestamp
+    seen_nonces: HashMap<String, u64>,
+    channel_keys: ChannelKeys,
+    /// Sequence number expected on the next sealed request
+    next_client_sequence: u64,
+}
+
+pub struct SessionController<R: RngCore + CryptoRng> {
+    sessions: HashMap<String, Session>,
+    rng: R,
+    mac_window_secs: u64,
+    session_ttl_secs: u64,
+}
+
+impl SessionController<OsRng> {
+    pub fn with_os_rng(mac_window_secs: u64, session_ttl_secs: u64) -> Self {
+        SessionController::new(OsRng, mac_window_secs, session_ttl_secs)
+    }
+}
+
+impl<R: RngCore + CryptoRng> SessionController<R> {
+    pub fn new(rng: R, mac_window_secs: u64, session_ttl_secs: u64) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            rng,
+            mac_window_secs,
+            session_ttl_secs,
+        }
+    }
+
+    /// Stores a new session keyed by a random session id and returns the id.
+    pub fn create(&mut self, username: String, session_key: &[u8], now: u64) -> String {
+        let mut id_bytes = [0u8; 32];
+        self.rng.fill_bytes(&mut id_bytes);
+        let session_id = hex::encode(id_bytes);
+
+        self.sessions.insert(
+            session_id.clone(),
+            Session {
+                username,
+                expires_at: now.saturating_add(self.session_ttl_secs),
+                mac_key: derive_mac_key(session_key),
+                seen_nonces: HashMap::new(),
+                channel_keys: ChannelKeys::derive(session_key),
+                next_client_sequence: 0,
+            },
+        );
+
+        session_id
+    }
+
+    pub fn revoke(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+    }
+
+    /// Drops every session of `username` and returns how many were dropped.
+    pub fn revoke_all_for_user(&mut self, username: &str) -> usize {
+        let before = self.sessions.len();
+        self.sessions
+            .retain(|_, session| session.username != username);
+        before - self.sessions.len()
+    }
+
+    /// Drops every session of `username` except `keep`, such as after a
+    /// password change made from `keep`.
+    pub fn revoke_other_sessions(&mut self, username: &str, keep: &str) -> usize {
+        let before = self.sessions.len();
+        self.sessions
+            .retain(|id, session| session.username != username || id == keep);
+        before - self.sessions.len()
+    }
+
+    /// Drops sessions past their expiry time and returns how many were dropped.
+    pub fn sweep_expired_sessions(&mut self, now: u64) -> usize {
+        let before = self.sessions.len();
+        self.sessions.retain(|_, session| session.expires_at >= now);
+        before - self.sessions.len()
+    }
+
+    /// Forgets nonces whose timestamps have left the MAC window, as those
+    /// requests would be rejected by the timestamp check anyway.
+    pub fn sweep_expired_nonces(&mut self, now: u64) {
+        let window = self.mac_window_secs;
+        for session in self.sessions.values_mut() {
+            session
+                .seen_nonces
+                .retain(|_, timestamp| now.abs_diff(*timestamp) <= window);
+        }
+    }
+
+    /// Verifies the MAC, timestamp window and nonce freshness of a request and
+    /// returns the username the session belongs to.
+    pub fn verify_request(
+        &mut self,
+        session_id: &str,
+        request: &RequestToSign,
+        signature: &[u8],
+        now: u64,
+    ) -> Result<String, ServiceError> {
+        let session = self
+            .sessions
+            .get_mut(session_id)
+            .ok_or(ServiceError::SessionMissingOrExpired)?;
+
+        if session.expires_at < now {
+            self.sessions.remove(session_id);
+            return Err(ServiceError::SessionMissingOrExpired);
+        }
+
+        if now.abs_diff(request.timestamp) > self.mac_window_secs {
+            return Err(ServiceError::RequestTimestampOutOfWindow);
+        }
+
+        if !request.verify(&session.mac_key, signature) {
+            return Err(ServiceError::InvalidRequestSignature);
+        }
+
+        let window = self.mac_window_secs;
+        session
+            .seen_nonces
+            .retain(|_, timestamp| now.abs_diff(*timestamp) <= window);
+
+        if session.seen_nonces.contains_key(request.nonce) {
+            return Err(ServiceError::ReplayedNonce);
+        }
+        if session.seen_nonces.len() >= MAX_SEEN_NONCES {
+            return Err(ServiceError::TooManyNonces);
+        }
+        session
+            .seen_nonces
+            .insert(request.nonce.to_string(), request.timestamp);
+
+        Ok(session.username.clone())
+    }
+
+    /// Opens a sealed request body. Sequence numbers must arrive strictly in
+    /// order, which rejects both replayed and reordered messages.
+    pub fn open_request(
+        &mut self,
+        session_id: &str,
+        sealed: &[u8],
+    ) -> Result<(u64, Vec<u8>), ServiceError> {
+        let session = self
+            .sessions
+            .get_mut(session_id)
+            .ok_or(ServiceError::SessionMissingOrExpired)?;
+
+        let (sequence, plaintext) = session
+            .channel_keys
+            .open(Direction::ClientToServer, sealed)?;
+
+        if sequence != session.next_client_sequence {
+            return Err(ServiceError::SealedSequenceMismatch {
+                expected: session.next_client_sequence,
+                actual: sequence,
+            });
+        }
+        session.next_client_sequence += 1;
+
+        Ok((sequence, plaintext))
+    }
+
+    /// Seals a response body under the sequence number of the request it
+    /// answers.
+    pub fn seal_response(
+        &self,
+        session_id: &str,
+        sequence: u64,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, ServiceError> {
+        self.sessions
+            .get(session_id)
+            .ok_or(ServiceError::SessionMissingOrExpired)?
+            .channel_keys
+            .seal(Direction::ServerToClient, sequence, plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+    use opaque_ke::rand::rngs::StdRng;
+    use opaque_ke::rand::SeedableRng;
+
+    const SESSION_KEY: [u8; 64] = [7u8; 64];
+    const NOW: u64 = 1_700_000_000;
+
+    fn request(nonce: &str, timestamp: u64) -> RequestToSign<'_> {
+        RequestToSign {
+            method: "GET",
+            path: "/auth/session",
+            timestamp,
+            nonce,
+            body: b"",
+        }
+    }
+
+    fn controller() -> (SessionController<StdRng>, String) {
+        let mut controller = SessionController::new(StdRng::from_seed([0u8; 32]), 300, 3600);
+        let session_id = controller.create("alice".to_string(), &SESSION_KEY, NOW);
+        (controller, session_id)
+    }
+
+    #[test]
+    fn accepts_correctly_signed_request() {
+        let (mut controller, session_id) = controller();
+        let req = request("nonce-1", NOW);
+        let signature = req.sign(&derive_mac_key(&SESSION_KEY));
+
+        let username = controller
+            .verify_request(&session_id, &req, &signature, NOW)
+            .unwrap();
+        assert_eq!(username, "alice");
+    }
+
+    #[test]
+    fn rejects_replayed_nonce() {
+        let (mut controller, session_id) = controller();
+        let req = request("nonce-1", NOW);
+        let signature = req.sign(&derive_mac_key(&SESSION_KEY));
+
+        controller
+            .verify_request(&session_id, &req, &signature, NOW)
+            .unwrap();
+        assert!(matches!(
+            controller.verify_request(&session_id, &req, &signature, NOW + 1),
+            Err(ServiceError::ReplayedNonce)
+        ));
+    }
+
+    #[test]
+    fn caps_nonces_inside_the_window() {
+        let (mut controller, session_id) = controller();
+        let mac_key = derive_mac_key(&SESSION_KEY);
+        for i in 0..MAX_SEEN_NONCES {
+            let nonce = format!("nonce-{}", i);
+            let req = request(&nonce, NOW);
+            controller
+                .verify_request(&session_id, &req, &req.sign(&mac_key), NOW)
+                .unwrap();
+        }
+
+        let req = request("one-too-many", NOW);
+        let signature = req.sign(&mac_key);
+        assert!(matches!(
+            controller.verify_request(&session_id, &req, &signature, NOW),
+            Err(ServiceError::TooManyNonces)
+        ));
+        // Once the earlier nonces have left the window there is room again
+        let later = NOW + 301;
+        let req = request("one-too-many", later);
+        controller
+            .verify_request(&session_id, &req, &req.sign(&mac_key), later)
+            .unwrap();
+    }
+
+    #[test]
+    fn rejects_stale_timestamp() {
+        let (mut controller, session_id) = controller();
+        let req = request("nonce-1", NOW - 301);
+        let signature = req.sign(&derive_mac_key(&SESSION_KEY));
+
+        assert!(matches!(
+            controller.verify_request(&session_id, &req, &signature, NOW),
+            Err(ServiceError::RequestTimestampOutOfWindow)
+        ));
+    }
+
+    #[test]
+    fn rejects_signature_from_other_key() {
+        let (mut controller, session_id) = controller();
+        let req = request("nonce-1", NOW);
+        let signature = req.sign(&derive_mac_key(&[8u8; 64]));
+
+        assert!(matches!(
+            controller.verify_request(&session_id, &req, &signature, NOW),
+            Err(ServiceError::InvalidRequestSignature)
+        ));
+    }
+
+    #[test]
+    fn sealed_requests_must_arrive_in_order() {
+        let (mut controller, session_id) = controller();
+        let keys = ChannelKeys::derive(&SESSION_KEY);
+
+        let first = keys.seal(Direction::ClientToServer, 0, b"first").unwrap();
+        let second = keys.seal(Direction::ClientToServer, 1, b"second").unwrap();
+
+        assert!(matches!(
+            controller.open_request(&session_id, &second),
+            Err(ServiceError::SealedSequenceMismatch { .. })
+        ));
+        assert_eq!(
+            controller.open_request(&session_id, &first).unwrap().1,
+            b"first"
+        );
+        assert!(matches!(
+            controller.open_request(&session_id, &first),
+            Err(ServiceError::SealedSequenceMismatch { .. })
+        ));
+        assert_eq!(
+            controller.open_request(&session_id, &second).unwrap().1,
+            b"second"
+        );
+    }
+
+    #[test]
+    fn rejects_expired_session() {
+        let (mut controller, session_id) = controller();
+        let later = NOW + 3601;
+        let req = request("nonce-1", later);
+        let signature = req.sign(&derive_mac_key(&SESSION_KEY));
+
+        assert!(matches!(
+            controller.verify_request(&session_id, &req, &signature, later),
+            Err(ServiceError::SessionMissingOrExpired)
+        ));
+        assert_eq!(controller.sweep_expired_sessions(later), 0);
+    }
+
+    #[test]
+    fn sweeps_expired_sessions() {
+        let (mut controller, _) = controller();
+
+        assert_eq!(controller.sweep_expired_sessions(NOW + 3600), 0);
+        assert_eq!(controller.sweep_expired_sessions(NOW + 3601), 1);
+    }
+
+    #[test]
+    fn revokes_every_session_of_a_user() {
+        let (mut controller, session_id) = controller();
+        controller.create("alice".to_string(), &SESSION_KEY, NOW);
+        let other = controller.create("bob".to_string(), &SESSION_KEY, NOW);
+
+        assert_eq!(controller.revoke_all_for_user("alice"), 2);
+        let req = request("nonce-1", NOW);
+        let signature = req.sign(&derive_mac_key(&SESSION_KEY));
+        assert!(matches!(
+            controller.verify_request(&session_id, &req, &signature, NOW),
+            Err(ServiceError::SessionMissingOrExpired)
+        ));
+        assert!(controller
+            .verify_request(&other, &req, &signature, NOW)
+            .is_ok());
+    }
+
+    #[test]
+    fn keeps_the_current_session_when_revoking_others() {
+        let (mut controller, session_id) = controller();
+        controller.create("alice".to_string(), &SESSION_KEY, NOW);
+        controller.create("bob".to_string(), &SESSION_KEY, NOW);
+
+        assert_eq!(controller.revoke_other_sessions("alice", &session_id), 1);
+        let req = request("nonce-1", NOW);
+        let signature = req.sign(&derive_mac_key(&SESSION_KEY));
+        assert!(controller
+            .verify_request(&session_id, &req, &signature, NOW)
+            .is_ok());
+        assert_eq!(controller.revoke_all_for_user("bob"), 1);
+    }
+
+    #[test]
+    fn rejects_unknown_session() {
+        let (mut controller, _) = controller();
+        let req = request("nonce-1", NOW);
+        let signature = req.sign(&derive_mac_key(&SESSION_KEY));
+
+        assert!(matches!(
+            controller.verify_request("unknown", &req, &signature, NOW),
+            Err(ServiceError::SessionMissingOrExpired)
+        ));
+    }
+}
@@ -0,0 +1,339 @@
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// `SHA-256(0x00 || data)`, as in RFC 6962.
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    Sha256::new()
+        .chain_update([LEAF_PREFIX])
+        .chain_update(data)
+        .finalize()
+        .into()
+}
+
+/// `SHA-256(0x01 || left || right)`, as in RFC 6962.
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new()
+        .chain_update([NODE_PREFIX])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+/// An append-only Merkle tree log as specified in RFC 6962, holding only the
+/// leaf hashes. The leaves themselves live in storage.
+#[derive(Default)]
+pub struct MerkleLog {
+    leaves: Vec<Hash>,
+}
+
+impl MerkleLog {
+    pub fn from_leaves<'a>(leaves: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        Self {
+            leaves: leaves.into_iter().map(leaf_hash).collect(),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.leaves.len() as u64
+    }
+
+    /// Appends a leaf and returns its index.
+    pub fn push(&mut self, leaf: &[u8]) -> u64 {
+        self.leaves.push(leaf_hash(leaf));
+        self.size() - 1
+    }
+
+    /// The root hash of the tree made of the first `size` leaves.
+    pub fn root(&self, size: u64) -> Option<Hash> {
+        self.prefix(size).map(root)
+    }
+
+    /// The audit path proving that leaf `index` is in the tree of `size`
+    /// leaves.
+    pub fn inclusion_proof(&self, index: u64, size: u64) -> Option<Vec<Hash>> {
+        if index >= size {
+            return None;
+        }
+        let mut proof = Vec::new();
+        inclusion_path(index as usize, self.prefix(size)?, &mut proof);
+        Some(proof)
+    }
+
+    /// Proves that the tree of `first` leaves is a prefix of the tree of
+    /// `second` leaves.
+    pub fn consistency_proof(&self, first: u64, second: u64) -> Option<Vec<Hash>> {
+        if first > second {
+            return None;
+        }
+        let leaves = self.prefix(second)?;
+        let mut proof = Vec::new();
+        if first > 0 && first < second {
+            subproof(first as usize, leaves, true, &mut proof);
+        }
+        Some(proof)
+    }
+
+    fn prefix(&self, size: u64) -> Option<&[Hash]> {
+        self.leaves.get(..usize::try_from(size).ok()?)
+    }
+}
+
+/// Largest power of two smaller than `n`, for `n > 1`.
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+fn root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        0 => Sha256::digest([]).into(),
+        1 => leaves[0],
+        n => {
+            let k = split_point(n);
+            node_hash(&root(&leaves[..k]), &root(&leaves[k..]))
+        }
+    }
+}
+
+fn inclusion_path(index: usize, leaves: &[Hash], proof: &mut Vec<Hash>) {
+    let n = leaves.len();
+    if n <= 1 {
+        return;
+    }
+    let k = split_point(n);
+    if index < k {
+        inclusion_path(index, &leaves[..k], proof);
+        proof.push(root(&leaves[k..]));
+    } else {
+        inclusion_path(index - k, &leaves[k..], proof);
+        proof.push(root(&leaves[..k]));
+    }
+}
+
+fn subproof(m: usize, leaves: &[Hash], whole: bool, proof: &mut Vec<Hash>) {
+    let n = leaves.len();
+    if m == n {
+        if !whole {
+            proof.push(root(leaves));
+        }
+        return;
+    }
+    let k = split_point(n);
+    if m <= k {
+        subproof(m, &leaves[..k], whole, proof);
+        proof.push(root(&leaves[k..]));
+    } else {
+        subproof(m - k, &leaves[k..], false, proof);
+        proof.push(root(&leaves[..k]));
+    }
+}
+
+/// Checks an inclusion proof, following RFC 9162 section 2.1.3.2.
+pub fn verify_inclusion(
+    leaf_hash: &Hash,
+    index: u64,
+    size: u64,
+    proof: &[Hash],
+    root: &Hash,
+) -> bool {
+    if index >= size {
+        return false;
+    }
+    let (mut fnode, mut snode) = (index, size - 1);
+    let mut hash = *leaf_hash;
+    for sibling in proof {
+        if snode == 0 {
+            return false;
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            hash = node_hash(sibling, &hash);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            hash = node_hash(&hash, sibling);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    snode == 0 && hash == *root
+}
+
+/// Checks a consistency proof, following RFC 9162 section 2.1.4.2.
+pub fn verify_consistency(
+    first: u64,
+    second: u64,
+    first_root: &Hash,
+    second_root: &Hash,
+    proof: &[Hash],
+) -> bool {
+    if first > second {
+        return false;
+    }
+    if first == second {
+        return proof.is_empty() && first_root == second_root;
+    }
+    if first == 0 {
+        return proof.is_empty();
+    }
+
+    let mut path = Vec::with_capacity(proof.len() + 1);
+    if first.is_power_of_two() {
+        path.push(*first_root);
+    }
+    path.extend_from_slice(proof);
+    let Some((first_hash, rest)) = path.split_first() else {
+        return false;
+    };
+
+    let (mut fnode, mut snode) = (first - 1, second - 1);
+    while fnode & 1 == 1 {
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    let (mut fr, mut sr) = (*first_hash, *first_hash);
+    for hash in rest {
+        if snode == 0 {
+            return false;
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            fr = node_hash(hash, &fr);
+            sr = node_hash(hash, &sr);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            sr = node_hash(&sr, hash);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    snode == 0 && fr == *first_root && sr == *second_root
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+
+    fn log_of(size: u64) -> MerkleLog {
+        let leaves: Vec<Vec<u8>> = (0..size)
+            .map(|i| format!("leaf {}", i).into_bytes())
+            .collect();
+        MerkleLog::from_leaves(leaves.iter().map(Vec::as_slice))
+    }
+
+    #[test]
+    fn empty_tree_root_is_hash_of_nothing() {
+        assert_eq!(
+            hex::encode(MerkleLog::default().root(0).unwrap()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn single_leaf_root_is_leaf_hash() {
+        let log = MerkleLog::from_leaves([&b"only"[..]]);
+        assert_eq!(log.root(1).unwrap(), leaf_hash(b"only"));
+        assert_eq!(log.inclusion_proof(0, 1).unwrap(), Vec::<Hash>::new());
+    }
+
+    #[test]
+    fn three_leaf_root_matches_rfc_6962_shape() {
+        let log = MerkleLog::from_leaves([&b"a"[..], b"b", b"c"]);
+        let expected = node_hash(
+            &node_hash(&leaf_hash(b"a"), &leaf_hash(b"b")),
+            &leaf_hash(b"c"),
+        );
+        assert_eq!(log.root(3).unwrap(), expected);
+    }
+
+    #[test]
+    fn inclusion_proofs_verify_for_every_leaf_and_size() {
+        let log = log_of(17);
+        for size in 1..=17 {
+            let root = log.root(size).unwrap();
+            for index in 0..size {
+                let proof = log.inclusion_proof(index, size).unwrap();
+                let leaf = leaf_hash(format!("leaf {}", index).as_bytes());
+                assert!(
+                    verify_inclusion(&leaf, index, size, &proof, &root),
+                    "index {} size {}",
+                    index,
+                    size
+                );
+                assert!(!verify_inclusion(
+                    &leaf_hash(b"other"),
+                    index,
+                    size,
+                    &proof,
+                    &root
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn consistency_proofs_verify_for_every_pair_of_sizes() {
+        let log = log_of(17);
+        for second in 1..=17 {
+            let second_root = log.root(second).unwrap();
+            for first in 1..=second {
+                let first_root = log.root(first).unwrap();
+                let proof = log.consistency_proof(first, second).unwrap();
+                assert!(
+                    verify_consistency(first, second, &first_root, &second_root, &proof),
+                    "first {} second {}",
+                    first,
+                    second
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn consistency_fails_for_rewritten_history() {
+        let log = log_of(8);
+        let mut forked: Vec<Vec<u8>> = (0..8).map(|i| format!("leaf {}", i).into_bytes()).collect();
+        forked[2] = b"rewritten".to_vec();
+        let forked = MerkleLog::from_leaves(forked.iter().map(Vec::as_slice));
+
+        let proof = forked.consistency_proof(3, 8).unwrap();
+        assert!(!verify_consistency(
+            3,
+            8,
+            &log.root(3).unwrap(),
+            &forked.root(8).unwrap(),
+            &proof
+        ));
+    }
+
+    #[test]
+    fn proofs_outside_the_tree_are_rejected() {
+        let log = log_of(4);
+        assert!(log.inclusion_proof(4, 4).is_none());
+        assert!(log.inclusion_proof(0, 5).is_none());
+        assert!(log.consistency_proof(3, 2).is_none());
+        assert!(log.root(5).is_none());
+    }
+
+    #[test]
+    fn push_returns_index() {
+        let mut log = MerkleLog::default();
+        assert_eq!(log.push(b"a"), 0);
+        assert_eq!(log.push(b"b"), 1);
+        assert_eq!(log.size(), 2);
+    }
+}
@@ -0,0 +1,726 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{FromRequestParts, Path, State},
+    http::{request::Parts, StatusCode},
+    middleware,
+};
+use opaque_ke::rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::types::chrono::Utc;
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use super::{
+    audit::{self, page_size, AuditPage, PageQuery},
+    errors::{ApiError, ApiResult, ErrorCode},
+    format::ApiBody,
+    query::ApiQuery,
+    sealed::SealedChannelLayer,
+    session::{verify_request_mac, AuthenticatedSession},
+    tls, AppState,
+};
+use crate::controllers::{
+    audit::{verify_chain, AuditAction, AuditChainError, ChainSummary},
+    errors::ServiceError,
+    key_provider::SIGNING_KEY,
+    server_setup::{ServerSetupError, ServerSetupGeneration, ServerSetupKeyring},
+    session::unix_timestamp,
+    signing::{SignedJson, SigningKey},
+};
+use crate::storage::{Account, Role};
+use crate::utils::base64::Base64String;
+
+pub fn router(state: AppState) -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(accounts))
+        .routes(routes!(lock_account))
+        .routes(routes!(unlock_account))
+        .routes(routes!(revoke_sessions))
+        .routes(routes!(rate_limits))
+        .routes(routes!(rotate_signing_key))
+        .routes(routes!(opaque_keys))
+        .routes(routes!(rotate_opaque_key))
+        .routes(routes!(retire_opaque_key))
+        .routes(routes!(audit_events))
+        .routes(routes!(verify_audit_events))
+        .route_layer(middleware::from_extractor_with_state::<AdminSession, _>(
+            state.clone(),
+        ))
+        .route_layer(SealedChannelLayer::new(state.clone()))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            verify_request_mac,
+        ))
+        // With a client CA configured, admins need a client certificate too
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            tls::require_client_certificate,
+        ))
+        .with_state(state)
+}
+
+/// An authenticated session of an account with the [`Role::Admin`] role.
+/// Cached in the request extensions, so handlers can extract it again for
+/// free after the router has checked it.
+#[derive(Clone, Debug)]
+pub(super) struct AdminSession {
+    account_id: i32,
+    username: String,
+}
+
+impl FromRequestParts<AppState> for AdminSession {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> ApiResult<Self> {
+        if let Some(admin) = parts.extensions.get::<AdminSession>() {
+            return Ok(admin.clone());
+        }
+
+        let session = AuthenticatedSession::from_request_parts(parts, state).await?;
+        let account = state
+            .storage
+            .find_account_by_username(&session.username)
+            .await
+            .map_err(ServiceError::from)?
+            .ok_or(ServiceError::SessionMissingOrExpired)?;
+        if account.role != Role::Admin || account.locked_at.is_some() {
+            return Err(ApiError::Forbidden);
+        }
+
+        let admin = AdminSession {
+            account_id: account.id,
+            username: account.username,
+        };
+        parts.extensions.insert(admin.clone());
+        Ok(admin)
+    }
+}
+
+impl AdminSession {
+    /// Audits an action that does not target a particular account.
+    async fn record(
+        &self,
+        state: &AppState,
+        action: AuditAction,
+        detail: serde_json::Value,
+    ) -> Result<(), ServiceError> {
+        audit::record(state, Some(self.account_id), &self.username, action, detail).await
+    }
+
+    /// Audits an action on `target`, so that it shows up in their own trail.
+    async fn record_for(
+        &self,
+        state: &AppState,
+        target: &Account,
+        action: AuditAction,
+        detail: serde_json::Value,
+    ) -> Result<(), ServiceError> {
+        let mut detail = detail;
+        detail["admin"] = json!(self.username);
+        audit::record(state, Some(target.id), &target.username, action, detail).await
+    }
+}
+
+/// What admins get to see of an account. Never any credential material.
+#[derive(Serialize, ToSchema)]
+struct AccountSummary {
+    id: i32,
+    username: String,
+    role: Role,
+    /// Unix seconds
+    locked_at: Option<i64>,
+}
+
+impl From<Account> for AccountSummary {
+    fn from(account: Account) -> Self {
+        Self {
+            id: account.id,
+            username: account.username,
+            role: account.role,
+            locked_at: account.locked_at.map(|locked_at| locked_at.timestamp()),
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AccountQuery {
+    /// Matches usernames containing it
+    username: Option<String>,
+    /// Id of the last account of the previous page
+    after: Option<i32>,
+    limit: Option<u32>,
+}
+
+/// In id order. Pass `next_after` as `after` to get the next page.
+#[derive(Serialize, ToSchema)]
+struct AccountPage {
+    accounts: Vec<AccountSummary>,
+    next_after: Option<i32>,
+}
+
+/// Searches accounts by username.
+#[utoipa::path(
+    get,
+    path = "/accounts",
+    tag = "admin",
+    security(("request_mac" = [])),
+    params(AccountQuery),
+    responses((status = 200, body = AccountPage))
+)]
+async fn accounts(
+    State(state): State<AppState>,
+    admin: AdminSession,
+    ApiQuery(query): ApiQuery<AccountQuery>,
+) -> ApiResult<ApiBody<AccountPage>> {
+    let limit = page_size(query.limit);
+    let username = query
+        .username
+        .as_deref()
+        .filter(|username| !username.is_empty());
+    let accounts = state
+        .storage
+        .search_accounts(username, query.after, limit)
+        .await
+        .map_err(ServiceError::from)?;
+    admin
+        .record(
+            &state,
+            AuditAction::AccountsSearched,
+            json!({ "username": username, "after": query.after, "results": accounts.len() }),
+        )
+        .await?;
+
+    let next_after = match accounts.last() {
+        Some(last) if accounts.len() == limit as usize => Some(last.id),
+        _ => None,
+    };
+    Ok(ApiBody(AccountPage {
+        accounts: accounts.into_iter().map(AccountSummary::from).collect(),
+        next_after,
+    }))
+}
+
+async fn find_account(state: &AppState, username: &str) -> ApiResult<Account> {
+    state
+        .storage
+        .find_account_by_username(username)
+        .await
+        .map_err(ServiceError::from)?
+        .ok_or(ApiError::NotFound)
+}
+
+/// Revokes the account's sessions both in storage and in the session
+/// controller, which is what requests are checked against.
+async fn revoke_all_sessions(state: &AppState, account: &Account) -> ApiResult<u64> {
+    let revoked = state
+        .storage
+        .revoke_all_sessions(account.id)
+        .await
+        .map_err(ServiceError::from)?;
+    state
+        .session_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .revoke_all_for_user(&account.username);
+    Ok(revoked)
+}
+
+#[derive(Serialize, ToSchema)]
+struct LockResponse {
+    account: AccountSummary,
+    sessions_revoked: u64,
+}
+
+/// Locks the account out of logging in and ends its sessions.
+#[utoipa::path(
+    post,
+    path = "/accounts/{username}/lock",
+    tag = "admin",
+    security(("request_mac" = [])),
+    params(("username" = String, Path)),
+    responses((status = 200, body = LockResponse))
+)]
+async fn lock_account(
+    State(state): State<AppState>,
+    admin: AdminSession,
+    Path(username): Path<String>,
+) -> ApiResult<ApiBody<LockResponse>> {
+    let mut account = find_account(&state, &username).await?;
+    if account.id == admin.account_id {
+        return Err(ApiError::BadRequest(
+            ErrorCode::InvalidValue,
+            "Admins cannot lock their own account".to_string(),
+        ));
+    }
+
+    let locked_at = Utc::now();
+    state
+        .storage
+        .set_account_locked(account.id, Some(locked_at))
+        .await
+        .map_err(ServiceError::from)?;
+    account.locked_at = Some(locked_at);
+    let sessions_revoked = revoke_all_sessions(&state, &account).await?;
+    admin
+        .record_for(
+            &state,
+            &account,
+            AuditAction::AccountLocked,
+            json!({ "sessions_revoked": sessions_revoked }),
+        )
+        .await?;
+
+    Ok(ApiBody(LockResponse {
+        account: account.into(),
+        sessions_revoked,
+    }))
+}
+
+/// Lets the account log in again.
+#[utoipa::path(
+    post,
+    path = "/accounts/{username}/unlock",
+    tag = "admin",
+    security(("request_mac" = [])),
+    params(("username" = String, Path)),
+    responses((status = 200, body = AccountSummary))
+)]
+async fn unlock_account(
+    State(state): State<AppState>,
+    admin: AdminSession,
+    Path(username): Path<String>,
+) -> ApiResult<ApiBody<AccountSummary>> {
+    let mut account = find_account(&state, &username).await?;
+    state
+        .storage
+        .set_account_locked(account.id, None)
+        .await
+        .map_err(ServiceError::from)?;
+    account.locked_at = None;
+    admin
+        .record_for(&state, &account, AuditAction::AccountUnlocked, json!({}))
+        .await?;
+
+    Ok(ApiBody(account.into()))
+}
+
+#[derive(Serialize, ToSchema)]
+struct RevokeSessionsResponse {
+    sessions_revoked: u64,
+}
+
+/// Ends every session of the account.
+#[utoipa::path(
+    delete,
+    path = "/accounts/{username}/sessions",
+    tag = "admin",
+    security(("request_mac" = [])),
+    params(("username" = String, Path)),
+    responses((status = 200, body = RevokeSessionsResponse))
+)]
+async fn revoke_sessions(
+    State(state): State<AppState>,
+    admin: AdminSession,
+    Path(username): Path<String>,
+) -> ApiResult<ApiBody<RevokeSessionsResponse>> {
+    let account = find_account(&state, &username).await?;
+    let sessions_revoked = revoke_all_sessions(&state, &account).await?;
+    admin
+        .record_for(
+            &state,
+            &account,
+            AuditAction::SessionsRevoked,
+            json!({ "sessions_revoked": sessions_revoked }),
+        )
+        .await?;
+
+    Ok(ApiBody(RevokeSessionsResponse { sessions_revoked }))
+}
+
+#[derive(Serialize, ToSchema)]
+struct ClientTokens {
+    client: String,
+    tokens: f64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RateLimitsResponse {
+    per_minute: u32,
+    burst: u32,
+    /// Clients with a partly drained bucket, most limited first
+    clients: Vec<ClientTokens>,
+}
+
+/// The rate limit and the clients it currently holds back.
+#[utoipa::path(
+    get,
+    path = "/rate-limits",
+    tag = "admin",
+    security(("request_mac" = [])),
+    responses((status = 200, body = RateLimitsResponse))
+)]
+async fn rate_limits(
+    State(state): State<AppState>,
+    admin: AdminSession,
+) -> ApiResult<ApiBody<RateLimitsResponse>> {
+    let response = {
+        let rate_limiter = state
+            .rate_limiter
+            .lock()
+            .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+        let mut clients: Vec<ClientTokens> = rate_limiter
+            .tokens(Instant::now())
+            .map(|(client, tokens)| ClientTokens {
+                client: client.to_string(),
+                tokens,
+            })
+            .collect();
+        clients.sort_by(|a, b| a.tokens.total_cmp(&b.tokens));
+        RateLimitsResponse {
+            per_minute: rate_limiter.per_minute(),
+            burst: rate_limiter.burst(),
+            clients,
+        }
+    };
+    admin
+        .record(
+            &state,
+            AuditAction::RateLimitsViewed,
+            json!({ "clients": response.clients.len() }),
+        )
+        .await?;
+
+    Ok(ApiBody(response))
+}
+
+/// Signed by the previous key, so that clients which pinned it can move on
+/// to the new one.
+#[derive(Serialize)]
+struct KeyRotation {
+    previous_fingerprint: String,
+    signing_public_key: Base64String,
+    rotated_at: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RotateSigningKeyResponse {
+    /// Payload is the previous key's statement of `previous_fingerprint`,
+    /// `signing_public_key` and `rotated_at`
+    signing_key_fingerprint: String,
+    endorsement: SignedJson,
+}
+
+/// Replaces the signing key. The new key is stored in the key provider, or
+/// written to `signing_key_path` without one, before it is used, so a restart
+/// keeps it.
+#[utoipa::path(
+    post,
+    path = "/signing-key/rotate",
+    tag = "admin",
+    security(("request_mac" = [])),
+    responses((status = 200, body = RotateSigningKeyResponse))
+)]
+async fn rotate_signing_key(
+    State(state): State<AppState>,
+    admin: AdminSession,
+) -> ApiResult<ApiBody<RotateSigningKeyResponse>> {
+    let new_key = match &state.key_provider {
+        Some(provider) => provider
+            .generate_signing_key(SIGNING_KEY)
+            .map_err(|e| ServiceError::InternalError(e.to_string()))?,
+        None => {
+            let key =
+                SigningKey::generate().map_err(|e| ServiceError::InternalError(e.to_string()))?;
+            if let Some(path) = &state.config.signing_key_path {
+                key.save(std::path::Path::new(path))
+                    .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+            }
+            key
+        }
+    };
+
+    let previous = {
+        let mut signing_key = state
+            .signing_key
+            .write()
+            .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+        std::mem::replace(&mut *signing_key, new_key.into())
+    };
+    let current = state.current_signing_key()?;
+    let endorsement = previous
+        .sign_json(&KeyRotation {
+            previous_fingerprint: previous.fingerprint(),
+            signing_public_key: Base64String::encode_bytes(current.public_key()),
+            rotated_at: unix_timestamp(),
+        })
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+    admin
+        .record(
+            &state,
+            AuditAction::SigningKeyRotated,
+            json!({
+                "previous_fingerprint": previous.fingerprint(),
+                "fingerprint": current.fingerprint(),
+            }),
+        )
+        .await?;
+
+    Ok(ApiBody(RotateSigningKeyResponse {
+        signing_key_fingerprint: current.fingerprint(),
+        endorsement,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+struct OpaqueKey {
+    key_id: i32,
+    created_at: u64,
+    public_key_fingerprint: String,
+    current: bool,
+    /// Accounts registered under this generation. Old generations can be
+    /// retired once this reaches zero
+    accounts: u64,
+}
+
+impl OpaqueKey {
+    fn new(generation: &ServerSetupGeneration, current_key_id: i32, accounts: u64) -> Self {
+        OpaqueKey {
+            key_id: generation.key_id,
+            created_at: generation.created_at,
+            public_key_fingerprint: generation.fingerprint(),
+            current: generation.key_id == current_key_id,
+            accounts,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct OpaqueKeysResponse {
+    current_key_id: i32,
+    keys: Vec<OpaqueKey>,
+}
+
+async fn count_accounts(state: &AppState, key_id: i32) -> ApiResult<u64> {
+    Ok(state
+        .storage
+        .count_accounts_by_opaque_key_id()
+        .await
+        .map_err(ServiceError::from)?
+        .into_iter()
+        .find_map(|(id, accounts)| (id == key_id).then_some(accounts))
+        .unwrap_or(0))
+}
+
+/// Applies `change` to a copy of the OPAQUE keyring and stores it in the key
+/// provider, or writes it to `opaque_keyring_path` without one, before the
+/// server starts using it.
+fn update_keyring<T>(
+    state: &AppState,
+    change: impl FnOnce(&mut ServerSetupKeyring) -> Result<T, ServerSetupError>,
+) -> ApiResult<T> {
+    let mut opaque_controller = state
+        .opaque_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+    let mut keyring = opaque_controller.keyring().clone();
+    let result = change(&mut keyring).map_err(|err| match err {
+        ServerSetupError::UnknownKeyId(_) => ApiError::NotFound,
+        ServerSetupError::CurrentKeyId(_) => {
+            ApiError::Conflict(ErrorCode::OpaqueKeyInUse, err.to_string())
+        }
+        err => ServiceError::InternalError(err.to_string()).into(),
+    })?;
+    match (&state.key_provider, &state.config.opaque_keyring_path) {
+        (Some(provider), _) => keyring.store_in(provider.as_ref()),
+        (None, Some(path)) => keyring.save(std::path::Path::new(path)),
+        (None, None) => Ok(()),
+    }
+    .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+    opaque_controller.replace_keyring(keyring);
+    Ok(result)
+}
+
+/// Every OPAQUE server setup generation and how many accounts depend on it.
+#[utoipa::path(
+    get,
+    path = "/opaque-keys",
+    tag = "admin",
+    security(("request_mac" = [])),
+    responses((status = 200, body = OpaqueKeysResponse))
+)]
+async fn opaque_keys(
+    State(state): State<AppState>,
+    admin: AdminSession,
+) -> ApiResult<ApiBody<OpaqueKeysResponse>> {
+    let counts = state
+        .storage
+        .count_accounts_by_opaque_key_id()
+        .await
+        .map_err(ServiceError::from)?;
+    let response = {
+        let opaque_controller = state
+            .opaque_controller
+            .lock()
+            .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+        let keyring = opaque_controller.keyring();
+        OpaqueKeysResponse {
+            current_key_id: keyring.current_key_id(),
+            keys: keyring
+                .generations()
+                .map(|generation| {
+                    let accounts = counts
+                        .iter()
+                        .find_map(|&(id, accounts)| (id == generation.key_id).then_some(accounts))
+                        .unwrap_or(0);
+                    OpaqueKey::new(generation, keyring.current_key_id(), accounts)
+                })
+                .collect(),
+        }
+    };
+    admin
+        .record(
+            &state,
+            AuditAction::OpaqueKeysViewed,
+            json!({ "keys": response.keys.len() }),
+        )
+        .await?;
+
+    Ok(ApiBody(response))
+}
+
+/// Starts registering new accounts under a fresh OPAQUE server setup. Older
+/// accounts keep logging in with theirs and are asked to register again.
+#[utoipa::path(
+    post,
+    path = "/opaque-keys/rotate",
+    tag = "admin",
+    security(("request_mac" = [])),
+    responses((status = 200, body = OpaqueKey))
+)]
+async fn rotate_opaque_key(
+    State(state): State<AppState>,
+    admin: AdminSession,
+) -> ApiResult<ApiBody<OpaqueKey>> {
+    let key = update_keyring(&state, |keyring| {
+        let key_id = keyring.rotate(&mut OsRng);
+        Ok(keyring
+            .get(key_id)
+            .map(|generation| OpaqueKey::new(generation, key_id, 0)))
+    })?
+    .ok_or_else(|| ServiceError::InternalError("rotated key is missing".to_string()))?;
+    admin
+        .record(
+            &state,
+            AuditAction::OpaqueKeyRotated,
+            json!({
+                "key_id": key.key_id,
+                "public_key_fingerprint": key.public_key_fingerprint,
+            }),
+        )
+        .await?;
+
+    Ok(ApiBody(key))
+}
+
+/// Forgets an old OPAQUE server setup. Refused while accounts still depend
+/// on it, as they could never log in again.
+#[utoipa::path(
+    delete,
+    path = "/opaque-keys/{key_id}",
+    tag = "admin",
+    security(("request_mac" = [])),
+    params(("key_id" = i32, Path)),
+    responses((status = 204, description = "The key was retired"))
+)]
+async fn retire_opaque_key(
+    State(state): State<AppState>,
+    admin: AdminSession,
+    Path(key_id): Path<String>,
+) -> ApiResult<StatusCode> {
+    let key_id: i32 = key_id.parse().map_err(|_| ApiError::NotFound)?;
+    let accounts = count_accounts(&state, key_id).await?;
+    if accounts > 0 {
+        return Err(ApiError::Conflict(
+            ErrorCode::OpaqueKeyInUse,
+            format!(
+                "{} accounts are still registered under OPAQUE key id {}",
+                accounts, key_id
+            ),
+        ));
+    }
+    update_keyring(&state, |keyring| keyring.retire(key_id))?;
+    admin
+        .record(
+            &state,
+            AuditAction::OpaqueKeyRetired,
+            json!({ "key_id": key_id }),
+        )
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The audit trail of every account, newest first.
+#[utoipa::path(
+    get,
+    path = "/audit-events",
+    tag = "admin",
+    security(("request_mac" = [])),
+    params(PageQuery),
+    responses((status = 200, body = AuditPage))
+)]
+async fn audit_events(
+    State(state): State<AppState>,
+    admin: AdminSession,
+    ApiQuery(query): ApiQuery<PageQuery>,
+) -> ApiResult<ApiBody<AuditPage>> {
+    let limit = query.limit();
+    let events = state
+        .storage
+        .list_audit_events(None, query.before, limit)
+        .await
+        .map_err(ServiceError::from)?;
+    admin
+        .record(
+            &state,
+            AuditAction::AuditLogViewed,
+            json!({ "before": query.before, "results": events.len() }),
+        )
+        .await?;
+
+    Ok(ApiBody(AuditPage::new(events, limit)))
+}
+
+/// Recomputes the whole hash chain and names the first bad event, if any.
+#[utoipa::path(
+    get,
+    path = "/audit-events/verify",
+    tag = "admin",
+    security(("request_mac" = [])),
+    responses((status = 200, body = ChainSummary))
+)]
+async fn verify_audit_events(
+    State(state): State<AppState>,
+    admin: AdminSession,
+) -> ApiResult<ApiBody<ChainSummary>> {
+    let result = verify_chain(state.storage.as_ref()).await;
+    let detail = match &result {
+        Ok(summary) => json!({ "events_checked": summary.events_checked }),
+        Err(err) => json!({ "error": err.to_string() }),
+    };
+    admin
+        .record(&state, AuditAction::AuditLogVerified, detail)
+        .await?;
+
+    match result {
+        Ok(summary) => Ok(ApiBody(summary)),
+        Err(AuditChainError::Storage(err)) => Err(ServiceError::from(err).into()),
+        Err(err) => Err(ApiError::Conflict(
+            ErrorCode::AuditChainBroken,
+            err.to_string(),
+        )),
+    }
+}
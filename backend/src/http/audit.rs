@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
+
+use super::AppState;
+use crate::controllers::{audit::AuditAction, errors::ServiceError};
+use crate::storage::{AuditEvent, NewAuditEvent};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+
+/// Appends an event to the audit log. Failing to audit fails the request.
+pub(super) async fn record(
+    state: &AppState,
+    account_id: Option<i32>,
+    username: &str,
+    action: AuditAction,
+    detail: Value,
+) -> Result<(), ServiceError> {
+    state
+        .storage
+        .record_audit_event(NewAuditEvent {
+            account_id,
+            username: Some(username),
+            action: action.as_str(),
+            detail: &detail,
+        })
+        .await
+        .map_err(ServiceError::from)?;
+    Ok(())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct PageQuery {
+    /// Id of the last event of the previous page
+    pub before: Option<i64>,
+    /// 50 by default, at most 200
+    pub limit: Option<u32>,
+}
+
+impl PageQuery {
+    pub fn limit(&self) -> u32 {
+        page_size(self.limit)
+    }
+}
+
+/// The requested page size, or the default, within the allowed range.
+pub(super) fn page_size(limit: Option<u32>) -> u32 {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct AuditEventResponse {
+    id: i64,
+    username: Option<String>,
+    action: String,
+    detail: Value,
+    /// Unix seconds
+    created_at: i64,
+    /// Hex encoded chain hash
+    hash: String,
+}
+
+impl From<AuditEvent> for AuditEventResponse {
+    fn from(event: AuditEvent) -> Self {
+        Self {
+            id: event.id,
+            username: event.username,
+            action: event.action,
+            detail: event.detail,
+            created_at: event.created_at.timestamp(),
+            hash: hex::encode(event.hash),
+        }
+    }
+}
+
+/// Newest first. Pass `next_before` as `before` to get the next page.
+#[derive(Serialize, ToSchema)]
+pub(super) struct AuditPage {
+    events: Vec<AuditEventResponse>,
+    next_before: Option<i64>,
+}
+
+impl AuditPage {
+    pub fn new(events: Vec<AuditEvent>, limit: u32) -> Self {
+        let next_before = match events.last() {
+            Some(last) if events.len() == limit as usize => Some(last.id),
+            _ => None,
+        };
+        Self {
+            events: events.into_iter().map(AuditEventResponse::from).collect(),
+            next_before,
+        }
+    }
+}
@@ -1,118 +1,889 @@
-use super::{errors::ApiResult, AppState};
-use crate::utils::base64::Base64String;
+use super::{
+    audit,
+    errors::{ApiError, ApiResult, ErrorCode, FieldError},
+    format::{ApiBody, Format},
+    rate_limit::limit_by_client_address,
+    sealed::SealedChannelLayer,
+    session::{verify_request_mac, AuthenticatedSession},
+    AppState,
+};
+use crate::controllers::{
+    audit::AuditAction,
+    opaque::{recovery_credential_id, RegistrationPurpose, REGISTRATION_TICKET_LEN},
+    session::unix_timestamp,
+};
+use crate::storage::{Account, NewAccount, RecoveryKey};
+use crate::utils::base64::{Base64Bytes, Base64String, Base64Vec};
+use crate::utils::config::Config;
 use crate::{controllers::errors::ServiceError, http::opaque::CS};
 use axum::{
     extract::State,
-    routing::{get, post},
-    Json, Router,
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
 };
-use generic_array::GenericArray;
+use generic_array::{typenum::U32, GenericArray};
 use opaque_ke::{
     CredentialFinalizationLen, CredentialRequestLen, RegistrationRequestLen, RegistrationUploadLen,
 };
-use serde::Deserialize;
-
-pub fn router(state: AppState) -> Router<AppState> {
-    Router::new()
-        .route("/register/init", post(register_init))
-        .route("/register/finish", post(register_finish))
-        .route("/login/init", post(login_init))
-        .route("/login/finish", post(login_finish))
-        .route("/session", get(session))
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::types::chrono::Utc;
+use std::time::Duration;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+use zeroize::Zeroizing;
+
+/// Room for the master key, an ephemeral public key, a nonce and a tag.
+const MAX_WRAPPED_MASTER_KEY_BYTES: usize = 1024;
+
+pub fn router(state: AppState) -> OpenApiRouter<AppState> {
+    let authenticated = OpenApiRouter::new()
+        .routes(routes!(session))
+        .routes(routes!(password_init))
+        .routes(routes!(password_finish))
+        .routes(routes!(recovery_key, remove_recovery_key))
+        .routes(routes!(recovery_init))
+        .routes(routes!(recovery_finish))
+        .route_layer(SealedChannelLayer::new(state.clone()))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            verify_request_mac,
+        ));
+    // Not sealed, as the response could not be sealed once the session is gone
+    let signed =
+        OpenApiRouter::new()
+            .routes(routes!(logout))
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                verify_request_mac,
+            ));
+
+    OpenApiRouter::new()
+        .routes(routes!(register_init))
+        .routes(routes!(register_finish))
+        .routes(routes!(login_init))
+        .routes(routes!(login_finish))
+        .routes(routes!(recovery_login_init))
+        .routes(routes!(recovery_login_finish))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            limit_by_client_address,
+        ))
+        .routes(routes!(parameters))
+        .merge(authenticated)
+        .merge(signed)
         .with_state(state)
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, ToSchema)]
+pub(super) struct KsfParameters {
+    /// Always `argon2id`
+    algorithm: &'static str,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl KsfParameters {
+    pub(super) fn from_config(config: &Config) -> Self {
+        Self {
+            algorithm: "argon2id",
+            memory_kib: config.argon2_memory_kib,
+            iterations: config.argon2_iterations,
+            parallelism: config.argon2_parallelism,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct ParametersResponse {
+    ksf: KsfParameters,
+}
+
+/// The key stretching parameters clients must use when registering and
+/// logging in.
+#[utoipa::path(
+    get,
+    path = "/parameters",
+    tag = "auth",
+    responses((status = 200, body = ParametersResponse))
+)]
+async fn parameters(State(state): State<AppState>) -> ApiBody<ParametersResponse> {
+    ApiBody(ParametersResponse {
+        ksf: KsfParameters::from_config(&state.config),
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
 struct RegisterInitRequest {
     username: String,
-    registration_request: Base64String,
+    /// The OPAQUE `RegistrationRequest`
+    #[schema(value_type = Base64String)]
+    registration_request: Base64Bytes<RegistrationRequestLen<CS>>,
 }
 
+#[derive(Serialize, ToSchema)]
+struct RegistrationInitResponse {
+    /// The OPAQUE `RegistrationResponse`
+    registration_response: Base64String,
+    /// Single-use, must be sent back with the matching finish request
+    registration_ticket: Base64String,
+}
+
+impl RegistrationInitResponse {
+    fn new(registration_response: &[u8], registration_ticket: &[u8]) -> Self {
+        RegistrationInitResponse {
+            registration_response: Base64String::encode_bytes(registration_response),
+            registration_ticket: Base64String::encode_bytes(registration_ticket),
+        }
+    }
+}
+
+/// Control characters are refused, which also keeps usernames apart from
+/// the credential identifiers of recovery codes.
+fn validate_username(username: &str) -> ApiResult<()> {
+    if username.chars().any(char::is_control) {
+        return Err(ApiError::InvalidFields(vec![FieldError {
+            field: "username".to_string(),
+            code: ErrorCode::InvalidValue,
+            message: "Must not contain control characters".to_string(),
+        }]));
+    }
+    Ok(())
+}
+
+/// Starts registering a new account.
+#[utoipa::path(
+    post,
+    path = "/register/init",
+    tag = "auth",
+    request_body = RegisterInitRequest,
+    responses((status = 200, body = RegistrationInitResponse))
+)]
 async fn register_init(
     State(state): State<AppState>,
-    Json(body): Json<RegisterInitRequest>,
-) -> ApiResult<Base64String> {
-    let registration_request: GenericArray<u8, RegistrationRequestLen<CS>> =
-        body.registration_request.decode()?;
-
+    ApiBody(body): ApiBody<RegisterInitRequest>,
+) -> ApiResult<ApiBody<RegistrationInitResponse>> {
+    validate_username(&body.username)?;
     let mut opaque_controller = state
         .opaque_controller
-        .try_lock()
+        .lock()
         .map_err(|e| ServiceError::InternalError(e.to_string()))?;
 
-    let registration_response =
-        opaque_controller.register_init(body.username, registration_request)?;
+    let (registration_response, ticket) = opaque_controller.register_init(
+        body.username,
+        RegistrationPurpose::Register,
+        &body.registration_request,
+    )?;
 
-    let response = Base64String::encode(&registration_response);
-    Ok(response)
+    Ok(ApiBody(RegistrationInitResponse::new(
+        &registration_response,
+        &ticket,
+    )))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct RegisterFinishRequest {
     username: String,
-    registration_finish: Base64String,
+    #[schema(value_type = Base64String)]
+    registration_ticket: Base64Vec<REGISTRATION_TICKET_LEN>,
+    /// The OPAQUE `RegistrationRequest` the ticket was issued for
+    #[schema(value_type = Base64String)]
+    registration_request: Base64Bytes<RegistrationRequestLen<CS>>,
+    /// The OPAQUE `RegistrationUpload`
+    #[schema(value_type = Base64String)]
+    registration_finish: Base64Bytes<RegistrationUploadLen<CS>>,
+    /// The vault master key wrapped by the client to the password's export
+    /// key
+    #[serde(default)]
+    #[schema(value_type = Option<Base64String>)]
+    password_wrapped_master_key: Option<Base64Vec<MAX_WRAPPED_MASTER_KEY_BYTES>>,
 }
+
+/// Stores the registration record and creates the account.
+#[utoipa::path(
+    post,
+    path = "/register/finish",
+    tag = "auth",
+    request_body = RegisterFinishRequest,
+    responses((status = 200, description = "The account was created"))
+)]
 async fn register_finish(
     State(state): State<AppState>,
-    Json(body): Json<RegisterFinishRequest>,
+    ApiBody(body): ApiBody<RegisterFinishRequest>,
 ) -> ApiResult<()> {
-    let registration_finish: GenericArray<u8, RegistrationUploadLen<CS>> =
-        body.registration_finish.decode()?;
-
-    let mut opaque_controller = state
+    let (registration_record, opaque_key_id) = state
         .opaque_controller
-        .try_lock()
-        .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .register_finish(
+            body.username.clone(),
+            RegistrationPurpose::Register,
+            &body.registration_ticket,
+            &body.registration_request,
+            &body.registration_finish,
+        )?;
 
-    opaque_controller.register_finish(body.username, registration_finish)?;
+    let account = state
+        .storage
+        .create_account(NewAccount {
+            username: &body.username,
+            credential_id: body.username.as_bytes(),
+            client_identity: &[],
+            registration_record: &registration_record,
+            opaque_key_id,
+            kek_id: None,
+            wrapped_dek: None,
+            password_wrapped_master_key: body.password_wrapped_master_key.as_deref(),
+        })
+        .await
+        .map_err(ServiceError::from)?;
+    audit::record(
+        &state,
+        Some(account.id),
+        &account.username,
+        AuditAction::AccountRegistered,
+        json!({}),
+    )
+    .await?;
 
     Ok(())
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct LoginInitRequest {
     username: String,
-    credential_request: Base64String,
+    /// The OPAQUE `CredentialRequest`
+    #[schema(value_type = Base64String)]
+    credential_request: Base64Bytes<CredentialRequestLen<CS>>,
 }
+
+/// What a login proves knowledge of.
+#[derive(Clone, Copy)]
+enum Credential {
+    Password,
+    RecoveryCode,
+}
+
+impl Credential {
+    /// The OPAQUE credential identifier, which also keys the pending login.
+    fn identifier(self, username: &str) -> String {
+        match self {
+            Credential::Password => username.to_string(),
+            Credential::RecoveryCode => recovery_credential_id(username),
+        }
+    }
+
+    /// The stored OPAQUE record and its server setup generation.
+    fn record(self, account: &Account) -> Option<(&[u8], i32)> {
+        match self {
+            Credential::Password => Some((
+                account.registration_record.as_slice(),
+                account.opaque_key_id,
+            )),
+            Credential::RecoveryCode => Some((
+                account.recovery_record.as_deref()?,
+                account.recovery_opaque_key_id?,
+            )),
+        }
+    }
+
+    /// Login audit events name the credential unless it is the password.
+    fn audit_detail(self, mut detail: Value) -> Value {
+        if let (Credential::RecoveryCode, Some(detail)) = (self, detail.as_object_mut()) {
+            detail.insert("credential".to_string(), json!("recovery_code"));
+        }
+        detail
+    }
+}
+
+/// Starts a login. Unknown usernames get a response too, so they cannot be
+/// told apart from registered ones.
+#[utoipa::path(
+    post,
+    path = "/login/init",
+    tag = "auth",
+    request_body = LoginInitRequest,
+    responses((
+        status = 200,
+        description = "The OPAQUE `CredentialResponse`, as bare base64 text unless CBOR was \
+            requested",
+        content(
+            (Base64String = "text/plain"),
+            (Base64String = "application/cbor"),
+        )
+    ))
+)]
 async fn login_init(
     State(state): State<AppState>,
-    Json(body): Json<LoginInitRequest>,
-) -> ApiResult<Base64String> {
-    let credential_request: GenericArray<u8, CredentialRequestLen<CS>> =
-        body.credential_request.decode()?;
+    ApiBody(body): ApiBody<LoginInitRequest>,
+) -> ApiResult<Response> {
+    start_login(&state, Credential::Password, body).await
+}
 
-    let mut opaque_controller = state
-        .opaque_controller
-        .try_lock()
-        .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+async fn start_login(
+    state: &AppState,
+    credential: Credential,
+    body: LoginInitRequest,
+) -> ApiResult<Response> {
+    let account = state
+        .storage
+        .find_account_by_username(&body.username)
+        .await
+        .map_err(ServiceError::from)?;
 
-    let credential_response = opaque_controller.login_start(body.username, credential_request)?;
+    let credential_response = state
+        .opaque_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .login_start(
+            credential.identifier(&body.username),
+            account
+                .as_ref()
+                .and_then(|account| credential.record(account)),
+            &body.credential_request,
+        )?;
 
     let response = Base64String::encode(&credential_response);
-    Ok(response)
+    // JSON clients have always received the bare base64 text
+    Ok(match Format::current() {
+        Format::Json => response.into_response(),
+        Format::Cbor => ApiBody(response).into_response(),
+    })
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct LoginFinishRequest {
     username: String,
-    credential_finish: Base64String,
+    /// The OPAQUE `CredentialFinalization`
+    #[schema(value_type = Base64String)]
+    credential_finish: Base64Bytes<CredentialFinalizationLen<CS>>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct LoginFinishResponse {
+    /// Sent as `x-session-id` with every authenticated request
+    session_id: String,
+    /// The account was registered under a rotated OPAQUE server key. The
+    /// client should register its password again, which moves the account
+    /// to the current key.
+    reregistration_required: bool,
+    /// The vault master key as the client wrapped it to the password's
+    /// export key. Accounts registered before it existed have none.
+    #[schema(value_type = Option<Base64String>)]
+    password_wrapped_master_key: Option<Base64String>,
 }
+
+/// Finishes a login and creates a session keyed by the OPAQUE session key.
+#[utoipa::path(
+    post,
+    path = "/login/finish",
+    tag = "auth",
+    request_body = LoginFinishRequest,
+    responses((status = 200, body = LoginFinishResponse))
+)]
 async fn login_finish(
     State(state): State<AppState>,
-    Json(body): Json<LoginFinishRequest>,
-) -> ApiResult<()> {
-    let credential_finish: GenericArray<u8, CredentialFinalizationLen<CS>> =
-        body.credential_finish.decode()?;
+    ApiBody(body): ApiBody<LoginFinishRequest>,
+) -> ApiResult<ApiBody<LoginFinishResponse>> {
+    let (response, _) = finish_login(&state, Credential::Password, body).await?;
+    Ok(ApiBody(response))
+}
 
-    let mut opaque_controller = state
+/// Creates a session for a finished login and returns the account it
+/// belongs to.
+async fn finish_login(
+    state: &AppState,
+    credential: Credential,
+    body: LoginFinishRequest,
+) -> ApiResult<(LoginFinishResponse, Account)> {
+    let login = state
         .opaque_controller
-        .try_lock()
-        .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .login_finish(
+            credential.identifier(&body.username),
+            &body.credential_finish,
+        );
 
-    opaque_controller.login_finish(body.username, credential_finish)?;
+    // Only accounts with a stored record can finish a login, unless the
+    // account or its recovery code was deleted in the meantime
+    let account = state
+        .storage
+        .find_account_by_username(&body.username)
+        .await
+        .map_err(ServiceError::from)?;
 
-    Ok(())
+    let (login, account) = match (login, account) {
+        (Ok(login), Some(account))
+            if account.locked_at.is_none() && credential.record(&account).is_some() =>
+        {
+            (login, account)
+        }
+        (login, account) => {
+            // Only the credential holder learns that the account is locked
+            let err = match (login, &account) {
+                (Ok(_), Some(account)) if account.locked_at.is_some() => {
+                    ServiceError::AccountLocked
+                }
+                (Ok(_), _) => ServiceError::InvalidCredentials,
+                (Err(err), _) => err,
+            };
+            let detail = match err {
+                ServiceError::InvalidCredentials => Some(json!({})),
+                ServiceError::AccountLocked => Some(json!({ "reason": "account_locked" })),
+                _ => None,
+            };
+            // Failed guesses against unknown usernames are audited too
+            if let Some(detail) = detail {
+                let recorded = audit::record(
+                    state,
+                    account.map(|account| account.id),
+                    &body.username,
+                    AuditAction::LoginFailed,
+                    credential.audit_detail(detail),
+                )
+                .await;
+                if let Err(audit_err) = recorded {
+                    tracing::error!(error = %audit_err, "failed to audit a failed login");
+                }
+            }
+            return Err(err.into());
+        }
+    };
+
+    let session_id = state
+        .session_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .create(body.username, login.session_key.expose(), unix_timestamp());
+
+    let expires_at = Utc::now() + Duration::from_secs(state.config.session_ttl_secs);
+    state
+        .storage
+        .create_session(&Sha256::digest(&session_id), account.id, expires_at)
+        .await
+        .map_err(ServiceError::from)?;
+    audit::record(
+        state,
+        Some(account.id),
+        &account.username,
+        AuditAction::LoginSucceeded,
+        credential.audit_detail(json!({})),
+    )
+    .await?;
+
+    Ok((
+        LoginFinishResponse {
+            session_id,
+            reregistration_required: login.reregistration_required,
+            password_wrapped_master_key: account
+                .password_wrapped_master_key
+                .as_deref()
+                .map(Base64String::encode_bytes),
+        },
+        account,
+    ))
+}
+
+/// Starts a login with the recovery code instead of the password. Accounts
+/// without a recovery code get a response too.
+#[utoipa::path(
+    post,
+    path = "/recovery/login/init",
+    tag = "auth",
+    request_body = LoginInitRequest,
+    responses((
+        status = 200,
+        description = "The OPAQUE `CredentialResponse`, as bare base64 text unless CBOR was \
+            requested",
+        content(
+            (Base64String = "text/plain"),
+            (Base64String = "application/cbor"),
+        )
+    ))
+)]
+async fn recovery_login_init(
+    State(state): State<AppState>,
+    ApiBody(body): ApiBody<LoginInitRequest>,
+) -> ApiResult<Response> {
+    start_login(&state, Credential::RecoveryCode, body).await
 }
-async fn session() -> Result<Json<String>, ()> {
-    todo!()
+
+#[derive(Serialize, ToSchema)]
+struct RecoveryLoginFinishResponse {
+    #[serde(flatten)]
+    login: LoginFinishResponse,
+    /// The master key as the client wrapped it, which only the recovery
+    /// code's export key opens
+    wrapped_master_key: Base64String,
+}
+
+/// Finishes a login with the recovery code, which uses it up. The session may
+/// do everything a password session can, which is how the client sets a new
+/// password and a new recovery code.
+#[utoipa::path(
+    post,
+    path = "/recovery/login/finish",
+    tag = "auth",
+    request_body = LoginFinishRequest,
+    responses((status = 200, body = RecoveryLoginFinishResponse))
+)]
+async fn recovery_login_finish(
+    State(state): State<AppState>,
+    ApiBody(body): ApiBody<LoginFinishRequest>,
+) -> ApiResult<ApiBody<RecoveryLoginFinishResponse>> {
+    let (login, account) = finish_login(&state, Credential::RecoveryCode, body).await?;
+    let wrapped_master_key = account.wrapped_master_key.ok_or_else(|| {
+        ServiceError::InternalError("recovery key without a wrapped master key".to_string())
+    })?;
+    // Of two logins with the same code only the one that removes it succeeds
+    if !state
+        .storage
+        .remove_recovery_key(account.id)
+        .await
+        .map_err(ServiceError::from)?
+    {
+        return Err(ServiceError::InvalidCredentials.into());
+    }
+    audit::record(
+        &state,
+        Some(account.id),
+        &account.username,
+        AuditAction::RecoveryKeyRemoved,
+        json!({ "reason": "used" }),
+    )
+    .await?;
+    Ok(ApiBody(RecoveryLoginFinishResponse {
+        login,
+        wrapped_master_key: Base64String::encode_bytes(&wrapped_master_key),
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+struct SessionResponse {
+    username: String,
+}
+
+/// Who the session belongs to.
+#[utoipa::path(
+    get,
+    path = "/session",
+    tag = "auth",
+    security(("request_mac" = [])),
+    responses((status = 200, body = SessionResponse))
+)]
+async fn session(session: AuthenticatedSession) -> ApiResult<ApiBody<SessionResponse>> {
+    Ok(ApiBody(SessionResponse {
+        username: session.username,
+    }))
+}
+
+/// Ends the session making the request.
+#[utoipa::path(
+    post,
+    path = "/logout",
+    tag = "auth",
+    security(("request_mac" = [])),
+    responses((status = 204, description = "The session has ended"))
+)]
+async fn logout(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+) -> ApiResult<StatusCode> {
+    state
+        .session_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .revoke(&session.session_id);
+    state
+        .storage
+        .revoke_session(&Sha256::digest(&session.session_id))
+        .await
+        .map_err(ServiceError::from)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PasswordInitRequest {
+    /// The OPAQUE `RegistrationRequest`
+    #[schema(value_type = Base64String)]
+    registration_request: Base64Bytes<RegistrationRequestLen<CS>>,
+}
+
+/// Starts registering a new password for the authenticated user.
+#[utoipa::path(
+    post,
+    path = "/password/init",
+    tag = "auth",
+    security(("request_mac" = [])),
+    request_body = PasswordInitRequest,
+    responses((status = 200, body = RegistrationInitResponse))
+)]
+async fn password_init(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+    ApiBody(body): ApiBody<PasswordInitRequest>,
+) -> ApiResult<ApiBody<RegistrationInitResponse>> {
+    let (registration_response, ticket) = state
+        .opaque_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .register_init(
+            session.username,
+            RegistrationPurpose::PasswordChange,
+            &body.registration_request,
+        )?;
+
+    Ok(ApiBody(RegistrationInitResponse::new(
+        &registration_response,
+        &ticket,
+    )))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PasswordFinishRequest {
+    #[schema(value_type = Base64String)]
+    registration_ticket: Base64Vec<REGISTRATION_TICKET_LEN>,
+    /// The OPAQUE `RegistrationRequest` the ticket was issued for
+    #[schema(value_type = Base64String)]
+    registration_request: Base64Bytes<RegistrationRequestLen<CS>>,
+    /// The OPAQUE `RegistrationUpload`
+    #[schema(value_type = Base64String)]
+    registration_finish: Base64Bytes<RegistrationUploadLen<CS>>,
+    /// The vault master key wrapped to the new password's export key
+    #[serde(default)]
+    #[schema(value_type = Option<Base64String>)]
+    password_wrapped_master_key: Option<Base64Vec<MAX_WRAPPED_MASTER_KEY_BYTES>>,
+}
+
+/// Replaces the authenticated user's registration record and wrapped master
+/// key together and revokes their other sessions.
+#[utoipa::path(
+    post,
+    path = "/password/finish",
+    tag = "auth",
+    security(("request_mac" = [])),
+    request_body = PasswordFinishRequest,
+    responses((status = 204, description = "The password was changed"))
+)]
+async fn password_finish(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+    ApiBody(body): ApiBody<PasswordFinishRequest>,
+) -> ApiResult<StatusCode> {
+    let (registration_record, opaque_key_id) = state
+        .opaque_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .register_finish(
+            session.username.clone(),
+            RegistrationPurpose::PasswordChange,
+            &body.registration_ticket,
+            &body.registration_request,
+            &body.registration_finish,
+        )?;
+
+    let account = state
+        .storage
+        .find_account_by_username(&session.username)
+        .await
+        .map_err(ServiceError::from)?
+        .ok_or(ServiceError::SessionMissingOrExpired)?;
+    state
+        .storage
+        .change_password(
+            account.id,
+            &registration_record,
+            opaque_key_id,
+            body.password_wrapped_master_key.as_deref(),
+            &Sha256::digest(&session.session_id),
+        )
+        .await
+        .map_err(ServiceError::from)?;
+    state
+        .session_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .revoke_other_sessions(&session.username, &session.session_id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Starts registering a recovery code for the authenticated user.
+#[utoipa::path(
+    post,
+    path = "/recovery/init",
+    tag = "auth",
+    security(("request_mac" = [])),
+    request_body = PasswordInitRequest,
+    responses((status = 200, body = RegistrationInitResponse))
+)]
+async fn recovery_init(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+    ApiBody(body): ApiBody<PasswordInitRequest>,
+) -> ApiResult<ApiBody<RegistrationInitResponse>> {
+    let (registration_response, ticket) = state
+        .opaque_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .register_init(
+            recovery_credential_id(&session.username),
+            RegistrationPurpose::RecoveryCode,
+            &body.registration_request,
+        )?;
+
+    Ok(ApiBody(RegistrationInitResponse::new(
+        &registration_response,
+        &ticket,
+    )))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct RecoveryFinishRequest {
+    #[schema(value_type = Base64String)]
+    registration_ticket: Base64Vec<REGISTRATION_TICKET_LEN>,
+    /// The OPAQUE `RegistrationRequest` the ticket was issued for
+    #[schema(value_type = Base64String)]
+    registration_request: Base64Bytes<RegistrationRequestLen<CS>>,
+    /// The OPAQUE `RegistrationUpload` of the recovery code
+    #[schema(value_type = Base64String)]
+    registration_finish: Base64Bytes<RegistrationUploadLen<CS>>,
+    /// The X25519 key the master key is wrapped to, derived from the
+    /// recovery code's export key
+    #[schema(value_type = Base64String)]
+    public_key: Base64Bytes<U32>,
+    #[schema(value_type = Base64String)]
+    wrapped_master_key: Base64Vec<MAX_WRAPPED_MASTER_KEY_BYTES>,
+}
+
+/// Sets the recovery code of the authenticated user, replacing any earlier
+/// one.
+#[utoipa::path(
+    post,
+    path = "/recovery/finish",
+    tag = "auth",
+    security(("request_mac" = [])),
+    request_body = RecoveryFinishRequest,
+    responses((status = 204, description = "The recovery code was set"))
+)]
+async fn recovery_finish(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+    ApiBody(body): ApiBody<RecoveryFinishRequest>,
+) -> ApiResult<StatusCode> {
+    let (registration_record, opaque_key_id) = state
+        .opaque_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .register_finish(
+            recovery_credential_id(&session.username),
+            RegistrationPurpose::RecoveryCode,
+            &body.registration_ticket,
+            &body.registration_request,
+            &body.registration_finish,
+        )?;
+
+    let account = state
+        .storage
+        .find_account_by_username(&session.username)
+        .await
+        .map_err(ServiceError::from)?
+        .ok_or(ServiceError::SessionMissingOrExpired)?;
+    state
+        .storage
+        .set_recovery_key(
+            account.id,
+            RecoveryKey {
+                registration_record: &registration_record,
+                opaque_key_id,
+                public_key: &body.public_key,
+                wrapped_master_key: &body.wrapped_master_key,
+            },
+        )
+        .await
+        .map_err(ServiceError::from)?;
+    audit::record(
+        &state,
+        Some(account.id),
+        &account.username,
+        AuditAction::RecoveryKeySet,
+        json!({}),
+    )
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize, ToSchema)]
+struct RecoveryKeyResponse {
+    /// Wrap the master key to this key again after changing the password
+    public_key: Base64String,
+}
+
+/// The recovery key of the authenticated user, 404 if none is set.
+#[utoipa::path(
+    get,
+    path = "/recovery",
+    tag = "auth",
+    security(("request_mac" = [])),
+    responses((status = 200, body = RecoveryKeyResponse))
+)]
+async fn recovery_key(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+) -> ApiResult<ApiBody<RecoveryKeyResponse>> {
+    let public_key = state
+        .storage
+        .find_account_by_username(&session.username)
+        .await
+        .map_err(ServiceError::from)?
+        .and_then(|account| account.recovery_public_key)
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(ApiBody(RecoveryKeyResponse {
+        public_key: Base64String::encode_bytes(&public_key),
+    }))
+}
+
+/// Removes the recovery code of the authenticated user.
+#[utoipa::path(
+    delete,
+    path = "/recovery",
+    tag = "auth",
+    security(("request_mac" = [])),
+    responses((status = 204, description = "The recovery code was removed"))
+)]
+async fn remove_recovery_key(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+) -> ApiResult<StatusCode> {
+    let account = state
+        .storage
+        .find_account_by_username(&session.username)
+        .await
+        .map_err(ServiceError::from)?
+        .ok_or(ServiceError::SessionMissingOrExpired)?;
+    if !state
+        .storage
+        .remove_recovery_key(account.id)
+        .await
+        .map_err(ServiceError::from)?
+    {
+        return Err(ApiError::NotFound);
+    }
+    audit::record(
+        &state,
+        Some(account.id),
+        &account.username,
+        AuditAction::RecoveryKeyRemoved,
+        json!({}),
+    )
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
 }
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use axum::http::{
+    header::{CONTENT_TYPE, LINK, RETRY_AFTER},
+    HeaderName, HeaderValue, Method,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use super::{
+    session::{NONCE_HEADER, SESSION_ID_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER},
+    versioning::{API_VERSION_HEADER, CLIENT_VERSION_HEADER, DEPRECATION, SUNSET},
+};
+use crate::utils::config::Config;
+
+/// Returns `None` when no origins are configured, which leaves cross-origin
+/// requests to the browser's same-origin policy.
+pub fn layer(config: &Config) -> Option<CorsLayer> {
+    if config.cors_allowed_origins.is_empty() {
+        return None;
+    }
+
+    let allow_origin = if config.cors_allowed_origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        // The origins were validated when the config was loaded
+        AllowOrigin::list(
+            config
+                .cors_allowed_origins
+                .iter()
+                .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+        )
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers([
+                CONTENT_TYPE,
+                HeaderName::from_static(SESSION_ID_HEADER),
+                HeaderName::from_static(TIMESTAMP_HEADER),
+                HeaderName::from_static(NONCE_HEADER),
+                HeaderName::from_static(SIGNATURE_HEADER),
+                HeaderName::from_static(API_VERSION_HEADER),
+                HeaderName::from_static(CLIENT_VERSION_HEADER),
+            ])
+            .expose_headers([
+                HeaderName::from_static("x-request-id"),
+                RETRY_AFTER,
+                HeaderName::from_static(API_VERSION_HEADER),
+                DEPRECATION,
+                SUNSET,
+                LINK,
+            ])
+            .max_age(Duration::from_secs(600)),
+    )
+}
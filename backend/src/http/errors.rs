@@ -1,50 +1,284 @@
 use axum::{
-    http::StatusCode,
+    body::Body,
+    extract::{rejection::QueryRejection, Request},
+    http::{header::CONTENT_TYPE, HeaderValue, StatusCode},
+    middleware::Next,
     response::{IntoResponse, Response},
 };
+use serde::Serialize;
+use tower_http::request_id::RequestId;
+use utoipa::ToSchema;
 
+use super::format::Format;
 use crate::controllers::errors::ServiceError;
 use crate::utils::base64::DecodeError;
 
+/// Stable machine-readable error codes. Clients match on these, so existing
+/// codes must never be renamed.
+#[derive(Serialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidJson,
+    InvalidCbor,
+    UnsupportedMediaType,
+    InvalidBody,
+    InvalidHeader,
+    InvalidFields,
+    DecodeInvalidBase64,
+    DecodeLengthMismatch,
+    InvalidCredentials,
+    LoginSessionExpired,
+    RegistrationTicketExpired,
+    Unauthenticated,
+    SessionExpired,
+    InvalidRequestSignature,
+    RequestTimestampOutOfWindow,
+    ReplayedNonce,
+    InvalidSealedMessage,
+    SealedSequenceMismatch,
+    SealedChannelRequired,
+    UsernameTaken,
+    Forbidden,
+    NotFound,
+    RateLimited,
+    InvalidQuery,
+    InvalidValue,
+    InvalidTreeSize,
+    AuditChainBroken,
+    AccountLocked,
+    OpaqueKeyInUse,
+    UnsupportedApiVersion,
+    ClientVersionUnsupported,
+    InternalError,
+}
+
+/// Why one field of the request is invalid.
+#[derive(Serialize, ToSchema, Clone, Debug)]
+pub struct FieldError {
+    /// Path of the field, such as `keys[0].key`
+    pub field: String,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
 pub enum ApiError {
-    BadRequest(String),
-    Unauthorized(String),
+    BadRequest(ErrorCode, String),
+    InvalidFields(Vec<FieldError>),
+    UnsupportedMediaType(String),
+    Unauthorized(ErrorCode, String),
     Forbidden,
     NotFound,
-    InternalServerError,
+    Conflict(ErrorCode, String),
+    TooManyRequests,
+    /// The client is older than the oldest release still served
+    UpgradeRequired(String),
+    /// The message is logged with the request id but never sent to the client
+    InternalServerError(String),
 }
 
 pub type ApiResult<T> = Result<T, ApiError>;
 
-impl IntoResponse for ApiError {
-    fn into_response(self) -> Response {
+#[derive(Serialize, ToSchema, Clone, Debug)]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    pub message: String,
+    /// Also sent as the `x-request-id` header
+    pub request_id: Option<String>,
+    /// Only with `invalid_fields`, one entry per invalid field
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<FieldError>,
+}
+
+/// The body of every error response.
+#[derive(Serialize, ToSchema)]
+pub(super) struct ErrorEnvelope<'a> {
+    error: &'a ErrorBody,
+}
+
+/// Response extension carrying the detail of an internal error to
+/// [`attach_request_id`], which logs it.
+#[derive(Clone)]
+struct InternalErrorDetail(String);
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
         match self {
-            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message).into_response(),
-            ApiError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message).into_response(),
-            ApiError::Forbidden => (StatusCode::FORBIDDEN, "Forbidden").into_response(),
-            ApiError::NotFound => (StatusCode::NOT_FOUND, "Not found").into_response(),
-            ApiError::InternalServerError => {
-                (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+            ApiError::BadRequest(..) | ApiError::InvalidFields(_) => StatusCode::BAD_REQUEST,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Unauthorized(..) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden => StatusCode::FORBIDDEN,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::Conflict(..) => StatusCode::CONFLICT,
+            ApiError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::UpgradeRequired(_) => StatusCode::UPGRADE_REQUIRED,
+            ApiError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn body(self) -> ErrorBody {
+        let (code, message, details) = match self {
+            ApiError::BadRequest(code, message) => (code, message, Vec::new()),
+            ApiError::InvalidFields(details) => (
+                ErrorCode::InvalidFields,
+                "One or more fields are invalid".to_string(),
+                details,
+            ),
+            ApiError::UnsupportedMediaType(message) => {
+                (ErrorCode::UnsupportedMediaType, message, Vec::new())
             }
+            ApiError::Unauthorized(code, message) => (code, message, Vec::new()),
+            ApiError::Forbidden => (ErrorCode::Forbidden, "Forbidden".to_string(), Vec::new()),
+            ApiError::NotFound => (ErrorCode::NotFound, "Not found".to_string(), Vec::new()),
+            ApiError::Conflict(code, message) => (code, message, Vec::new()),
+            ApiError::TooManyRequests => (
+                ErrorCode::RateLimited,
+                "Too many requests".to_string(),
+                Vec::new(),
+            ),
+            ApiError::UpgradeRequired(message) => {
+                (ErrorCode::ClientVersionUnsupported, message, Vec::new())
+            }
+            ApiError::InternalServerError(_) => (
+                ErrorCode::InternalError,
+                "Internal server error".to_string(),
+                Vec::new(),
+            ),
+        };
+
+        ErrorBody {
+            code,
+            message,
+            request_id: None,
+            details,
         }
     }
 }
 
+impl ErrorBody {
+    fn to_response_body(&self, format: Format) -> Body {
+        match format.serialize(&ErrorEnvelope { error: self }) {
+            Ok(body) => Body::from(body),
+            Err(_) => Body::from(self.message.clone()),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let internal_detail = match &self {
+            ApiError::InternalServerError(detail) => Some(InternalErrorDetail(detail.clone())),
+            _ => None,
+        };
+        let body = self.body();
+        let format = Format::current();
+
+        let mut response = (status, body.to_response_body(format)).into_response();
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(format.content_type()),
+        );
+        response.extensions_mut().insert(body);
+        if let Some(detail) = internal_detail {
+            response.extensions_mut().insert(detail);
+        }
+        response
+    }
+}
+
+/// Fills the request id into error envelopes and logs internal errors. Must
+/// run inside the layer that assigns the [`RequestId`].
+pub async fn attach_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .map(str::to_string);
+
+    let mut response = next.run(request).await;
+
+    if let Some(InternalErrorDetail(detail)) = response.extensions_mut().remove() {
+        tracing::error!(request_id = request_id.as_deref(), error = %detail, "internal server error");
+    }
+
+    let Some(mut body) = response.extensions_mut().remove::<ErrorBody>() else {
+        return response;
+    };
+    body.request_id = request_id;
+
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(axum::http::header::CONTENT_LENGTH);
+    // Keep the format the envelope was first written in
+    let format = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(Format::from_content_type)
+        .unwrap_or_default();
+    Response::from_parts(parts, body.to_response_body(format))
+}
+
+pub async fn not_found() -> ApiError {
+    ApiError::NotFound
+}
+
+fn decode_error_code(err: &DecodeError) -> ErrorCode {
+    match err {
+        DecodeError::Base64Error(_) => ErrorCode::DecodeInvalidBase64,
+        DecodeError::LengthMismatch { .. } => ErrorCode::DecodeLengthMismatch,
+        DecodeError::TooLong { .. } => ErrorCode::InvalidValue,
+    }
+}
+
 impl From<DecodeError> for ApiError {
     fn from(err: DecodeError) -> Self {
-        ApiError::BadRequest(err.to_string())
+        ApiError::BadRequest(decode_error_code(&err), err.to_string())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::BadRequest(ErrorCode::InvalidQuery, rejection.body_text())
     }
 }
 
 impl From<ServiceError> for ApiError {
     fn from(err: ServiceError) -> Self {
+        let message = err.to_string();
         match err {
-            ServiceError::InternalError(err) => {
-                println!("{:}", err);
-                Self::InternalServerError
+            ServiceError::InternalError(detail) => Self::InternalServerError(detail),
+            ServiceError::InvalidCredentials => {
+                Self::Unauthorized(ErrorCode::InvalidCredentials, message)
+            }
+            ServiceError::LoginSessionMissingOrExpired => {
+                Self::Unauthorized(ErrorCode::LoginSessionExpired, message)
+            }
+            ServiceError::RegistrationTicketMissingOrExpired => {
+                Self::Unauthorized(ErrorCode::RegistrationTicketExpired, message)
+            }
+            ServiceError::SessionMissingOrExpired => {
+                Self::Unauthorized(ErrorCode::SessionExpired, message)
+            }
+            ServiceError::InvalidRequestSignature => {
+                Self::Unauthorized(ErrorCode::InvalidRequestSignature, message)
+            }
+            ServiceError::RequestTimestampOutOfWindow => {
+                Self::Unauthorized(ErrorCode::RequestTimestampOutOfWindow, message)
+            }
+            ServiceError::ReplayedNonce => Self::Unauthorized(ErrorCode::ReplayedNonce, message),
+            ServiceError::TooManyNonces | ServiceError::TooManyPendingRegistrations => {
+                Self::TooManyRequests
+            }
+            ServiceError::InvalidSealedMessage => {
+                Self::BadRequest(ErrorCode::InvalidSealedMessage, message)
+            }
+            ServiceError::SealedSequenceMismatch { .. } => {
+                Self::Unauthorized(ErrorCode::SealedSequenceMismatch, message)
+            }
+            ServiceError::SealedChannelRequired => {
+                Self::BadRequest(ErrorCode::SealedChannelRequired, message)
             }
-            ServiceError::InvalidCredentials => Self::Unauthorized(err.to_string()),
-            ServiceError::LoginSessionMissingOrExpired => Self::Unauthorized(err.to_string()),
+            ServiceError::UsernameTaken => Self::Conflict(ErrorCode::UsernameTaken, message),
+            ServiceError::AccountLocked => Self::Unauthorized(ErrorCode::AccountLocked, message),
         }
     }
 }
@@ -0,0 +1,388 @@
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap, HeaderValue,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::errors::{ApiError, ErrorCode, FieldError};
+use super::value::ValueDeserializer;
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+pub const CBOR_CONTENT_TYPE: &str = "application/cbor";
+
+/// Wire formats the API speaks. JSON carries binary fields as URL-safe
+/// base64 strings, CBOR as raw byte strings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Json,
+    Cbor,
+}
+
+tokio::task_local! {
+    static RESPONSE_FORMAT: Format;
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => JSON_CONTENT_TYPE,
+            Format::Cbor => CBOR_CONTENT_TYPE,
+        }
+    }
+
+    /// The format named by a `Content-Type` header, ignoring parameters.
+    /// `+json` types such as `application/problem+json` count as JSON.
+    pub fn from_content_type(value: &HeaderValue) -> Option<Self> {
+        let essence = value.to_str().ok()?.split(';').next()?.trim();
+        if essence.eq_ignore_ascii_case(CBOR_CONTENT_TYPE) {
+            Some(Format::Cbor)
+        } else if essence.eq_ignore_ascii_case(JSON_CONTENT_TYPE)
+            || essence.to_ascii_lowercase().ends_with("+json")
+        {
+            Some(Format::Json)
+        } else {
+            None
+        }
+    }
+
+    /// The format to respond with. The `Accept` header wins by quality, the
+    /// first listed breaking ties. Wildcards state no preference, so without
+    /// either type in `Accept` the response mirrors the request body's format.
+    pub fn negotiate(headers: &HeaderMap) -> Self {
+        let mut best: Option<(f32, Format)> = None;
+        for range in headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+        {
+            let mut params = range.split(';');
+            let media_type = params.next().unwrap_or_default().trim();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let format = if media_type.eq_ignore_ascii_case(CBOR_CONTENT_TYPE) {
+                Format::Cbor
+            } else if media_type.eq_ignore_ascii_case(JSON_CONTENT_TYPE) {
+                Format::Json
+            } else {
+                continue;
+            };
+            if quality > 0.0 && best.is_none_or(|(best_quality, _)| quality > best_quality) {
+                best = Some((quality, format));
+            }
+        }
+
+        best.map(|(_, format)| format).unwrap_or_else(|| {
+            headers
+                .get(CONTENT_TYPE)
+                .and_then(Format::from_content_type)
+                .unwrap_or_default()
+        })
+    }
+
+    /// The format negotiated for the request being handled, JSON outside of
+    /// [`negotiate_format`].
+    pub fn current() -> Self {
+        RESPONSE_FORMAT
+            .try_with(|format| *format)
+            .unwrap_or_default()
+    }
+
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Format::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes).map_err(|e| e.to_string())?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+/// Picks the response format from the request headers for everything that
+/// runs inside, see [`Format::negotiate`].
+pub async fn negotiate_format(request: Request, next: Next) -> Response {
+    let format = Format::negotiate(request.headers());
+    RESPONSE_FORMAT.scope(format, next.run(request)).await
+}
+
+/// Request body in the format named by `Content-Type`, or response body in
+/// the negotiated format. Rejections are reported in the error envelope.
+pub struct ApiBody<T>(pub T);
+
+impl<T, S> FromRequest<S> for ApiBody<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let format = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(Format::from_content_type)
+            .ok_or_else(|| {
+                ApiError::UnsupportedMediaType(format!(
+                    "Expected request with `Content-Type: {}` or `{}`",
+                    JSON_CONTENT_TYPE, CBOR_CONTENT_TYPE
+                ))
+            })?;
+        let bytes = Bytes::from_request(request, state)
+            .await
+            .map_err(|e| ApiError::BadRequest(ErrorCode::InvalidBody, e.body_text()))?;
+
+        let invalid_body = |message: String| {
+            let code = match format {
+                Format::Json => ErrorCode::InvalidJson,
+                Format::Cbor => ErrorCode::InvalidCbor,
+            };
+            ApiError::BadRequest(code, format!("Failed to deserialize the body: {}", message))
+        };
+
+        // Parsed into a value first so that fields are deserialized by one
+        // deserializer for both formats, which keeps the path of the field
+        // at fault
+        let value: ciborium::Value = match format {
+            Format::Json => serde_json::from_slice(&bytes).map_err(|e| e.to_string()),
+            Format::Cbor => ciborium::from_reader(bytes.as_ref()).map_err(|e| e.to_string()),
+        }
+        .map_err(invalid_body)?;
+        T::deserialize(ValueDeserializer::new(value, format == Format::Json))
+            .map(ApiBody)
+            .map_err(|err| match err.decode_error_code() {
+                Some(code) => ApiError::InvalidFields(vec![FieldError {
+                    field: err.path(),
+                    code,
+                    message: err.message().to_string(),
+                }]),
+                None => invalid_body(err.to_string()),
+            })
+    }
+}
+
+impl<T: Serialize> IntoResponse for ApiBody<T> {
+    fn into_response(self) -> Response {
+        let format = Format::current();
+        match format.serialize(&self.0) {
+            Ok(body) => (
+                [(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static(format.content_type()),
+                )],
+                body,
+            )
+                .into_response(),
+            Err(err) => ApiError::InternalServerError(err).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+
+    use axum::body::Body;
+    use generic_array::typenum::U4;
+    use serde::Deserialize;
+
+    use crate::utils::base64::{Base64Bytes, Base64String, Base64Vec};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Message {
+        username: String,
+        registration_request: Base64String,
+        keys: Vec<Base64String>,
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn json_and_cbor_round_trip_identically() {
+        let message = Message {
+            username: "alice".to_string(),
+            registration_request: Base64String::encode_bytes(&[0xfb; 32]),
+            keys: vec![Base64String::encode_bytes(b"key")],
+        };
+
+        let json = Format::Json.serialize(&message).unwrap();
+        let cbor = Format::Cbor.serialize(&message).unwrap();
+        let from_json: Message = serde_json::from_slice(&json).unwrap();
+        let from_cbor: Message = ciborium::from_reader(cbor.as_slice()).unwrap();
+        assert_eq!(from_json, message);
+        assert_eq!(from_cbor, message);
+        assert!(cbor.len() < json.len());
+
+        // Binary fields are byte strings, not base64 text
+        let value: ciborium::Value = ciborium::from_reader(cbor.as_slice()).unwrap();
+        let field = value
+            .as_map()
+            .unwrap()
+            .iter()
+            .find(|(key, _)| key.as_text() == Some("registration_request"))
+            .map(|(_, value)| value.clone())
+            .unwrap();
+        assert_eq!(field.as_bytes().unwrap(), &vec![0xfb; 32]);
+    }
+
+    #[test]
+    fn accept_picks_the_response_format() {
+        assert_eq!(Format::negotiate(&headers(&[])), Format::Json);
+        assert_eq!(
+            Format::negotiate(&headers(&[("accept", "application/cbor")])),
+            Format::Cbor
+        );
+        assert_eq!(
+            Format::negotiate(&headers(&[(
+                "accept",
+                "application/json;q=0.5, application/cbor"
+            )])),
+            Format::Cbor
+        );
+        assert_eq!(
+            Format::negotiate(&headers(&[("accept", "*/*, application/cbor")])),
+            Format::Cbor
+        );
+        assert_eq!(
+            Format::negotiate(&headers(&[(
+                "accept",
+                "application/json, application/cbor"
+            )])),
+            Format::Json
+        );
+        assert_eq!(
+            Format::negotiate(&headers(&[("accept", "application/cbor;q=0")])),
+            Format::Json
+        );
+        assert_eq!(
+            Format::negotiate(&headers(&[("content-type", "application/cbor")])),
+            Format::Cbor
+        );
+        assert_eq!(
+            Format::negotiate(&headers(&[
+                ("content-type", "application/cbor"),
+                ("accept", "*/*"),
+            ])),
+            Format::Cbor
+        );
+        assert_eq!(
+            Format::negotiate(&headers(&[
+                ("content-type", "application/cbor"),
+                ("accept", "application/json"),
+            ])),
+            Format::Json
+        );
+    }
+
+    #[derive(Deserialize)]
+    struct Keys {
+        nonce: Base64Bytes<U4>,
+        keys: Vec<Base64Vec<4>>,
+    }
+
+    async fn extract(content_type: &'static str, body: Vec<u8>) -> Result<Keys, ApiError> {
+        let request = Request::builder()
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap();
+        ApiBody::<Keys>::from_request(request, &())
+            .await
+            .map(|ApiBody(keys)| keys)
+    }
+
+    fn field_errors(result: Result<Keys, ApiError>) -> Vec<(String, ErrorCode)> {
+        match result {
+            Err(ApiError::InvalidFields(errors)) => errors
+                .into_iter()
+                .map(|error| (error.field, error.code))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn base64_errors_are_reported_per_field() {
+        let keys = extract(
+            JSON_CONTENT_TYPE,
+            br#"{"nonce": "AAAAAA", "keys": ["+/8", "AAAA"]}"#.to_vec(),
+        )
+        .await
+        .ok()
+        .expect("valid body was rejected");
+        assert_eq!(&keys.nonce[..], [0; 4]);
+        assert_eq!(&keys.keys[0][..], [0xfb, 0xff]);
+
+        assert_eq!(
+            field_errors(
+                extract(
+                    JSON_CONTENT_TYPE,
+                    br#"{"nonce": "AAAAAA", "keys": ["AAAA", "AAAAAAAA"]}"#.to_vec()
+                )
+                .await
+            ),
+            [("keys[1]".to_string(), ErrorCode::InvalidValue)]
+        );
+        assert_eq!(
+            field_errors(
+                extract(
+                    JSON_CONTENT_TYPE,
+                    br#"{"nonce": "AA", "keys": []}"#.to_vec()
+                )
+                .await
+            ),
+            [("nonce".to_string(), ErrorCode::DecodeLengthMismatch)]
+        );
+        assert_eq!(
+            field_errors(
+                extract(
+                    CBOR_CONTENT_TYPE,
+                    Format::Cbor
+                        .serialize(&serde_json::json!({ "nonce": "!!!!", "keys": [] }))
+                        .unwrap()
+                )
+                .await
+            ),
+            [("nonce".to_string(), ErrorCode::DecodeInvalidBase64)]
+        );
+
+        // Other malformed bodies keep their format's error code, naming the
+        // field at fault
+        assert!(matches!(
+            extract(JSON_CONTENT_TYPE, br#"{"keys": []}"#.to_vec()).await,
+            Err(ApiError::BadRequest(ErrorCode::InvalidJson, _))
+        ));
+        assert!(matches!(
+            extract(
+                CBOR_CONTENT_TYPE,
+                Format::Cbor
+                    .serialize(&serde_json::json!({ "nonce": "AAAAAA", "keys": "AAAA" }))
+                    .unwrap()
+            )
+            .await,
+            Err(ApiError::BadRequest(ErrorCode::InvalidCbor, message))
+                if message.contains("keys: invalid type")
+        ));
+        assert!(matches!(
+            extract("text/plain", Vec::new()).await,
+            Err(ApiError::UnsupportedMediaType(_))
+        ));
+    }
+}
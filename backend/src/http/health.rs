@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode};
+use serde::Serialize;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use super::{format::ApiBody, AppState};
+
+const DATABASE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(healthz))
+        .routes(routes!(readyz))
+}
+
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum CheckStatus {
+    Ok,
+    Unavailable,
+}
+
+#[derive(Serialize, ToSchema)]
+struct HealthResponse {
+    status: CheckStatus,
+}
+
+/// Liveness: the process is up and serving requests.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, body = HealthResponse))
+)]
+async fn healthz() -> ApiBody<HealthResponse> {
+    ApiBody(HealthResponse {
+        status: CheckStatus::Ok,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReadinessChecks {
+    database: CheckStatus,
+    server_setup: CheckStatus,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReadinessResponse {
+    status: CheckStatus,
+    checks: ReadinessChecks,
+}
+
+/// Readiness: the dependencies needed to serve logins are available.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, body = ReadinessResponse),
+        (status = 503, description = "A check failed", body = ReadinessResponse),
+    )
+)]
+async fn readyz(State(state): State<AppState>) -> (StatusCode, ApiBody<ReadinessResponse>) {
+    let database = match tokio::time::timeout(DATABASE_CHECK_TIMEOUT, state.storage.ping()).await {
+        Ok(Ok(_)) => CheckStatus::Ok,
+        Ok(Err(err)) => {
+            tracing::warn!(error = %err, "readiness database check failed");
+            CheckStatus::Unavailable
+        }
+        Err(_) => {
+            tracing::warn!("readiness database check timed out");
+            CheckStatus::Unavailable
+        }
+    };
+
+    // A poisoned lock means a request panicked while holding the controller
+    // and the server setup can no longer be trusted
+    let server_setup = match state.opaque_controller.lock() {
+        Ok(opaque_controller) if opaque_controller.keyring().current_is_usable() => CheckStatus::Ok,
+        Ok(_) => {
+            tracing::warn!("readiness server setup check failed");
+            CheckStatus::Unavailable
+        }
+        Err(_) => CheckStatus::Unavailable,
+    };
+
+    let checks = ReadinessChecks {
+        database,
+        server_setup,
+    };
+    if checks.database == CheckStatus::Ok && checks.server_setup == CheckStatus::Ok {
+        (
+            StatusCode::OK,
+            ApiBody(ReadinessResponse {
+                status: CheckStatus::Ok,
+                checks,
+            }),
+        )
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            ApiBody(ReadinessResponse {
+                status: CheckStatus::Unavailable,
+                checks,
+            }),
+        )
+    }
+}
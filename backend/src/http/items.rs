@@ -0,0 +1,235 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    middleware,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::types::Uuid;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use super::{
+    audit,
+    errors::{ApiError, ApiResult, ErrorCode, FieldError},
+    format::ApiBody,
+    sealed::SealedChannelLayer,
+    session::{verify_request_mac, AuthenticatedSession},
+    AppState,
+};
+use crate::controllers::{audit::AuditAction, errors::ServiceError};
+use crate::storage::{Account, Item};
+use crate::utils::base64::{Base64String, Base64Vec};
+
+/// Leaves room for the base64 and sealing overhead within the request limit.
+const MAX_CIPHERTEXT_BYTES: usize = 1024 * 1024;
+
+/// Items are opaque ciphertexts. The server never sees the keys, so it can
+/// only store, list and delete them for the account that owns them.
+pub fn router(state: AppState) -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(list_items, create_item))
+        .routes(routes!(get_item, update_item, delete_item))
+        .route_layer(SealedChannelLayer::new(state.clone()))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            verify_request_mac,
+        ))
+        .with_state(state)
+}
+
+#[derive(Deserialize, ToSchema)]
+struct ItemRequest {
+    /// At most 1 MiB
+    #[schema(value_type = Base64String)]
+    ciphertext: Base64Vec<MAX_CIPHERTEXT_BYTES>,
+}
+
+impl ItemRequest {
+    fn ciphertext(self) -> ApiResult<Vec<u8>> {
+        if self.ciphertext.is_empty() {
+            return Err(ApiError::InvalidFields(vec![FieldError {
+                field: "ciphertext".to_string(),
+                code: ErrorCode::InvalidValue,
+                message: "Must not be empty".to_string(),
+            }]));
+        }
+        Ok(self.ciphertext.into_vec())
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct ItemResponse {
+    id: String,
+    ciphertext: Base64String,
+    /// Unix seconds
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl From<Item> for ItemResponse {
+    fn from(item: Item) -> Self {
+        Self {
+            id: item.id.to_string(),
+            ciphertext: Base64String::encode_bytes(&item.ciphertext),
+            created_at: item.created_at.timestamp(),
+            updated_at: item.updated_at.timestamp(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct ItemList {
+    items: Vec<ItemResponse>,
+}
+
+async fn session_account(state: &AppState, session: &AuthenticatedSession) -> ApiResult<Account> {
+    Ok(state
+        .storage
+        .find_account_by_username(&session.username)
+        .await
+        .map_err(ServiceError::from)?
+        .ok_or(ServiceError::SessionMissingOrExpired)?)
+}
+
+/// Ids that do not parse cannot name an item either.
+fn parse_id(id: &str) -> ApiResult<Uuid> {
+    Uuid::parse_str(id).map_err(|_| ApiError::NotFound)
+}
+
+/// Oldest first.
+#[utoipa::path(
+    get,
+    path = "",
+    tag = "items",
+    security(("request_mac" = [])),
+    responses((status = 200, body = ItemList))
+)]
+async fn list_items(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+) -> ApiResult<ApiBody<ItemList>> {
+    let account = session_account(&state, &session).await?;
+    let items = state
+        .storage
+        .list_items(account.id)
+        .await
+        .map_err(ServiceError::from)?;
+
+    Ok(ApiBody(ItemList {
+        items: items.into_iter().map(ItemResponse::from).collect(),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "items",
+    security(("request_mac" = [])),
+    request_body = ItemRequest,
+    responses((status = 201, body = ItemResponse))
+)]
+async fn create_item(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+    ApiBody(body): ApiBody<ItemRequest>,
+) -> ApiResult<(StatusCode, ApiBody<ItemResponse>)> {
+    let ciphertext = body.ciphertext()?;
+    let account = session_account(&state, &session).await?;
+    let item = state
+        .storage
+        .create_item(account.id, &ciphertext)
+        .await
+        .map_err(ServiceError::from)?;
+
+    Ok((StatusCode::CREATED, ApiBody(item.into())))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tag = "items",
+    security(("request_mac" = [])),
+    params(("id" = String, Path)),
+    responses((status = 200, body = ItemResponse))
+)]
+async fn get_item(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+    Path(id): Path<String>,
+) -> ApiResult<ApiBody<ItemResponse>> {
+    let id = parse_id(&id)?;
+    let account = session_account(&state, &session).await?;
+    let item = state
+        .storage
+        .find_item(account.id, id)
+        .await
+        .map_err(ServiceError::from)?
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(ApiBody(item.into()))
+}
+
+#[utoipa::path(
+    put,
+    path = "/{id}",
+    tag = "items",
+    security(("request_mac" = [])),
+    params(("id" = String, Path)),
+    request_body = ItemRequest,
+    responses((status = 200, body = ItemResponse))
+)]
+async fn update_item(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+    Path(id): Path<String>,
+    ApiBody(body): ApiBody<ItemRequest>,
+) -> ApiResult<ApiBody<ItemResponse>> {
+    let id = parse_id(&id)?;
+    let ciphertext = body.ciphertext()?;
+    let account = session_account(&state, &session).await?;
+    let item = state
+        .storage
+        .update_item(account.id, id, &ciphertext)
+        .await
+        .map_err(ServiceError::from)?
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(ApiBody(item.into()))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/{id}",
+    tag = "items",
+    security(("request_mac" = [])),
+    params(("id" = String, Path)),
+    responses((status = 204, description = "The item was deleted"))
+)]
+async fn delete_item(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+    Path(id): Path<String>,
+) -> ApiResult<StatusCode> {
+    let id = parse_id(&id)?;
+    let account = session_account(&state, &session).await?;
+    let deleted = state
+        .storage
+        .delete_item(account.id, id)
+        .await
+        .map_err(ServiceError::from)?;
+
+    if !deleted {
+        return Err(ApiError::NotFound);
+    }
+    audit::record(
+        &state,
+        Some(account.id),
+        &account.username,
+        AuditAction::ItemDeleted,
+        json!({ "item_id": id.to_string() }),
+    )
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
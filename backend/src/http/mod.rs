@@ -1,43 +1,391 @@
 use std::{
+    future::{Future, IntoFuture},
     iter::Copied,
-    sync::{Arc, Mutex},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
 };
 
-use axum::Router;
+use axum::{
+    http::{header::STRICT_TRANSPORT_SECURITY, HeaderValue},
+    middleware,
+    routing::get,
+    Router,
+};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use metrics_exporter_prometheus::PrometheusHandle;
 use opaque_ke::rand::rngs::OsRng;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tower::util::option_layer;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    set_header::SetResponseHeaderLayer,
+    trace::TraceLayer,
+};
+use utoipa::OpenApi;
+use utoipa_axum::router::OpenApiRouter;
 
-use crate::{controllers::opaque, utils::config::Config};
+use crate::{
+    controllers::{
+        errors::ServiceError,
+        key_provider::{KeyProvider, SIGNING_KEY},
+        opaque,
+        rate_limit::RateLimiter,
+        server_setup::ServerSetupKeyring,
+        session::SessionController,
+        signing::SigningKey,
+        transparency::MerkleLog,
+    },
+    storage::Storage,
+    utils::{config::Config, telemetry},
+};
+mod admin;
+mod audit;
 mod auth;
+mod cors;
 mod errors;
+mod format;
+mod health;
 mod index;
+mod items;
+mod observability;
+mod openapi;
+mod query;
+mod rate_limit;
+pub mod sealed;
+pub mod session;
+mod sweeper;
+pub mod tls;
+mod transparency;
+mod users;
+mod value;
+mod versioning;
+mod well_known;
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: Arc<Config>,
+    pub storage: Arc<dyn Storage>,
+    /// Holds the signing key and OPAQUE keyring when configured, rotations
+    /// are stored there
+    pub key_provider: Option<Arc<dyn KeyProvider>>,
+    pub metrics: PrometheusHandle,
     pub opaque_controller: Arc<Mutex<opaque::OpaqueController<OsRng>>>,
+    pub session_controller: Arc<Mutex<SessionController<OsRng>>>,
+    pub rate_limiter: Arc<Mutex<RateLimiter<IpAddr>>>,
+    /// Replaced when an admin rotates the key, see [`Self::current_signing_key`]
+    pub signing_key: Arc<RwLock<Arc<SigningKey>>>,
+    /// Held across storage calls so that the tree and the stored leaves never
+    /// disagree
+    pub key_log: Arc<tokio::sync::Mutex<MerkleLog>>,
+}
+
+impl AppState {
+    /// The signing key in use right now. Holding on to it does not block a
+    /// rotation.
+    pub fn current_signing_key(&self) -> Result<Arc<SigningKey>, ServiceError> {
+        self.signing_key
+            .read()
+            .map(|key| Arc::clone(&key))
+            .map_err(|e| ServiceError::InternalError(e.to_string()))
+    }
+}
+
+/// Rebuilds the key transparency tree from the stored leaves.
+async fn load_key_log(
+    storage: &dyn Storage,
+) -> Result<MerkleLog, Box<dyn std::error::Error + Send + Sync>> {
+    let entries = storage.list_key_log_entries().await?;
+    for (position, entry) in entries.iter().enumerate() {
+        if entry.leaf_index != position as i64 {
+            return Err(format!(
+                "key log is missing leaf {}, found leaf {} in its place",
+                position, entry.leaf_index
+            )
+            .into());
+        }
+    }
+    Ok(MerkleLog::from_leaves(
+        entries.iter().map(|entry| entry.leaf.as_slice()),
+    ))
+}
+
+/// Loads the signing key held by `provider`. On first start the key at
+/// `import_path` is imported if it exists, otherwise a new one is generated.
+fn load_signing_key(
+    provider: &dyn KeyProvider,
+    import_path: Option<&str>,
+) -> Result<SigningKey, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(key) = provider.signing_key(SIGNING_KEY)? {
+        return Ok(key);
+    }
+    match import_path {
+        Some(path) if std::path::Path::new(path).exists() => {
+            let key = SigningKey::load(std::path::Path::new(path))?;
+            let pkcs8 = key.pkcs8().ok_or("signing key has no PKCS#8 encoding")?;
+            tracing::info!(path, "imported the signing key into the key provider");
+            Ok(provider.import_signing_key(SIGNING_KEY, pkcs8)?)
+        }
+        _ => {
+            tracing::info!("generated a new signing key");
+            Ok(provider.generate_signing_key(SIGNING_KEY)?)
+        }
+    }
 }
 
-pub fn initialize_app_state(config: Config) -> AppState {
-    let opaque_controller = opaque::OpaqueController::default();
+pub async fn initialize_app_state(
+    config: Config,
+    storage: Arc<dyn Storage>,
+    key_provider: Option<Arc<dyn KeyProvider>>,
+) -> Result<AppState, Box<dyn std::error::Error + Send + Sync>> {
+    let (signing_key, keyring) = match &key_provider {
+        Some(provider) => (
+            load_signing_key(provider.as_ref(), config.signing_key_path.as_deref())?,
+            ServerSetupKeyring::load_from(
+                provider.as_ref(),
+                config.opaque_keyring_path.as_deref(),
+                config.opaque_server_setup_path.as_deref(),
+                &mut OsRng,
+            )?,
+        ),
+        None => (
+            SigningKey::load_or_generate(config.signing_key_path.as_deref())?,
+            ServerSetupKeyring::load_or_generate(
+                config.opaque_keyring_path.as_deref(),
+                config.opaque_server_setup_path.as_deref(),
+                &mut OsRng,
+            )?,
+        ),
+    };
+    let key_log = load_key_log(storage.as_ref()).await?;
+    for (key_id, accounts) in storage.count_accounts_by_opaque_key_id().await? {
+        if keyring.get(key_id).is_none() {
+            tracing::warn!(
+                key_id,
+                accounts,
+                "accounts depend on an OPAQUE key id missing from the keyring and cannot log in"
+            );
+        }
+    }
+    let opaque_controller = opaque::OpaqueController::with_keyring(OsRng, keyring)
+        .with_registration_ticket_ttl(Duration::from_secs(config.registration_ticket_ttl_secs))
+        .with_login_session_ttl(Duration::from_secs(config.login_session_ttl_secs))
+        .with_server_identity(config.server_identity.clone());
+    let session_controller =
+        SessionController::with_os_rng(config.request_mac_window_secs, config.session_ttl_secs);
+    let rate_limiter = RateLimiter::new(config.rate_limit_per_minute, config.rate_limit_burst);
 
-    AppState {
+    Ok(AppState {
         config: Arc::new(config),
+        storage,
+        key_provider,
+        metrics: telemetry::metrics_handle(),
         opaque_controller: Arc::new(Mutex::new(opaque_controller)),
-    }
+        session_controller: Arc::new(Mutex::new(session_controller)),
+        rate_limiter: Arc::new(Mutex::new(rate_limiter)),
+        signing_key: Arc::new(RwLock::new(Arc::new(signing_key))),
+        key_log: Arc::new(tokio::sync::Mutex::new(key_log)),
+    })
+}
+
+pub async fn serve(
+    config: Config,
+    storage: Arc<dyn Storage>,
+    key_provider: Option<Arc<dyn KeyProvider>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let address = SocketAddr::new(config.bind_address, config.port);
+
+    let state = initialize_app_state(config, storage, key_provider).await?;
+
+    let listener = TcpListener::bind(address).await?;
+
+    serve_until(listener, state, shutdown_signal()).await
 }
 
-pub async fn serve(config: Config) -> Result<(), Box<dyn std::error::Error>> {
-    let port = config.port;
+/// Serves until `signal` resolves, then stops accepting connections and lets
+/// in-flight requests drain for at most the configured shutdown timeout.
+/// Background tasks are stopped before returning.
+pub async fn serve_until(
+    listener: TcpListener,
+    state: AppState,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Load the certificates up front so a bad TLS setup fails before any
+    // background task is started
+    let tls = match tls::TlsPaths::from_config(&state.config)? {
+        Some(paths) => {
+            let server_config = tls::load_server_config(&paths)?;
+            Some((paths, RustlsConfig::from_config(server_config)))
+        }
+        None => None,
+    };
 
-    let state = initialize_app_state(config);
+    let shutdown = CancellationToken::new();
+    let sweeper = sweeper::spawn(state.clone(), shutdown.clone());
 
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
-    let app = router(state.clone()).with_state(state);
+    let app = router(state.clone()).with_state(state.clone());
 
-    axum::serve(listener, app).await?;
+    let (mut server, tls_reloader) = match tls {
+        None => {
+            let server = tokio::spawn(
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+                .into_future(),
+            );
+            (server, None)
+        }
+        Some((paths, rustls_config)) => {
+            let reloader = tls::spawn_reloader(
+                paths,
+                rustls_config.clone(),
+                Duration::from_secs(state.config.tls_reload_interval_secs),
+                shutdown.clone(),
+            );
+
+            let app = match state.config.hsts_max_age_secs {
+                0 => app,
+                max_age => app.layer(SetResponseHeaderLayer::if_not_present(
+                    STRICT_TRANSPORT_SECURITY,
+                    hsts_header(max_age),
+                )),
+            };
+
+            let handle = Handle::new();
+            let drain_timeout = Duration::from_secs(state.config.shutdown_timeout_secs);
+            tokio::spawn({
+                let handle = handle.clone();
+                let shutdown = shutdown.clone();
+                async move {
+                    shutdown.cancelled().await;
+                    handle.graceful_shutdown(Some(drain_timeout));
+                }
+            });
+
+            let server = tokio::spawn(
+                axum_server::from_tcp(listener.into_std()?)
+                    .acceptor(tls::PeerCertificateAcceptor::new(rustls_config))
+                    .handle(handle)
+                    .serve(app.into_make_service_with_connect_info::<SocketAddr>()),
+            );
+            (server, Some(reloader))
+        }
+    };
+
+    let server_result = tokio::select! {
+        result = &mut server => Some(result),
+        _ = signal => None,
+    };
+    shutdown.cancel();
+
+    let server_result = match server_result {
+        Some(result) => result,
+        None => {
+            tracing::info!("shutdown signal received, draining in-flight requests");
+            let timeout = Duration::from_secs(state.config.shutdown_timeout_secs);
+            match tokio::time::timeout(timeout, &mut server).await {
+                Ok(result) => result,
+                Err(_) => {
+                    tracing::warn!("in-flight requests did not drain before the shutdown timeout");
+                    server.abort();
+                    Ok(Ok(()))
+                }
+            }
+        }
+    };
+
+    sweeper.await?;
+    if let Some(tls_reloader) = tls_reloader {
+        tls_reloader.await?;
+    }
+    server_result??;
+    tracing::info!("server stopped");
     Ok(())
 }
 
+#[allow(clippy::expect_used)]
+fn hsts_header(max_age_secs: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("max-age={}; includeSubDomains", max_age_secs))
+        .expect("HSTS header is always valid ASCII")
+}
+
+/// Resolves on SIGINT or SIGTERM.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %err, "failed to listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!(error = %err, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// The routes of API version 1, relative to their prefix.
+fn v1_router(state: &AppState) -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .nest("/auth", auth::router(state.clone()))
+        .nest("/users", users::router(state.clone()))
+        .nest("/items", items::router(state.clone()))
+        .nest("/transparency", transparency::router())
+        .nest("/admin", admin::router(state.clone()))
+}
+
+/// The documented API. Its OpenAPI document is collected from the handlers
+/// as the routes are added.
+fn api_router(state: &AppState) -> OpenApiRouter<AppState> {
+    OpenApiRouter::with_openapi(openapi::ApiDoc::openapi())
+        .merge(health::router())
+        .merge(well_known::router())
+        .nest(versioning::API_PREFIX, v1_router(state))
+}
+
 pub fn router(state: AppState) -> Router<AppState> {
-    index::router().nest("/auth", auth::router(state.clone()))
+    let (api, document) = api_router(&state).split_for_parts();
+    // The version 1 routes are still served without their prefix for older
+    // clients, marked as deprecated and left out of the document
+    let (unversioned, _) = v1_router(&state).split_for_parts();
+    let unversioned = unversioned.layer(middleware::from_fn_with_state(
+        versioning::Deprecation::unversioned(&state.config.unversioned_routes_sunset),
+        versioning::mark_deprecated,
+    ));
+    index::router()
+        .route("/metrics", get(observability::metrics))
+        .merge(openapi::router(openapi::finish(document)))
+        .merge(api)
+        .merge(unversioned)
+        .fallback(errors::not_found)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            versioning::check_versions,
+        ))
+        .layer(middleware::from_fn(errors::attach_request_id))
+        .layer(middleware::from_fn(format::negotiate_format))
+        .layer(middleware::from_fn(observability::track_metrics))
+        .layer(option_layer(cors::layer(&state.config)))
+        .layer(TraceLayer::new_for_http().make_span_with(observability::request_span))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
 }
@@ -0,0 +1,71 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tower_http::request_id::RequestId;
+use tracing::Span;
+
+use super::AppState;
+
+/// Route label for requests that did not match any route, so arbitrary
+/// client paths never become label values.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+fn route_label(request: &Request) -> String {
+    request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or(UNMATCHED_ROUTE.to_string())
+}
+
+/// Builds the span every request is handled in. Only the method, route
+/// template and request id are recorded, never usernames or raw paths.
+pub fn request_span(request: &Request) -> Span {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default();
+
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        route = %route_label(request),
+        request_id = %request_id,
+    )
+}
+
+pub async fn track_metrics(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = route_label(&request);
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    metrics::counter!(
+        "http_requests_total",
+        "method" => method.clone(),
+        "route" => route.clone(),
+        "status" => status,
+    )
+    .increment(1);
+    metrics::histogram!(
+        "http_request_duration_seconds",
+        "method" => method,
+        "route" => route,
+    )
+    .record(started.elapsed().as_secs_f64());
+
+    response
+}
+
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    state.storage.record_metrics();
+    state.metrics.run_upkeep();
+    state.metrics.render()
+}
@@ -0,0 +1,140 @@
+use axum::{routing::get, Json, Router};
+use utoipa::{
+    openapi::{
+        path::Operation,
+        security::{ApiKey, ApiKeyValue, SecurityScheme},
+        ContentBuilder, OpenApi as OpenApiDocument, PathItem, Ref, RefOr, ResponseBuilder,
+    },
+    Modify, OpenApi, ToSchema,
+};
+
+use super::{
+    errors::{ErrorCode, ErrorEnvelope, FieldError},
+    format::{CBOR_CONTENT_TYPE, JSON_CONTENT_TYPE},
+    AppState,
+};
+
+/// Name of the security scheme of routes that need a session.
+pub(super) const REQUEST_MAC: &str = "request_mac";
+const ERROR_RESPONSE: &str = "Error";
+
+/// The parts of the document that no handler describes. The paths come from
+/// the routers and are completed by [`finish`].
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "salauskilke",
+        description = "End-to-end encrypted storage with OPAQUE password logins. \
+            Every body documented as `application/json` may be sent and requested as \
+            `application/cbor` too, see the `Accept` and `Content-Type` headers. \
+            Errors always use the error envelope."
+    ),
+    components(schemas(ErrorEnvelope, ErrorCode, FieldError)),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "auth", description = "Registration, login and sessions"),
+        (name = "users", description = "Public keys and personal audit trails"),
+        (name = "items", description = "Encrypted items of the authenticated user"),
+        (name = "transparency", description = "Key transparency log"),
+        (name = "admin", description = "Account administration, admins only"),
+        (name = "health", description = "Liveness and readiness probes"),
+        (name = "discovery", description = "Server document for clients"),
+    )
+)]
+pub(super) struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            REQUEST_MAC,
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "x-session-id",
+                "The session id from `/v1/auth/login/finish`. Requests must also carry \
+                 `x-timestamp` (Unix seconds), a single-use `x-nonce` and \
+                 `x-signature`, the URL-safe base64 HMAC-SHA256 under the session's \
+                 MAC key of `METHOD \\n path \\n timestamp \\n nonce \\n \
+                 hex(sha256(body))`. Except on `/v1/auth/logout`, bodies in both \
+                 directions are sealed as `application/salauskilke-sealed` with the \
+                 session's channel keys, the documented body being the plaintext.",
+            ))),
+        );
+    }
+}
+
+/// Completes the document the routers collected: every operation can fail
+/// with the error envelope, and every JSON body may be CBOR instead.
+pub(super) fn finish(mut openapi: OpenApiDocument) -> OpenApiDocument {
+    // The crate names no license, which would otherwise be listed as ""
+    openapi.info.license = None;
+    let components = openapi.components.get_or_insert_with(Default::default);
+    let envelope = ContentBuilder::new()
+        .schema(Some(Ref::from_schema_name(ErrorEnvelope::name())))
+        .build();
+    components.responses.insert(
+        ERROR_RESPONSE.to_string(),
+        RefOr::T(
+            ResponseBuilder::new()
+                .description("The error envelope. Clients match on `code`.")
+                .content(JSON_CONTENT_TYPE, envelope.clone())
+                .content(CBOR_CONTENT_TYPE, envelope)
+                .build(),
+        ),
+    );
+
+    for item in openapi.paths.paths.values_mut() {
+        for operation in operations(item) {
+            if let Some(body) = operation.request_body.as_mut() {
+                if let Some(json) = body.content.get(JSON_CONTENT_TYPE).cloned() {
+                    body.content.insert(CBOR_CONTENT_TYPE.to_string(), json);
+                }
+            }
+            for response in operation.responses.responses.values_mut() {
+                if let RefOr::T(response) = response {
+                    if let Some(json) = response.content.get(JSON_CONTENT_TYPE).cloned() {
+                        response.content.insert(CBOR_CONTENT_TYPE.to_string(), json);
+                    }
+                }
+            }
+            operation
+                .responses
+                .responses
+                .entry("default".to_string())
+                .or_insert_with(|| RefOr::Ref(Ref::from_response_name(ERROR_RESPONSE)));
+        }
+    }
+    openapi
+}
+
+fn operations(item: &mut PathItem) -> impl Iterator<Item = &mut Operation> {
+    [
+        item.get.as_mut(),
+        item.put.as_mut(),
+        item.post.as_mut(),
+        item.delete.as_mut(),
+        item.patch.as_mut(),
+    ]
+    .into_iter()
+    .flatten()
+}
+
+/// Serves `document` at `/openapi.json`, and with the `openapi-viewer`
+/// feature a browsable copy at `/docs`.
+pub(super) fn router(document: OpenApiDocument) -> Router<AppState> {
+    #[cfg(feature = "openapi-viewer")]
+    let viewer: Router<AppState> = {
+        use utoipa_scalar::{Scalar, Servable};
+        Scalar::with_url("/docs", document.clone()).into()
+    };
+    #[cfg(not(feature = "openapi-viewer"))]
+    let viewer = Router::new();
+
+    Router::new()
+        .route(
+            "/openapi.json",
+            get(move || std::future::ready(Json(document.clone()))),
+        )
+        .merge(viewer)
+}
@@ -0,0 +1,9 @@
+use axum::extract::FromRequestParts;
+
+use super::errors::ApiError;
+
+/// [`axum::extract::Query`] extractor whose rejections are reported in the
+/// JSON error envelope instead of as plain text.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct ApiQuery<T>(pub T);
@@ -0,0 +1,46 @@
+use std::{net::SocketAddr, time::Instant};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header::RETRY_AFTER, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use super::{errors::ApiError, AppState};
+use crate::controllers::errors::ServiceError;
+
+/// Middleware that limits requests per client address. Requests without a
+/// known peer address, such as those from in-process tests, are not limited.
+pub async fn limit_by_client_address(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.config.rate_limit_per_minute == 0 {
+        return next.run(request).await;
+    }
+
+    let Some(ConnectInfo(peer)) = request.extensions().get::<ConnectInfo<SocketAddr>>() else {
+        return next.run(request).await;
+    };
+
+    let checked = match state.rate_limiter.lock() {
+        Ok(mut rate_limiter) => rate_limiter.check(peer.ip(), Instant::now()),
+        Err(e) => {
+            return ApiError::from(ServiceError::InternalError(e.to_string())).into_response()
+        }
+    };
+
+    match checked {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            let mut response = ApiError::TooManyRequests.into_response();
+            let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+            response
+        }
+    }
+}
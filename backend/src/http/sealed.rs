@@ -0,0 +1,177 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderValue,
+    },
+    response::{IntoResponse, Response},
+};
+use tower::{Layer, Service};
+
+use super::{
+    errors::{ApiError, ApiResult, ErrorBody, ErrorCode},
+    format::Format,
+    session::AuthenticatedSession,
+    AppState,
+};
+use crate::controllers::{errors::ServiceError, sealed_channel::SEALED_CONTENT_TYPE};
+
+const MAX_SEALED_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Opens `application/salauskilke-sealed` request bodies and seals the
+/// responses with the session's channel keys, so handlers only ever see
+/// plaintext. The plaintext is JSON, or CBOR when the client accepts
+/// `application/cbor`. Must run inside the request MAC middleware, which provides
+/// the [`AuthenticatedSession`].
+#[derive(Clone)]
+pub struct SealedChannelLayer {
+    state: AppState,
+}
+
+impl SealedChannelLayer {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+}
+
+impl<S> Layer<S> for SealedChannelLayer {
+    type Service = SealedChannel<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SealedChannel {
+            inner,
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SealedChannel<S> {
+    inner: S,
+    state: AppState,
+}
+
+impl<S> Service<Request> for SealedChannel<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // The clone is not guaranteed to be ready, so keep the one that is
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let state = self.state.clone();
+
+        Box::pin(async move {
+            if !is_sealed(&request) {
+                if state.config.require_sealed_channel {
+                    return Ok(ApiError::from(ServiceError::SealedChannelRequired).into_response());
+                }
+                return inner.call(request).await;
+            }
+
+            let (session_id, sequence, request) = match open_request(&state, request).await {
+                Ok(opened) => opened,
+                Err(err) => return Ok(err.into_response()),
+            };
+
+            let response = inner.call(request).await?;
+
+            Ok(seal_response(&state, &session_id, sequence, response)
+                .await
+                .unwrap_or_else(IntoResponse::into_response))
+        })
+    }
+}
+
+fn is_sealed(request: &Request) -> bool {
+    request
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes() == SEALED_CONTENT_TYPE.as_bytes())
+}
+
+async fn open_request(state: &AppState, request: Request) -> ApiResult<(String, u64, Request)> {
+    let (mut parts, body) = request.into_parts();
+
+    let session_id = parts
+        .extensions
+        .get::<AuthenticatedSession>()
+        .map(|session| session.session_id.clone())
+        .ok_or(ApiError::Unauthorized(
+            ErrorCode::Unauthenticated,
+            "Sealed requests require an authenticated session".to_string(),
+        ))?;
+
+    let sealed = to_bytes(body, MAX_SEALED_BODY_BYTES)
+        .await
+        .map_err(|e| ApiError::BadRequest(ErrorCode::InvalidBody, e.to_string()))?;
+
+    let (sequence, plaintext) = state
+        .session_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .open_request(&session_id, &sealed)?;
+
+    parts.headers.remove(CONTENT_LENGTH);
+    if plaintext.is_empty() {
+        parts.headers.remove(CONTENT_TYPE);
+    } else {
+        // The plaintext is in the negotiated format, like the response
+        parts.headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(Format::current().content_type()),
+        );
+    }
+
+    Ok((
+        session_id,
+        sequence,
+        Request::from_parts(parts, Body::from(plaintext)),
+    ))
+}
+
+async fn seal_response(
+    state: &AppState,
+    session_id: &str,
+    sequence: u64,
+    response: Response,
+) -> ApiResult<Response> {
+    let (mut parts, body) = response.into_parts();
+    // The error envelope is already in the body, which the request id
+    // middleware must not replace with a plaintext copy
+    parts.extensions.remove::<ErrorBody>();
+
+    let plaintext = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+
+    let sealed = state
+        .session_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .seal_response(session_id, sequence, &plaintext)?;
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static(SEALED_CONTENT_TYPE));
+
+    Ok(Response::from_parts(parts, Body::from(sealed)))
+}
@@ -0,0 +1,119 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{FromRequestParts, OriginalUri, Request, State},
+    http::{request::Parts, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use generic_array::{typenum::U32, GenericArray};
+
+use super::{
+    errors::{ApiError, ApiResult, ErrorCode},
+    AppState,
+};
+use crate::controllers::errors::ServiceError;
+use crate::controllers::session::{unix_timestamp, RequestToSign};
+use crate::utils::base64::Base64String;
+use zeroize::Zeroizing;
+
+pub const SESSION_ID_HEADER: &str = "x-session-id";
+pub const TIMESTAMP_HEADER: &str = "x-timestamp";
+pub const NONCE_HEADER: &str = "x-nonce";
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+const MAX_SIGNED_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Inserted into the request extensions once the request MAC has been verified.
+#[derive(Clone)]
+pub struct AuthenticatedSession {
+    pub session_id: String,
+    pub username: String,
+}
+
+/// Leaves out the session id, it identifies the session to the server.
+impl std::fmt::Debug for AuthenticatedSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthenticatedSession")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AuthenticatedSession {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<AuthenticatedSession>()
+            .cloned()
+            .ok_or(ApiError::Unauthorized(
+                ErrorCode::Unauthenticated,
+                "Request is not authenticated".to_string(),
+            ))
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> ApiResult<&'a str> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(ApiError::Unauthorized(
+            ErrorCode::Unauthenticated,
+            format!("Missing {} header", name),
+        ))
+}
+
+/// Middleware that authenticates a request by the HMAC computed with the key
+/// derived from the OPAQUE session key.
+pub async fn verify_request_mac(
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    request: Request,
+    next: Next,
+) -> ApiResult<Response> {
+    let (mut parts, body) = request.into_parts();
+
+    let session_id = header(&parts.headers, SESSION_ID_HEADER)?.to_string();
+    let timestamp: u64 = header(&parts.headers, TIMESTAMP_HEADER)?
+        .parse()
+        .map_err(|_| {
+            ApiError::BadRequest(
+                ErrorCode::InvalidHeader,
+                format!("Invalid {} header", TIMESTAMP_HEADER),
+            )
+        })?;
+    let nonce = header(&parts.headers, NONCE_HEADER)?.to_string();
+    let signature: Zeroizing<GenericArray<u8, U32>> =
+        Base64String::from(header(&parts.headers, SIGNATURE_HEADER)?.to_string()).decode()?;
+
+    let body = to_bytes(body, MAX_SIGNED_BODY_BYTES)
+        .await
+        .map_err(|e| ApiError::BadRequest(ErrorCode::InvalidBody, e.to_string()))?;
+
+    let path = uri
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or(uri.path());
+
+    let request_to_sign = RequestToSign {
+        method: parts.method.as_str(),
+        path,
+        timestamp,
+        nonce: &nonce,
+        body: &body,
+    };
+
+    let username = state
+        .session_controller
+        .lock()
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .verify_request(&session_id, &request_to_sign, &signature, unix_timestamp())?;
+
+    parts.extensions.insert(AuthenticatedSession {
+        session_id,
+        username,
+    });
+
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant};
+
+use sqlx::types::chrono::Utc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::AppState;
+use crate::controllers::session::unix_timestamp;
+
+/// Accounts re-encrypted per storage call.
+const REENCRYPT_BATCH: u32 = 100;
+
+/// Periodically drops expired login sessions, registration tickets,
+/// in-memory and stored sessions, request nonces, idle rate limit buckets
+/// and audit events past retention, and re-encrypts accounts not yet under
+/// the current KEK, until the shutdown token is cancelled.
+pub fn spawn(state: AppState, shutdown: CancellationToken) -> JoinHandle<()> {
+    let interval = Duration::from_secs(state.config.sweep_interval_secs.max(1));
+    let audit_retention = match state.config.audit_retention_days {
+        0 => None,
+        days => Some(Duration::from_secs(days.saturating_mul(24 * 60 * 60))),
+    };
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => {}
+            }
+
+            match state.opaque_controller.lock() {
+                Ok(mut opaque_controller) => {
+                    let swept = opaque_controller.sweep_expired_login_sessions();
+                    if swept > 0 {
+                        tracing::debug!(swept, "swept expired login sessions");
+                    }
+                    let swept = opaque_controller.sweep_expired_registrations();
+                    if swept > 0 {
+                        tracing::debug!(swept, "swept expired registration tickets");
+                    }
+                }
+                Err(err) => tracing::error!(error = %err, "opaque controller lock poisoned"),
+            }
+
+            match state.session_controller.lock() {
+                Ok(mut session_controller) => {
+                    let now = unix_timestamp();
+                    let swept = session_controller.sweep_expired_sessions(now);
+                    if swept > 0 {
+                        tracing::debug!(swept, "swept expired sessions");
+                    }
+                    session_controller.sweep_expired_nonces(now);
+                }
+                Err(err) => tracing::error!(error = %err, "session controller lock poisoned"),
+            }
+
+            match state.storage.delete_expired_sessions(Utc::now()).await {
+                Ok(0) => {}
+                Ok(deleted) => tracing::debug!(deleted, "deleted expired stored sessions"),
+                Err(err) => tracing::error!(error = %err, "failed to delete expired sessions"),
+            }
+
+            if let Some(retention) = audit_retention {
+                match state
+                    .storage
+                    .delete_audit_events_before(Utc::now() - retention)
+                    .await
+                {
+                    Ok(0) => {}
+                    Ok(deleted) => tracing::info!(deleted, "deleted audit events past retention"),
+                    Err(err) => tracing::error!(error = %err, "failed to delete old audit events"),
+                }
+            }
+
+            let mut reencrypted = 0;
+            while !shutdown.is_cancelled() {
+                match state.storage.reencrypt_accounts(REENCRYPT_BATCH).await {
+                    Ok(0) => break,
+                    Ok(accounts) => reencrypted += accounts,
+                    Err(err) => {
+                        tracing::error!(error = %err, "failed to re-encrypt accounts");
+                        break;
+                    }
+                }
+            }
+            if reencrypted > 0 {
+                tracing::info!(reencrypted, "re-encrypted accounts under the current KEK");
+            }
+
+            match state.rate_limiter.lock() {
+                Ok(mut rate_limiter) => {
+                    rate_limiter.sweep(Instant::now());
+                }
+                Err(err) => tracing::error!(error = %err, "rate limiter lock poisoned"),
+            }
+        }
+        tracing::debug!("sweeper stopped");
+    })
+}
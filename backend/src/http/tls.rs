@@ -0,0 +1,267 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    future::Future,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use axum_server::{
+    accept::{Accept, DefaultAcceptor},
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::{VerifierBuilderError, WebPkiClientVerifier},
+    RootCertStore, ServerConfig,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    task::JoinHandle,
+};
+use tokio_rustls::server::TlsStream;
+use tokio_util::sync::CancellationToken;
+use tower_http::add_extension::AddExtension;
+
+use super::{
+    errors::{ApiError, ApiResult},
+    AppState,
+};
+use crate::utils::config::Config;
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(PathBuf, io::Error),
+    MissingPath(&'static str),
+    NoPrivateKey(PathBuf),
+    Rustls(rustls::Error),
+    ClientVerifier(VerifierBuilderError),
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsError::Io(path, err) => write!(f, "Failed to read {}: {}", path.display(), err),
+            TlsError::MissingPath(name) => write!(f, "{} must be set when TLS is enabled", name),
+            TlsError::NoPrivateKey(path) => {
+                write!(f, "No private key found in {}", path.display())
+            }
+            TlsError::Rustls(err) => write!(f, "Invalid TLS configuration: {}", err),
+            TlsError::ClientVerifier(err) => {
+                write!(f, "Invalid client certificate authority: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+impl From<rustls::Error> for TlsError {
+    fn from(err: rustls::Error) -> Self {
+        TlsError::Rustls(err)
+    }
+}
+
+impl From<VerifierBuilderError> for TlsError {
+    fn from(err: VerifierBuilderError) -> Self {
+        TlsError::ClientVerifier(err)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TlsPaths {
+    pub cert_chain: PathBuf,
+    pub private_key: PathBuf,
+    /// When set, clients may present a certificate signed by this authority,
+    /// which [`require_client_certificate`] then demands on admin routes
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsPaths {
+    /// Returns `None` when TLS is not configured.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, TlsError> {
+        match (&config.tls_cert_path, &config.tls_key_path) {
+            (None, None) => Ok(None),
+            (Some(_), None) => Err(TlsError::MissingPath("TLS_KEY_PATH")),
+            (None, Some(_)) => Err(TlsError::MissingPath("TLS_CERT_PATH")),
+            (Some(cert_chain), Some(private_key)) => Ok(Some(Self {
+                cert_chain: cert_chain.into(),
+                private_key: private_key.into(),
+                client_ca: config.tls_client_ca_path.as_ref().map(PathBuf::from),
+            })),
+        }
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [
+            Some(&self.cert_chain),
+            Some(&self.private_key),
+            self.client_ca.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| TlsError::Io(path.to_path_buf(), err))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| TlsError::Io(path.to_path_buf(), err))
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    rustls_pemfile::private_key(&mut open(path)?)
+        .map_err(|err| TlsError::Io(path.to_path_buf(), err))?
+        .ok_or(TlsError::NoPrivateKey(path.to_path_buf()))
+}
+
+pub fn load_server_config(paths: &TlsPaths) -> Result<Arc<ServerConfig>, TlsError> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match &paths.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca)? {
+                roots.add(cert)?;
+            }
+            // Certificates are optional at the TLS layer so that public routes
+            // keep working; admin routes enforce them per request
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .allow_unauthenticated()
+                .build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder.with_single_cert(
+        load_certs(&paths.cert_chain)?,
+        load_private_key(&paths.private_key)?,
+    )?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(Arc::new(server_config))
+}
+
+/// Polls the certificate files and swaps in a new server config whenever one
+/// of them changes. A config that fails to load is logged and the previous
+/// one stays in use.
+pub fn spawn_reloader(
+    paths: TlsPaths,
+    rustls_config: RustlsConfig,
+    interval: Duration,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut last_modified = paths.modified();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => {}
+            }
+
+            let modified = paths.modified();
+            if modified == last_modified {
+                continue;
+            }
+
+            match load_server_config(&paths) {
+                Ok(server_config) => {
+                    rustls_config.reload_from_config(server_config);
+                    last_modified = modified;
+                    tracing::info!("reloaded TLS certificates");
+                }
+                Err(err) => {
+                    tracing::warn!(error = %err, "failed to reload TLS certificates");
+                }
+            }
+        }
+    })
+}
+
+/// The leaf certificate the client authenticated with, if any. Present on
+/// every request served over TLS.
+#[derive(Clone, Debug)]
+pub struct PeerCertificate(pub Option<CertificateDer<'static>>);
+
+/// Performs the TLS handshake and exposes the verified client certificate to
+/// handlers as a [`PeerCertificate`] request extension.
+#[derive(Clone)]
+pub struct PeerCertificateAcceptor {
+    inner: RustlsAcceptor<DefaultAcceptor>,
+}
+
+impl PeerCertificateAcceptor {
+    pub fn new(rustls_config: RustlsConfig) -> Self {
+        Self {
+            inner: RustlsAcceptor::new(rustls_config),
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for PeerCertificateAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = AddExtension<S, PeerCertificate>;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let handshake = self.inner.accept(stream, service);
+
+        Box::pin(async move {
+            let (stream, service) = handshake.await?;
+            let peer_certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|cert| cert.clone().into_owned());
+
+            Ok((
+                stream,
+                AddExtension::new(service, PeerCertificate(peer_certificate)),
+            ))
+        })
+    }
+}
+
+/// Middleware for routes that require a client certificate when mTLS is
+/// configured. Without a client CA it lets every request through.
+pub async fn require_client_certificate(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> ApiResult<Response> {
+    if state.config.tls_client_ca_path.is_none() {
+        return Ok(next.run(request).await);
+    }
+
+    match request.extensions().get::<PeerCertificate>() {
+        Some(PeerCertificate(Some(_))) => Ok(next.run(request).await),
+        _ => Err(ApiError::Forbidden),
+    }
+}
@@ -0,0 +1,183 @@
+use axum::extract::{Path, State};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use super::{
+    errors::{ApiError, ApiResult, ErrorCode},
+    format::ApiBody,
+    query::ApiQuery,
+    AppState,
+};
+use crate::controllers::{
+    errors::ServiceError,
+    session::unix_timestamp,
+    signing::SignedJson,
+    transparency::{Hash, MerkleLog},
+};
+use crate::storage::KeyLogEntry;
+use crate::utils::base64::Base64String;
+
+pub fn router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(tree_head))
+        .routes(routes!(consistency))
+        .routes(routes!(user_history))
+}
+
+/// The signed statement that the log held `tree_size` leaves with
+/// `root_hash` at `timestamp`.
+#[derive(Serialize)]
+struct TreeHead {
+    tree_size: u64,
+    root_hash: Base64String,
+    timestamp: u64,
+}
+
+/// Signs the head of the whole log as it is now with the current signing key.
+pub(super) fn signed_tree_head(
+    state: &AppState,
+    log: &MerkleLog,
+) -> Result<SignedJson, ServiceError> {
+    let tree_size = log.size();
+    let root_hash = log
+        .root(tree_size)
+        .ok_or(ServiceError::InternalError("Tree has no root".to_string()))?;
+
+    state
+        .current_signing_key()?
+        .sign_json(&TreeHead {
+            tree_size,
+            root_hash: Base64String::encode_bytes(&root_hash),
+            timestamp: unix_timestamp(),
+        })
+        .map_err(|e| ServiceError::InternalError(e.to_string()))
+}
+
+pub(super) fn encode_proof(proof: &[Hash]) -> Vec<Base64String> {
+    proof
+        .iter()
+        .map(|hash| Base64String::encode_bytes(hash))
+        .collect()
+}
+
+/// A log entry together with the proof that it is in the current tree.
+#[derive(Serialize, ToSchema)]
+pub(super) struct ProvenEntry {
+    leaf_index: u64,
+    /// The exact bytes that were hashed into the tree
+    leaf: Base64String,
+    inclusion_proof: Vec<Base64String>,
+}
+
+impl ProvenEntry {
+    pub(super) fn new(log: &MerkleLog, entry: &KeyLogEntry) -> Result<Self, ServiceError> {
+        let leaf_index = u64::try_from(entry.leaf_index)
+            .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+        let inclusion_proof = log.inclusion_proof(leaf_index, log.size()).ok_or_else(|| {
+            ServiceError::InternalError(format!("Leaf {} is not in the tree", leaf_index))
+        })?;
+
+        Ok(Self {
+            leaf_index,
+            leaf: Base64String::encode_bytes(&entry.leaf),
+            inclusion_proof: encode_proof(&inclusion_proof),
+        })
+    }
+}
+
+/// The current tree head. Its payload holds `tree_size`, `root_hash` and
+/// `timestamp`.
+#[utoipa::path(
+    get,
+    path = "/tree-head",
+    tag = "transparency",
+    responses((status = 200, body = SignedJson))
+)]
+async fn tree_head(State(state): State<AppState>) -> ApiResult<ApiBody<SignedJson>> {
+    let log = state.key_log.lock().await;
+    Ok(ApiBody(signed_tree_head(&state, &log)?))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ConsistencyQuery {
+    first: u64,
+    second: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ConsistencyResponse {
+    first: u64,
+    second: u64,
+    proof: Vec<Base64String>,
+}
+
+/// Proves that the tree of `first` leaves is a prefix of the tree of `second`
+/// leaves, so clients can check that a newer tree head did not rewrite the
+/// history they have already seen.
+#[utoipa::path(
+    get,
+    path = "/consistency",
+    tag = "transparency",
+    params(ConsistencyQuery),
+    responses((status = 200, body = ConsistencyResponse))
+)]
+async fn consistency(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<ConsistencyQuery>,
+) -> ApiResult<ApiBody<ConsistencyResponse>> {
+    let log = state.key_log.lock().await;
+    let proof = log
+        .consistency_proof(query.first, query.second)
+        .ok_or_else(|| {
+            ApiError::BadRequest(
+                ErrorCode::InvalidTreeSize,
+                format!("Tree sizes must satisfy first <= second <= {}", log.size()),
+            )
+        })?;
+
+    Ok(ApiBody(ConsistencyResponse {
+        first: query.first,
+        second: query.second,
+        proof: encode_proof(&proof),
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+struct UserHistoryResponse {
+    username: String,
+    entries: Vec<ProvenEntry>,
+    tree_head: SignedJson,
+}
+
+/// Every key binding ever logged for `username`, each with an inclusion proof
+/// against the same signed tree head. Monitors poll this to notice keys they
+/// did not publish.
+#[utoipa::path(
+    get,
+    path = "/users/{username}",
+    tag = "transparency",
+    params(("username" = String, Path)),
+    responses((status = 200, body = UserHistoryResponse))
+)]
+async fn user_history(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> ApiResult<ApiBody<UserHistoryResponse>> {
+    let log = state.key_log.lock().await;
+    let entries = state
+        .storage
+        .list_key_log_entries_for_user(&username)
+        .await
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .iter()
+        .map(|entry| ProvenEntry::new(&log, entry))
+        .collect::<Result<_, _>>()?;
+
+    Ok(ApiBody(UserHistoryResponse {
+        username,
+        entries,
+        tree_head: signed_tree_head(&state, &log)?,
+    }))
+}
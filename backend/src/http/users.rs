@@ -0,0 +1,334 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    middleware,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use super::{
+    audit::{self, AuditPage, PageQuery},
+    errors::{ApiError, ApiResult, ErrorCode, FieldError},
+    format::ApiBody,
+    query::ApiQuery,
+    sealed::SealedChannelLayer,
+    session::{verify_request_mac, AuthenticatedSession},
+    transparency::{signed_tree_head, ProvenEntry},
+    AppState,
+};
+use crate::controllers::{
+    audit::AuditAction, errors::ServiceError, session::unix_timestamp, signing::SignedJson,
+};
+use crate::storage::Account;
+use crate::utils::base64::{Base64String, Base64Vec};
+
+const MAX_PUBLIC_KEYS: usize = 16;
+const MAX_ALGORITHM_LENGTH: usize = 64;
+/// Room for post-quantum public keys, which run to a few kilobytes.
+const MAX_PUBLIC_KEY_BYTES: usize = 4096;
+
+pub fn router(state: AppState) -> OpenApiRouter<AppState> {
+    let authenticated = OpenApiRouter::new()
+        .routes(routes!(put_public_keys))
+        .routes(routes!(record_share))
+        .routes(routes!(audit_events))
+        .route_layer(SealedChannelLayer::new(state.clone()))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            verify_request_mac,
+        ));
+
+    OpenApiRouter::new()
+        .routes(routes!(get_public_keys))
+        .merge(authenticated)
+        .with_state(state)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct PublicKey {
+    /// Free-form name of the key type, such as `x25519`
+    pub algorithm: String,
+    #[schema(value_type = Base64String)]
+    pub key: Base64Vec<MAX_PUBLIC_KEY_BYTES>,
+}
+
+/// The leaf logged for every change of a user's keys. Its JSON encoding is
+/// what gets hashed into the tree.
+#[derive(Serialize, Deserialize)]
+struct KeyBinding {
+    username: String,
+    keys: Vec<PublicKey>,
+    timestamp: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PublicKeysResponse {
+    username: String,
+    keys: Vec<PublicKey>,
+    #[serde(flatten)]
+    entry: ProvenEntry,
+    tree_head: SignedJson,
+}
+
+/// The current keys of `username` with the proof that they are the latest
+/// binding in the transparency log.
+#[utoipa::path(
+    get,
+    path = "/{username}/public-keys",
+    tag = "users",
+    params(("username" = String, Path)),
+    responses((status = 200, body = PublicKeysResponse))
+)]
+async fn get_public_keys(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> ApiResult<ApiBody<PublicKeysResponse>> {
+    let log = state.key_log.lock().await;
+    let entry = state
+        .storage
+        .latest_key_log_entry_for_user(&username)
+        .await
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .ok_or(ApiError::NotFound)?;
+    let binding: KeyBinding = serde_json::from_slice(&entry.leaf)
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+
+    Ok(ApiBody(PublicKeysResponse {
+        username,
+        keys: binding.keys,
+        entry: ProvenEntry::new(&log, &entry)?,
+        tree_head: signed_tree_head(&state, &log)?,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PutPublicKeysRequest {
+    keys: Vec<PublicKey>,
+}
+
+fn validate_keys(keys: &[PublicKey]) -> ApiResult<()> {
+    if keys.is_empty() || keys.len() > MAX_PUBLIC_KEYS {
+        return Err(ApiError::InvalidFields(vec![FieldError {
+            field: "keys".to_string(),
+            code: ErrorCode::InvalidValue,
+            message: format!("Between 1 and {} keys are required", MAX_PUBLIC_KEYS),
+        }]));
+    }
+
+    let mut errors = Vec::new();
+    for (i, key) in keys.iter().enumerate() {
+        if key.algorithm.is_empty() || key.algorithm.len() > MAX_ALGORITHM_LENGTH {
+            errors.push(FieldError {
+                field: format!("keys[{}].algorithm", i),
+                code: ErrorCode::InvalidValue,
+                message: format!("Must be between 1 and {} characters", MAX_ALGORITHM_LENGTH),
+            });
+        }
+        if key.key.is_empty() {
+            errors.push(FieldError {
+                field: format!("keys[{}].key", i),
+                code: ErrorCode::InvalidValue,
+                message: "Must not be empty".to_string(),
+            });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::InvalidFields(errors))
+    }
+}
+
+/// Replaces the keys of the authenticated user by appending a new binding to
+/// the transparency log. Only one server may append to the log.
+#[utoipa::path(
+    put,
+    path = "/{username}/public-keys",
+    tag = "users",
+    security(("request_mac" = [])),
+    params(("username" = String, Path, description = "Must be the authenticated user")),
+    request_body = PutPublicKeysRequest,
+    responses((status = 200, body = PublicKeysResponse))
+)]
+async fn put_public_keys(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+    Path(username): Path<String>,
+    ApiBody(body): ApiBody<PutPublicKeysRequest>,
+) -> ApiResult<ApiBody<PublicKeysResponse>> {
+    let account = own_account(&state, &session, &username).await?;
+    validate_keys(&body.keys)?;
+
+    let leaf = serde_json::to_vec(&KeyBinding {
+        username: username.clone(),
+        keys: body.keys.clone(),
+        timestamp: unix_timestamp(),
+    })
+    .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+
+    let mut log = state.key_log.lock().await;
+    let previous_keys = latest_binding(&state, &username)
+        .await?
+        .map(|binding| binding.keys)
+        .unwrap_or_default();
+    let entry = state
+        .storage
+        .append_key_log_entry(log.size(), &username, &leaf)
+        .await
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+    log.push(&leaf);
+    audit::record(
+        &state,
+        Some(account.id),
+        &username,
+        AuditAction::PublicKeysPublished,
+        json!({ "leaf_index": entry.leaf_index, "key_count": body.keys.len() }),
+    )
+    .await?;
+
+    // Every device publishes its own key, so a key appearing or disappearing
+    // is a device joining or leaving the account
+    let changes = body
+        .keys
+        .iter()
+        .filter(|key| !contains_key(&previous_keys, key))
+        .map(|key| (AuditAction::DeviceKeyAdded, key))
+        .chain(
+            previous_keys
+                .iter()
+                .filter(|key| !contains_key(&body.keys, key))
+                .map(|key| (AuditAction::DeviceKeyRemoved, key)),
+        );
+    for (action, key) in changes {
+        audit::record(
+            &state,
+            Some(account.id),
+            &username,
+            action,
+            json!({
+                "leaf_index": entry.leaf_index,
+                "algorithm": key.algorithm,
+                "key": Base64String::encode_bytes(&key.key),
+            }),
+        )
+        .await?;
+    }
+
+    Ok(ApiBody(PublicKeysResponse {
+        username,
+        keys: body.keys,
+        entry: ProvenEntry::new(&log, &entry)?,
+        tree_head: signed_tree_head(&state, &log)?,
+    }))
+}
+
+fn contains_key(keys: &[PublicKey], key: &PublicKey) -> bool {
+    keys.iter()
+        .any(|other| other.algorithm == key.algorithm && *other.key == *key.key)
+}
+
+async fn latest_binding(state: &AppState, username: &str) -> ApiResult<Option<KeyBinding>> {
+    let Some(entry) = state
+        .storage
+        .latest_key_log_entry_for_user(username)
+        .await
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+    else {
+        return Ok(None);
+    };
+    let binding = serde_json::from_slice(&entry.leaf)
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+    Ok(Some(binding))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct ShareRequest {
+    /// The user the item was sealed to
+    recipient: String,
+}
+
+/// Records that the authenticated user sealed an item to `recipient`.
+/// Sharing happens on the client, so the server only learns of a grant when
+/// the client reports it here.
+#[utoipa::path(
+    post,
+    path = "/{username}/shares",
+    tag = "users",
+    security(("request_mac" = [])),
+    params(("username" = String, Path, description = "Must be the authenticated user")),
+    request_body = ShareRequest,
+    responses(
+        (status = 204, description = "The grant was recorded"),
+        (status = 404, description = "The recipient has not published keys")
+    )
+)]
+async fn record_share(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+    Path(username): Path<String>,
+    ApiBody(body): ApiBody<ShareRequest>,
+) -> ApiResult<StatusCode> {
+    let account = own_account(&state, &session, &username).await?;
+    let entry = state
+        .storage
+        .latest_key_log_entry_for_user(&body.recipient)
+        .await
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?
+        .ok_or(ApiError::NotFound)?;
+    audit::record(
+        &state,
+        Some(account.id),
+        &username,
+        AuditAction::ShareGranted,
+        json!({ "recipient": body.recipient, "leaf_index": entry.leaf_index }),
+    )
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The account of the authenticated user, who may only act as themselves.
+async fn own_account(
+    state: &AppState,
+    session: &AuthenticatedSession,
+    username: &str,
+) -> ApiResult<Account> {
+    if session.username != username {
+        return Err(ApiError::Forbidden);
+    }
+    Ok(state
+        .storage
+        .find_account_by_username(username)
+        .await
+        .map_err(ServiceError::from)?
+        .ok_or(ServiceError::SessionMissingOrExpired)?)
+}
+
+/// The authenticated user's own security audit trail.
+#[utoipa::path(
+    get,
+    path = "/{username}/audit-events",
+    tag = "users",
+    security(("request_mac" = [])),
+    params(("username" = String, Path, description = "Must be the authenticated user"), PageQuery),
+    responses((status = 200, body = AuditPage))
+)]
+async fn audit_events(
+    State(state): State<AppState>,
+    session: AuthenticatedSession,
+    Path(username): Path<String>,
+    ApiQuery(query): ApiQuery<PageQuery>,
+) -> ApiResult<ApiBody<AuditPage>> {
+    let account = own_account(&state, &session, &username).await?;
+    let limit = query.limit();
+    let events = state
+        .storage
+        .list_audit_events(Some(account.id), query.before, limit)
+        .await
+        .map_err(ServiceError::from)?;
+
+    Ok(ApiBody(AuditPage::new(events, limit)))
+}
@@ -0,0 +1,302 @@
+use std::fmt::{Display, Formatter};
+
+use ciborium::Value;
+use serde::de::value::{MapAccessDeserializer, StringDeserializer};
+use serde::de::{
+    self, DeserializeSeed, Deserializer, Expected, IntoDeserializer, MapAccess, SeqAccess,
+    Unexpected, Visitor,
+};
+use serde::forward_to_deserialize_any;
+
+use super::errors::ErrorCode;
+use crate::utils::base64::BASE64_NEWTYPE;
+
+/// One step of the path to a field, such as `keys` or `[1]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Field(String),
+    Index(usize),
+}
+
+/// The serde constructor an error was reported with, which is how the typed
+/// base64 wrappers tell their [`DecodeError`](crate::utils::base64::DecodeError)
+/// cases apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Reported {
+    InvalidValue,
+    InvalidLength,
+    Other,
+}
+
+/// Why a request body does not deserialize into the handler's type, with
+/// the path of the field at fault.
+#[derive(Clone, Debug)]
+pub struct BodyError {
+    /// Innermost segment first, as the error travels outwards
+    path: Vec<Segment>,
+    reported: Reported,
+    /// Whether a typed base64 wrapper reported the error
+    base64: bool,
+    message: String,
+}
+
+impl BodyError {
+    fn new(reported: Reported, message: String) -> Self {
+        Self {
+            path: Vec::new(),
+            reported,
+            base64: false,
+            message,
+        }
+    }
+
+    fn at(mut self, segment: Segment) -> Self {
+        self.path.push(segment);
+        self
+    }
+
+    /// The error code of a base64 field that failed to decode, see
+    /// `decode_error` in `crate::utils::base64`. `None` for anything else
+    /// wrong with the body.
+    pub fn decode_error_code(&self) -> Option<ErrorCode> {
+        if !self.base64 {
+            return None;
+        }
+        Some(match self.reported {
+            Reported::InvalidValue => ErrorCode::DecodeInvalidBase64,
+            Reported::InvalidLength => ErrorCode::DecodeLengthMismatch,
+            Reported::Other => ErrorCode::InvalidValue,
+        })
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The path of the field at fault, such as `keys[1]`, empty for the
+    /// body as a whole.
+    pub fn path(&self) -> String {
+        let mut path = String::new();
+        for segment in self.path.iter().rev() {
+            match segment {
+                Segment::Field(name) if path.is_empty() => path.push_str(name),
+                Segment::Field(name) => {
+                    path.push('.');
+                    path.push_str(name);
+                }
+                Segment::Index(index) => path.push_str(&format!("[{}]", index)),
+            }
+        }
+        path
+    }
+}
+
+impl Display for BodyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.path() {
+            path if path.is_empty() => write!(f, "{}", self.message),
+            path => write!(f, "{}: {}", path, self.message),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+impl de::Error for BodyError {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self::new(Reported::Other, msg.to_string())
+    }
+
+    fn invalid_value(unexpected: Unexpected, expected: &dyn Expected) -> Self {
+        Self::new(
+            Reported::InvalidValue,
+            format!("invalid value: {}, expected {}", unexpected, expected),
+        )
+    }
+
+    fn invalid_length(len: usize, expected: &dyn Expected) -> Self {
+        Self::new(
+            Reported::InvalidLength,
+            format!("invalid length {}, expected {}", len, expected),
+        )
+    }
+}
+
+/// Deserializes from a [`Value`] that either wire format was parsed into,
+/// reporting errors as [`BodyError`]s.
+pub struct ValueDeserializer {
+    value: Value,
+    human_readable: bool,
+}
+
+impl ValueDeserializer {
+    pub fn new(value: Value, human_readable: bool) -> Self {
+        Self {
+            value,
+            human_readable,
+        }
+    }
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer {
+    type Error = BodyError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BodyError> {
+        let human_readable = self.human_readable;
+        match self.value {
+            Value::Integer(integer) => {
+                let integer = i128::from(integer);
+                match (u64::try_from(integer), i64::try_from(integer)) {
+                    (Ok(unsigned), _) => visitor.visit_u64(unsigned),
+                    (_, Ok(signed)) => visitor.visit_i64(signed),
+                    _ => visitor.visit_i128(integer),
+                }
+            }
+            Value::Bytes(bytes) => visitor.visit_byte_buf(bytes),
+            Value::Float(float) => visitor.visit_f64(float),
+            Value::Text(text) => visitor.visit_string(text),
+            Value::Bool(value) => visitor.visit_bool(value),
+            Value::Null => visitor.visit_unit(),
+            Value::Tag(_, value) => {
+                ValueDeserializer::new(*value, human_readable).deserialize_any(visitor)
+            }
+            Value::Array(values) => visitor.visit_seq(Seq {
+                values: values.into_iter().enumerate(),
+                human_readable,
+            }),
+            Value::Map(entries) => visitor.visit_map(Map {
+                entries: entries.into_iter(),
+                value: None,
+                human_readable,
+            }),
+            _ => Err(de::Error::custom("unsupported value")),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BodyError> {
+        match self.value {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, BodyError> {
+        if name == BASE64_NEWTYPE {
+            return visitor.visit_newtype_struct(self).map_err(|mut err| {
+                err.base64 = err.path.is_empty();
+                err
+            });
+        }
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, BodyError> {
+        match self.value {
+            Value::Text(variant) => {
+                let variant: StringDeserializer<BodyError> = variant.into_deserializer();
+                visitor.visit_enum(variant)
+            }
+            Value::Map(entries) if entries.len() == 1 => {
+                visitor.visit_enum(MapAccessDeserializer::new(Map {
+                    entries: entries.into_iter(),
+                    value: None,
+                    human_readable: self.human_readable,
+                }))
+            }
+            _ => Err(de::Error::custom("expected an enum variant")),
+        }
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.human_readable
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct Seq {
+    values: std::iter::Enumerate<std::vec::IntoIter<Value>>,
+    human_readable: bool,
+}
+
+impl<'de> SeqAccess<'de> for Seq {
+    type Error = BodyError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, BodyError> {
+        let Some((index, value)) = self.values.next() else {
+            return Ok(None);
+        };
+        seed.deserialize(ValueDeserializer::new(value, self.human_readable))
+            .map(Some)
+            .map_err(|err| err.at(Segment::Index(index)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+struct Map {
+    entries: std::vec::IntoIter<(Value, Value)>,
+    /// The value of the last key handed out, with the key for error paths
+    value: Option<(Segment, Value)>,
+    human_readable: bool,
+}
+
+fn key_segment(key: &Value) -> Segment {
+    match key {
+        Value::Text(name) => Segment::Field(name.clone()),
+        Value::Integer(index) => usize::try_from(i128::from(*index))
+            .map(Segment::Index)
+            .unwrap_or_else(|_| Segment::Field("?".to_string())),
+        _ => Segment::Field("?".to_string()),
+    }
+}
+
+impl<'de> MapAccess<'de> for Map {
+    type Error = BodyError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, BodyError> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        let segment = key_segment(&key);
+        let key = seed
+            .deserialize(ValueDeserializer::new(key, self.human_readable))
+            .map_err(|err| err.at(segment.clone()))?;
+        self.value = Some((segment, value));
+        Ok(Some(key))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, BodyError> {
+        let (segment, value) = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value requested before its key"))?;
+        seed.deserialize(ValueDeserializer::new(value, self.human_readable))
+            .map_err(|err| err.at(segment))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
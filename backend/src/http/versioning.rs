@@ -0,0 +1,162 @@
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::{
+        header::{HeaderName, LINK},
+        HeaderValue,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::types::chrono::{DateTime, Utc};
+
+use super::{
+    errors::{ApiError, ErrorCode},
+    AppState,
+};
+
+/// Request header naming the API version a client speaks. Answered on every
+/// response with the version the server spoke.
+pub const API_VERSION_HEADER: &str = "x-api-version";
+/// Request header carrying the semver version of the client release.
+pub const CLIENT_VERSION_HEADER: &str = "x-client-version";
+/// The current API version, served under `/v1`.
+pub const API_VERSION: u32 = 1;
+/// Path prefix of the current API version.
+pub const API_PREFIX: &str = "/v1";
+
+pub const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+pub const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// When the routes outside `/v1` were deprecated, 2026-10-19T00:00:00Z.
+const UNVERSIONED_DEPRECATED_AT: i64 = 1_792_368_000;
+
+/// Rejects requests for an API version other than [`API_VERSION`] and
+/// clients older than the configured minimum. Requests naming neither are
+/// served as the current version.
+pub async fn check_versions(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Err(error) = check_request(&state, &request) {
+        return with_api_version(error.into_response());
+    }
+    with_api_version(next.run(request).await)
+}
+
+fn check_request(state: &AppState, request: &Request) -> Result<(), ApiError> {
+    let headers = request.headers();
+
+    if let Some(value) = headers.get(API_VERSION_HEADER) {
+        let requested = value
+            .to_str()
+            .ok()
+            .and_then(|v| v.trim().parse::<u32>().ok());
+        if requested != Some(API_VERSION) {
+            return Err(ApiError::BadRequest(
+                ErrorCode::UnsupportedApiVersion,
+                format!("Only API version {} is served", API_VERSION),
+            ));
+        }
+    }
+
+    let Some(value) = headers.get(CLIENT_VERSION_HEADER) else {
+        return Ok(());
+    };
+    let client_version = value
+        .to_str()
+        .ok()
+        .and_then(|v| semver::Version::parse(v.trim()).ok())
+        .ok_or_else(|| {
+            ApiError::BadRequest(
+                ErrorCode::InvalidHeader,
+                format!("{} must be a version like 1.2.0", CLIENT_VERSION_HEADER),
+            )
+        })?;
+
+    // The minimum was validated when the config was loaded
+    let minimum = state
+        .config
+        .min_client_version
+        .as_deref()
+        .and_then(|v| semver::Version::parse(v).ok());
+    match minimum {
+        Some(minimum) if client_version < minimum => Err(ApiError::UpgradeRequired(format!(
+            "Client version {} is no longer supported, upgrade to {} or later",
+            client_version, minimum
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn with_api_version(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert(API_VERSION_HEADER, HeaderValue::from(API_VERSION));
+    response
+}
+
+/// The headers announcing that a route is deprecated, see [`mark_deprecated`].
+#[derive(Clone)]
+pub struct Deprecation {
+    deprecation: HeaderValue,
+    sunset: Option<HeaderValue>,
+}
+
+impl Deprecation {
+    /// Headers for the routes outside `/v1`, removed after `sunset`, an
+    /// RFC 3339 time.
+    pub fn unversioned(sunset: &str) -> Self {
+        // The sunset was validated when the config was loaded
+        let sunset = DateTime::parse_from_rfc3339(sunset)
+            .ok()
+            .map(|time| time.with_timezone(&Utc).format("%a, %d %b %Y %H:%M:%S GMT"))
+            .and_then(|date| HeaderValue::from_str(&date.to_string()).ok());
+        Self {
+            deprecation: HeaderValue::from_str(&format!("@{}", UNVERSIONED_DEPRECATED_AT))
+                .unwrap_or(HeaderValue::from_static("true")),
+            sunset,
+        }
+    }
+}
+
+/// Adds the `Deprecation` and `Sunset` headers, and a `Link` to the same
+/// route under `/v1`, to every response.
+pub async fn mark_deprecated(
+    State(deprecation): State<Deprecation>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let uri = match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri,
+        None => request.uri(),
+    };
+    let successor = uri
+        .path_and_query()
+        .map(|path| format!("<{}{}>; rel=\"successor-version\"", API_PREFIX, path));
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(DEPRECATION, deprecation.deprecation);
+    if let Some(sunset) = deprecation.sunset {
+        headers.insert(SUNSET, sunset);
+    }
+    if let Some(link) = successor.and_then(|link| HeaderValue::from_str(&link).ok()) {
+        headers.append(LINK, link);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn sunset_is_an_http_date() {
+        let deprecation = Deprecation::unversioned("2027-10-19T02:30:00+02:00");
+        assert_eq!(deprecation.deprecation, "@1792368000");
+        assert_eq!(deprecation.sunset.unwrap(), "Tue, 19 Oct 2027 00:30:00 GMT");
+    }
+}
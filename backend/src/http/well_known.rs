@@ -0,0 +1,89 @@
+use axum::extract::State;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use super::{auth::KsfParameters, errors::ApiResult, format::ApiBody, AppState};
+use crate::controllers::{
+    errors::ServiceError, opaque::CIPHERSUITE_ID, session::unix_timestamp, signing::SignedJson,
+};
+use crate::utils::base64::Base64String;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub fn router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().routes(routes!(server_document))
+}
+
+#[derive(Serialize)]
+struct ServerDocument {
+    protocol_version: u32,
+    ciphersuites: Vec<&'static str>,
+    ksf: KsfParameters,
+    server_identity: String,
+    /// Used for new registrations
+    opaque_key_id: i32,
+    opaque_public_key: Base64String,
+    /// Hex encoded SHA-256 of `opaque_public_key`
+    opaque_public_key_fingerprint: String,
+    /// Older generations that accounts registered before a rotation still
+    /// log in with
+    previous_opaque_public_keys: Vec<PreviousOpaqueKey>,
+    signing_public_key: Base64String,
+    issued_at: u64,
+}
+
+#[derive(Serialize)]
+struct PreviousOpaqueKey {
+    key_id: i32,
+    public_key: Base64String,
+}
+
+/// Responds with the [`ServerDocument`] signed by the server's signing key.
+#[utoipa::path(
+    get,
+    path = "/.well-known/salauskilke",
+    tag = "discovery",
+    responses((status = 200, body = SignedJson))
+)]
+async fn server_document(State(state): State<AppState>) -> ApiResult<ApiBody<SignedJson>> {
+    let (opaque_key_id, opaque_public_key, previous_opaque_public_keys) = {
+        let opaque_controller = state
+            .opaque_controller
+            .lock()
+            .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+        let keyring = opaque_controller.keyring();
+        let previous = keyring
+            .generations()
+            .filter(|generation| generation.key_id != keyring.current_key_id())
+            .map(|generation| PreviousOpaqueKey {
+                key_id: generation.key_id,
+                public_key: Base64String::encode_bytes(&generation.public_key()),
+            })
+            .collect::<Vec<_>>();
+        (
+            keyring.current_key_id(),
+            opaque_controller.server_public_key(),
+            previous,
+        )
+    };
+    let signing_key = state.current_signing_key()?;
+
+    let document = ServerDocument {
+        protocol_version: PROTOCOL_VERSION,
+        ciphersuites: vec![CIPHERSUITE_ID],
+        ksf: KsfParameters::from_config(&state.config),
+        server_identity: state.config.server_identity.clone(),
+        opaque_key_id,
+        opaque_public_key_fingerprint: hex::encode(Sha256::digest(&opaque_public_key)),
+        opaque_public_key: Base64String::encode_bytes(&opaque_public_key),
+        previous_opaque_public_keys,
+        signing_public_key: Base64String::encode_bytes(signing_key.public_key()),
+        issued_at: unix_timestamp(),
+    };
+    let signed = signing_key
+        .sign_json(&document)
+        .map_err(|e| ServiceError::InternalError(e.to_string()))?;
+
+    Ok(ApiBody(signed))
+}
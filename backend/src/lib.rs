@@ -7,4 +7,5 @@
 pub mod controllers;
 pub mod http;
 pub mod models;
+pub mod storage;
 pub mod utils;
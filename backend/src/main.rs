@@ -4,27 +4,136 @@
 #![deny(clippy::panic)]
 #![deny(unused_must_use)]
 
+use clap::Parser;
 use dotenv::dotenv;
-use envconfig::Envconfig;
 
-use backend::controllers;
+use backend::controllers::{self, audit::AuditAction, envelope::KekRing, key_provider};
 use backend::http;
-use backend::models::Models;
-use backend::utils::config;
-use backend::utils::pg_pool;
+use backend::storage::{self, NewAuditEvent, Role, Storage};
+use backend::utils::config::{Config, ConfigOverrides};
+use backend::utils::telemetry;
+use serde_json::json;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigOverrides,
+
+    /// Print the effective configuration with secrets redacted and exit
+    #[arg(long)]
+    print_config: bool,
+
+    /// Check the audit log's hash chain, print a summary and exit
+    #[arg(long)]
+    verify_audit_log: bool,
+
+    /// Give an existing account the admin role and exit
+    #[arg(long, value_name = "USERNAME", conflicts_with = "revoke_admin")]
+    grant_admin: Option<String>,
+
+    /// Take the admin role away from an account and exit
+    #[arg(long, value_name = "USERNAME")]
+    revoke_admin: Option<String>,
+
+    /// Add a new key-encryption key to the key provider and exit. Once every
+    /// server is restarted, they rewrap the data keys in the background
+    #[arg(long)]
+    rotate_kek: bool,
+}
+
+/// Changes the role of `username` and audits the change.
+async fn set_role(
+    storage: &dyn Storage,
+    username: &str,
+    role: Role,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let account = storage
+        .find_account_by_username(username)
+        .await?
+        .ok_or_else(|| format!("No account named {}", username))?;
+    storage.set_account_role(account.id, role).await?;
+    storage
+        .record_audit_event(NewAuditEvent {
+            account_id: Some(account.id),
+            username: Some(&account.username),
+            action: AuditAction::RoleChanged.as_str(),
+            detail: &json!({ "role": role, "via": "command_line" }),
+        })
+        .await?;
+    Ok(())
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     dotenv().ok();
+    let cli = Cli::parse();
+
+    let config = match Config::load(&cli.config) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    if cli.print_config {
+        print!("{}", config);
+        return Ok(());
+    }
+
+    telemetry::init_tracing(config.log_format, &config.log_filter)?;
+
+    let key_provider = match key_provider::connect(&config) {
+        Ok(provider) => provider,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    if cli.rotate_kek {
+        let Some(provider) = key_provider.as_deref() else {
+            eprintln!("key_provider is not configured");
+            std::process::exit(2);
+        };
+        let mut keks = KekRing::load(provider)?;
+        let kek_id = keks.rotate(provider)?;
+        println!("KEK {} is now current", kek_id);
+        return Ok(());
+    }
 
-    let config = config::Config::init_from_env()?;
-    let pool = pg_pool::create_pg_pool(&config.database_url, 3).await?;
+    let storage = storage::connect(&config, key_provider.as_deref()).await?;
 
-    sqlx::migrate!("db/migrations").run(&pool).await?;
+    if cli.verify_audit_log {
+        match controllers::audit::verify_chain(storage.as_ref()).await {
+            Ok(summary) => {
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+                return Ok(());
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let models = Models::new(pool);
+    let role_change = match (cli.grant_admin, cli.revoke_admin) {
+        (Some(username), _) => Some((username, Role::Admin)),
+        (None, Some(username)) => Some((username, Role::User)),
+        (None, None) => None,
+    };
+    if let Some((username, role)) = role_change {
+        if let Err(err) = set_role(storage.as_ref(), &username, role).await {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        println!("{} now has the {} role", username, role);
+        return Ok(());
+    }
 
-    http::serve(config).await?;
+    tracing::info!(address = %config.bind_address, port = config.port, "starting server");
+    http::serve(config, storage, key_provider).await?;
 
     Ok(())
 }
@@ -0,0 +1,456 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use serde::Serialize;
+use sqlx::{
+    database::Database,
+    error::BoxDynError,
+    types::chrono::{DateTime, Utc},
+    Decode, Executor, FromRow, Postgres, Type,
+};
+use utoipa::ToSchema;
+
+/// Stored as text so that new roles need no schema change beyond the check
+/// constraint.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(Role::User),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unknown role {}", other)),
+        }
+    }
+}
+
+impl<DB: Database> Type<DB> for Role
+where
+    String: Type<DB>,
+{
+    fn type_info() -> DB::TypeInfo {
+        <String as Type<DB>>::type_info()
+    }
+
+    fn compatible(ty: &DB::TypeInfo) -> bool {
+        <String as Type<DB>>::compatible(ty)
+    }
+}
+
+impl<'r, DB: Database> Decode<'r, DB> for Role
+where
+    String: Decode<'r, DB>,
+{
+    fn decode(value: <DB as Database>::ValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(String::decode(value)?.parse()?)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct Account {
+    pub id: i32,
+    pub username: String,
+    pub credential_id: Vec<u8>,
+    pub client_identity: Vec<u8>,
+    pub registration_record: Vec<u8>,
+    pub role: Role,
+    /// Locked accounts cannot log in
+    pub locked_at: Option<DateTime<Utc>>,
+    /// The OPAQUE server setup generation the record was registered under
+    pub opaque_key_id: i32,
+    /// The KEK `wrapped_dek` is wrapped with, `None` while the row is stored
+    /// in plaintext
+    pub kek_id: Option<i32>,
+    /// The data key encrypting the credential id, client identity and
+    /// registration record, see [`crate::controllers::envelope`]
+    pub wrapped_dek: Option<Vec<u8>>,
+    /// The OPAQUE record of the recovery code, encrypted like the
+    /// registration record. The recovery columns are set together or not at
+    /// all, see [`RecoveryKey`]
+    pub recovery_record: Option<Vec<u8>>,
+    pub recovery_opaque_key_id: Option<i32>,
+    pub recovery_public_key: Option<Vec<u8>>,
+    pub wrapped_master_key: Option<Vec<u8>>,
+    /// The client's master key wrapped with a key derived from the
+    /// password's export key, `None` for accounts whose vault keys are
+    /// derived from the export key itself
+    pub password_wrapped_master_key: Option<Vec<u8>>,
+}
+
+pub struct NewAccount<'a> {
+    pub username: &'a str,
+    pub credential_id: &'a [u8],
+    pub client_identity: &'a [u8],
+    pub registration_record: &'a [u8],
+    pub opaque_key_id: i32,
+    pub kek_id: Option<i32>,
+    pub wrapped_dek: Option<&'a [u8]>,
+    pub password_wrapped_master_key: Option<&'a [u8]>,
+}
+
+/// The encrypted columns of a row that was stored in plaintext until now.
+pub struct AccountEncryption<'a> {
+    pub kek_id: i32,
+    pub wrapped_dek: &'a [u8],
+    pub credential_id: &'a [u8],
+    pub client_identity: &'a [u8],
+    pub registration_record: &'a [u8],
+    pub recovery_record: Option<&'a [u8]>,
+}
+
+/// An opt-in second credential. The client registers a recovery code with
+/// OPAQUE and wraps its master key to a key pair derived from the code's
+/// export key, so the server can store the wrapped key but never open it.
+pub struct RecoveryKey<'a> {
+    pub registration_record: &'a [u8],
+    pub opaque_key_id: i32,
+    pub public_key: &'a [u8],
+    pub wrapped_master_key: &'a [u8],
+}
+
+pub struct AccountRepository<A> {
+    db: A,
+}
+
+impl<'c, A> AccountRepository<A>
+where
+    A: Executor<'c, Database = Postgres>,
+{
+    pub(super) fn new(db: A) -> Self {
+        Self { db }
+    }
+
+    pub async fn create(self, account: NewAccount<'_>) -> sqlx::Result<Account> {
+        sqlx::query_as!(
+            Account,
+            r#"
+            insert into account (username, credential_id, client_identity, registration_record,
+                opaque_key_id, kek_id, wrapped_dek, password_wrapped_master_key)
+            values ($1, $2, $3, $4, $5, $6, $7, $8)
+            returning id, username, credential_id, client_identity, registration_record,
+                role as "role: Role", locked_at, opaque_key_id, kek_id, wrapped_dek, recovery_record,
+                recovery_opaque_key_id, recovery_public_key, wrapped_master_key, password_wrapped_master_key
+            "#,
+            account.username,
+            account.credential_id,
+            account.client_identity,
+            account.registration_record,
+            account.opaque_key_id,
+            account.kek_id,
+            account.wrapped_dek,
+            account.password_wrapped_master_key,
+        )
+        .fetch_one(self.db)
+        .await
+    }
+
+    pub async fn find_by_username(self, username: &str) -> sqlx::Result<Option<Account>> {
+        sqlx::query_as!(
+            Account,
+            r#"
+            select id, username, credential_id, client_identity, registration_record,
+                role as "role: Role", locked_at, opaque_key_id, kek_id, wrapped_dek, recovery_record,
+                recovery_opaque_key_id, recovery_public_key, wrapped_master_key, password_wrapped_master_key
+            from account
+            where username = $1
+            "#,
+            username,
+        )
+        .fetch_optional(self.db)
+        .await
+    }
+
+    pub async fn find_by_id(self, account_id: i32) -> sqlx::Result<Option<Account>> {
+        sqlx::query_as!(
+            Account,
+            r#"
+            select id, username, credential_id, client_identity, registration_record,
+                role as "role: Role", locked_at, opaque_key_id, kek_id, wrapped_dek, recovery_record,
+                recovery_opaque_key_id, recovery_public_key, wrapped_master_key, password_wrapped_master_key
+            from account
+            where id = $1
+            "#,
+            account_id,
+        )
+        .fetch_optional(self.db)
+        .await
+    }
+
+    /// Accounts whose username contains `username`, in id order. `after` is
+    /// the id of the last account of the previous page.
+    pub async fn search(
+        self,
+        username: Option<&str>,
+        after: Option<i32>,
+        limit: i64,
+    ) -> sqlx::Result<Vec<Account>> {
+        sqlx::query_as!(
+            Account,
+            r#"
+            select id, username, credential_id, client_identity, registration_record,
+                role as "role: Role", locked_at, opaque_key_id, kek_id, wrapped_dek, recovery_record,
+                recovery_opaque_key_id, recovery_public_key, wrapped_master_key, password_wrapped_master_key
+            from account
+            where ($1::text is null or strpos(username, $1) > 0)
+                and ($2::integer is null or id > $2)
+            order by id
+            limit $3
+            "#,
+            username,
+            after,
+            limit,
+        )
+        .fetch_all(self.db)
+        .await
+    }
+
+    /// Fails with [`sqlx::Error::RowNotFound`] if the account does not exist.
+    pub async fn set_role(self, account_id: i32, role: Role) -> sqlx::Result<()> {
+        let result = sqlx::query!(
+            "update account set role = $2 where id = $1",
+            account_id,
+            role.as_str(),
+        )
+        .execute(self.db)
+        .await?;
+
+        match result.rows_affected() {
+            0 => Err(sqlx::Error::RowNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Locks the account at `locked_at`, or unlocks it with `None`. Fails
+    /// with [`sqlx::Error::RowNotFound`] if the account does not exist.
+    pub async fn set_locked_at(
+        self,
+        account_id: i32,
+        locked_at: Option<DateTime<Utc>>,
+    ) -> sqlx::Result<()> {
+        let result = sqlx::query!(
+            "update account set locked_at = $2 where id = $1",
+            account_id,
+            locked_at,
+        )
+        .execute(self.db)
+        .await?;
+
+        match result.rows_affected() {
+            0 => Err(sqlx::Error::RowNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replaces the OPAQUE registration record, e.g. after a password change.
+    /// Fails with [`sqlx::Error::RowNotFound`] if the account does not exist.
+    pub async fn update_registration_record(
+        self,
+        account_id: i32,
+        registration_record: &[u8],
+        opaque_key_id: i32,
+    ) -> sqlx::Result<()> {
+        let result = sqlx::query!(
+            "update account set registration_record = $2, opaque_key_id = $3 where id = $1",
+            account_id,
+            registration_record,
+            opaque_key_id,
+        )
+        .execute(self.db)
+        .await?;
+
+        match result.rows_affected() {
+            0 => Err(sqlx::Error::RowNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets or replaces the recovery key. Fails with
+    /// [`sqlx::Error::RowNotFound`] if the account does not exist.
+    pub async fn set_recovery_key(
+        self,
+        account_id: i32,
+        recovery: RecoveryKey<'_>,
+    ) -> sqlx::Result<()> {
+        let result = sqlx::query!(
+            r#"
+            update account
+            set recovery_record = $2, recovery_opaque_key_id = $3, recovery_public_key = $4,
+                wrapped_master_key = $5
+            where id = $1
+            "#,
+            account_id,
+            recovery.registration_record,
+            recovery.opaque_key_id,
+            recovery.public_key,
+            recovery.wrapped_master_key,
+        )
+        .execute(self.db)
+        .await?;
+
+        match result.rows_affected() {
+            0 => Err(sqlx::Error::RowNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replaces the master key wrapped with the password's export key, or
+    /// clears it with `None`. Fails with [`sqlx::Error::RowNotFound`] if the
+    /// account does not exist.
+    pub async fn set_password_wrapped_master_key(
+        self,
+        account_id: i32,
+        password_wrapped_master_key: Option<&[u8]>,
+    ) -> sqlx::Result<()> {
+        let result = sqlx::query!(
+            "update account set password_wrapped_master_key = $2 where id = $1",
+            account_id,
+            password_wrapped_master_key,
+        )
+        .execute(self.db)
+        .await?;
+
+        match result.rows_affected() {
+            0 => Err(sqlx::Error::RowNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns false if the account has no recovery key.
+    pub async fn remove_recovery_key(self, account_id: i32) -> sqlx::Result<bool> {
+        let result = sqlx::query!(
+            r#"
+            update account
+            set recovery_record = null, recovery_opaque_key_id = null,
+                recovery_public_key = null, wrapped_master_key = null
+            where id = $1 and recovery_record is not null
+            "#,
+            account_id,
+        )
+        .execute(self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Stores the encrypted columns of a plaintext row. Returns false if the
+    /// account is gone or already encrypted.
+    pub async fn encrypt(
+        self,
+        account_id: i32,
+        encryption: AccountEncryption<'_>,
+    ) -> sqlx::Result<bool> {
+        let result = sqlx::query!(
+            r#"
+            update account
+            set kek_id = $2, wrapped_dek = $3, credential_id = $4, client_identity = $5,
+                registration_record = $6, recovery_record = $7
+            where id = $1 and kek_id is null
+            "#,
+            account_id,
+            encryption.kek_id,
+            encryption.wrapped_dek,
+            encryption.credential_id,
+            encryption.client_identity,
+            encryption.registration_record,
+            encryption.recovery_record,
+        )
+        .execute(self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Replaces the wrapped data key if it is still wrapped with
+    /// `from_kek_id`. Returns false otherwise.
+    pub async fn rewrap_key(
+        self,
+        account_id: i32,
+        from_kek_id: i32,
+        to_kek_id: i32,
+        wrapped_dek: &[u8],
+    ) -> sqlx::Result<bool> {
+        let result = sqlx::query!(
+            "update account set kek_id = $3, wrapped_dek = $4 where id = $1 and kek_id = $2",
+            account_id,
+            from_kek_id,
+            to_kek_id,
+            wrapped_dek,
+        )
+        .execute(self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Accounts that are stored in plaintext or under another KEK than
+    /// `kek_id`, in id order.
+    pub async fn list_not_under_kek(self, kek_id: i32, limit: i64) -> sqlx::Result<Vec<Account>> {
+        sqlx::query_as!(
+            Account,
+            r#"
+            select id, username, credential_id, client_identity, registration_record,
+                role as "role: Role", locked_at, opaque_key_id, kek_id, wrapped_dek, recovery_record,
+                recovery_opaque_key_id, recovery_public_key, wrapped_master_key, password_wrapped_master_key
+            from account
+            where kek_id is distinct from $1
+            order by id
+            limit $2
+            "#,
+            kek_id,
+            limit,
+        )
+        .fetch_all(self.db)
+        .await
+    }
+
+    /// How many accounts depend on each OPAQUE server setup generation, by
+    /// key id. Recovery records count too.
+    pub async fn count_by_opaque_key_id(self) -> sqlx::Result<Vec<(i32, i64)>> {
+        let rows = sqlx::query!(
+            r#"
+            select key_id as "key_id!", count(distinct id) as "accounts!"
+            from (
+                select id, opaque_key_id as key_id from account
+                union all
+                select id, recovery_opaque_key_id from account
+                where recovery_opaque_key_id is not null
+            ) as records
+            group by key_id
+            order by key_id
+            "#,
+        )
+        .fetch_all(self.db)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.key_id, row.accounts))
+            .collect())
+    }
+
+    pub async fn delete(self, account_id: i32) -> sqlx::Result<bool> {
+        let result = sqlx::query!("delete from account where id = $1", account_id)
+            .execute(self.db)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,236 @@
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::{
+    types::chrono::{DateTime, Utc},
+    Executor, FromRow, Postgres,
+};
+
+/// `prev_hash` of the first event in the chain.
+pub const GENESIS_HASH: [u8; 32] = [0; 32];
+
+/// Key of the advisory lock that serializes appends to the chain.
+const CHAIN_LOCK_KEY: i64 = 0x6175_6469_7400;
+
+#[derive(Clone, Debug, PartialEq, FromRow)]
+pub struct AuditEvent {
+    pub id: i64,
+    /// `None` once the account has been deleted
+    pub account_id: Option<i32>,
+    pub username: Option<String>,
+    pub action: String,
+    pub detail: Value,
+    pub created_at: DateTime<Utc>,
+    pub prev_hash: Vec<u8>,
+    pub hash: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct NewAuditEvent<'a> {
+    pub account_id: Option<i32>,
+    pub username: Option<&'a str>,
+    pub action: &'a str,
+    pub detail: &'a Value,
+}
+
+/// SHA-256 over `prev_hash` and the length-prefixed event fields. The
+/// account id is left out because deleting the account clears it.
+pub fn chain_hash(
+    prev_hash: &[u8],
+    username: Option<&str>,
+    action: &str,
+    detail: &Value,
+    created_at: DateTime<Utc>,
+) -> Vec<u8> {
+    fn field(hasher: &mut Sha256, bytes: &[u8]) {
+        hasher.update((bytes.len() as u64).to_be_bytes());
+        hasher.update(bytes);
+    }
+
+    let mut hasher = Sha256::new();
+    field(&mut hasher, prev_hash);
+    match username {
+        Some(username) => {
+            hasher.update([1]);
+            field(&mut hasher, username.as_bytes());
+        }
+        None => hasher.update([0]),
+    }
+    field(&mut hasher, action.as_bytes());
+    field(&mut hasher, detail.to_string().as_bytes());
+    hasher.update(created_at.timestamp().to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+impl AuditEvent {
+    /// Recomputes the hash from the stored fields.
+    pub fn expected_hash(&self) -> Vec<u8> {
+        chain_hash(
+            &self.prev_hash,
+            self.username.as_deref(),
+            &self.action,
+            &self.detail,
+            self.created_at,
+        )
+    }
+}
+
+pub struct AuditRepository<A> {
+    db: A,
+}
+
+impl<'c, A> AuditRepository<A>
+where
+    A: Executor<'c, Database = Postgres>,
+{
+    pub(super) fn new(db: A) -> Self {
+        Self { db }
+    }
+
+    /// Blocks other appends until the transaction ends. Only meaningful
+    /// inside a [`super::UnitOfWork`].
+    pub async fn lock_chain(self) -> sqlx::Result<()> {
+        sqlx::query!("select pg_advisory_xact_lock($1)", CHAIN_LOCK_KEY)
+            .execute(self.db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn latest_hash(self) -> sqlx::Result<Option<Vec<u8>>> {
+        let row = sqlx::query!("select hash from audit_event order by id desc limit 1")
+            .fetch_optional(self.db)
+            .await?;
+        Ok(row.map(|row| row.hash))
+    }
+
+    /// Inserts an event whose hashes were computed by the caller, see
+    /// [`super::UnitOfWork::record_audit_event`].
+    pub async fn insert(
+        self,
+        event: NewAuditEvent<'_>,
+        created_at: DateTime<Utc>,
+        prev_hash: &[u8],
+        hash: &[u8],
+    ) -> sqlx::Result<AuditEvent> {
+        sqlx::query_as!(
+            AuditEvent,
+            r#"
+            insert into audit_event (account_id, username, action, detail, created_at, prev_hash, hash)
+            values ($1, $2, $3, $4, $5, $6, $7)
+            returning id, account_id, username, action, detail, created_at, prev_hash, hash
+            "#,
+            event.account_id,
+            event.username,
+            event.action,
+            event.detail,
+            created_at,
+            prev_hash,
+            hash,
+        )
+        .fetch_one(self.db)
+        .await
+    }
+
+    /// The events of an account, newest first. `before` is the id of the
+    /// last event of the previous page.
+    pub async fn list_for_account(
+        self,
+        account_id: i32,
+        before: Option<i64>,
+        limit: i64,
+    ) -> sqlx::Result<Vec<AuditEvent>> {
+        sqlx::query_as!(
+            AuditEvent,
+            r#"
+            select id, account_id, username, action, detail, created_at, prev_hash, hash
+            from audit_event
+            where account_id = $1 and ($2::bigint is null or id < $2)
+            order by id desc
+            limit $3
+            "#,
+            account_id,
+            before,
+            limit,
+        )
+        .fetch_all(self.db)
+        .await
+    }
+
+    /// Every event, newest first.
+    pub async fn list_all(self, before: Option<i64>, limit: i64) -> sqlx::Result<Vec<AuditEvent>> {
+        sqlx::query_as!(
+            AuditEvent,
+            r#"
+            select id, account_id, username, action, detail, created_at, prev_hash, hash
+            from audit_event
+            where $1::bigint is null or id < $1
+            order by id desc
+            limit $2
+            "#,
+            before,
+            limit,
+        )
+        .fetch_all(self.db)
+        .await
+    }
+
+    /// Events in chain order, for verifying the chain page by page.
+    pub async fn list_after(self, after: i64, limit: i64) -> sqlx::Result<Vec<AuditEvent>> {
+        sqlx::query_as!(
+            AuditEvent,
+            r#"
+            select id, account_id, username, action, detail, created_at, prev_hash, hash
+            from audit_event
+            where id > $1
+            order by id
+            limit $2
+            "#,
+            after,
+            limit,
+        )
+        .fetch_all(self.db)
+        .await
+    }
+
+    /// Events without a hash, in id order, see
+    /// [`super::Models::chain_legacy_audit_events`].
+    pub async fn list_unchained(self) -> sqlx::Result<Vec<AuditEvent>> {
+        sqlx::query_as!(
+            AuditEvent,
+            r#"
+            select id, account_id, username, action, detail, created_at, prev_hash, hash
+            from audit_event
+            where hash = ''
+            order by id
+            "#,
+        )
+        .fetch_all(self.db)
+        .await
+    }
+
+    pub async fn set_hashes(self, id: i64, prev_hash: &[u8], hash: &[u8]) -> sqlx::Result<()> {
+        sqlx::query!(
+            "update audit_event set prev_hash = $2, hash = $3 where id = $1",
+            id,
+            prev_hash,
+            hash,
+        )
+        .execute(self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes the oldest events up to the last one created before
+    /// `cutoff`, so that the rest of the chain stays intact.
+    pub async fn delete_before(self, cutoff: DateTime<Utc>) -> sqlx::Result<u64> {
+        let result = sqlx::query!(
+            r#"
+            delete from audit_event
+            where id <= (select max(id) from audit_event where created_at < $1)
+            "#,
+            cutoff,
+        )
+        .execute(self.db)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,110 @@
+use sqlx::{
+    types::{
+        chrono::{DateTime, Utc},
+        Uuid,
+    },
+    Executor, FromRow, Postgres,
+};
+
+/// A client-encrypted blob. The server never sees its plaintext.
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct Item {
+    pub id: Uuid,
+    pub account_id: i32,
+    pub ciphertext: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Every query is scoped to an account, so one account can never read or
+/// modify another's items by guessing ids.
+pub struct ItemRepository<A> {
+    db: A,
+}
+
+impl<'c, A> ItemRepository<A>
+where
+    A: Executor<'c, Database = Postgres>,
+{
+    pub(super) fn new(db: A) -> Self {
+        Self { db }
+    }
+
+    pub async fn create(self, account_id: i32, ciphertext: &[u8]) -> sqlx::Result<Item> {
+        sqlx::query_as!(
+            Item,
+            r#"
+            insert into item (account_id, ciphertext)
+            values ($1, $2)
+            returning id, account_id, ciphertext, created_at, updated_at
+            "#,
+            account_id,
+            ciphertext,
+        )
+        .fetch_one(self.db)
+        .await
+    }
+
+    pub async fn find(self, account_id: i32, id: Uuid) -> sqlx::Result<Option<Item>> {
+        sqlx::query_as!(
+            Item,
+            r#"
+            select id, account_id, ciphertext, created_at, updated_at
+            from item
+            where account_id = $1 and id = $2
+            "#,
+            account_id,
+            id,
+        )
+        .fetch_optional(self.db)
+        .await
+    }
+
+    pub async fn list_for_account(self, account_id: i32) -> sqlx::Result<Vec<Item>> {
+        sqlx::query_as!(
+            Item,
+            r#"
+            select id, account_id, ciphertext, created_at, updated_at
+            from item
+            where account_id = $1
+            order by created_at, id
+            "#,
+            account_id,
+        )
+        .fetch_all(self.db)
+        .await
+    }
+
+    /// Returns `None` if the account has no item with this id.
+    pub async fn update(
+        self,
+        account_id: i32,
+        id: Uuid,
+        ciphertext: &[u8],
+    ) -> sqlx::Result<Option<Item>> {
+        sqlx::query_as!(
+            Item,
+            r#"
+            update item set ciphertext = $3, updated_at = now()
+            where account_id = $1 and id = $2
+            returning id, account_id, ciphertext, created_at, updated_at
+            "#,
+            account_id,
+            id,
+            ciphertext,
+        )
+        .fetch_optional(self.db)
+        .await
+    }
+
+    pub async fn delete(self, account_id: i32, id: Uuid) -> sqlx::Result<bool> {
+        let result = sqlx::query!(
+            "delete from item where account_id = $1 and id = $2",
+            account_id,
+            id,
+        )
+        .execute(self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
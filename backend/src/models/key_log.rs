@@ -0,0 +1,95 @@
+use sqlx::{
+    types::chrono::{DateTime, Utc},
+    Executor, FromRow, Postgres,
+};
+
+/// A leaf of the key transparency log. Entries are never updated or deleted,
+/// not even when the account is.
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct KeyLogEntry {
+    pub leaf_index: i64,
+    pub username: String,
+    /// The exact bytes hashed into the Merkle tree
+    pub leaf: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct KeyLogRepository<A> {
+    db: A,
+}
+
+impl<'c, A> KeyLogRepository<A>
+where
+    A: Executor<'c, Database = Postgres>,
+{
+    pub(super) fn new(db: A) -> Self {
+        Self { db }
+    }
+
+    /// Fails with a unique violation if `leaf_index` is already taken.
+    pub async fn append(
+        self,
+        leaf_index: i64,
+        username: &str,
+        leaf: &[u8],
+    ) -> sqlx::Result<KeyLogEntry> {
+        sqlx::query_as!(
+            KeyLogEntry,
+            r#"
+            insert into key_log_entry (leaf_index, username, leaf)
+            values ($1, $2, $3)
+            returning leaf_index, username, leaf, created_at
+            "#,
+            leaf_index,
+            username,
+            leaf,
+        )
+        .fetch_one(self.db)
+        .await
+    }
+
+    /// Every entry in log order, for rebuilding the tree on startup.
+    pub async fn list_all(self) -> sqlx::Result<Vec<KeyLogEntry>> {
+        sqlx::query_as!(
+            KeyLogEntry,
+            r#"
+            select leaf_index, username, leaf, created_at
+            from key_log_entry
+            order by leaf_index
+            "#,
+        )
+        .fetch_all(self.db)
+        .await
+    }
+
+    pub async fn list_for_username(self, username: &str) -> sqlx::Result<Vec<KeyLogEntry>> {
+        sqlx::query_as!(
+            KeyLogEntry,
+            r#"
+            select leaf_index, username, leaf, created_at
+            from key_log_entry
+            where username = $1
+            order by leaf_index
+            "#,
+            username,
+        )
+        .fetch_all(self.db)
+        .await
+    }
+
+    pub async fn latest_for_username(self, username: &str) -> sqlx::Result<Option<KeyLogEntry>> {
+        sqlx::query_as!(
+            KeyLogEntry,
+            r#"
+            select leaf_index, username, leaf, created_at
+            from key_log_entry
+            where username = $1
+            order by leaf_index desc
+            limit 1
+            "#,
+            username,
+        )
+        .fetch_optional(self.db)
+        .await
+    }
+}
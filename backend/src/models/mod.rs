@@ -1,7 +1,195 @@
-pub struct Models {}
+use serde_json::json;
+use sqlx::{types::chrono::Utc, PgConnection, PgPool, Postgres, Transaction};
+
+pub mod accounts;
+pub mod audit;
+pub mod items;
+pub mod key_log;
+pub mod sessions;
+
+use accounts::AccountRepository;
+use audit::{AuditEvent, AuditRepository, NewAuditEvent, GENESIS_HASH};
+use items::ItemRepository;
+use key_log::KeyLogRepository;
+use sessions::SessionRepository;
+
+use crate::controllers::audit::AuditAction;
+
+/// Owns the connection pool and hands out repositories. Repositories taken
+/// from `Models` run each query on its own pooled connection; those taken from
+/// a [`UnitOfWork`] share its transaction.
+#[derive(Clone)]
+pub struct Models {
+    pool: PgPool,
+}
 
 impl Models {
-    pub fn new(pool: sqlx::PgPool) -> Self {
-        Self {}
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub fn accounts(&self) -> AccountRepository<&PgPool> {
+        AccountRepository::new(&self.pool)
+    }
+
+    pub fn sessions(&self) -> SessionRepository<&PgPool> {
+        SessionRepository::new(&self.pool)
+    }
+
+    pub fn items(&self) -> ItemRepository<&PgPool> {
+        ItemRepository::new(&self.pool)
+    }
+
+    pub fn audit(&self) -> AuditRepository<&PgPool> {
+        AuditRepository::new(&self.pool)
+    }
+
+    pub fn key_log(&self) -> KeyLogRepository<&PgPool> {
+        KeyLogRepository::new(&self.pool)
+    }
+
+    pub async fn begin(&self) -> sqlx::Result<UnitOfWork> {
+        Ok(UnitOfWork {
+            tx: self.pool.begin().await?,
+        })
+    }
+
+    /// Stores the new registration record and wrapped master key, revokes
+    /// every session of the account but the one the change was made from and
+    /// records the change, all or nothing. Returns how many sessions were
+    /// revoked.
+    pub async fn change_password(
+        &self,
+        account_id: i32,
+        registration_record: &[u8],
+        opaque_key_id: i32,
+        password_wrapped_master_key: Option<&[u8]>,
+        keep_session_id_hash: &[u8],
+    ) -> sqlx::Result<u64> {
+        let mut uow = self.begin().await?;
+
+        let account = uow
+            .accounts()
+            .find_by_id(account_id)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?;
+        uow.accounts()
+            .update_registration_record(account_id, registration_record, opaque_key_id)
+            .await?;
+        uow.accounts()
+            .set_password_wrapped_master_key(account_id, password_wrapped_master_key)
+            .await?;
+        let revoked = uow
+            .sessions()
+            .revoke_others_for_account(account_id, keep_session_id_hash)
+            .await?;
+        uow.record_audit_event(NewAuditEvent {
+            account_id: Some(account_id),
+            username: Some(&account.username),
+            action: AuditAction::PasswordChanged.as_str(),
+            detail: &json!({ "sessions_revoked": revoked }),
+        })
+        .await?;
+
+        uow.commit().await?;
+        Ok(revoked)
+    }
+
+    /// Appends an event to the audit chain in a transaction of its own.
+    pub async fn record_audit_event(&self, event: NewAuditEvent<'_>) -> sqlx::Result<AuditEvent> {
+        let mut uow = self.begin().await?;
+        let event = uow.record_audit_event(event).await?;
+        uow.commit().await?;
+        Ok(event)
+    }
+
+    /// Chains the events recorded before the audit chain existed, which the
+    /// migration left with an empty hash. They are the oldest events, so
+    /// the first of them starts from the genesis hash. Returns how many
+    /// events were chained.
+    pub async fn chain_legacy_audit_events(&self) -> sqlx::Result<u64> {
+        let mut uow = self.begin().await?;
+        uow.audit().lock_chain().await?;
+        let events = uow.audit().list_unchained().await?;
+        let mut prev_hash = GENESIS_HASH.to_vec();
+        for event in &events {
+            let hash = audit::chain_hash(
+                &prev_hash,
+                event.username.as_deref(),
+                &event.action,
+                &event.detail,
+                event.created_at,
+            );
+            uow.audit().set_hashes(event.id, &prev_hash, &hash).await?;
+            prev_hash = hash;
+        }
+
+        uow.commit().await?;
+        Ok(events.len() as u64)
+    }
+}
+
+/// A database transaction. Dropping it without calling [`UnitOfWork::commit`]
+/// rolls back everything done through its repositories.
+pub struct UnitOfWork {
+    tx: Transaction<'static, Postgres>,
+}
+
+impl UnitOfWork {
+    pub fn accounts(&mut self) -> AccountRepository<&mut PgConnection> {
+        AccountRepository::new(&mut *self.tx)
+    }
+
+    pub fn sessions(&mut self) -> SessionRepository<&mut PgConnection> {
+        SessionRepository::new(&mut *self.tx)
+    }
+
+    pub fn items(&mut self) -> ItemRepository<&mut PgConnection> {
+        ItemRepository::new(&mut *self.tx)
+    }
+
+    pub fn audit(&mut self) -> AuditRepository<&mut PgConnection> {
+        AuditRepository::new(&mut *self.tx)
+    }
+
+    pub fn key_log(&mut self) -> KeyLogRepository<&mut PgConnection> {
+        KeyLogRepository::new(&mut *self.tx)
+    }
+
+    /// Appends an event to the audit chain. Other appends wait until this
+    /// unit of work ends, so the chain never forks.
+    pub async fn record_audit_event(
+        &mut self,
+        event: NewAuditEvent<'_>,
+    ) -> sqlx::Result<AuditEvent> {
+        self.audit().lock_chain().await?;
+        let prev_hash = self
+            .audit()
+            .latest_hash()
+            .await?
+            .unwrap_or(GENESIS_HASH.to_vec());
+        let created_at = Utc::now();
+        let hash = audit::chain_hash(
+            &prev_hash,
+            event.username,
+            event.action,
+            event.detail,
+            created_at,
+        );
+        self.audit()
+            .insert(event, created_at, &prev_hash, &hash)
+            .await
+    }
+
+    pub async fn commit(self) -> sqlx::Result<()> {
+        self.tx.commit().await
+    }
+
+    pub async fn rollback(self) -> sqlx::Result<()> {
+        self.tx.rollback().await
     }
 }
@@ -0,0 +1,117 @@
+use sqlx::{
+    types::chrono::{DateTime, Utc},
+    Executor, FromRow, Postgres,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct SessionRecord {
+    /// SHA-256 of the session id, so a database leak does not leak sessions
+    pub id_hash: Vec<u8>,
+    pub account_id: i32,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+pub struct SessionRepository<A> {
+    db: A,
+}
+
+impl<'c, A> SessionRepository<A>
+where
+    A: Executor<'c, Database = Postgres>,
+{
+    pub(super) fn new(db: A) -> Self {
+        Self { db }
+    }
+
+    pub async fn create(
+        self,
+        id_hash: &[u8],
+        account_id: i32,
+        expires_at: DateTime<Utc>,
+    ) -> sqlx::Result<SessionRecord> {
+        sqlx::query_as!(
+            SessionRecord,
+            r#"
+            insert into session (id_hash, account_id, expires_at)
+            values ($1, $2, $3)
+            returning id_hash, account_id, created_at, expires_at, revoked_at
+            "#,
+            id_hash,
+            account_id,
+            expires_at,
+        )
+        .fetch_one(self.db)
+        .await
+    }
+
+    /// Returns the session unless it has expired or been revoked.
+    pub async fn find_active(
+        self,
+        id_hash: &[u8],
+        now: DateTime<Utc>,
+    ) -> sqlx::Result<Option<SessionRecord>> {
+        sqlx::query_as!(
+            SessionRecord,
+            r#"
+            select id_hash, account_id, created_at, expires_at, revoked_at
+            from session
+            where id_hash = $1 and revoked_at is null and expires_at > $2
+            "#,
+            id_hash,
+            now,
+        )
+        .fetch_optional(self.db)
+        .await
+    }
+
+    pub async fn revoke(self, id_hash: &[u8]) -> sqlx::Result<bool> {
+        let result = sqlx::query!(
+            "update session set revoked_at = now() where id_hash = $1 and revoked_at is null",
+            id_hash,
+        )
+        .execute(self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revokes every active session of the account and returns how many there
+    /// were.
+    pub async fn revoke_all_for_account(self, account_id: i32) -> sqlx::Result<u64> {
+        let result = sqlx::query!(
+            "update session set revoked_at = now() where account_id = $1 and revoked_at is null",
+            account_id,
+        )
+        .execute(self.db)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Revokes every active session of the account except `keep_id_hash`
+    /// and returns how many there were.
+    pub async fn revoke_others_for_account(
+        self,
+        account_id: i32,
+        keep_id_hash: &[u8],
+    ) -> sqlx::Result<u64> {
+        let result = sqlx::query!(
+            r#"
+            update session set revoked_at = now()
+            where account_id = $1 and id_hash <> $2 and revoked_at is null
+            "#,
+            account_id,
+            keep_id_hash,
+        )
+        .execute(self.db)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn delete_expired(self, now: DateTime<Utc>) -> sqlx::Result<u64> {
+        let result = sqlx::query!("delete from session where expires_at <= $1", now)
+            .execute(self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
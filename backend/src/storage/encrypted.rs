@@ -0,0 +1,713 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use opaque_ke::rand::rngs::OsRng;
+use sqlx::types::{
+    chrono::{DateTime, Utc},
+    Uuid,
+};
+
+use super::{
+    Account, AccountEncryption, AccountStore, AuditEvent, AuditStore, Item, ItemStore, KeyLogEntry,
+    KeyLogStore, NewAccount, NewAuditEvent, RecoveryKey, Role, SessionRecord, SessionStore,
+    Storage, StorageError, StorageResult,
+};
+use crate::controllers::envelope::{DataKey, EnvelopeError, KekRing};
+
+const CREDENTIAL_ID: &str = "credential_id";
+const CLIENT_IDENTITY: &str = "client_identity";
+const REGISTRATION_RECORD: &str = "registration_record";
+const RECOVERY_RECORD: &str = "recovery_record";
+
+impl From<EnvelopeError> for StorageError {
+    fn from(err: EnvelopeError) -> Self {
+        StorageError::Backend(err.to_string())
+    }
+}
+
+/// Binds a wrapped data key to its account.
+fn data_key_aad(username: &str) -> Vec<u8> {
+    [b"data_key\0", username.as_bytes()].concat()
+}
+
+/// Binds an encrypted column to its account and column.
+fn column_aad(username: &str, column: &str) -> Vec<u8> {
+    [column.as_bytes(), b"\0", username.as_bytes()].concat()
+}
+
+/// Encrypts the credential id, client identity, registration record and
+/// recovery record of every account stored by another backend. Each account gets a data key of
+/// its own, wrapped with the current KEK of `keks`. Accounts stored in
+/// plaintext before encryption was turned on stay readable until
+/// [`Storage::reencrypt_accounts`] gets to them.
+pub struct EncryptedStorage {
+    inner: Arc<dyn Storage>,
+    keks: Arc<KekRing>,
+}
+
+impl EncryptedStorage {
+    pub fn new(inner: Arc<dyn Storage>, keks: Arc<KekRing>) -> Self {
+        Self { inner, keks }
+    }
+
+    pub fn inner(&self) -> &Arc<dyn Storage> {
+        &self.inner
+    }
+
+    fn seal(&self, data_key: &DataKey, username: &str, column: &str, plaintext: &[u8]) -> Vec<u8> {
+        data_key.seal(&column_aad(username, column), plaintext, &mut OsRng)
+    }
+
+    fn data_key(&self, account: &Account) -> StorageResult<Option<DataKey>> {
+        match (account.kek_id, &account.wrapped_dek) {
+            (Some(kek_id), Some(wrapped_dek)) => Ok(Some(self.keks.unwrap(
+                kek_id,
+                &data_key_aad(&account.username),
+                wrapped_dek,
+            )?)),
+            (None, _) => Ok(None),
+            (Some(_), None) => Err(StorageError::Backend(format!(
+                "account {} has a KEK id but no data key",
+                account.id
+            ))),
+        }
+    }
+
+    fn decrypt(&self, mut account: Account) -> StorageResult<Account> {
+        let Some(data_key) = self.data_key(&account)? else {
+            return Ok(account);
+        };
+        let open =
+            |column, sealed: &[u8]| data_key.open(&column_aad(&account.username, column), sealed);
+        let credential_id = open(CREDENTIAL_ID, &account.credential_id)?;
+        let client_identity = open(CLIENT_IDENTITY, &account.client_identity)?;
+        let registration_record = open(REGISTRATION_RECORD, &account.registration_record)?;
+        let recovery_record = account
+            .recovery_record
+            .as_deref()
+            .map(|sealed| open(RECOVERY_RECORD, sealed))
+            .transpose()?;
+        account.credential_id = credential_id;
+        account.client_identity = client_identity;
+        account.registration_record = registration_record;
+        account.recovery_record = recovery_record;
+        Ok(account)
+    }
+
+    /// Encrypts a plaintext account under a new data key. Returns `None` if
+    /// the account changed in the meantime.
+    async fn encrypt(&self, account: &Account) -> StorageResult<Option<DataKey>> {
+        let data_key = DataKey::generate(&mut OsRng);
+        let (kek_id, wrapped_dek) = self
+            .keks
+            .wrap(&data_key_aad(&account.username), &data_key)?;
+        let recovery_record = account
+            .recovery_record
+            .as_deref()
+            .map(|record| self.seal(&data_key, &account.username, RECOVERY_RECORD, record));
+        let encrypted = self
+            .inner
+            .encrypt_account(
+                account.id,
+                AccountEncryption {
+                    kek_id,
+                    wrapped_dek: &wrapped_dek,
+                    credential_id: &self.seal(
+                        &data_key,
+                        &account.username,
+                        CREDENTIAL_ID,
+                        &account.credential_id,
+                    ),
+                    client_identity: &self.seal(
+                        &data_key,
+                        &account.username,
+                        CLIENT_IDENTITY,
+                        &account.client_identity,
+                    ),
+                    registration_record: &self.seal(
+                        &data_key,
+                        &account.username,
+                        REGISTRATION_RECORD,
+                        &account.registration_record,
+                    ),
+                    recovery_record: recovery_record.as_deref(),
+                },
+            )
+            .await?;
+        Ok(encrypted.then_some(data_key))
+    }
+
+    /// The data key of an existing account, which is encrypted first if it
+    /// is still stored in plaintext.
+    async fn account_data_key(&self, account_id: i32) -> StorageResult<(String, DataKey)> {
+        loop {
+            let account = self
+                .inner
+                .find_account_by_id(account_id)
+                .await?
+                .ok_or(StorageError::NotFound)?;
+            let data_key = match self.data_key(&account)? {
+                Some(data_key) => Some(data_key),
+                None => self.encrypt(&account).await?,
+            };
+            if let Some(data_key) = data_key {
+                return Ok((account.username, data_key));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AccountStore for EncryptedStorage {
+    async fn create_account(&self, account: NewAccount<'_>) -> StorageResult<Account> {
+        let data_key = DataKey::generate(&mut OsRng);
+        let (kek_id, wrapped_dek) = self.keks.wrap(&data_key_aad(account.username), &data_key)?;
+        let created = self
+            .inner
+            .create_account(NewAccount {
+                username: account.username,
+                credential_id: &self.seal(
+                    &data_key,
+                    account.username,
+                    CREDENTIAL_ID,
+                    account.credential_id,
+                ),
+                client_identity: &self.seal(
+                    &data_key,
+                    account.username,
+                    CLIENT_IDENTITY,
+                    account.client_identity,
+                ),
+                registration_record: &self.seal(
+                    &data_key,
+                    account.username,
+                    REGISTRATION_RECORD,
+                    account.registration_record,
+                ),
+                opaque_key_id: account.opaque_key_id,
+                kek_id: Some(kek_id),
+                wrapped_dek: Some(&wrapped_dek),
+                // Encrypted by the client already
+                password_wrapped_master_key: account.password_wrapped_master_key,
+            })
+            .await?;
+        self.decrypt(created)
+    }
+
+    async fn find_account_by_username(&self, username: &str) -> StorageResult<Option<Account>> {
+        self.inner
+            .find_account_by_username(username)
+            .await?
+            .map(|account| self.decrypt(account))
+            .transpose()
+    }
+
+    async fn find_account_by_id(&self, account_id: i32) -> StorageResult<Option<Account>> {
+        self.inner
+            .find_account_by_id(account_id)
+            .await?
+            .map(|account| self.decrypt(account))
+            .transpose()
+    }
+
+    async fn search_accounts(
+        &self,
+        username: Option<&str>,
+        after: Option<i32>,
+        limit: u32,
+    ) -> StorageResult<Vec<Account>> {
+        self.inner
+            .search_accounts(username, after, limit)
+            .await?
+            .into_iter()
+            .map(|account| self.decrypt(account))
+            .collect()
+    }
+
+    async fn set_account_role(&self, account_id: i32, role: Role) -> StorageResult<()> {
+        self.inner.set_account_role(account_id, role).await
+    }
+
+    async fn set_account_locked(
+        &self,
+        account_id: i32,
+        locked_at: Option<DateTime<Utc>>,
+    ) -> StorageResult<()> {
+        self.inner.set_account_locked(account_id, locked_at).await
+    }
+
+    async fn update_registration_record(
+        &self,
+        account_id: i32,
+        registration_record: &[u8],
+        opaque_key_id: i32,
+    ) -> StorageResult<()> {
+        let (username, data_key) = self.account_data_key(account_id).await?;
+        let sealed = self.seal(
+            &data_key,
+            &username,
+            REGISTRATION_RECORD,
+            registration_record,
+        );
+        self.inner
+            .update_registration_record(account_id, &sealed, opaque_key_id)
+            .await
+    }
+
+    async fn delete_account(&self, account_id: i32) -> StorageResult<bool> {
+        self.inner.delete_account(account_id).await
+    }
+
+    async fn change_password(
+        &self,
+        account_id: i32,
+        registration_record: &[u8],
+        opaque_key_id: i32,
+        password_wrapped_master_key: Option<&[u8]>,
+        keep_session_id_hash: &[u8],
+    ) -> StorageResult<u64> {
+        let (username, data_key) = self.account_data_key(account_id).await?;
+        let sealed = self.seal(
+            &data_key,
+            &username,
+            REGISTRATION_RECORD,
+            registration_record,
+        );
+        self.inner
+            .change_password(
+                account_id,
+                &sealed,
+                opaque_key_id,
+                password_wrapped_master_key,
+                keep_session_id_hash,
+            )
+            .await
+    }
+
+    async fn set_recovery_key(
+        &self,
+        account_id: i32,
+        recovery: RecoveryKey<'_>,
+    ) -> StorageResult<()> {
+        let (username, data_key) = self.account_data_key(account_id).await?;
+        let sealed = self.seal(
+            &data_key,
+            &username,
+            RECOVERY_RECORD,
+            recovery.registration_record,
+        );
+        self.inner
+            .set_recovery_key(
+                account_id,
+                RecoveryKey {
+                    registration_record: &sealed,
+                    ..recovery
+                },
+            )
+            .await
+    }
+
+    async fn remove_recovery_key(&self, account_id: i32) -> StorageResult<bool> {
+        self.inner.remove_recovery_key(account_id).await
+    }
+
+    async fn count_accounts_by_opaque_key_id(&self) -> StorageResult<Vec<(i32, u64)>> {
+        self.inner.count_accounts_by_opaque_key_id().await
+    }
+
+    async fn encrypt_account(
+        &self,
+        account_id: i32,
+        encryption: AccountEncryption<'_>,
+    ) -> StorageResult<bool> {
+        self.inner.encrypt_account(account_id, encryption).await
+    }
+
+    async fn rewrap_account_key(
+        &self,
+        account_id: i32,
+        from_kek_id: i32,
+        to_kek_id: i32,
+        wrapped_dek: &[u8],
+    ) -> StorageResult<bool> {
+        self.inner
+            .rewrap_account_key(account_id, from_kek_id, to_kek_id, wrapped_dek)
+            .await
+    }
+
+    async fn list_accounts_not_under_kek(
+        &self,
+        kek_id: i32,
+        limit: u32,
+    ) -> StorageResult<Vec<Account>> {
+        self.inner.list_accounts_not_under_kek(kek_id, limit).await
+    }
+}
+
+#[async_trait]
+impl SessionStore for EncryptedStorage {
+    async fn create_session(
+        &self,
+        id_hash: &[u8],
+        account_id: i32,
+        expires_at: DateTime<Utc>,
+    ) -> StorageResult<SessionRecord> {
+        self.inner
+            .create_session(id_hash, account_id, expires_at)
+            .await
+    }
+
+    async fn find_active_session(
+        &self,
+        id_hash: &[u8],
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<SessionRecord>> {
+        self.inner.find_active_session(id_hash, now).await
+    }
+
+    async fn revoke_session(&self, id_hash: &[u8]) -> StorageResult<bool> {
+        self.inner.revoke_session(id_hash).await
+    }
+
+    async fn revoke_all_sessions(&self, account_id: i32) -> StorageResult<u64> {
+        self.inner.revoke_all_sessions(account_id).await
+    }
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> StorageResult<u64> {
+        self.inner.delete_expired_sessions(now).await
+    }
+}
+
+#[async_trait]
+impl ItemStore for EncryptedStorage {
+    async fn create_item(&self, account_id: i32, ciphertext: &[u8]) -> StorageResult<Item> {
+        self.inner.create_item(account_id, ciphertext).await
+    }
+
+    async fn find_item(&self, account_id: i32, id: Uuid) -> StorageResult<Option<Item>> {
+        self.inner.find_item(account_id, id).await
+    }
+
+    async fn list_items(&self, account_id: i32) -> StorageResult<Vec<Item>> {
+        self.inner.list_items(account_id).await
+    }
+
+    async fn update_item(
+        &self,
+        account_id: i32,
+        id: Uuid,
+        ciphertext: &[u8],
+    ) -> StorageResult<Option<Item>> {
+        self.inner.update_item(account_id, id, ciphertext).await
+    }
+
+    async fn delete_item(&self, account_id: i32, id: Uuid) -> StorageResult<bool> {
+        self.inner.delete_item(account_id, id).await
+    }
+}
+
+#[async_trait]
+impl KeyLogStore for EncryptedStorage {
+    async fn append_key_log_entry(
+        &self,
+        leaf_index: u64,
+        username: &str,
+        leaf: &[u8],
+    ) -> StorageResult<KeyLogEntry> {
+        self.inner
+            .append_key_log_entry(leaf_index, username, leaf)
+            .await
+    }
+
+    async fn list_key_log_entries(&self) -> StorageResult<Vec<KeyLogEntry>> {
+        self.inner.list_key_log_entries().await
+    }
+
+    async fn list_key_log_entries_for_user(
+        &self,
+        username: &str,
+    ) -> StorageResult<Vec<KeyLogEntry>> {
+        self.inner.list_key_log_entries_for_user(username).await
+    }
+
+    async fn latest_key_log_entry_for_user(
+        &self,
+        username: &str,
+    ) -> StorageResult<Option<KeyLogEntry>> {
+        self.inner.latest_key_log_entry_for_user(username).await
+    }
+}
+
+#[async_trait]
+impl AuditStore for EncryptedStorage {
+    async fn record_audit_event(&self, event: NewAuditEvent<'_>) -> StorageResult<AuditEvent> {
+        self.inner.record_audit_event(event).await
+    }
+
+    async fn list_audit_events(
+        &self,
+        account_id: Option<i32>,
+        before: Option<i64>,
+        limit: u32,
+    ) -> StorageResult<Vec<AuditEvent>> {
+        self.inner
+            .list_audit_events(account_id, before, limit)
+            .await
+    }
+
+    async fn list_audit_events_after(
+        &self,
+        after: i64,
+        limit: u32,
+    ) -> StorageResult<Vec<AuditEvent>> {
+        self.inner.list_audit_events_after(after, limit).await
+    }
+
+    async fn delete_audit_events_before(&self, cutoff: DateTime<Utc>) -> StorageResult<u64> {
+        self.inner.delete_audit_events_before(cutoff).await
+    }
+}
+
+#[async_trait]
+impl Storage for EncryptedStorage {
+    async fn ping(&self) -> StorageResult<()> {
+        self.inner.ping().await
+    }
+
+    fn record_metrics(&self) {
+        self.inner.record_metrics()
+    }
+
+    /// Plaintext accounts are encrypted, data keys under an old KEK are
+    /// rewrapped with the current one. Returns how many accounts were looked
+    /// at. Accounts changed concurrently are skipped, the next batch no
+    /// longer lists them.
+    async fn reencrypt_accounts(&self, limit: u32) -> StorageResult<u64> {
+        let current = self.keks.current_kek_id();
+        let accounts = self
+            .inner
+            .list_accounts_not_under_kek(current, limit)
+            .await?;
+        for account in &accounts {
+            let Some(data_key) = self.data_key(account)? else {
+                self.encrypt(account).await?;
+                continue;
+            };
+            let (kek_id, wrapped_dek) = self
+                .keks
+                .wrap(&data_key_aad(&account.username), &data_key)?;
+            if let Some(from_kek_id) = account.kek_id {
+                self.inner
+                    .rewrap_account_key(account.id, from_kek_id, kek_id, &wrapped_dek)
+                    .await?;
+            }
+        }
+        Ok(accounts.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+    use crate::controllers::key_provider::memory::MemoryKeyProvider;
+    use crate::storage::memory::MemoryStorage;
+
+    fn test_keks() -> KekRing {
+        KekRing::load(&MemoryKeyProvider::default()).unwrap()
+    }
+
+    fn new_account(username: &str) -> NewAccount<'_> {
+        NewAccount {
+            username,
+            credential_id: username.as_bytes(),
+            client_identity: b"identity",
+            registration_record: b"record",
+            opaque_key_id: 1,
+            kek_id: None,
+            wrapped_dek: None,
+            password_wrapped_master_key: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn stored_columns_are_encrypted() {
+        let inner: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let storage = EncryptedStorage::new(inner.clone(), Arc::new(test_keks()));
+
+        let created = storage.create_account(new_account("alice")).await.unwrap();
+        assert_eq!(created.registration_record, b"record");
+
+        let stored = inner.find_account_by_id(created.id).await.unwrap().unwrap();
+        assert_eq!(stored.kek_id, Some(1));
+        assert_ne!(stored.credential_id, b"alice");
+        assert_ne!(stored.client_identity, b"identity");
+        assert_ne!(stored.registration_record, b"record");
+
+        storage
+            .change_password(created.id, b"new record", 2, None, b"session")
+            .await
+            .unwrap();
+        let stored = inner.find_account_by_id(created.id).await.unwrap().unwrap();
+        assert_ne!(stored.registration_record, b"new record");
+        let found = storage
+            .find_account_by_username("alice")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.credential_id, b"alice");
+        assert_eq!(found.client_identity, b"identity");
+        assert_eq!(found.registration_record, b"new record");
+        assert_eq!(found.opaque_key_id, 2);
+    }
+
+    #[tokio::test]
+    async fn swapped_records_do_not_decrypt() {
+        let inner: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let storage = EncryptedStorage::new(inner.clone(), Arc::new(test_keks()));
+        let alice = storage.create_account(new_account("alice")).await.unwrap();
+        let bob = storage.create_account(new_account("bob")).await.unwrap();
+
+        let stored = inner.find_account_by_id(alice.id).await.unwrap().unwrap();
+        inner
+            .update_registration_record(bob.id, &stored.registration_record, 1)
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage.find_account_by_id(bob.id).await,
+            Err(StorageError::Backend(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn plaintext_accounts_are_encrypted_in_the_background() {
+        let inner: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let legacy = inner.create_account(new_account("alice")).await.unwrap();
+        inner.create_account(new_account("bob")).await.unwrap();
+        let storage = EncryptedStorage::new(inner.clone(), Arc::new(test_keks()));
+
+        // Plaintext rows stay readable until they are encrypted
+        let found = storage
+            .find_account_by_id(legacy.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.registration_record, b"record");
+
+        assert_eq!(storage.reencrypt_accounts(1).await.unwrap(), 1);
+        assert_eq!(storage.reencrypt_accounts(10).await.unwrap(), 1);
+        assert_eq!(storage.reencrypt_accounts(10).await.unwrap(), 0);
+
+        for account in inner.search_accounts(None, None, 10).await.unwrap() {
+            assert_eq!(account.kek_id, Some(1));
+            assert_ne!(account.registration_record, b"record");
+        }
+        let found = storage
+            .find_account_by_id(legacy.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.registration_record, b"record");
+    }
+
+    #[tokio::test]
+    async fn password_change_encrypts_plaintext_account() {
+        let inner: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let legacy = inner.create_account(new_account("alice")).await.unwrap();
+        let storage = EncryptedStorage::new(inner.clone(), Arc::new(test_keks()));
+
+        storage
+            .change_password(legacy.id, b"new record", 1, None, b"session")
+            .await
+            .unwrap();
+        let stored = inner.find_account_by_id(legacy.id).await.unwrap().unwrap();
+        assert_eq!(stored.kek_id, Some(1));
+        assert_ne!(stored.credential_id, b"alice");
+        let found = storage
+            .find_account_by_id(legacy.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.credential_id, b"alice");
+        assert_eq!(found.registration_record, b"new record");
+    }
+
+    fn recovery_key(record: &[u8]) -> RecoveryKey<'_> {
+        RecoveryKey {
+            registration_record: record,
+            opaque_key_id: 1,
+            public_key: b"public key",
+            wrapped_master_key: b"wrapped",
+        }
+    }
+
+    #[tokio::test]
+    async fn recovery_records_are_encrypted() {
+        let inner: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let legacy = inner.create_account(new_account("alice")).await.unwrap();
+        inner
+            .set_recovery_key(legacy.id, recovery_key(b"old recovery"))
+            .await
+            .unwrap();
+        let storage = EncryptedStorage::new(inner.clone(), Arc::new(test_keks()));
+
+        // Sealed along with the other columns when the account is encrypted
+        assert_eq!(storage.reencrypt_accounts(10).await.unwrap(), 1);
+        let stored = inner.find_account_by_id(legacy.id).await.unwrap().unwrap();
+        assert_ne!(
+            stored.recovery_record.as_deref(),
+            Some(&b"old recovery"[..])
+        );
+        let found = storage
+            .find_account_by_id(legacy.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.recovery_record.as_deref(), Some(&b"old recovery"[..]));
+
+        storage
+            .set_recovery_key(legacy.id, recovery_key(b"new recovery"))
+            .await
+            .unwrap();
+        let stored = inner.find_account_by_id(legacy.id).await.unwrap().unwrap();
+        assert_ne!(
+            stored.recovery_record.as_deref(),
+            Some(&b"new recovery"[..])
+        );
+        assert_eq!(stored.wrapped_master_key.as_deref(), Some(&b"wrapped"[..]));
+        let found = storage
+            .find_account_by_id(legacy.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.recovery_record.as_deref(), Some(&b"new recovery"[..]));
+    }
+
+    #[tokio::test]
+    async fn kek_rotation_rewraps_data_keys() {
+        let inner: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+        let provider = MemoryKeyProvider::default();
+        let mut keks = KekRing::load(&provider).unwrap();
+        let old = EncryptedStorage::new(inner.clone(), Arc::new(keks.clone()));
+        let alice = old.create_account(new_account("alice")).await.unwrap();
+        let before = inner.find_account_by_id(alice.id).await.unwrap().unwrap();
+
+        keks.rotate(&provider).unwrap();
+        let storage = EncryptedStorage::new(inner.clone(), Arc::new(keks));
+        assert_eq!(storage.reencrypt_accounts(10).await.unwrap(), 1);
+        assert_eq!(storage.reencrypt_accounts(10).await.unwrap(), 0);
+
+        let after = inner.find_account_by_id(alice.id).await.unwrap().unwrap();
+        assert_eq!(after.kek_id, Some(2));
+        assert_ne!(after.wrapped_dek, before.wrapped_dek);
+        // Only the data key is rewrapped, the columns stay as they are
+        assert_eq!(after.registration_record, before.registration_record);
+        let found = storage.find_account_by_id(alice.id).await.unwrap().unwrap();
+        assert_eq!(found.registration_record, b"record");
+
+        // A ring without the new KEK cannot read the account any more
+        assert!(old.find_account_by_id(alice.id).await.is_err());
+    }
+}
@@ -0,0 +1,596 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use serde_json::json;
+use sqlx::types::{
+    chrono::{DateTime, Utc},
+    Uuid,
+};
+
+use super::{
+    Account, AccountEncryption, AccountStore, AuditEvent, AuditStore, Item, ItemStore, KeyLogEntry,
+    KeyLogStore, NewAccount, NewAuditEvent, RecoveryKey, Role, SessionRecord, SessionStore,
+    Storage, StorageError, StorageResult,
+};
+use crate::controllers::audit::AuditAction;
+use crate::models::audit::{chain_hash, GENESIS_HASH};
+
+#[derive(Default)]
+struct State {
+    accounts: BTreeMap<i32, Account>,
+    next_account_id: i32,
+    sessions: HashMap<Vec<u8>, SessionRecord>,
+    items: HashMap<Uuid, Item>,
+    key_log: Vec<KeyLogEntry>,
+    audit_events: Vec<AuditEvent>,
+    next_audit_event_id: i64,
+}
+
+impl State {
+    fn revoke_all_sessions(&mut self, account_id: i32) -> u64 {
+        self.revoke_other_sessions(account_id, None)
+    }
+
+    /// Revokes the account's sessions but `keep_id_hash`.
+    fn revoke_other_sessions(&mut self, account_id: i32, keep_id_hash: Option<&[u8]>) -> u64 {
+        let now = Utc::now();
+        let mut revoked = 0;
+        for (id_hash, session) in self.sessions.iter_mut() {
+            if session.account_id == account_id
+                && Some(id_hash.as_slice()) != keep_id_hash
+                && session.revoked_at.is_none()
+            {
+                session.revoked_at = Some(now);
+                revoked += 1;
+            }
+        }
+        revoked
+    }
+
+    fn record_audit_event(&mut self, event: NewAuditEvent<'_>) -> AuditEvent {
+        let prev_hash = self
+            .audit_events
+            .last()
+            .map_or(GENESIS_HASH.to_vec(), |last| last.hash.clone());
+        let created_at = Utc::now();
+        self.next_audit_event_id += 1;
+
+        let event = AuditEvent {
+            id: self.next_audit_event_id,
+            account_id: event.account_id,
+            username: event.username.map(str::to_string),
+            action: event.action.to_string(),
+            detail: event.detail.clone(),
+            created_at,
+            hash: chain_hash(
+                &prev_hash,
+                event.username,
+                event.action,
+                event.detail,
+                created_at,
+            ),
+            prev_hash,
+        };
+        self.audit_events.push(event.clone());
+        event
+    }
+}
+
+/// Keeps everything in process memory. Meant for tests and throwaway
+/// development servers.
+#[derive(Default)]
+pub struct MemoryStorage {
+    state: Mutex<State>,
+}
+
+impl MemoryStorage {
+    fn state(&self) -> StorageResult<MutexGuard<'_, State>> {
+        self.state
+            .lock()
+            .map_err(|e| StorageError::Backend(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl AccountStore for MemoryStorage {
+    async fn create_account(&self, account: NewAccount<'_>) -> StorageResult<Account> {
+        let mut state = self.state()?;
+        if state
+            .accounts
+            .values()
+            .any(|existing| existing.username == account.username)
+        {
+            return Err(StorageError::Conflict);
+        }
+
+        state.next_account_id += 1;
+        let account = Account {
+            id: state.next_account_id,
+            username: account.username.to_string(),
+            credential_id: account.credential_id.to_vec(),
+            client_identity: account.client_identity.to_vec(),
+            registration_record: account.registration_record.to_vec(),
+            role: Role::User,
+            locked_at: None,
+            opaque_key_id: account.opaque_key_id,
+            kek_id: account.kek_id,
+            wrapped_dek: account.wrapped_dek.map(<[u8]>::to_vec),
+            recovery_record: None,
+            recovery_opaque_key_id: None,
+            recovery_public_key: None,
+            wrapped_master_key: None,
+            password_wrapped_master_key: account.password_wrapped_master_key.map(<[u8]>::to_vec),
+        };
+        state.accounts.insert(account.id, account.clone());
+        Ok(account)
+    }
+
+    async fn find_account_by_username(&self, username: &str) -> StorageResult<Option<Account>> {
+        Ok(self
+            .state()?
+            .accounts
+            .values()
+            .find(|account| account.username == username)
+            .cloned())
+    }
+
+    async fn find_account_by_id(&self, account_id: i32) -> StorageResult<Option<Account>> {
+        Ok(self.state()?.accounts.get(&account_id).cloned())
+    }
+
+    async fn search_accounts(
+        &self,
+        username: Option<&str>,
+        after: Option<i32>,
+        limit: u32,
+    ) -> StorageResult<Vec<Account>> {
+        let start = after.map_or(i32::MIN, |after| after.saturating_add(1));
+        Ok(self
+            .state()?
+            .accounts
+            .range(start..)
+            .map(|(_, account)| account)
+            .filter(|account| username.is_none_or(|username| account.username.contains(username)))
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn set_account_role(&self, account_id: i32, role: Role) -> StorageResult<()> {
+        let mut state = self.state()?;
+        let account = state
+            .accounts
+            .get_mut(&account_id)
+            .ok_or(StorageError::NotFound)?;
+        account.role = role;
+        Ok(())
+    }
+
+    async fn set_account_locked(
+        &self,
+        account_id: i32,
+        locked_at: Option<DateTime<Utc>>,
+    ) -> StorageResult<()> {
+        let mut state = self.state()?;
+        let account = state
+            .accounts
+            .get_mut(&account_id)
+            .ok_or(StorageError::NotFound)?;
+        account.locked_at = locked_at;
+        Ok(())
+    }
+
+    async fn update_registration_record(
+        &self,
+        account_id: i32,
+        registration_record: &[u8],
+        opaque_key_id: i32,
+    ) -> StorageResult<()> {
+        let mut state = self.state()?;
+        let account = state
+            .accounts
+            .get_mut(&account_id)
+            .ok_or(StorageError::NotFound)?;
+        account.registration_record = registration_record.to_vec();
+        account.opaque_key_id = opaque_key_id;
+        Ok(())
+    }
+
+    async fn delete_account(&self, account_id: i32) -> StorageResult<bool> {
+        let mut state = self.state()?;
+        if state.accounts.remove(&account_id).is_none() {
+            return Ok(false);
+        }
+        state
+            .sessions
+            .retain(|_, session| session.account_id != account_id);
+        state.items.retain(|_, item| item.account_id != account_id);
+        for event in &mut state.audit_events {
+            if event.account_id == Some(account_id) {
+                event.account_id = None;
+            }
+        }
+        Ok(true)
+    }
+
+    async fn change_password(
+        &self,
+        account_id: i32,
+        registration_record: &[u8],
+        opaque_key_id: i32,
+        password_wrapped_master_key: Option<&[u8]>,
+        keep_session_id_hash: &[u8],
+    ) -> StorageResult<u64> {
+        // Both steps happen under one lock, which makes them atomic
+        let mut state = self.state()?;
+        let account = state
+            .accounts
+            .get_mut(&account_id)
+            .ok_or(StorageError::NotFound)?;
+        account.registration_record = registration_record.to_vec();
+        account.opaque_key_id = opaque_key_id;
+        account.password_wrapped_master_key = password_wrapped_master_key.map(<[u8]>::to_vec);
+        let username = account.username.clone();
+        let revoked = state.revoke_other_sessions(account_id, Some(keep_session_id_hash));
+        state.record_audit_event(NewAuditEvent {
+            account_id: Some(account_id),
+            username: Some(&username),
+            action: AuditAction::PasswordChanged.as_str(),
+            detail: &json!({ "sessions_revoked": revoked }),
+        });
+        Ok(revoked)
+    }
+
+    async fn set_recovery_key(
+        &self,
+        account_id: i32,
+        recovery: RecoveryKey<'_>,
+    ) -> StorageResult<()> {
+        let mut state = self.state()?;
+        let account = state
+            .accounts
+            .get_mut(&account_id)
+            .ok_or(StorageError::NotFound)?;
+        account.recovery_record = Some(recovery.registration_record.to_vec());
+        account.recovery_opaque_key_id = Some(recovery.opaque_key_id);
+        account.recovery_public_key = Some(recovery.public_key.to_vec());
+        account.wrapped_master_key = Some(recovery.wrapped_master_key.to_vec());
+        Ok(())
+    }
+
+    async fn remove_recovery_key(&self, account_id: i32) -> StorageResult<bool> {
+        let mut state = self.state()?;
+        let Some(account) = state
+            .accounts
+            .get_mut(&account_id)
+            .filter(|account| account.recovery_record.is_some())
+        else {
+            return Ok(false);
+        };
+        account.recovery_record = None;
+        account.recovery_opaque_key_id = None;
+        account.recovery_public_key = None;
+        account.wrapped_master_key = None;
+        Ok(true)
+    }
+
+    async fn count_accounts_by_opaque_key_id(&self) -> StorageResult<Vec<(i32, u64)>> {
+        let mut counts = BTreeMap::new();
+        for account in self.state()?.accounts.values() {
+            *counts.entry(account.opaque_key_id).or_insert(0) += 1;
+            match account.recovery_opaque_key_id {
+                Some(key_id) if key_id != account.opaque_key_id => {
+                    *counts.entry(key_id).or_insert(0) += 1;
+                }
+                _ => {}
+            }
+        }
+        Ok(counts.into_iter().collect())
+    }
+
+    async fn encrypt_account(
+        &self,
+        account_id: i32,
+        encryption: AccountEncryption<'_>,
+    ) -> StorageResult<bool> {
+        let mut state = self.state()?;
+        let Some(account) = state
+            .accounts
+            .get_mut(&account_id)
+            .filter(|account| account.kek_id.is_none())
+        else {
+            return Ok(false);
+        };
+        account.kek_id = Some(encryption.kek_id);
+        account.wrapped_dek = Some(encryption.wrapped_dek.to_vec());
+        account.credential_id = encryption.credential_id.to_vec();
+        account.client_identity = encryption.client_identity.to_vec();
+        account.registration_record = encryption.registration_record.to_vec();
+        account.recovery_record = encryption.recovery_record.map(<[u8]>::to_vec);
+        Ok(true)
+    }
+
+    async fn rewrap_account_key(
+        &self,
+        account_id: i32,
+        from_kek_id: i32,
+        to_kek_id: i32,
+        wrapped_dek: &[u8],
+    ) -> StorageResult<bool> {
+        let mut state = self.state()?;
+        let Some(account) = state
+            .accounts
+            .get_mut(&account_id)
+            .filter(|account| account.kek_id == Some(from_kek_id))
+        else {
+            return Ok(false);
+        };
+        account.kek_id = Some(to_kek_id);
+        account.wrapped_dek = Some(wrapped_dek.to_vec());
+        Ok(true)
+    }
+
+    async fn list_accounts_not_under_kek(
+        &self,
+        kek_id: i32,
+        limit: u32,
+    ) -> StorageResult<Vec<Account>> {
+        Ok(self
+            .state()?
+            .accounts
+            .values()
+            .filter(|account| account.kek_id != Some(kek_id))
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStorage {
+    async fn create_session(
+        &self,
+        id_hash: &[u8],
+        account_id: i32,
+        expires_at: DateTime<Utc>,
+    ) -> StorageResult<SessionRecord> {
+        let mut state = self.state()?;
+        if !state.accounts.contains_key(&account_id) {
+            return Err(StorageError::NotFound);
+        }
+        if state.sessions.contains_key(id_hash) {
+            return Err(StorageError::Conflict);
+        }
+
+        let session = SessionRecord {
+            id_hash: id_hash.to_vec(),
+            account_id,
+            created_at: Utc::now(),
+            expires_at,
+            revoked_at: None,
+        };
+        state.sessions.insert(id_hash.to_vec(), session.clone());
+        Ok(session)
+    }
+
+    async fn find_active_session(
+        &self,
+        id_hash: &[u8],
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<SessionRecord>> {
+        Ok(self
+            .state()?
+            .sessions
+            .get(id_hash)
+            .filter(|session| session.revoked_at.is_none() && session.expires_at > now)
+            .cloned())
+    }
+
+    async fn revoke_session(&self, id_hash: &[u8]) -> StorageResult<bool> {
+        let mut state = self.state()?;
+        match state.sessions.get_mut(id_hash) {
+            Some(session) if session.revoked_at.is_none() => {
+                session.revoked_at = Some(Utc::now());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke_all_sessions(&self, account_id: i32) -> StorageResult<u64> {
+        Ok(self.state()?.revoke_all_sessions(account_id))
+    }
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> StorageResult<u64> {
+        let mut state = self.state()?;
+        let before = state.sessions.len();
+        state.sessions.retain(|_, session| session.expires_at > now);
+        Ok((before - state.sessions.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl ItemStore for MemoryStorage {
+    async fn create_item(&self, account_id: i32, ciphertext: &[u8]) -> StorageResult<Item> {
+        let mut state = self.state()?;
+        if !state.accounts.contains_key(&account_id) {
+            return Err(StorageError::NotFound);
+        }
+
+        let now = Utc::now();
+        let item = Item {
+            id: Uuid::new_v4(),
+            account_id,
+            ciphertext: ciphertext.to_vec(),
+            created_at: now,
+            updated_at: now,
+        };
+        state.items.insert(item.id, item.clone());
+        Ok(item)
+    }
+
+    async fn find_item(&self, account_id: i32, id: Uuid) -> StorageResult<Option<Item>> {
+        Ok(self
+            .state()?
+            .items
+            .get(&id)
+            .filter(|item| item.account_id == account_id)
+            .cloned())
+    }
+
+    async fn list_items(&self, account_id: i32) -> StorageResult<Vec<Item>> {
+        let mut items: Vec<Item> = self
+            .state()?
+            .items
+            .values()
+            .filter(|item| item.account_id == account_id)
+            .cloned()
+            .collect();
+        items.sort_by_key(|item| (item.created_at, item.id));
+        Ok(items)
+    }
+
+    async fn update_item(
+        &self,
+        account_id: i32,
+        id: Uuid,
+        ciphertext: &[u8],
+    ) -> StorageResult<Option<Item>> {
+        let mut state = self.state()?;
+        match state.items.get_mut(&id) {
+            Some(item) if item.account_id == account_id => {
+                item.ciphertext = ciphertext.to_vec();
+                item.updated_at = Utc::now();
+                Ok(Some(item.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn delete_item(&self, account_id: i32, id: Uuid) -> StorageResult<bool> {
+        let mut state = self.state()?;
+        match state.items.get(&id) {
+            Some(item) if item.account_id == account_id => {
+                state.items.remove(&id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl KeyLogStore for MemoryStorage {
+    async fn append_key_log_entry(
+        &self,
+        leaf_index: u64,
+        username: &str,
+        leaf: &[u8],
+    ) -> StorageResult<KeyLogEntry> {
+        let mut state = self.state()?;
+        if leaf_index != state.key_log.len() as u64 {
+            return Err(StorageError::Conflict);
+        }
+
+        let entry = KeyLogEntry {
+            leaf_index: i64::try_from(leaf_index)
+                .map_err(|e| StorageError::Backend(e.to_string()))?,
+            username: username.to_string(),
+            leaf: leaf.to_vec(),
+            created_at: Utc::now(),
+        };
+        state.key_log.push(entry.clone());
+        Ok(entry)
+    }
+
+    async fn list_key_log_entries(&self) -> StorageResult<Vec<KeyLogEntry>> {
+        Ok(self.state()?.key_log.clone())
+    }
+
+    async fn list_key_log_entries_for_user(
+        &self,
+        username: &str,
+    ) -> StorageResult<Vec<KeyLogEntry>> {
+        Ok(self
+            .state()?
+            .key_log
+            .iter()
+            .filter(|entry| entry.username == username)
+            .cloned()
+            .collect())
+    }
+
+    async fn latest_key_log_entry_for_user(
+        &self,
+        username: &str,
+    ) -> StorageResult<Option<KeyLogEntry>> {
+        Ok(self
+            .state()?
+            .key_log
+            .iter()
+            .rev()
+            .find(|entry| entry.username == username)
+            .cloned())
+    }
+}
+
+#[async_trait]
+impl AuditStore for MemoryStorage {
+    async fn record_audit_event(&self, event: NewAuditEvent<'_>) -> StorageResult<AuditEvent> {
+        Ok(self.state()?.record_audit_event(event))
+    }
+
+    async fn list_audit_events(
+        &self,
+        account_id: Option<i32>,
+        before: Option<i64>,
+        limit: u32,
+    ) -> StorageResult<Vec<AuditEvent>> {
+        Ok(self
+            .state()?
+            .audit_events
+            .iter()
+            .rev()
+            .filter(|event| account_id.is_none() || event.account_id == account_id)
+            .filter(|event| before.is_none_or(|before| event.id < before))
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_audit_events_after(
+        &self,
+        after: i64,
+        limit: u32,
+    ) -> StorageResult<Vec<AuditEvent>> {
+        Ok(self
+            .state()?
+            .audit_events
+            .iter()
+            .filter(|event| event.id > after)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_audit_events_before(&self, cutoff: DateTime<Utc>) -> StorageResult<u64> {
+        let mut state = self.state()?;
+        let Some(last) = state
+            .audit_events
+            .iter()
+            .rposition(|event| event.created_at < cutoff)
+        else {
+            return Ok(0);
+        };
+        state.audit_events.drain(..=last);
+        Ok(last as u64 + 1)
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn ping(&self) -> StorageResult<()> {
+        self.state().map(|_| ())
+    }
+}
@@ -0,0 +1,328 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use sqlx::types::{
+    chrono::{DateTime, Utc},
+    Uuid,
+};
+
+use crate::controllers::envelope::KekRing;
+use crate::controllers::key_provider::KeyProvider;
+pub use crate::models::{
+    accounts::{Account, AccountEncryption, NewAccount, RecoveryKey, Role},
+    audit::{AuditEvent, NewAuditEvent},
+    items::Item,
+    key_log::KeyLogEntry,
+    sessions::SessionRecord,
+};
+use crate::utils::config::Config;
+
+pub mod encrypted;
+pub mod memory;
+pub mod postgres;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+#[derive(Debug)]
+pub enum StorageError {
+    NotFound,
+    /// A unique constraint, such as the username, was violated
+    Conflict,
+    UnsupportedUrl(String),
+    Backend(String),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::NotFound => write!(f, "Record not found"),
+            StorageError::Conflict => write!(f, "Record already exists"),
+            StorageError::UnsupportedUrl(url) => {
+                write!(f, "No storage backend for database url {}", url)
+            }
+            StorageError::Backend(err) => write!(f, "Storage backend error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<sqlx::Error> for StorageError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => StorageError::NotFound,
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => StorageError::Conflict,
+            err => StorageError::Backend(err.to_string()),
+        }
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for StorageError {
+    fn from(err: sqlx::migrate::MigrateError) -> Self {
+        StorageError::Backend(err.to_string())
+    }
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+#[async_trait]
+pub trait AccountStore: Send + Sync {
+    /// Fails with [`StorageError::Conflict`] if the username is taken.
+    async fn create_account(&self, account: NewAccount<'_>) -> StorageResult<Account>;
+
+    async fn find_account_by_username(&self, username: &str) -> StorageResult<Option<Account>>;
+
+    async fn find_account_by_id(&self, account_id: i32) -> StorageResult<Option<Account>>;
+
+    /// Accounts whose username contains `username`, in id order. `after` is
+    /// the id of the last account of the previous page.
+    async fn search_accounts(
+        &self,
+        username: Option<&str>,
+        after: Option<i32>,
+        limit: u32,
+    ) -> StorageResult<Vec<Account>>;
+
+    /// Fails with [`StorageError::NotFound`] if the account does not exist.
+    async fn set_account_role(&self, account_id: i32, role: Role) -> StorageResult<()>;
+
+    /// Locks the account at `locked_at`, or unlocks it with `None`. Fails
+    /// with [`StorageError::NotFound`] if the account does not exist.
+    async fn set_account_locked(
+        &self,
+        account_id: i32,
+        locked_at: Option<DateTime<Utc>>,
+    ) -> StorageResult<()>;
+
+    /// Fails with [`StorageError::NotFound`] if the account does not exist.
+    async fn update_registration_record(
+        &self,
+        account_id: i32,
+        registration_record: &[u8],
+        opaque_key_id: i32,
+    ) -> StorageResult<()>;
+
+    /// Also deletes the account's sessions and items.
+    async fn delete_account(&self, account_id: i32) -> StorageResult<bool>;
+
+    /// Stores the new registration record and the master key wrapped with
+    /// the new password, revokes every session of the account but
+    /// `keep_session_id_hash`, the one the change was made from, and records
+    /// a `password_changed` audit event atomically. Returns how many sessions
+    /// were revoked.
+    async fn change_password(
+        &self,
+        account_id: i32,
+        registration_record: &[u8],
+        opaque_key_id: i32,
+        password_wrapped_master_key: Option<&[u8]>,
+        keep_session_id_hash: &[u8],
+    ) -> StorageResult<u64>;
+
+    /// Sets or replaces the recovery key of the account. Fails with
+    /// [`StorageError::NotFound`] if the account does not exist.
+    async fn set_recovery_key(
+        &self,
+        account_id: i32,
+        recovery: RecoveryKey<'_>,
+    ) -> StorageResult<()>;
+
+    /// Returns false if the account has no recovery key.
+    async fn remove_recovery_key(&self, account_id: i32) -> StorageResult<bool>;
+
+    /// How many accounts have a password or recovery code registered under
+    /// each OPAQUE server setup generation, in key id order. Generations
+    /// without accounts are left out.
+    async fn count_accounts_by_opaque_key_id(&self) -> StorageResult<Vec<(i32, u64)>>;
+
+    /// Stores the encrypted columns of a plaintext account. Returns false if
+    /// the account is gone or already encrypted.
+    async fn encrypt_account(
+        &self,
+        account_id: i32,
+        encryption: AccountEncryption<'_>,
+    ) -> StorageResult<bool>;
+
+    /// Replaces the wrapped data key of the account if it is still wrapped
+    /// with `from_kek_id`. Returns false otherwise.
+    async fn rewrap_account_key(
+        &self,
+        account_id: i32,
+        from_kek_id: i32,
+        to_kek_id: i32,
+        wrapped_dek: &[u8],
+    ) -> StorageResult<bool>;
+
+    /// Accounts, as stored, that are in plaintext or under another KEK than
+    /// `kek_id`, in id order.
+    async fn list_accounts_not_under_kek(
+        &self,
+        kek_id: i32,
+        limit: u32,
+    ) -> StorageResult<Vec<Account>>;
+}
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn create_session(
+        &self,
+        id_hash: &[u8],
+        account_id: i32,
+        expires_at: DateTime<Utc>,
+    ) -> StorageResult<SessionRecord>;
+
+    /// Returns the session unless it has expired or been revoked.
+    async fn find_active_session(
+        &self,
+        id_hash: &[u8],
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<SessionRecord>>;
+
+    async fn revoke_session(&self, id_hash: &[u8]) -> StorageResult<bool>;
+
+    async fn revoke_all_sessions(&self, account_id: i32) -> StorageResult<u64>;
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> StorageResult<u64>;
+}
+
+/// Items are always looked up by account and id, so one account can never
+/// reach another's items.
+#[async_trait]
+pub trait ItemStore: Send + Sync {
+    async fn create_item(&self, account_id: i32, ciphertext: &[u8]) -> StorageResult<Item>;
+
+    async fn find_item(&self, account_id: i32, id: Uuid) -> StorageResult<Option<Item>>;
+
+    /// Oldest first.
+    async fn list_items(&self, account_id: i32) -> StorageResult<Vec<Item>>;
+
+    async fn update_item(
+        &self,
+        account_id: i32,
+        id: Uuid,
+        ciphertext: &[u8],
+    ) -> StorageResult<Option<Item>>;
+
+    async fn delete_item(&self, account_id: i32, id: Uuid) -> StorageResult<bool>;
+}
+
+/// The append-only key transparency log.
+#[async_trait]
+pub trait KeyLogStore: Send + Sync {
+    /// Fails with [`StorageError::Conflict`] if `leaf_index` is taken.
+    async fn append_key_log_entry(
+        &self,
+        leaf_index: u64,
+        username: &str,
+        leaf: &[u8],
+    ) -> StorageResult<KeyLogEntry>;
+
+    /// Every entry in log order.
+    async fn list_key_log_entries(&self) -> StorageResult<Vec<KeyLogEntry>>;
+
+    /// The entries of one user in log order.
+    async fn list_key_log_entries_for_user(
+        &self,
+        username: &str,
+    ) -> StorageResult<Vec<KeyLogEntry>>;
+
+    async fn latest_key_log_entry_for_user(
+        &self,
+        username: &str,
+    ) -> StorageResult<Option<KeyLogEntry>>;
+}
+
+/// The hash-chained security audit log, see [`crate::models::audit`].
+#[async_trait]
+pub trait AuditStore: Send + Sync {
+    /// Chains the event to the latest one. Appends are serialized.
+    async fn record_audit_event(&self, event: NewAuditEvent<'_>) -> StorageResult<AuditEvent>;
+
+    /// Newest first, of one account or of all of them. `before` is the id of
+    /// the last event of the previous page.
+    async fn list_audit_events(
+        &self,
+        account_id: Option<i32>,
+        before: Option<i64>,
+        limit: u32,
+    ) -> StorageResult<Vec<AuditEvent>>;
+
+    /// Chain order, starting after the event with id `after`.
+    async fn list_audit_events_after(
+        &self,
+        after: i64,
+        limit: u32,
+    ) -> StorageResult<Vec<AuditEvent>>;
+
+    /// Deletes the oldest events up to the last one created before `cutoff`.
+    async fn delete_audit_events_before(&self, cutoff: DateTime<Utc>) -> StorageResult<u64>;
+}
+
+#[async_trait]
+pub trait Storage: AccountStore + SessionStore + ItemStore + KeyLogStore + AuditStore {
+    /// Checks that the backend is reachable, for the readiness probe.
+    async fn ping(&self) -> StorageResult<()>;
+
+    /// Publishes backend specific gauges, such as connection pool usage.
+    fn record_metrics(&self) {}
+
+    /// Encrypts plaintext accounts and rewraps data keys still under an old
+    /// KEK, up to `limit` accounts. Returns how many accounts it went
+    /// through, 0 once there is nothing left to do or the storage does not
+    /// encrypt.
+    async fn reencrypt_accounts(&self, _limit: u32) -> StorageResult<u64> {
+        Ok(0)
+    }
+}
+
+/// Connects to the backend named by the scheme of `config.database_url` and
+/// applies its migrations. With a key provider, account secrets are
+/// encrypted at rest under its KEKs, see [`encrypted::EncryptedStorage`].
+pub async fn connect(
+    config: &Config,
+    key_provider: Option<&dyn KeyProvider>,
+) -> StorageResult<Arc<dyn Storage>> {
+    let storage = connect_backend(config).await?;
+    match key_provider {
+        Some(key_provider) => {
+            let keks = KekRing::load(key_provider)?;
+            Ok(Arc::new(encrypted::EncryptedStorage::new(
+                storage,
+                Arc::new(keks),
+            )))
+        }
+        None => {
+            tracing::warn!(
+                "no key_provider configured, account registration records are stored unencrypted"
+            );
+            Ok(storage)
+        }
+    }
+}
+
+/// `postgres://`, `sqlite://` with the `sqlite` feature, or `memory://` for
+/// throwaway development servers.
+async fn connect_backend(config: &Config) -> StorageResult<Arc<dyn Storage>> {
+    let url = config.database_url.as_str();
+
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        let storage = postgres::PostgresStorage::connect(url, config.db_max_connections).await?;
+        return Ok(Arc::new(storage));
+    }
+
+    #[cfg(feature = "sqlite")]
+    if url.starts_with("sqlite:") {
+        let storage = sqlite::SqliteStorage::connect(url, config.db_max_connections).await?;
+        return Ok(Arc::new(storage));
+    }
+
+    if url.starts_with("memory://") {
+        tracing::warn!("using in-memory storage, all data is lost on restart");
+        return Ok(Arc::new(memory::MemoryStorage::default()));
+    }
+
+    Err(StorageError::UnsupportedUrl(
+        config.redacted().database_url.clone(),
+    ))
+}
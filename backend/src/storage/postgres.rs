@@ -0,0 +1,349 @@
+use async_trait::async_trait;
+use sqlx::types::{
+    chrono::{DateTime, Utc},
+    Uuid,
+};
+
+use super::{
+    Account, AccountEncryption, AccountStore, AuditEvent, AuditStore, Item, ItemStore, KeyLogEntry,
+    KeyLogStore, NewAccount, NewAuditEvent, RecoveryKey, Role, SessionRecord, SessionStore,
+    Storage, StorageError, StorageResult,
+};
+use crate::models::Models;
+use crate::utils::pg_pool;
+
+/// The production backend, a thin layer over the [`Models`] repositories.
+#[derive(Clone)]
+pub struct PostgresStorage {
+    models: Models,
+}
+
+impl PostgresStorage {
+    pub fn new(models: Models) -> Self {
+        Self { models }
+    }
+
+    pub async fn connect(url: &str, max_connections: u32) -> StorageResult<Self> {
+        let pool = pg_pool::create_pg_pool(url, max_connections).await?;
+        sqlx::migrate!("db/migrations").run(&pool).await?;
+        let models = Models::new(pool);
+        let chained = models.chain_legacy_audit_events().await?;
+        if chained > 0 {
+            tracing::info!(
+                events = chained,
+                "chained audit events recorded before the chain"
+            );
+        }
+        Ok(Self::new(models))
+    }
+
+    pub fn models(&self) -> &Models {
+        &self.models
+    }
+}
+
+#[async_trait]
+impl AccountStore for PostgresStorage {
+    async fn create_account(&self, account: NewAccount<'_>) -> StorageResult<Account> {
+        Ok(self.models.accounts().create(account).await?)
+    }
+
+    async fn find_account_by_username(&self, username: &str) -> StorageResult<Option<Account>> {
+        Ok(self.models.accounts().find_by_username(username).await?)
+    }
+
+    async fn find_account_by_id(&self, account_id: i32) -> StorageResult<Option<Account>> {
+        Ok(self.models.accounts().find_by_id(account_id).await?)
+    }
+
+    async fn search_accounts(
+        &self,
+        username: Option<&str>,
+        after: Option<i32>,
+        limit: u32,
+    ) -> StorageResult<Vec<Account>> {
+        Ok(self
+            .models
+            .accounts()
+            .search(username, after, i64::from(limit))
+            .await?)
+    }
+
+    async fn set_account_role(&self, account_id: i32, role: Role) -> StorageResult<()> {
+        Ok(self.models.accounts().set_role(account_id, role).await?)
+    }
+
+    async fn set_account_locked(
+        &self,
+        account_id: i32,
+        locked_at: Option<DateTime<Utc>>,
+    ) -> StorageResult<()> {
+        Ok(self
+            .models
+            .accounts()
+            .set_locked_at(account_id, locked_at)
+            .await?)
+    }
+
+    async fn update_registration_record(
+        &self,
+        account_id: i32,
+        registration_record: &[u8],
+        opaque_key_id: i32,
+    ) -> StorageResult<()> {
+        Ok(self
+            .models
+            .accounts()
+            .update_registration_record(account_id, registration_record, opaque_key_id)
+            .await?)
+    }
+
+    async fn delete_account(&self, account_id: i32) -> StorageResult<bool> {
+        Ok(self.models.accounts().delete(account_id).await?)
+    }
+
+    async fn change_password(
+        &self,
+        account_id: i32,
+        registration_record: &[u8],
+        opaque_key_id: i32,
+        password_wrapped_master_key: Option<&[u8]>,
+        keep_session_id_hash: &[u8],
+    ) -> StorageResult<u64> {
+        Ok(self
+            .models
+            .change_password(
+                account_id,
+                registration_record,
+                opaque_key_id,
+                password_wrapped_master_key,
+                keep_session_id_hash,
+            )
+            .await?)
+    }
+
+    async fn set_recovery_key(
+        &self,
+        account_id: i32,
+        recovery: RecoveryKey<'_>,
+    ) -> StorageResult<()> {
+        Ok(self
+            .models
+            .accounts()
+            .set_recovery_key(account_id, recovery)
+            .await?)
+    }
+
+    async fn remove_recovery_key(&self, account_id: i32) -> StorageResult<bool> {
+        Ok(self
+            .models
+            .accounts()
+            .remove_recovery_key(account_id)
+            .await?)
+    }
+
+    async fn count_accounts_by_opaque_key_id(&self) -> StorageResult<Vec<(i32, u64)>> {
+        let counts = self.models.accounts().count_by_opaque_key_id().await?;
+        Ok(counts
+            .into_iter()
+            .map(|(key_id, accounts)| (key_id, accounts as u64))
+            .collect())
+    }
+
+    async fn encrypt_account(
+        &self,
+        account_id: i32,
+        encryption: AccountEncryption<'_>,
+    ) -> StorageResult<bool> {
+        Ok(self
+            .models
+            .accounts()
+            .encrypt(account_id, encryption)
+            .await?)
+    }
+
+    async fn rewrap_account_key(
+        &self,
+        account_id: i32,
+        from_kek_id: i32,
+        to_kek_id: i32,
+        wrapped_dek: &[u8],
+    ) -> StorageResult<bool> {
+        Ok(self
+            .models
+            .accounts()
+            .rewrap_key(account_id, from_kek_id, to_kek_id, wrapped_dek)
+            .await?)
+    }
+
+    async fn list_accounts_not_under_kek(
+        &self,
+        kek_id: i32,
+        limit: u32,
+    ) -> StorageResult<Vec<Account>> {
+        Ok(self
+            .models
+            .accounts()
+            .list_not_under_kek(kek_id, i64::from(limit))
+            .await?)
+    }
+}
+
+#[async_trait]
+impl SessionStore for PostgresStorage {
+    async fn create_session(
+        &self,
+        id_hash: &[u8],
+        account_id: i32,
+        expires_at: DateTime<Utc>,
+    ) -> StorageResult<SessionRecord> {
+        Ok(self
+            .models
+            .sessions()
+            .create(id_hash, account_id, expires_at)
+            .await?)
+    }
+
+    async fn find_active_session(
+        &self,
+        id_hash: &[u8],
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<SessionRecord>> {
+        Ok(self.models.sessions().find_active(id_hash, now).await?)
+    }
+
+    async fn revoke_session(&self, id_hash: &[u8]) -> StorageResult<bool> {
+        Ok(self.models.sessions().revoke(id_hash).await?)
+    }
+
+    async fn revoke_all_sessions(&self, account_id: i32) -> StorageResult<u64> {
+        Ok(self
+            .models
+            .sessions()
+            .revoke_all_for_account(account_id)
+            .await?)
+    }
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> StorageResult<u64> {
+        Ok(self.models.sessions().delete_expired(now).await?)
+    }
+}
+
+#[async_trait]
+impl ItemStore for PostgresStorage {
+    async fn create_item(&self, account_id: i32, ciphertext: &[u8]) -> StorageResult<Item> {
+        Ok(self.models.items().create(account_id, ciphertext).await?)
+    }
+
+    async fn find_item(&self, account_id: i32, id: Uuid) -> StorageResult<Option<Item>> {
+        Ok(self.models.items().find(account_id, id).await?)
+    }
+
+    async fn list_items(&self, account_id: i32) -> StorageResult<Vec<Item>> {
+        Ok(self.models.items().list_for_account(account_id).await?)
+    }
+
+    async fn update_item(
+        &self,
+        account_id: i32,
+        id: Uuid,
+        ciphertext: &[u8],
+    ) -> StorageResult<Option<Item>> {
+        Ok(self
+            .models
+            .items()
+            .update(account_id, id, ciphertext)
+            .await?)
+    }
+
+    async fn delete_item(&self, account_id: i32, id: Uuid) -> StorageResult<bool> {
+        Ok(self.models.items().delete(account_id, id).await?)
+    }
+}
+
+#[async_trait]
+impl KeyLogStore for PostgresStorage {
+    async fn append_key_log_entry(
+        &self,
+        leaf_index: u64,
+        username: &str,
+        leaf: &[u8],
+    ) -> StorageResult<KeyLogEntry> {
+        let leaf_index =
+            i64::try_from(leaf_index).map_err(|e| StorageError::Backend(e.to_string()))?;
+        Ok(self
+            .models
+            .key_log()
+            .append(leaf_index, username, leaf)
+            .await?)
+    }
+
+    async fn list_key_log_entries(&self) -> StorageResult<Vec<KeyLogEntry>> {
+        Ok(self.models.key_log().list_all().await?)
+    }
+
+    async fn list_key_log_entries_for_user(
+        &self,
+        username: &str,
+    ) -> StorageResult<Vec<KeyLogEntry>> {
+        Ok(self.models.key_log().list_for_username(username).await?)
+    }
+
+    async fn latest_key_log_entry_for_user(
+        &self,
+        username: &str,
+    ) -> StorageResult<Option<KeyLogEntry>> {
+        Ok(self.models.key_log().latest_for_username(username).await?)
+    }
+}
+
+#[async_trait]
+impl AuditStore for PostgresStorage {
+    async fn record_audit_event(&self, event: NewAuditEvent<'_>) -> StorageResult<AuditEvent> {
+        Ok(self.models.record_audit_event(event).await?)
+    }
+
+    async fn list_audit_events(
+        &self,
+        account_id: Option<i32>,
+        before: Option<i64>,
+        limit: u32,
+    ) -> StorageResult<Vec<AuditEvent>> {
+        let audit = self.models.audit();
+        Ok(match account_id {
+            Some(account_id) => {
+                audit
+                    .list_for_account(account_id, before, limit.into())
+                    .await?
+            }
+            None => audit.list_all(before, limit.into()).await?,
+        })
+    }
+
+    async fn list_audit_events_after(
+        &self,
+        after: i64,
+        limit: u32,
+    ) -> StorageResult<Vec<AuditEvent>> {
+        Ok(self.models.audit().list_after(after, limit.into()).await?)
+    }
+
+    async fn delete_audit_events_before(&self, cutoff: DateTime<Utc>) -> StorageResult<u64> {
+        Ok(self.models.audit().delete_before(cutoff).await?)
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn ping(&self) -> StorageResult<()> {
+        sqlx::query("SELECT 1").execute(self.models.pool()).await?;
+        Ok(())
+    }
+
+    fn record_metrics(&self) {
+        let pool = self.models.pool();
+        metrics::gauge!("db_pool_connections", "state" => "idle").set(pool.num_idle() as f64);
+        metrics::gauge!("db_pool_connections", "state" => "total").set(pool.size() as f64);
+        metrics::gauge!("db_pool_max_connections").set(pool.options().get_max_connections() as f64);
+    }
+}
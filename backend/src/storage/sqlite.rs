@@ -0,0 +1,708 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::json;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    types::{
+        chrono::{DateTime, Utc},
+        Uuid,
+    },
+    SqliteConnection, SqlitePool,
+};
+use tokio::sync::Mutex;
+
+use super::{
+    Account, AccountEncryption, AccountStore, AuditEvent, AuditStore, Item, ItemStore, KeyLogEntry,
+    KeyLogStore, NewAccount, NewAuditEvent, RecoveryKey, Role, SessionRecord, SessionStore,
+    Storage, StorageError, StorageResult,
+};
+use crate::controllers::audit::AuditAction;
+use crate::models::audit::{chain_hash, GENESIS_HASH};
+
+/// Stores everything in a single SQLite file, for single-user and
+/// self-hosted deployments. The `query!` macros are checked against Postgres,
+/// so this backend uses the unchecked query functions.
+#[derive(Clone)]
+pub struct SqliteStorage {
+    pool: SqlitePool,
+    /// Serializes appends to the audit chain. SQLite has no advisory locks,
+    /// and a deferred transaction cannot be upgraded once another writer
+    /// got in first.
+    audit_lock: Arc<Mutex<()>>,
+}
+
+impl SqliteStorage {
+    pub async fn connect(url: &str, max_connections: u32) -> StorageResult<Self> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        // Every connection to an in-memory database gets a database of its own
+        let max_connections = if url.contains(":memory:") || url.contains("mode=memory") {
+            1
+        } else {
+            max_connections
+        };
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await?;
+        sqlx::migrate!("db/sqlite/migrations").run(&pool).await?;
+        Ok(Self {
+            pool,
+            audit_lock: Arc::default(),
+        })
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+}
+
+/// Chains and inserts an audit event. Callers hold the audit lock.
+async fn insert_audit_event(
+    conn: &mut SqliteConnection,
+    event: NewAuditEvent<'_>,
+) -> StorageResult<AuditEvent> {
+    let prev_hash: Vec<u8> =
+        sqlx::query_scalar("select hash from audit_event order by id desc limit 1")
+            .fetch_optional(&mut *conn)
+            .await?
+            .unwrap_or(GENESIS_HASH.to_vec());
+    let created_at = Utc::now();
+    let hash = chain_hash(
+        &prev_hash,
+        event.username,
+        event.action,
+        event.detail,
+        created_at,
+    );
+
+    Ok(sqlx::query_as(
+        r#"
+        insert into audit_event (account_id, username, action, detail, created_at, prev_hash, hash)
+        values (?, ?, ?, ?, ?, ?, ?)
+        returning id, account_id, username, action, detail, created_at, prev_hash, hash
+        "#,
+    )
+    .bind(event.account_id)
+    .bind(event.username)
+    .bind(event.action)
+    .bind(event.detail)
+    .bind(created_at.timestamp())
+    .bind(prev_hash)
+    .bind(hash)
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+#[async_trait]
+impl AccountStore for SqliteStorage {
+    async fn create_account(&self, account: NewAccount<'_>) -> StorageResult<Account> {
+        Ok(sqlx::query_as(
+            r#"
+            insert into account (username, credential_id, client_identity, registration_record,
+                opaque_key_id, kek_id, wrapped_dek, password_wrapped_master_key)
+            values (?, ?, ?, ?, ?, ?, ?, ?)
+            returning id, username, credential_id, client_identity, registration_record,
+                role, locked_at, opaque_key_id, kek_id, wrapped_dek, recovery_record,
+                recovery_opaque_key_id, recovery_public_key, wrapped_master_key, password_wrapped_master_key
+            "#,
+        )
+        .bind(account.username)
+        .bind(account.credential_id)
+        .bind(account.client_identity)
+        .bind(account.registration_record)
+        .bind(account.opaque_key_id)
+        .bind(account.kek_id)
+        .bind(account.wrapped_dek)
+        .bind(account.password_wrapped_master_key)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    async fn find_account_by_username(&self, username: &str) -> StorageResult<Option<Account>> {
+        Ok(sqlx::query_as(
+            r#"
+            select id, username, credential_id, client_identity, registration_record,
+                role, locked_at, opaque_key_id, kek_id, wrapped_dek, recovery_record,
+                recovery_opaque_key_id, recovery_public_key, wrapped_master_key, password_wrapped_master_key
+            from account
+            where username = ?
+            "#,
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn find_account_by_id(&self, account_id: i32) -> StorageResult<Option<Account>> {
+        Ok(sqlx::query_as(
+            r#"
+            select id, username, credential_id, client_identity, registration_record,
+                role, locked_at, opaque_key_id, kek_id, wrapped_dek, recovery_record,
+                recovery_opaque_key_id, recovery_public_key, wrapped_master_key, password_wrapped_master_key
+            from account
+            where id = ?
+            "#,
+        )
+        .bind(account_id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn search_accounts(
+        &self,
+        username: Option<&str>,
+        after: Option<i32>,
+        limit: u32,
+    ) -> StorageResult<Vec<Account>> {
+        Ok(sqlx::query_as(
+            r#"
+            select id, username, credential_id, client_identity, registration_record,
+                role, locked_at, opaque_key_id, kek_id, wrapped_dek, recovery_record,
+                recovery_opaque_key_id, recovery_public_key, wrapped_master_key, password_wrapped_master_key
+            from account
+            where (?1 is null or instr(username, ?1) > 0) and (?2 is null or id > ?2)
+            order by id
+            limit ?3
+            "#,
+        )
+        .bind(username)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn set_account_role(&self, account_id: i32, role: Role) -> StorageResult<()> {
+        let result = sqlx::query("update account set role = ? where id = ?")
+            .bind(role.as_str())
+            .bind(account_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(())
+    }
+
+    async fn set_account_locked(
+        &self,
+        account_id: i32,
+        locked_at: Option<DateTime<Utc>>,
+    ) -> StorageResult<()> {
+        let result = sqlx::query("update account set locked_at = ? where id = ?")
+            .bind(locked_at.map(|locked_at| locked_at.timestamp()))
+            .bind(account_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(())
+    }
+
+    async fn update_registration_record(
+        &self,
+        account_id: i32,
+        registration_record: &[u8],
+        opaque_key_id: i32,
+    ) -> StorageResult<()> {
+        let result = sqlx::query(
+            "update account set registration_record = ?, opaque_key_id = ? where id = ?",
+        )
+        .bind(registration_record)
+        .bind(opaque_key_id)
+        .bind(account_id)
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(())
+    }
+
+    async fn delete_account(&self, account_id: i32) -> StorageResult<bool> {
+        let result = sqlx::query("delete from account where id = ?")
+            .bind(account_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn change_password(
+        &self,
+        account_id: i32,
+        registration_record: &[u8],
+        opaque_key_id: i32,
+        password_wrapped_master_key: Option<&[u8]>,
+        keep_session_id_hash: &[u8],
+    ) -> StorageResult<u64> {
+        let _audit_lock = self.audit_lock.lock().await;
+        let mut tx = self.pool.begin().await?;
+
+        let username: String = sqlx::query_scalar(
+            r#"
+            update account
+            set registration_record = ?, opaque_key_id = ?, password_wrapped_master_key = ?
+            where id = ?
+            returning username
+            "#,
+        )
+        .bind(registration_record)
+        .bind(opaque_key_id)
+        .bind(password_wrapped_master_key)
+        .bind(account_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        let revoked = sqlx::query(
+            r#"
+            update session set revoked_at = ?
+            where account_id = ? and id_hash <> ? and revoked_at is null
+            "#,
+        )
+        .bind(Utc::now().timestamp())
+        .bind(account_id)
+        .bind(keep_session_id_hash)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        insert_audit_event(
+            &mut tx,
+            NewAuditEvent {
+                account_id: Some(account_id),
+                username: Some(&username),
+                action: AuditAction::PasswordChanged.as_str(),
+                detail: &json!({ "sessions_revoked": revoked }),
+            },
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(revoked)
+    }
+
+    async fn set_recovery_key(
+        &self,
+        account_id: i32,
+        recovery: RecoveryKey<'_>,
+    ) -> StorageResult<()> {
+        let result = sqlx::query(
+            r#"
+            update account
+            set recovery_record = ?, recovery_opaque_key_id = ?, recovery_public_key = ?,
+                wrapped_master_key = ?
+            where id = ?
+            "#,
+        )
+        .bind(recovery.registration_record)
+        .bind(recovery.opaque_key_id)
+        .bind(recovery.public_key)
+        .bind(recovery.wrapped_master_key)
+        .bind(account_id)
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(())
+    }
+
+    async fn remove_recovery_key(&self, account_id: i32) -> StorageResult<bool> {
+        let result = sqlx::query(
+            r#"
+            update account
+            set recovery_record = null, recovery_opaque_key_id = null,
+                recovery_public_key = null, wrapped_master_key = null
+            where id = ? and recovery_record is not null
+            "#,
+        )
+        .bind(account_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn count_accounts_by_opaque_key_id(&self) -> StorageResult<Vec<(i32, u64)>> {
+        let counts: Vec<(i32, i64)> = sqlx::query_as(
+            r#"
+            select key_id, count(distinct id)
+            from (
+                select id, opaque_key_id as key_id from account
+                union all
+                select id, recovery_opaque_key_id from account
+                where recovery_opaque_key_id is not null
+            )
+            group by key_id
+            order by key_id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(counts
+            .into_iter()
+            .map(|(key_id, accounts)| (key_id, accounts as u64))
+            .collect())
+    }
+
+    async fn encrypt_account(
+        &self,
+        account_id: i32,
+        encryption: AccountEncryption<'_>,
+    ) -> StorageResult<bool> {
+        let result = sqlx::query(
+            r#"
+            update account
+            set kek_id = ?, wrapped_dek = ?, credential_id = ?, client_identity = ?,
+                registration_record = ?, recovery_record = ?
+            where id = ? and kek_id is null
+            "#,
+        )
+        .bind(encryption.kek_id)
+        .bind(encryption.wrapped_dek)
+        .bind(encryption.credential_id)
+        .bind(encryption.client_identity)
+        .bind(encryption.registration_record)
+        .bind(encryption.recovery_record)
+        .bind(account_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn rewrap_account_key(
+        &self,
+        account_id: i32,
+        from_kek_id: i32,
+        to_kek_id: i32,
+        wrapped_dek: &[u8],
+    ) -> StorageResult<bool> {
+        let result = sqlx::query(
+            "update account set kek_id = ?, wrapped_dek = ? where id = ? and kek_id = ?",
+        )
+        .bind(to_kek_id)
+        .bind(wrapped_dek)
+        .bind(account_id)
+        .bind(from_kek_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_accounts_not_under_kek(
+        &self,
+        kek_id: i32,
+        limit: u32,
+    ) -> StorageResult<Vec<Account>> {
+        Ok(sqlx::query_as(
+            r#"
+            select id, username, credential_id, client_identity, registration_record,
+                role, locked_at, opaque_key_id, kek_id, wrapped_dek, recovery_record,
+                recovery_opaque_key_id, recovery_public_key, wrapped_master_key, password_wrapped_master_key
+            from account
+            where kek_id is not ?
+            order by id
+            limit ?
+            "#,
+        )
+        .bind(kek_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+}
+
+#[async_trait]
+impl SessionStore for SqliteStorage {
+    async fn create_session(
+        &self,
+        id_hash: &[u8],
+        account_id: i32,
+        expires_at: DateTime<Utc>,
+    ) -> StorageResult<SessionRecord> {
+        Ok(sqlx::query_as(
+            r#"
+            insert into session (id_hash, account_id, expires_at)
+            values (?, ?, ?)
+            returning id_hash, account_id, created_at, expires_at, revoked_at
+            "#,
+        )
+        .bind(id_hash)
+        .bind(account_id)
+        .bind(expires_at.timestamp())
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    async fn find_active_session(
+        &self,
+        id_hash: &[u8],
+        now: DateTime<Utc>,
+    ) -> StorageResult<Option<SessionRecord>> {
+        Ok(sqlx::query_as(
+            r#"
+            select id_hash, account_id, created_at, expires_at, revoked_at
+            from session
+            where id_hash = ? and revoked_at is null and expires_at > ?
+            "#,
+        )
+        .bind(id_hash)
+        .bind(now.timestamp())
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn revoke_session(&self, id_hash: &[u8]) -> StorageResult<bool> {
+        let result = sqlx::query(
+            "update session set revoked_at = ? where id_hash = ? and revoked_at is null",
+        )
+        .bind(Utc::now().timestamp())
+        .bind(id_hash)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn revoke_all_sessions(&self, account_id: i32) -> StorageResult<u64> {
+        let result = sqlx::query(
+            "update session set revoked_at = ? where account_id = ? and revoked_at is null",
+        )
+        .bind(Utc::now().timestamp())
+        .bind(account_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> StorageResult<u64> {
+        let result = sqlx::query("delete from session where expires_at <= ?")
+            .bind(now.timestamp())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl ItemStore for SqliteStorage {
+    async fn create_item(&self, account_id: i32, ciphertext: &[u8]) -> StorageResult<Item> {
+        Ok(sqlx::query_as(
+            r#"
+            insert into item (id, account_id, ciphertext)
+            values (?, ?, ?)
+            returning id, account_id, ciphertext, created_at, updated_at
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(account_id)
+        .bind(ciphertext)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    async fn find_item(&self, account_id: i32, id: Uuid) -> StorageResult<Option<Item>> {
+        Ok(sqlx::query_as(
+            r#"
+            select id, account_id, ciphertext, created_at, updated_at
+            from item
+            where account_id = ? and id = ?
+            "#,
+        )
+        .bind(account_id)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn list_items(&self, account_id: i32) -> StorageResult<Vec<Item>> {
+        // Timestamps only have second precision, the rowid keeps insertion order
+        Ok(sqlx::query_as(
+            r#"
+            select id, account_id, ciphertext, created_at, updated_at
+            from item
+            where account_id = ?
+            order by created_at, rowid
+            "#,
+        )
+        .bind(account_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn update_item(
+        &self,
+        account_id: i32,
+        id: Uuid,
+        ciphertext: &[u8],
+    ) -> StorageResult<Option<Item>> {
+        Ok(sqlx::query_as(
+            r#"
+            update item set ciphertext = ?, updated_at = ?
+            where account_id = ? and id = ?
+            returning id, account_id, ciphertext, created_at, updated_at
+            "#,
+        )
+        .bind(ciphertext)
+        .bind(Utc::now().timestamp())
+        .bind(account_id)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn delete_item(&self, account_id: i32, id: Uuid) -> StorageResult<bool> {
+        let result = sqlx::query("delete from item where account_id = ? and id = ?")
+            .bind(account_id)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+impl KeyLogStore for SqliteStorage {
+    async fn append_key_log_entry(
+        &self,
+        leaf_index: u64,
+        username: &str,
+        leaf: &[u8],
+    ) -> StorageResult<KeyLogEntry> {
+        let leaf_index =
+            i64::try_from(leaf_index).map_err(|e| StorageError::Backend(e.to_string()))?;
+        Ok(sqlx::query_as(
+            r#"
+            insert into key_log_entry (leaf_index, username, leaf)
+            values (?, ?, ?)
+            returning leaf_index, username, leaf, created_at
+            "#,
+        )
+        .bind(leaf_index)
+        .bind(username)
+        .bind(leaf)
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    async fn list_key_log_entries(&self) -> StorageResult<Vec<KeyLogEntry>> {
+        Ok(sqlx::query_as(
+            r#"
+            select leaf_index, username, leaf, created_at
+            from key_log_entry
+            order by leaf_index
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn list_key_log_entries_for_user(
+        &self,
+        username: &str,
+    ) -> StorageResult<Vec<KeyLogEntry>> {
+        Ok(sqlx::query_as(
+            r#"
+            select leaf_index, username, leaf, created_at
+            from key_log_entry
+            where username = ?
+            order by leaf_index
+            "#,
+        )
+        .bind(username)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn latest_key_log_entry_for_user(
+        &self,
+        username: &str,
+    ) -> StorageResult<Option<KeyLogEntry>> {
+        Ok(sqlx::query_as(
+            r#"
+            select leaf_index, username, leaf, created_at
+            from key_log_entry
+            where username = ?
+            order by leaf_index desc
+            limit 1
+            "#,
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+}
+
+#[async_trait]
+impl AuditStore for SqliteStorage {
+    async fn record_audit_event(&self, event: NewAuditEvent<'_>) -> StorageResult<AuditEvent> {
+        let _audit_lock = self.audit_lock.lock().await;
+        let mut tx = self.pool.begin().await?;
+        let event = insert_audit_event(&mut tx, event).await?;
+        tx.commit().await?;
+        Ok(event)
+    }
+
+    async fn list_audit_events(
+        &self,
+        account_id: Option<i32>,
+        before: Option<i64>,
+        limit: u32,
+    ) -> StorageResult<Vec<AuditEvent>> {
+        Ok(sqlx::query_as(
+            r#"
+            select id, account_id, username, action, detail, created_at, prev_hash, hash
+            from audit_event
+            where (?1 is null or account_id = ?1) and (?2 is null or id < ?2)
+            order by id desc
+            limit ?3
+            "#,
+        )
+        .bind(account_id)
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn list_audit_events_after(
+        &self,
+        after: i64,
+        limit: u32,
+    ) -> StorageResult<Vec<AuditEvent>> {
+        Ok(sqlx::query_as(
+            r#"
+            select id, account_id, username, action, detail, created_at, prev_hash, hash
+            from audit_event
+            where id > ?
+            order by id
+            limit ?
+            "#,
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn delete_audit_events_before(&self, cutoff: DateTime<Utc>) -> StorageResult<u64> {
+        let result = sqlx::query(
+            r#"
+            delete from audit_event
+            where id <= (select max(id) from audit_event where created_at < ?)
+            "#,
+        )
+        .bind(cutoff.timestamp())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn ping(&self) -> StorageResult<()> {
+        sqlx::query("select 1").execute(&self.pool).await?;
+        Ok(())
+    }
+}
@@ -1,14 +1,23 @@
 use std::fmt::Display;
+use std::marker::PhantomData;
+use std::ops::Deref;
 
 use axum::response::{IntoResponse, Response};
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
 use base64::Engine;
-use generic_array::GenericArray;
-use serde::{Deserialize, Serialize};
+use generic_array::{ArrayLength, GenericArray};
+use serde::de::{self, Unexpected};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use utoipa::openapi::schema::{ObjectBuilder, Schema, Type};
+use utoipa::openapi::RefOr;
+use utoipa::{PartialSchema, ToSchema};
+use zeroize::Zeroizing;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum DecodeError {
     Base64Error(base64::DecodeError),
     LengthMismatch { expected: usize, actual: usize },
+    TooLong { max: usize, actual: usize },
 }
 
 impl std::fmt::Display for DecodeError {
@@ -20,29 +29,87 @@ impl std::fmt::Display for DecodeError {
                 "Incorrect length: expected {} bytes but got {}",
                 expected, actual
             ),
+            DecodeError::TooLong { max, actual } => write!(
+                f,
+                "Too long: expected at most {} bytes but got {}",
+                max, actual
+            ),
         }
     }
 }
 
 impl std::error::Error for DecodeError {}
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(transparent)]
+/// Binary data as URL-safe base64. Human-readable formats such as JSON carry
+/// the base64 text, binary ones such as CBOR the raw bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Base64String(String);
 
+impl Serialize for Base64String {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            return serializer.serialize_str(&self.0);
+        }
+        let bytes = Zeroizing::new(
+            self.decode_bytes()
+                .map_err(|_| serde::ser::Error::custom("invalid base64 in byte string"))?,
+        );
+        serializer.serialize_bytes(&bytes)
+    }
+}
+
+impl<'de> Deserialize<'de> for Base64String {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_string(Base64Visitor)
+        } else {
+            // Binary formats may still send the base64 text
+            deserializer.deserialize_any(Base64Visitor)
+        }
+    }
+}
+
+struct Base64Visitor;
+
+impl de::Visitor<'_> for Base64Visitor {
+    type Value = Base64String;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a base64 string or a byte string")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        Ok(Base64String(value.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Self::Value, E> {
+        Ok(Base64String(value))
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+        Ok(Base64String::encode_bytes(value))
+    }
+}
+
 impl Base64String {
+    /// The result and every intermediate buffer are zeroized when dropped,
+    /// so decoding key material leaves no copies behind.
     pub fn decode<N: generic_array::ArrayLength<u8>>(
         &self,
-    ) -> Result<GenericArray<u8, N>, DecodeError> {
-        let binary_data = base64::engine::general_purpose::URL_SAFE
-            .decode(self.0.clone())
-            .map_err(DecodeError::Base64Error)?;
-        let input_len = binary_data.len();
-        let result: GenericArray<u8, N> = GenericArray::from_exact_iter(binary_data.into_iter())
-            .ok_or(DecodeError::LengthMismatch {
+    ) -> Result<Zeroizing<GenericArray<u8, N>>, DecodeError> {
+        let binary_data = Zeroizing::new(
+            base64::engine::general_purpose::URL_SAFE
+                .decode(&self.0)
+                .map_err(DecodeError::Base64Error)?,
+        );
+        if binary_data.len() != N::to_usize() {
+            return Err(DecodeError::LengthMismatch {
                 expected: N::to_usize(),
-                actual: input_len,
-            })?;
+                actual: binary_data.len(),
+            });
+        }
+        let mut result = Zeroizing::new(GenericArray::default());
+        result.copy_from_slice(&binary_data);
 
         Ok(result)
     }
@@ -50,6 +117,17 @@ impl Base64String {
     pub fn encode<N: generic_array::ArrayLength<u8>>(data: &GenericArray<u8, N>) -> Self {
         Base64String(base64::engine::general_purpose::URL_SAFE.encode(data))
     }
+
+    /// Like [`Base64String::encode`], for data without a fixed length.
+    pub fn encode_bytes(data: &[u8]) -> Self {
+        Base64String(base64::engine::general_purpose::URL_SAFE.encode(data))
+    }
+
+    pub fn decode_bytes(&self) -> Result<Vec<u8>, DecodeError> {
+        base64::engine::general_purpose::URL_SAFE
+            .decode(&self.0)
+            .map_err(DecodeError::Base64Error)
+    }
 }
 
 impl<N: generic_array::ArrayLength<u8>> From<&GenericArray<u8, N>> for Base64String {
@@ -88,6 +166,337 @@ impl From<String> for Base64String {
     }
 }
 
+/// Documented as the JSON encoding. Request fields holding [`Base64Bytes`] or
+/// [`Base64Vec`] use this schema too.
+impl PartialSchema for Base64String {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .content_encoding("base64url")
+            .description(Some(
+                "URL-safe base64 with padding. Requests may also use standard or \
+                 unpadded base64. CBOR bodies carry a byte string instead.",
+            ))
+            .into()
+    }
+}
+
+impl ToSchema for Base64String {
+    fn name() -> std::borrow::Cow<'static, str> {
+        "Base64String".into()
+    }
+}
+
+/// The alphabets a [`Base64Policy`] accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alphabet {
+    UrlSafe,
+    Standard,
+    /// Either one, told apart by the characters the input uses
+    Any,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Padding {
+    Required,
+    Forbidden,
+    Optional,
+}
+
+/// How strictly [`Base64Bytes`] and [`Base64Vec`] decode their input. They
+/// always encode URL-safe base64 with padding, whatever the policy.
+pub trait Base64Policy {
+    const ALPHABET: Alphabet;
+    const PADDING: Padding;
+
+    fn decode(input: &str) -> Result<Zeroizing<Vec<u8>>, DecodeError> {
+        let padding = match Self::PADDING {
+            Padding::Required => DecodePaddingMode::RequireCanonical,
+            Padding::Forbidden => DecodePaddingMode::RequireNone,
+            Padding::Optional => DecodePaddingMode::Indifferent,
+        };
+        let alphabet = match Self::ALPHABET {
+            Alphabet::UrlSafe => &base64::alphabet::URL_SAFE,
+            Alphabet::Standard => &base64::alphabet::STANDARD,
+            Alphabet::Any if input.contains(['+', '/']) => &base64::alphabet::STANDARD,
+            Alphabet::Any => &base64::alphabet::URL_SAFE,
+        };
+        GeneralPurpose::new(
+            alphabet,
+            GeneralPurposeConfig::new().with_decode_padding_mode(padding),
+        )
+        .decode(input)
+        .map(Zeroizing::new)
+        .map_err(DecodeError::Base64Error)
+    }
+}
+
+/// Only what the server writes itself: URL-safe base64 with padding.
+pub struct Strict;
+
+impl Base64Policy for Strict {
+    const ALPHABET: Alphabet = Alphabet::UrlSafe;
+    const PADDING: Padding = Padding::Required;
+}
+
+/// Also standard and unpadded base64, as many client libraries produce.
+pub struct Lenient;
+
+impl Base64Policy for Lenient {
+    const ALPHABET: Alphabet = Alphabet::Any;
+    const PADDING: Padding = Padding::Optional;
+}
+
+/// The newtype name the typed wrappers deserialize through, so that a
+/// deserializer can tell their errors apart from the rest of the input's.
+pub const BASE64_NEWTYPE: &str = "$salauskilke::base64";
+
+/// What a [`Base64Policy`] accepts, as error messages put it.
+struct PolicyExpected<P>(PhantomData<P>);
+
+impl<P: Base64Policy> de::Expected for PolicyExpected<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let alphabet = match P::ALPHABET {
+            Alphabet::UrlSafe => "URL-safe base64",
+            Alphabet::Standard => "standard base64",
+            Alphabet::Any => "URL-safe or standard base64",
+        };
+        let padding = match P::PADDING {
+            Padding::Required => "with padding",
+            Padding::Forbidden => "without padding",
+            Padding::Optional => "with optional padding",
+        };
+        write!(f, "{} {}", alphabet, padding)
+    }
+}
+
+struct ExactLength(usize);
+
+impl de::Expected for ExactLength {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes", self.0)
+    }
+}
+
+/// Reports a [`DecodeError`] with serde's own constructors, so that the
+/// cases stay apart in every format: text the policy refuses is an invalid
+/// value, a fixed length that does not match an invalid length, and input
+/// over a maximum a custom error.
+fn decode_error<E: de::Error, P: Base64Policy>(err: DecodeError) -> E {
+    match err {
+        DecodeError::Base64Error(err) => E::invalid_value(
+            Unexpected::Other(&err.to_string()),
+            &PolicyExpected::<P>(PhantomData),
+        ),
+        DecodeError::LengthMismatch { expected, actual } => {
+            E::invalid_length(actual, &ExactLength(expected))
+        }
+        err @ DecodeError::TooLong { .. } => E::custom(err),
+    }
+}
+
+/// Shared by the typed wrappers, which take base64 text from human-readable
+/// formats and byte strings from binary ones.
+trait FromDecoded: Sized {
+    type Policy: Base64Policy;
+
+    fn decode_str(input: &str) -> Result<Zeroizing<Vec<u8>>, DecodeError>;
+    fn from_decoded(bytes: &[u8]) -> Result<Self, DecodeError>;
+}
+
+struct DecodedVisitor<T>(PhantomData<T>);
+
+impl<'de, T: FromDecoded> de::Visitor<'de> for DecodedVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a base64 string or a byte string")
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<T, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(self)
+        } else {
+            deserializer.deserialize_any(self)
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+        T::decode_str(value)
+            .and_then(|bytes| T::from_decoded(&bytes))
+            .map_err(decode_error::<E, T::Policy>)
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<T, E> {
+        T::from_decoded(value).map_err(decode_error::<E, T::Policy>)
+    }
+}
+
+fn deserialize_decoded<'de, D: Deserializer<'de>, T: FromDecoded>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    deserializer.deserialize_newtype_struct(BASE64_NEWTYPE, DecodedVisitor(PhantomData))
+}
+
+fn serialize_encoded<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        let encoded = Zeroizing::new(base64::engine::general_purpose::URL_SAFE.encode(bytes));
+        serializer.serialize_str(&encoded)
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+/// Exactly `N` bytes, decoded while deserializing so that a request holding
+/// one has already been validated. Zeroized on drop.
+pub struct Base64Bytes<N: ArrayLength<u8>, P = Lenient> {
+    bytes: Zeroizing<GenericArray<u8, N>>,
+    policy: PhantomData<P>,
+}
+
+impl<N: ArrayLength<u8>, P> Base64Bytes<N, P> {
+    pub fn new(bytes: GenericArray<u8, N>) -> Self {
+        Self {
+            bytes: Zeroizing::new(bytes),
+            policy: PhantomData,
+        }
+    }
+}
+
+impl<N: ArrayLength<u8>, P> Deref for Base64Bytes<N, P> {
+    type Target = GenericArray<u8, N>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.bytes
+    }
+}
+
+impl<N: ArrayLength<u8>, P> Clone for Base64Bytes<N, P> {
+    fn clone(&self) -> Self {
+        Self::new((*self.bytes).clone())
+    }
+}
+
+impl<N: ArrayLength<u8>, P> std::fmt::Debug for Base64Bytes<N, P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Base64Bytes({} bytes)", N::to_usize())
+    }
+}
+
+impl<N: ArrayLength<u8>, P: Base64Policy> FromDecoded for Base64Bytes<N, P> {
+    type Policy = P;
+
+    fn decode_str(input: &str) -> Result<Zeroizing<Vec<u8>>, DecodeError> {
+        P::decode(input)
+    }
+
+    fn from_decoded(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if bytes.len() != N::to_usize() {
+            return Err(DecodeError::LengthMismatch {
+                expected: N::to_usize(),
+                actual: bytes.len(),
+            });
+        }
+        let mut result = Self::new(GenericArray::default());
+        result.bytes.copy_from_slice(bytes);
+        Ok(result)
+    }
+}
+
+impl<N: ArrayLength<u8>, P> Serialize for Base64Bytes<N, P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_encoded(&self.bytes, serializer)
+    }
+}
+
+impl<'de, N: ArrayLength<u8>, P: Base64Policy> Deserialize<'de> for Base64Bytes<N, P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_decoded(deserializer)
+    }
+}
+
+/// At most `MAX` bytes of binary data such as a ciphertext, decoded while
+/// deserializing like [`Base64Bytes`].
+pub struct Base64Vec<const MAX: usize, P = Lenient> {
+    bytes: Vec<u8>,
+    policy: PhantomData<P>,
+}
+
+impl<const MAX: usize, P> Base64Vec<MAX, P> {
+    pub fn new(bytes: Vec<u8>) -> Result<Self, DecodeError> {
+        if bytes.len() > MAX {
+            return Err(DecodeError::TooLong {
+                max: MAX,
+                actual: bytes.len(),
+            });
+        }
+        Ok(Self {
+            bytes,
+            policy: PhantomData,
+        })
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl<const MAX: usize, P> Deref for Base64Vec<MAX, P> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.bytes
+    }
+}
+
+impl<const MAX: usize, P> Clone for Base64Vec<MAX, P> {
+    fn clone(&self) -> Self {
+        Self {
+            bytes: self.bytes.clone(),
+            policy: PhantomData,
+        }
+    }
+}
+
+impl<const MAX: usize, P> std::fmt::Debug for Base64Vec<MAX, P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Base64Vec({} bytes)", self.bytes.len())
+    }
+}
+
+impl<const MAX: usize, P: Base64Policy> FromDecoded for Base64Vec<MAX, P> {
+    type Policy = P;
+
+    fn decode_str(input: &str) -> Result<Zeroizing<Vec<u8>>, DecodeError> {
+        // Every 4 characters hold 3 bytes, so overly long input is refused
+        // before decoding
+        let max_len = MAX.div_ceil(3) * 4;
+        if input.len() > max_len {
+            return Err(DecodeError::TooLong {
+                max: MAX,
+                actual: input.len() / 4 * 3,
+            });
+        }
+        P::decode(input)
+    }
+
+    fn from_decoded(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::new(bytes.to_vec())
+    }
+}
+
+impl<const MAX: usize, P> Serialize for Base64Vec<MAX, P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_encoded(&self.bytes, serializer)
+    }
+}
+
+impl<'de, const MAX: usize, P: Base64Policy> Deserialize<'de> for Base64Vec<MAX, P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_decoded(deserializer)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::unwrap_used)]
@@ -102,13 +511,14 @@ mod tests {
     #[test]
     fn correctly_decodes_input() {
         let input = Base64String("aGVsbG8=".to_string());
-        let decoded: GenericArray<u8, U5> = input.decode().unwrap();
+        let decoded: Zeroizing<GenericArray<u8, U5>> = input.decode().unwrap();
         assert_eq!(&decoded[..], b"hello");
 
         let input = Base64String("9Cd6foK9Xqr3w9TQ9tJZrrEDcckn9pkxch1R3j13yT4=".to_string());
-        let decoded: GenericArray<u8, RegistrationRequestLen<CS>> = input.decode().unwrap();
+        let decoded: Zeroizing<GenericArray<u8, RegistrationRequestLen<CS>>> =
+            input.decode().unwrap();
         assert_eq!(
-            decoded,
+            *decoded,
             [
                 244, 39, 122, 126, 130, 189, 94, 170, 247, 195, 212, 208, 246, 210, 89, 174, 177,
                 3, 113, 201, 39, 246, 153, 49, 114, 29, 81, 222, 61, 119, 201, 62
@@ -149,4 +559,52 @@ mod tests {
             Err(DecodeError::LengthMismatch { .. })
         ));
     }
+
+    #[test]
+    fn lenient_accepts_standard_and_unpadded_input() {
+        // 0xfb 0xff encodes with the characters the alphabets disagree on
+        for input in ["-_8=", "-_8", "+/8=", "+/8"] {
+            assert_eq!(*Lenient::decode(input).unwrap(), [0xfb, 0xff], "{}", input);
+        }
+        assert!(Strict::decode("-_8=").is_ok());
+        for input in ["-_8", "+/8="] {
+            assert!(Strict::decode(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn typed_wrappers_decode_while_deserializing() {
+        let bytes: Base64Bytes<U5> = serde_json::from_str(r#""aGVsbG8""#).unwrap();
+        assert_eq!(&bytes[..], b"hello");
+        assert_eq!(serde_json::to_string(&bytes).unwrap(), r#""aGVsbG8=""#);
+
+        fn error<T>(result: serde_json::Result<T>) -> String {
+            result.err().unwrap().to_string()
+        }
+        assert!(
+            error(serde_json::from_str::<Base64Bytes<U3>>(r#""aGVsbG8=""#))
+                .starts_with("invalid length 5, expected 3 bytes")
+        );
+        assert!(error(serde_json::from_str::<Base64Bytes<U5, Strict>>(
+            r#""aGVsbG8""#
+        ))
+        .contains("expected URL-safe base64 with padding"));
+
+        let vec: Base64Vec<5> = serde_json::from_str(r#""aGVsbG8=""#).unwrap();
+        assert_eq!(&vec[..], b"hello");
+        assert!(error(serde_json::from_str::<Base64Vec<4>>(r#""aGVsbG8=""#))
+            .starts_with("Too long: expected at most 4 bytes but got 5"));
+    }
+
+    #[test]
+    fn typed_wrappers_are_byte_strings_in_cbor() {
+        let vec: Base64Vec<16> = Base64Vec::new(b"hello".to_vec()).unwrap();
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&vec, &mut cbor).unwrap();
+        assert_eq!(cbor, b"\x45hello");
+
+        let bytes: Base64Bytes<U5> = ciborium::from_reader(cbor.as_slice()).unwrap();
+        assert_eq!(&bytes[..], b"hello");
+        assert!(ciborium::from_reader::<Base64Vec<4>, _>(cbor.as_slice()).is_err());
+    }
 }
@@ -1,3 +1,6 @@
 pub mod base64;
 pub mod config;
+pub mod fs;
 pub mod pg_pool;
+pub mod secret;
+pub mod telemetry;
@@ -6,3 +6,10 @@ pub async fn create_pg_pool(db_url: &str, max_connections: u32) -> Result<Pool<P
         .connect(db_url)
         .await
 }
+
+/// Creates a pool that only connects when a connection is first needed.
+pub fn create_lazy_pg_pool(db_url: &str, max_connections: u32) -> Result<Pool<Postgres>, Error> {
+    PgPoolOptions::new()
+        .max_connections(max_connections)
+        .connect_lazy(db_url)
+}
@@ -0,0 +1,75 @@
+use std::fmt::{self, Debug, Display};
+
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Key material and anything else that must not outlive its use or show up
+/// in logs. The value is zeroized when dropped, `Debug` and `Display` print
+/// `[REDACTED]`, and there is deliberately no `Serialize`, so getting at the
+/// value always goes through [`Secret::expose`].
+#[derive(Clone, Default)]
+pub struct Secret<T: Zeroize>(T);
+
+/// Secret byte strings of any length, such as OPAQUE session keys.
+pub type SecretBytes = Secret<Vec<u8>>;
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    /// For filling a buffer in place, like a key read from an RNG.
+    pub fn expose_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Zeroize> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Zeroize> ZeroizeOnDrop for Secret<T> {}
+
+impl<T: Zeroize> Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret([REDACTED])")
+    }
+}
+
+impl<T: Zeroize> Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[REDACTED]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+
+    #[test]
+    fn formatting_never_shows_the_value() {
+        let secret = SecretBytes::new(b"hunter2".to_vec());
+
+        assert_eq!(format!("{}", secret), "[REDACTED]");
+        assert_eq!(format!("{:?}", secret), "Secret([REDACTED])");
+        assert_eq!(
+            format!("{:#?}", Some(&secret)),
+            "Some(\n    Secret([REDACTED]),\n)"
+        );
+        assert_eq!(secret.expose(), b"hunter2");
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::OnceLock;
+
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use tracing_subscriber::EnvFilter;
+
+use super::config::LogFormat;
+
+/// Buckets from 1 ms to ~16 s, suitable for both request latencies and
+/// OPAQUE server operations.
+const DURATION_BUCKETS_SECS: &[f64] = &[
+    0.001, 0.002, 0.004, 0.008, 0.016, 0.032, 0.064, 0.128, 0.256, 0.512, 1.024, 2.048, 4.096,
+    8.192, 16.384,
+];
+
+static METRICS_HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+pub fn init_tracing(
+    format: LogFormat,
+    filter: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::try_new(filter)?);
+
+    match format {
+        LogFormat::Json => subscriber.json().try_init(),
+        LogFormat::Pretty => subscriber.pretty().try_init(),
+    }
+}
+
+/// Installs the global Prometheus recorder on first use and returns a handle
+/// for rendering it. Safe to call from every test server in the same process.
+pub fn metrics_handle() -> PrometheusHandle {
+    METRICS_HANDLE
+        .get_or_init(|| {
+            let builder = PrometheusBuilder::new();
+            let recorder = match builder.set_buckets(DURATION_BUCKETS_SECS) {
+                Ok(builder) => builder.build_recorder(),
+                Err(_) => PrometheusBuilder::new().build_recorder(),
+            };
+            let handle = recorder.handle();
+            if let Err(err) = metrics::set_global_recorder(recorder) {
+                tracing::warn!(error = %err, "metrics recorder was already installed");
+            }
+            handle
+        })
+        .clone()
+}
@@ -0,0 +1,368 @@
+mod utils;
+
+use backend::storage::{Role, Storage};
+use backend::utils::{base64::Base64String, config::Config};
+use opaque_ke::rand::rngs::OsRng;
+use reqwest::{Client, Method, StatusCode};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Admin tests log in more often than the default burst allows.
+fn config() -> Config {
+    Config {
+        rate_limit_burst: 100,
+        ..utils::test_config()
+    }
+}
+
+/// Starts a server and registers `root@example.com` as an admin.
+async fn setup_with_admin(client: &Client, rng: &mut OsRng) -> (String, Arc<dyn Storage>) {
+    let state = utils::test_state(config()).await;
+    let storage = state.storage.clone();
+    let (base_url, _server_handle) = utils::serve_state(state).await;
+
+    utils::register("root@example.com", "r00t", &base_url, client, rng).await;
+    let root = storage
+        .find_account_by_username("root@example.com")
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    storage
+        .set_account_role(root.id, Role::Admin)
+        .await
+        .expect("Failed to grant admin");
+
+    (base_url, storage)
+}
+
+fn decode(value: &Value) -> Vec<u8> {
+    Base64String::from(value.as_str().expect("Not a string").to_string())
+        .decode_bytes()
+        .expect("Invalid base64")
+}
+
+#[tokio::test]
+async fn admins_lock_and_unlock_accounts() {
+    let client = Client::new();
+    let mut rng = OsRng;
+    let (base_url, storage) = setup_with_admin(&client, &mut rng).await;
+    utils::register("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+    utils::register("bob@example.org", "secret", &base_url, &client, &mut rng).await;
+
+    let (root, root_session) =
+        utils::login("root@example.com", "r00t", &base_url, &client, &mut rng).await;
+    let (alice, alice_session) =
+        utils::login("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+    let admin = |method: Method, path: &'static str, nonce: &'static str| {
+        utils::signed_request(
+            method,
+            path,
+            nonce,
+            &root_session,
+            &root.session_key,
+            &base_url,
+            &client,
+        )
+    };
+
+    let response = utils::signed_get(
+        "/admin/accounts",
+        "nonce-1",
+        &alice_session,
+        &alice.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let page: Value = admin(
+        Method::GET,
+        "/admin/accounts?username=example.com",
+        "nonce-1",
+    )
+    .await
+    .json()
+    .await
+    .expect("Body is not JSON");
+    let accounts = page["accounts"].as_array().expect("Not an array");
+    assert_eq!(accounts.len(), 2);
+    assert_eq!(accounts[0]["username"], "root@example.com");
+    assert_eq!(accounts[0]["role"], "admin");
+    assert_eq!(accounts[1]["username"], "alice@example.com");
+    assert_eq!(accounts[1]["role"], "user");
+    assert!(accounts[1].get("registration_record").is_none());
+
+    let response = admin(
+        Method::POST,
+        "/admin/accounts/alice@example.com/lock",
+        "nonce-2",
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let locked: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(locked["sessions_revoked"], 1);
+    assert!(locked["account"]["locked_at"].is_i64());
+
+    // Alice's session is gone and her password no longer gets her in
+    let response = utils::signed_get(
+        "/users/alice@example.com/audit-events",
+        "nonce-2",
+        &alice_session,
+        &alice.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let (_, response) =
+        utils::try_login("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["code"], "account_locked");
+
+    let response = admin(
+        Method::POST,
+        "/admin/accounts/root@example.com/lock",
+        "nonce-3",
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = admin(Method::POST, "/admin/accounts/nobody/lock", "nonce-4").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = admin(
+        Method::POST,
+        "/admin/accounts/alice@example.com/unlock",
+        "nonce-5",
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    utils::login("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+
+    let response = admin(
+        Method::DELETE,
+        "/admin/accounts/alice@example.com/sessions",
+        "nonce-6",
+    )
+    .await;
+    let revoked: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(revoked["sessions_revoked"], 1);
+
+    // Actions on alice show up in her own trail, naming the admin
+    let alice_id = storage
+        .find_account_by_username("alice@example.com")
+        .await
+        .expect("Query failed")
+        .expect("Account not found")
+        .id;
+    let events = storage
+        .list_audit_events(Some(alice_id), None, 10)
+        .await
+        .expect("Query failed");
+    let actions: Vec<&str> = events.iter().map(|event| event.action.as_str()).collect();
+    assert_eq!(
+        actions[..5],
+        [
+            "sessions_revoked",
+            "login_succeeded",
+            "account_unlocked",
+            "login_failed",
+            "account_locked",
+        ]
+    );
+    assert_eq!(events[0].detail["admin"], "root@example.com");
+    assert_eq!(events[3].detail["reason"], "account_locked");
+
+    let root_events = storage
+        .list_audit_events(None, None, 100)
+        .await
+        .expect("Query failed");
+    assert!(root_events
+        .iter()
+        .any(|event| event.action == "accounts_searched"
+            && event.username.as_deref() == Some("root@example.com")));
+}
+
+#[tokio::test]
+async fn admins_rotate_the_signing_key() {
+    let client = Client::new();
+    let mut rng = OsRng;
+    let (base_url, _) = setup_with_admin(&client, &mut rng).await;
+    let (root, session_id) =
+        utils::login("root@example.com", "r00t", &base_url, &client, &mut rng).await;
+
+    let signing_public_key = || async {
+        let document: Value = client
+            .get(format!("{}/.well-known/salauskilke", base_url))
+            .send()
+            .await
+            .expect("Failed to send request")
+            .json()
+            .await
+            .expect("Body is not JSON");
+        let payload: Value =
+            serde_json::from_slice(&decode(&document["payload"])).expect("Payload is not JSON");
+        decode(&payload["signing_public_key"])
+    };
+    let previous_key = signing_public_key().await;
+
+    let response = utils::signed_request(
+        Method::POST,
+        "/admin/signing-key/rotate",
+        "nonce-1",
+        &session_id,
+        &root.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let rotation: Value = response.json().await.expect("Body is not JSON");
+
+    // The old key vouches for the new one
+    let endorsement = &rotation["endorsement"];
+    let payload = decode(&endorsement["payload"]);
+    UnparsedPublicKey::new(&ED25519, &previous_key)
+        .verify(&payload, &decode(&endorsement["signature"]))
+        .expect("Invalid endorsement");
+    let payload: Value = serde_json::from_slice(&payload).expect("Payload is not JSON");
+    let current_key = signing_public_key().await;
+    assert_ne!(current_key, previous_key);
+    assert_eq!(decode(&payload["signing_public_key"]), current_key);
+
+    let limits: Value = utils::signed_get(
+        "/admin/rate-limits",
+        "nonce-2",
+        &session_id,
+        &root.session_key,
+        &base_url,
+        &client,
+    )
+    .await
+    .json()
+    .await
+    .expect("Body is not JSON");
+    assert_eq!(limits["burst"], 100);
+    assert_eq!(limits["clients"][0]["client"], "127.0.0.1");
+}
+
+#[tokio::test]
+async fn admins_rotate_and_retire_opaque_keys() {
+    let client = Client::new();
+    let mut rng = OsRng;
+    let (base_url, storage) = setup_with_admin(&client, &mut rng).await;
+    utils::register("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+    let (root, session_id) =
+        utils::login("root@example.com", "r00t", &base_url, &client, &mut rng).await;
+    let admin = |method: Method, path: &'static str, nonce: &'static str| {
+        utils::signed_request(
+            method,
+            path,
+            nonce,
+            &session_id,
+            &root.session_key,
+            &base_url,
+            &client,
+        )
+    };
+
+    let keys: Value = admin(Method::GET, "/admin/opaque-keys", "nonce-1")
+        .await
+        .json()
+        .await
+        .expect("Body is not JSON");
+    assert_eq!(keys["current_key_id"], 1);
+    assert_eq!(keys["keys"][0]["accounts"], 2);
+
+    let response = admin(Method::POST, "/admin/opaque-keys/rotate", "nonce-2").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let rotated: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(rotated["key_id"], 2);
+    assert_eq!(rotated["current"], true);
+
+    // Alice still gets in with the old generation but is asked to register
+    // again, while new accounts use the new one
+    let (_, response) =
+        utils::try_login("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let login: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(login["reregistration_required"], true);
+    utils::register("bob@example.com", "secret", &base_url, &client, &mut rng).await;
+    let (_, response) =
+        utils::try_login("bob@example.com", "secret", &base_url, &client, &mut rng).await;
+    let login: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(login["reregistration_required"], false);
+
+    let document: Value = client
+        .get(format!("{}/.well-known/salauskilke", base_url))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Body is not JSON");
+    let payload: Value =
+        serde_json::from_slice(&decode(&document["payload"])).expect("Payload is not JSON");
+    assert_eq!(payload["opaque_key_id"], 2);
+    assert_eq!(payload["previous_opaque_public_keys"][0]["key_id"], 1);
+
+    for (path, nonce) in [
+        ("/admin/opaque-keys/1", "nonce-3"),
+        ("/admin/opaque-keys/2", "nonce-4"),
+    ] {
+        let response = admin(Method::DELETE, path, nonce).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: Value = response.json().await.expect("Body is not JSON");
+        assert_eq!(body["error"]["code"], "opaque_key_in_use");
+    }
+    let keys: Value = admin(Method::GET, "/admin/opaque-keys", "nonce-5")
+        .await
+        .json()
+        .await
+        .expect("Body is not JSON");
+    assert_eq!(keys["keys"][0]["accounts"], 2);
+    assert_eq!(keys["keys"][1]["accounts"], 1);
+
+    // Stands in for root and alice registering again
+    for username in ["root@example.com", "alice@example.com"] {
+        let account = storage
+            .find_account_by_username(username)
+            .await
+            .expect("Query failed")
+            .expect("Account not found");
+        storage
+            .update_registration_record(account.id, &account.registration_record, 2)
+            .await
+            .expect("Failed to update record");
+    }
+    let response = admin(Method::DELETE, "/admin/opaque-keys/1", "nonce-6").await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = admin(Method::DELETE, "/admin/opaque-keys/1", "nonce-7").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let actions: Vec<String> = storage
+        .list_audit_events(None, None, 20)
+        .await
+        .expect("Query failed")
+        .into_iter()
+        .map(|event| event.action)
+        .collect();
+    assert!(actions.contains(&"opaque_key_rotated".to_string()));
+    assert!(actions.contains(&"opaque_key_retired".to_string()));
+}
+
+#[tokio::test]
+async fn admin_routes_need_a_session() {
+    let (base_url, server_handle) = utils::setup_server().await;
+
+    let response = Client::new()
+        .get(format!("{}/admin/audit-events", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    server_handle.abort();
+}
@@ -0,0 +1,328 @@
+mod utils;
+
+use backend::controllers::{
+    opaque::CS,
+    session::{derive_mac_key, unix_timestamp, RequestToSign},
+};
+use backend::utils::base64::Base64String;
+use opaque_ke::{rand::rngs::OsRng, ClientLogin};
+use reqwest::{Client, Method, StatusCode};
+use serde_json::{json, Value};
+
+/// Sends `body` as JSON, signed with the session's MAC key.
+async fn signed_json(
+    method: Method,
+    path: &str,
+    body: Value,
+    nonce: &str,
+    (session_id, session_key): (&str, &[u8]),
+    base_url: &str,
+    client: &Client,
+) -> reqwest::Response {
+    let body = serde_json::to_vec(&body).expect("Failed to encode body");
+    let timestamp = unix_timestamp();
+    let signature = RequestToSign {
+        method: method.as_str(),
+        path,
+        timestamp,
+        nonce,
+        body: &body,
+    }
+    .sign(&derive_mac_key(session_key));
+
+    client
+        .request(method, format!("{}{}", base_url, path))
+        .header("content-type", "application/json")
+        .header("x-session-id", session_id)
+        .header("x-timestamp", timestamp.to_string())
+        .header("x-nonce", nonce)
+        .header("x-signature", Base64String::encode(&signature).to_string())
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+#[tokio::test]
+async fn users_read_their_own_audit_trail() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+    let mut rng = OsRng;
+
+    utils::register("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+    let (login_finish, session_id) =
+        utils::login("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+    utils::register("bob@example.com", "secret", &base_url, &client, &mut rng).await;
+
+    // Start a login and answer with a finalization that cannot verify
+    let login_start = ClientLogin::<CS>::start(&mut rng, b"guess").expect("Failed to start login");
+    client
+        .post(format!("{}/auth/login/init", base_url))
+        .json(&json!({
+            "username": "alice@example.com",
+            "credential_request": Base64String::encode(&login_start.message.serialize()),
+        }))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .error_for_status()
+        .expect("Login init failed");
+    let response = client
+        .post(format!("{}/auth/login/finish", base_url))
+        .json(&json!({
+            "username": "alice@example.com",
+            "credential_finish": Base64String::encode_bytes(&[0; 64]),
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let path = "/users/alice@example.com/audit-events?limit=2";
+    let response = utils::signed_get(
+        path,
+        "nonce-1",
+        &session_id,
+        &login_finish.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page: Value = response.json().await.expect("Body is not JSON");
+    let actions: Vec<&str> = page["events"]
+        .as_array()
+        .expect("Not an array")
+        .iter()
+        .map(|event| event["action"].as_str().expect("Missing action"))
+        .collect();
+    assert_eq!(actions, vec!["login_failed", "login_succeeded"]);
+    assert_eq!(page["events"][0]["username"], "alice@example.com");
+
+    let path = format!(
+        "/users/alice@example.com/audit-events?before={}",
+        page["next_before"]
+    );
+    let page: Value = utils::signed_get(
+        &path,
+        "nonce-2",
+        &session_id,
+        &login_finish.session_key,
+        &base_url,
+        &client,
+    )
+    .await
+    .json()
+    .await
+    .expect("Body is not JSON");
+    assert_eq!(page["events"][0]["action"], "account_registered");
+    assert_eq!(page["events"].as_array().map(Vec::len), Some(1));
+    assert_eq!(page["next_before"], Value::Null);
+
+    let response = utils::signed_get(
+        "/users/bob@example.com/audit-events",
+        "nonce-3",
+        &session_id,
+        &login_finish.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn recovery_logins_without_a_code_fail_like_wrong_guesses() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+    let mut rng = OsRng;
+
+    utils::register("carol@example.com", "hunter2", &base_url, &client, &mut rng).await;
+    let (login_finish, session_id) =
+        utils::login("carol@example.com", "hunter2", &base_url, &client, &mut rng).await;
+
+    let response = utils::signed_get(
+        "/v1/auth/recovery",
+        "nonce-1",
+        &session_id,
+        &login_finish.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Accounts without a recovery code still answer the login
+    let login_start = ClientLogin::<CS>::start(&mut rng, b"a code").expect("Failed to start login");
+    client
+        .post(format!("{}/v1/auth/recovery/login/init", base_url))
+        .json(&json!({
+            "username": "carol@example.com",
+            "credential_request": Base64String::encode(&login_start.message.serialize()),
+        }))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .error_for_status()
+        .expect("Recovery login init failed");
+    let response = client
+        .post(format!("{}/v1/auth/recovery/login/finish", base_url))
+        .json(&json!({
+            "username": "carol@example.com",
+            "credential_finish": Base64String::encode_bytes(&[0; 64]),
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["code"], "invalid_credentials");
+
+    let page: Value = utils::signed_get(
+        "/v1/users/carol@example.com/audit-events?limit=1",
+        "nonce-2",
+        &session_id,
+        &login_finish.session_key,
+        &base_url,
+        &client,
+    )
+    .await
+    .json()
+    .await
+    .expect("Body is not JSON");
+    assert_eq!(page["events"][0]["action"], "login_failed");
+    assert_eq!(page["events"][0]["detail"]["credential"], "recovery_code");
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn device_keys_shares_and_deletions_are_audited() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+    let mut rng = OsRng;
+
+    utils::register("dave@example.com", "pa55word", &base_url, &client, &mut rng).await;
+    let (login_finish, session_id) =
+        utils::login("dave@example.com", "pa55word", &base_url, &client, &mut rng).await;
+    let dave = (session_id.as_str(), login_finish.session_key.as_slice());
+    utils::register("erin@example.com", "s3cret", &base_url, &client, &mut rng).await;
+    let (login_finish, session_id) =
+        utils::login("erin@example.com", "s3cret", &base_url, &client, &mut rng).await;
+    let erin = (session_id.as_str(), login_finish.session_key.as_slice());
+
+    let laptop = json!({ "algorithm": "x25519", "key": Base64String::encode_bytes(&[1; 32]) });
+    let phone = json!({ "algorithm": "x25519", "key": Base64String::encode_bytes(&[2; 32]) });
+    for (nonce, keys) in [("nonce-1", json!([laptop])), ("nonce-2", json!([phone]))] {
+        let response = signed_json(
+            Method::PUT,
+            "/v1/users/dave@example.com/public-keys",
+            json!({ "keys": keys }),
+            nonce,
+            dave,
+            &base_url,
+            &client,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let response = signed_json(
+        Method::POST,
+        "/v1/users/dave@example.com/shares",
+        json!({ "recipient": "erin@example.com" }),
+        "nonce-3",
+        dave,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    signed_json(
+        Method::PUT,
+        "/v1/users/erin@example.com/public-keys",
+        json!({ "keys": [laptop] }),
+        "nonce-1",
+        erin,
+        &base_url,
+        &client,
+    )
+    .await
+    .error_for_status()
+    .expect("Publishing keys failed");
+    let response = signed_json(
+        Method::POST,
+        "/v1/users/dave@example.com/shares",
+        json!({ "recipient": "erin@example.com" }),
+        "nonce-4",
+        dave,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let item: Value = signed_json(
+        Method::POST,
+        "/v1/items",
+        json!({ "ciphertext": Base64String::encode_bytes(b"sealed") }),
+        "nonce-5",
+        dave,
+        &base_url,
+        &client,
+    )
+    .await
+    .json()
+    .await
+    .expect("Body is not JSON");
+    let item_id = item["id"].as_str().expect("Missing id");
+    let response = utils::signed_request(
+        Method::DELETE,
+        &format!("/v1/items/{}", item_id),
+        "nonce-6",
+        dave.0,
+        dave.1,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let page: Value = utils::signed_get(
+        "/v1/users/dave@example.com/audit-events?limit=7",
+        "nonce-7",
+        dave.0,
+        dave.1,
+        &base_url,
+        &client,
+    )
+    .await
+    .json()
+    .await
+    .expect("Body is not JSON");
+    let events = page["events"].as_array().expect("Not an array");
+    let actions: Vec<&str> = events
+        .iter()
+        .map(|event| event["action"].as_str().expect("Missing action"))
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            "item_deleted",
+            "share_granted",
+            "device_key_removed",
+            "device_key_added",
+            "public_keys_published",
+            "device_key_added",
+            "public_keys_published",
+        ]
+    );
+    assert_eq!(events[0]["detail"]["item_id"], item_id);
+    assert_eq!(events[1]["detail"]["recipient"], "erin@example.com");
+    assert_eq!(events[2]["detail"]["key"], laptop["key"]);
+    assert_eq!(events[3]["detail"]["key"], phone["key"]);
+
+    server_handle.abort();
+}
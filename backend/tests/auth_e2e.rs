@@ -1,15 +1,24 @@
 #![allow(unused)]
 mod utils;
 
-use backend::{controllers::opaque::CS, utils::base64::Base64String};
-use generic_array::GenericArray;
-use opaque_ke::{
-    rand::rngs::OsRng, ClientLogin, ClientLoginFinishParameters, ClientLoginFinishResult,
-    ClientRegistration, ClientRegistrationFinishParameters, CredentialResponse,
-    CredentialResponseLen, RegistrationResponse, RegistrationResponseLen,
+use std::sync::Arc;
+
+use backend::{
+    controllers::{
+        envelope::KekRing,
+        key_provider::{memory::MemoryKeyProvider, KeyProvider},
+        opaque::CS,
+        sealed_channel::{ChannelKeys, Direction, SEALED_CONTENT_TYPE},
+        session::{derive_mac_key, unix_timestamp, RequestToSign},
+    },
+    http::initialize_app_state,
+    storage::{encrypted::EncryptedStorage, memory::MemoryStorage, Storage},
+    utils::base64::Base64String,
 };
+use opaque_ke::rand::rngs::OsRng;
 use reqwest::Client;
 use serde_json::json;
+use zeroize::Zeroizing;
 
 #[tokio::test]
 async fn test_server_setup() {
@@ -30,139 +39,468 @@ async fn test_server_setup() {
     server_handle.abort();
 }
 
-async fn register(
-    username: &str,
-    password: &str,
-    base_url: &str,
-    client: &Client,
-    rng: &mut OsRng,
-) {
-    let registration_start = ClientRegistration::<CS>::start(rng, password.as_bytes()).unwrap();
-    let registration_request = Base64String::encode(&registration_start.message.serialize());
+#[tokio::test]
+async fn test_register_login_e2e() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+    let mut rng = OsRng;
 
-    let response_body = client
-        .post(format!("{}/auth/register/init", base_url))
-        .json(&json!({ "username": username, "registration_request": registration_request }))
-        .send()
+    utils::register("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+
+    let (login_finish1, _) =
+        utils::login("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+
+    let (login_finish2, _) =
+        utils::login("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+
+    assert_eq!(login_finish1.export_key, login_finish2.export_key);
+    assert_eq!(login_finish1.server_s_pk, login_finish2.server_s_pk);
+
+    utils::register("bob@example.com", "secret", &base_url, &client, &mut rng).await;
+
+    utils::login("bob@example.com", "secret", &base_url, &client, &mut rng).await;
+
+    server_handle.abort();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_logins_wait_for_the_sweeper_e2e() {
+    let state = utils::test_state(backend::utils::config::Config {
+        sweep_interval_secs: 1,
+        rate_limit_per_minute: 0,
+        ..utils::test_config()
+    })
+    .await;
+    let (base_url, server_handle) = utils::serve_state(state.clone()).await;
+    let client = Client::new();
+    let usernames: Vec<String> = (0..4).map(|i| format!("user{}@example.com", i)).collect();
+    for username in &usernames {
+        utils::register(username, "a$$word", &base_url, &client, &mut OsRng).await;
+    }
+
+    // Sweeps back to back besides the sweeper, so that logins keep finding
+    // the OPAQUE controller locked
+    let sweeping = Arc::new(std::sync::atomic::AtomicBool::new(true));
+    let sweeper = {
+        let sweeping = sweeping.clone();
+        let opaque_controller = state.opaque_controller.clone();
+        std::thread::spawn(move || {
+            while sweeping.load(std::sync::atomic::Ordering::Relaxed) {
+                let mut opaque_controller = opaque_controller.lock().unwrap();
+                opaque_controller.sweep_expired_login_sessions();
+                opaque_controller.sweep_expired_registrations();
+            }
+        })
+    };
+
+    let logins: Vec<_> = usernames
+        .into_iter()
+        .map(|username| {
+            let base_url = base_url.clone();
+            let client = client.clone();
+            tokio::spawn(async move {
+                utils::try_login(&username, "a$$word", &base_url, &client, &mut OsRng)
+                    .await
+                    .1
+                    .status()
+            })
+        })
+        .collect();
+    for login in logins {
+        assert_eq!(login.await.unwrap(), reqwest::StatusCode::OK);
+    }
+
+    sweeping.store(false, std::sync::atomic::Ordering::Relaxed);
+    sweeper.join().unwrap();
+    server_handle.abort();
+}
+
+/// Builds the app state the way `main` does with a key provider configured.
+async fn provider_state(
+    inner: Arc<dyn Storage>,
+    provider: Arc<dyn KeyProvider>,
+) -> backend::http::AppState {
+    let keks = KekRing::load(provider.as_ref()).unwrap();
+    let storage = EncryptedStorage::new(inner, Arc::new(keks));
+    initialize_app_state(utils::test_config(), Arc::new(storage), Some(provider))
         .await
         .unwrap()
-        .error_for_status()
+}
+
+#[tokio::test]
+async fn login_after_restart_e2e() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = utils::test_config();
+    config.opaque_keyring_path = Some(
+        dir.path()
+            .join("opaque-keyring.json")
+            .to_str()
+            .unwrap()
+            .to_string(),
+    );
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+    let client = Client::new();
+    let mut rng = OsRng;
+
+    let state = utils::test_state_with_storage(config.clone(), storage.clone()).await;
+    let (base_url, server_handle) = utils::serve_state(state).await;
+    utils::register("heidi@example.com", "hunter2", &base_url, &client, &mut rng).await;
+    server_handle.abort();
+
+    // The restarted server loads the same keyring, so the stored record still
+    // verifies
+    let state = utils::test_state_with_storage(config, storage).await;
+    let (base_url, server_handle) = utils::serve_state(state).await;
+    utils::login("heidi@example.com", "hunter2", &base_url, &client, &mut rng).await;
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn registration_records_are_encrypted_at_rest_e2e() {
+    let inner: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+    let state = provider_state(inner.clone(), Arc::new(MemoryKeyProvider::default())).await;
+    let (base_url, server_handle) = utils::serve_state(state.clone()).await;
+    let client = Client::new();
+    let mut rng = OsRng;
+
+    utils::register("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+    utils::login("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+
+    let stored = inner
+        .find_account_by_username("alice@example.com")
+        .await
         .unwrap()
-        .text()
+        .unwrap();
+    let account = state
+        .storage
+        .find_account_by_username("alice@example.com")
         .await
+        .unwrap()
         .unwrap();
+    assert_eq!(stored.kek_id, Some(1));
+    assert_eq!(account.credential_id, b"alice@example.com");
+    assert_ne!(stored.credential_id, account.credential_id);
+    assert_ne!(stored.registration_record, account.registration_record);
 
-    let registration_response = Base64String::decode(&response_body.into())
-        .map(|r: GenericArray<u8, RegistrationResponseLen<CS>>| {
-            RegistrationResponse::deserialize(&r)
-        })
-        .unwrap()
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn key_provider_secrets_survive_restart_e2e() {
+    let inner: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+    let provider: Arc<dyn KeyProvider> = Arc::new(MemoryKeyProvider::default());
+    let state = provider_state(inner.clone(), provider.clone()).await;
+    let signing_key = state.current_signing_key().unwrap();
+    let (base_url, server_handle) = utils::serve_state(state).await;
+    let client = Client::new();
+    let mut rng = OsRng;
+
+    utils::register("carol@example.com", "hunter2", &base_url, &client, &mut rng).await;
+    let (before, _) =
+        utils::login("carol@example.com", "hunter2", &base_url, &client, &mut rng).await;
+    server_handle.abort();
+
+    let restarted = provider_state(inner, provider).await;
+    assert_eq!(
+        restarted.current_signing_key().unwrap().public_key(),
+        signing_key.public_key()
+    );
+    let (base_url, server_handle) = utils::serve_state(restarted).await;
+    let (after, _) =
+        utils::login("carol@example.com", "hunter2", &base_url, &client, &mut rng).await;
+    assert_eq!(before.export_key, after.export_key);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+#[should_panic]
+async fn incorrect_password_fails_e2e() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+    let mut rng = OsRng;
+
+    utils::register("dave@example.com", "letmein", &base_url, &client, &mut rng).await;
+
+    utils::login("dave@example.com", "what", &base_url, &client, &mut rng).await;
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+#[should_panic]
+async fn registering_taken_username_fails_e2e() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+    let mut rng = OsRng;
+
+    utils::register("grace@example.com", "first", &base_url, &client, &mut rng).await;
+
+    utils::register("grace@example.com", "second", &base_url, &client, &mut rng).await;
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn usernames_with_control_characters_are_refused_e2e() {
+    use opaque_ke::ClientRegistration;
+
+    let (base_url, server_handle) = utils::setup_server().await;
+    let mut rng = OsRng;
+    let start = ClientRegistration::<CS>::start(&mut rng, b"hunter2").unwrap();
+
+    let response = Client::new()
+        .post(format!("{}/auth/register/init", base_url))
+        .json(&json!({
+            "username": "recovery\0alice@example.com",
+            "registration_request": Base64String::encode(&start.message.serialize()),
+        }))
+        .send()
+        .await
         .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "invalid_fields");
+    assert_eq!(body["error"]["details"][0]["field"], "username");
+
+    server_handle.abort();
+}
 
-    let registration_finish = registration_start
+#[tokio::test]
+async fn register_finish_requires_ticket_from_init_e2e() {
+    use generic_array::GenericArray;
+    use opaque_ke::{
+        ClientRegistration, ClientRegistrationFinishParameters, RegistrationResponse,
+        RegistrationResponseLen,
+    };
+
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+    let mut rng = OsRng;
+
+    let start = ClientRegistration::<CS>::start(&mut rng, b"hunter2").unwrap();
+    let request = Base64String::encode(&start.message.serialize());
+    let mut inits = Vec::new();
+    for _ in 0..2 {
+        let init: serde_json::Value = client
+            .post(format!("{}/auth/register/init", base_url))
+            .json(&json!({
+                "username": "heidi@example.com",
+                "registration_request": request,
+            }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        inits.push(init);
+    }
+    let init = &inits[0];
+    let registration_response: Zeroizing<GenericArray<u8, RegistrationResponseLen<CS>>> =
+        Base64String::from(init["registration_response"].as_str().unwrap().to_string())
+            .decode()
+            .unwrap();
+    let upload = start
         .state
         .finish(
-            rng,
-            password.as_bytes(),
-            registration_response,
+            &mut rng,
+            b"hunter2",
+            RegistrationResponse::deserialize(&registration_response).unwrap(),
             ClientRegistrationFinishParameters::default(),
         )
         .unwrap()
         .message
         .serialize();
+    let ticket = init["registration_ticket"].as_str().unwrap();
+    let other_ticket = inits[1]["registration_ticket"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let other_request = Base64String::encode(
+        &ClientRegistration::<CS>::start(&mut rng, b"hunter2")
+            .unwrap()
+            .message
+            .serialize(),
+    );
 
-    client
-        .post(format!("{}/auth/register/finish", base_url))
-        .json(&json!({ "username": username, "registration_finish": Base64String::encode(&registration_finish) }))
-        .send()
+    let finish = |username: &'static str, ticket: String, request: &Base64String| {
+        client
+            .post(format!("{}/auth/register/finish", base_url))
+            .json(&json!({
+                "username": username,
+                "registration_ticket": ticket,
+                "registration_request": request,
+                "registration_finish": Base64String::encode(&upload),
+            }))
+            .send()
+    };
+
+    for (username, ticket, request) in [
+        (
+            "heidi@example.com",
+            Base64String::encode_bytes(&[0u8; 32]).to_string(),
+            &request,
+        ),
+        ("ivan@example.com", ticket.to_string(), &request),
+        // The upload must answer the request the ticket was issued for
+        ("heidi@example.com", other_ticket, &other_request),
+    ] {
+        let response = finish(username, ticket, request).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "registration_ticket_expired");
+    }
+
+    // A rejected attempt for another username consumed the ticket
+    let response = finish("heidi@example.com", ticket.to_string(), &request)
         .await
-        .unwrap()
-        .error_for_status()
         .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    server_handle.abort();
 }
 
-async fn login(
-    username: &str,
-    password: &str,
+async fn sealed_get(
+    path: &str,
+    nonce: &str,
+    sequence: u64,
+    session_id: &str,
+    session_key: &[u8],
     base_url: &str,
     client: &Client,
-    rng: &mut OsRng,
-) -> ClientLoginFinishResult<CS> {
-    let login_start = ClientLogin::<CS>::start(rng, password.as_bytes()).unwrap();
-    let credential_request = Base64String::encode(&login_start.message.serialize());
-
-    let response_body = client
-        .post(format!("{}/auth/login/init", base_url))
-        .json(&json!({ "username": username, "credential_request": credential_request }))
-        .send()
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
-
-    let credential_response = Base64String::decode(&response_body.into())
-        .map(|r: GenericArray<u8, CredentialResponseLen<CS>>| CredentialResponse::deserialize(&r))
-        .unwrap()
-        .unwrap();
-
-    let login_finish = login_start
-        .state
-        .finish(
-            password.as_bytes(),
-            credential_response,
-            ClientLoginFinishParameters::default(),
-        )
+) -> reqwest::Response {
+    let body = ChannelKeys::derive(session_key)
+        .seal(Direction::ClientToServer, sequence, b"")
         .unwrap();
-
-    let login_finish_message = login_finish.message.serialize();
+    let timestamp = unix_timestamp();
+    let signature = RequestToSign {
+        method: "GET",
+        path,
+        timestamp,
+        nonce,
+        body: &body,
+    }
+    .sign(&derive_mac_key(session_key));
 
     client
-        .post(format!("{}/auth/login/finish", base_url))
-        .json(&json!({ "username": username, "credential_finish": Base64String::encode(&login_finish_message) }))
+        .get(format!("{}{}", base_url, path))
+        .header("content-type", SEALED_CONTENT_TYPE)
+        .header("x-session-id", session_id)
+        .header("x-timestamp", timestamp.to_string())
+        .header("x-nonce", nonce)
+        .header("x-signature", Base64String::encode(&signature).to_string())
+        .body(body)
         .send()
         .await
         .unwrap()
-        .error_for_status()
-        .unwrap();
-
-    login_finish
 }
 
 #[tokio::test]
-async fn test_register_login_e2e() {
+async fn signed_session_request_e2e() {
     let (base_url, server_handle) = utils::setup_server().await;
     let client = Client::new();
     let mut rng = OsRng;
 
-    register("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
-
-    let login_finish1 = login("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+    utils::register("erin@example.com", "hunter2", &base_url, &client, &mut rng).await;
+    let (login_finish, session_id) =
+        utils::login("erin@example.com", "hunter2", &base_url, &client, &mut rng).await;
 
-    let login_finish2 = login("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+    let response = utils::signed_get(
+        "/auth/session",
+        "nonce-1",
+        &session_id,
+        &login_finish.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["username"], "erin@example.com");
 
-    assert_eq!(login_finish1.export_key, login_finish2.export_key);
-    assert_eq!(login_finish1.server_s_pk, login_finish2.server_s_pk);
+    // Replaying the same nonce is rejected
+    let response = utils::signed_get(
+        "/auth/session",
+        "nonce-1",
+        &session_id,
+        &login_finish.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
 
-    register("bob@example.com", "secret", &base_url, &client, &mut rng).await;
+    // The session id alone is useless without the session key
+    let response = utils::signed_get(
+        "/auth/session",
+        "nonce-2",
+        &session_id,
+        &[0u8; 64],
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
 
-    login("bob@example.com", "secret", &base_url, &client, &mut rng).await;
+    let response = client
+        .get(format!("{}/auth/session", base_url))
+        .header("x-session-id", &session_id)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
 
     server_handle.abort();
 }
 
 #[tokio::test]
-#[should_panic]
-async fn incorrect_password_fails_e2e() {
+async fn sealed_session_request_e2e() {
     let (base_url, server_handle) = utils::setup_server().await;
     let client = Client::new();
     let mut rng = OsRng;
 
-    register("dave@example.com", "letmein", &base_url, &client, &mut rng).await;
+    utils::register("frank@example.com", "pa55", &base_url, &client, &mut rng).await;
+    let (login_finish, session_id) =
+        utils::login("frank@example.com", "pa55", &base_url, &client, &mut rng).await;
+    let keys = ChannelKeys::derive(&login_finish.session_key);
+
+    for sequence in 0..2 {
+        let response = sealed_get(
+            "/auth/session",
+            &format!("nonce-{}", sequence),
+            sequence,
+            &session_id,
+            &login_finish.session_key,
+            &base_url,
+            &client,
+        )
+        .await;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], SEALED_CONTENT_TYPE);
+
+        let sealed = response.bytes().await.unwrap();
+        let (response_sequence, plaintext) = keys.open(Direction::ServerToClient, &sealed).unwrap();
+        assert_eq!(response_sequence, sequence);
+        let body: serde_json::Value = serde_json::from_slice(&plaintext).unwrap();
+        assert_eq!(body["username"], "frank@example.com");
+    }
 
-    login("dave@example.com", "what", &base_url, &client, &mut rng).await;
+    // A sealed message replayed under a fresh MAC nonce is still rejected
+    let response = sealed_get(
+        "/auth/session",
+        "nonce-replay",
+        0,
+        &session_id,
+        &login_finish.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
 
     server_handle.abort();
 }
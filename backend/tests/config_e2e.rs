@@ -0,0 +1,105 @@
+mod utils;
+
+use reqwest::{header, Client, Method, StatusCode};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn advertises_ksf_parameters() {
+    let mut config = utils::test_config();
+    config.argon2_memory_kib = 65536;
+    config.argon2_iterations = 3;
+    let (base_url, server_handle) = utils::setup_server_with(config).await;
+
+    let body: Value = Client::new()
+        .get(format!("{}/auth/parameters", base_url))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Body is not JSON");
+
+    assert_eq!(body["ksf"]["algorithm"], "argon2id");
+    assert_eq!(body["ksf"]["memory_kib"], 65536);
+    assert_eq!(body["ksf"]["iterations"], 3);
+    assert_eq!(body["ksf"]["parallelism"], 1);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn rate_limits_login_attempts_per_client() {
+    let mut config = utils::test_config();
+    config.rate_limit_per_minute = 1;
+    config.rate_limit_burst = 2;
+    let (base_url, server_handle) = utils::setup_server_with(config).await;
+    let client = Client::new();
+
+    let mut statuses = Vec::new();
+    let mut last_response = None;
+    for _ in 0..3 {
+        let response = client
+            .post(format!("{}/auth/login/init", base_url))
+            .json(&json!({
+                "username": "nobody@example.com",
+                "credential_request": "AAAA"
+            }))
+            .send()
+            .await
+            .expect("Failed to send request");
+        statuses.push(response.status());
+        last_response = Some(response);
+    }
+
+    assert_eq!(statuses[0], StatusCode::BAD_REQUEST);
+    assert_eq!(statuses[1], StatusCode::BAD_REQUEST);
+    assert_eq!(statuses[2], StatusCode::TOO_MANY_REQUESTS);
+
+    let response = last_response.expect("No response");
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["code"], "rate_limited");
+
+    // Other routes are not limited
+    let response = client
+        .get(format!("{}/healthz", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn answers_cors_preflight_for_allowed_origins() {
+    let mut config = utils::test_config();
+    config.cors_allowed_origins = vec!["https://app.example.com".to_string()];
+    let (base_url, server_handle) = utils::setup_server_with(config).await;
+    let client = Client::new();
+
+    let preflight = |origin: &'static str| {
+        client
+            .request(Method::OPTIONS, format!("{}/auth/login/init", base_url))
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .send()
+    };
+
+    let allowed = preflight("https://app.example.com")
+        .await
+        .expect("Failed to send request");
+    assert_eq!(
+        allowed.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://app.example.com"
+    );
+
+    let denied = preflight("https://evil.example.com")
+        .await
+        .expect("Failed to send request");
+    assert!(!denied
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+    server_handle.abort();
+}
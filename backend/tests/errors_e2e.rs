@@ -0,0 +1,132 @@
+mod utils;
+
+use backend::controllers::opaque::CS;
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use opaque_ke::{rand::rngs::OsRng, ClientLogin};
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn error_envelope_has_stable_code_and_request_id() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    let response = client
+        .post(format!("{}/auth/login/finish", base_url))
+        .json(&json!({
+            "username": "nobody@example.com",
+            "credential_finish": "AAAA"
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let request_id = response.headers()["x-request-id"]
+        .to_str()
+        .expect("Request id is not a string")
+        .to_string();
+    let body: Value = response.json().await.expect("Body is not JSON");
+
+    assert_eq!(body["error"]["code"], "invalid_fields");
+    assert_eq!(body["error"]["request_id"], request_id);
+    assert_eq!(body["error"]["details"][0]["field"], "credential_finish");
+    assert_eq!(
+        body["error"]["details"][0]["code"],
+        "decode_length_mismatch"
+    );
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn propagates_client_request_id() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    let response = client
+        .get(format!("{}/auth/session", base_url))
+        .header("x-request-id", "client-chosen-id")
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()["x-request-id"], "client-chosen-id");
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["code"], "unauthenticated");
+    assert_eq!(body["error"]["request_id"], "client-chosen-id");
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn malformed_json_is_reported_as_invalid_json() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    let response = client
+        .post(format!("{}/auth/login/init", base_url))
+        .header("content-type", "application/json")
+        .body("{\"username\": ")
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["code"], "invalid_json");
+
+    let response = client
+        .get(format!("{}/does-not-exist", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["code"], "not_found");
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn standard_and_unpadded_base64_are_accepted() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    // Encoded the way most base64 libraries do by default
+    let credential_request = STANDARD_NO_PAD.encode(
+        ClientLogin::<CS>::start(&mut OsRng, b"hunter2")
+            .unwrap()
+            .message
+            .serialize(),
+    );
+    let response = client
+        .post(format!("{}/auth/login/init", base_url))
+        .json(&json!({
+            "username": "nobody@example.com",
+            "credential_request": credential_request,
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .post(format!("{}/auth/login/init", base_url))
+        .json(&json!({
+            "username": "nobody@example.com",
+            "credential_request": "not base64!",
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["code"], "invalid_fields");
+    assert_eq!(body["error"]["details"][0]["field"], "credential_request");
+    assert_eq!(body["error"]["details"][0]["code"], "decode_invalid_base64");
+
+    server_handle.abort();
+}
@@ -0,0 +1,72 @@
+mod utils;
+
+use std::time::Duration;
+
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+use tokio::sync::oneshot;
+
+#[tokio::test]
+async fn healthz_reports_liveness() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    let response = client
+        .get(format!("{}/healthz", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["status"], "ok");
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn readyz_reports_each_check() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    let response = client
+        .get(format!("{}/readyz", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["checks"]["server_setup"], "ok");
+    assert_eq!(body["checks"]["database"], "ok");
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn server_stops_after_shutdown_signal() {
+    let (base_url, listener) = utils::bind().await;
+    let state = utils::test_state(utils::test_config()).await;
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+    let server_handle = tokio::spawn(backend::http::serve_until(listener, state, async {
+        shutdown_rx.await.ok();
+    }));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let response = Client::new()
+        .get(format!("{}/healthz", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    shutdown_tx.send(()).expect("Server already stopped");
+
+    let result = tokio::time::timeout(Duration::from_secs(5), server_handle)
+        .await
+        .expect("Server did not stop in time")
+        .expect("Server task panicked");
+    assert!(result.is_ok());
+}
@@ -0,0 +1,261 @@
+use backend::controllers::audit::verify_chain;
+use backend::models::{accounts::NewAccount, audit::NewAuditEvent, Models};
+use backend::storage::postgres::PostgresStorage;
+use serde_json::json;
+use sqlx::{types::chrono::Utc, PgPool};
+use std::{path::Path, time::Duration};
+
+async fn create_account(models: &Models, username: &str) -> i32 {
+    models
+        .accounts()
+        .create(NewAccount {
+            username,
+            credential_id: username.as_bytes(),
+            client_identity: username.as_bytes(),
+            registration_record: b"record",
+            opaque_key_id: 1,
+            kek_id: None,
+            wrapped_dek: None,
+            password_wrapped_master_key: None,
+        })
+        .await
+        .expect("Failed to create account")
+        .id
+}
+
+#[sqlx::test(migrations = "db/migrations")]
+async fn finds_account_by_username(pool: PgPool) {
+    let models = Models::new(pool);
+    let id = create_account(&models, "alice@example.com").await;
+
+    let account = models
+        .accounts()
+        .find_by_username("alice@example.com")
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    assert_eq!(account.id, id);
+    assert_eq!(account.registration_record, b"record");
+
+    assert!(models
+        .accounts()
+        .find_by_username("bob@example.com")
+        .await
+        .expect("Query failed")
+        .is_none());
+}
+
+#[sqlx::test(migrations = "db/migrations")]
+async fn password_change_revokes_sessions_atomically(pool: PgPool) {
+    let models = Models::new(pool);
+    let id = create_account(&models, "alice@example.com").await;
+    let expires_at = Utc::now() + Duration::from_secs(3600);
+    models
+        .sessions()
+        .create(b"session-1", id, expires_at)
+        .await
+        .expect("Failed to create session");
+    models
+        .sessions()
+        .create(b"session-2", id, expires_at)
+        .await
+        .expect("Failed to create session");
+
+    let revoked = models
+        .change_password(id, b"new record", 1, None, b"session-1")
+        .await
+        .expect("Password change failed");
+    assert_eq!(revoked, 1);
+
+    assert!(models
+        .sessions()
+        .find_active(b"session-1", Utc::now())
+        .await
+        .expect("Query failed")
+        .is_some());
+    assert!(models
+        .sessions()
+        .find_active(b"session-2", Utc::now())
+        .await
+        .expect("Query failed")
+        .is_none());
+    let account = models
+        .accounts()
+        .find_by_username("alice@example.com")
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    assert_eq!(account.registration_record, b"new record");
+
+    let events = models
+        .audit()
+        .list_for_account(id, None, 10)
+        .await
+        .expect("Query failed");
+    assert_eq!(events[0].action, "password_changed");
+    assert_eq!(events[0].detail, json!({ "sessions_revoked": 1 }));
+}
+
+#[sqlx::test(migrations = "db/migrations")]
+async fn password_change_of_missing_account_fails(pool: PgPool) {
+    let models = Models::new(pool);
+
+    assert!(matches!(
+        models
+            .change_password(404, b"new record", 1, None, b"session-1")
+            .await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+    assert!(models
+        .audit()
+        .list_for_account(404, None, 10)
+        .await
+        .expect("Query failed")
+        .is_empty());
+}
+
+#[sqlx::test(migrations = "db/migrations")]
+async fn dropped_unit_of_work_rolls_back(pool: PgPool) {
+    let models = Models::new(pool);
+    let id = create_account(&models, "alice@example.com").await;
+
+    {
+        let mut uow = models.begin().await.expect("Failed to begin");
+        uow.items()
+            .create(id, b"ciphertext")
+            .await
+            .expect("Failed to create item");
+        assert_eq!(
+            uow.items()
+                .list_for_account(id)
+                .await
+                .expect("Query failed")
+                .len(),
+            1
+        );
+    }
+
+    assert!(models
+        .items()
+        .list_for_account(id)
+        .await
+        .expect("Query failed")
+        .is_empty());
+}
+
+#[sqlx::test(migrations = "db/migrations")]
+async fn items_are_scoped_to_their_account(pool: PgPool) {
+    let models = Models::new(pool);
+    let alice = create_account(&models, "alice@example.com").await;
+    let bob = create_account(&models, "bob@example.com").await;
+
+    let item = models
+        .items()
+        .create(alice, b"ciphertext")
+        .await
+        .expect("Failed to create item");
+
+    assert!(models
+        .items()
+        .find(bob, item.id)
+        .await
+        .expect("Query failed")
+        .is_none());
+    assert!(models
+        .items()
+        .update(bob, item.id, b"overwritten")
+        .await
+        .expect("Query failed")
+        .is_none());
+    assert!(!models
+        .items()
+        .delete(bob, item.id)
+        .await
+        .expect("Query failed"));
+
+    let updated = models
+        .items()
+        .update(alice, item.id, b"updated")
+        .await
+        .expect("Query failed")
+        .expect("Item not found");
+    assert_eq!(updated.ciphertext, b"updated");
+    assert!(updated.updated_at >= item.updated_at);
+}
+
+/// Applies the up migrations whose version is before `version` when
+/// `before` is set, or the rest of them otherwise.
+async fn migrate_around(pool: &PgPool, version: &str, before: bool) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("db/migrations");
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .expect("Failed to list migrations")
+        .map(|entry| entry.expect("Failed to list migrations").path())
+        .filter(|path| path.to_string_lossy().ends_with(".up.sql"))
+        .collect();
+    files.sort();
+    for file in files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        if (name.as_ref() < version) == before {
+            let sql = std::fs::read_to_string(&file).expect("Failed to read migration");
+            sqlx::raw_sql(&sql)
+                .execute(pool)
+                .await
+                .expect("Migration failed");
+        }
+    }
+}
+
+#[sqlx::test(migrations = false)]
+async fn audit_events_from_before_the_chain_are_chained(pool: PgPool) {
+    migrate_around(&pool, "20261019150000", true).await;
+    sqlx::raw_sql(
+        r#"
+        insert into account (username, credential_id, client_identity, registration_record)
+        values ('alice@example.com', '', '', '');
+        insert into audit_event (account_id, action, detail)
+        select id, 'password_changed', '{"sessions_revoked": 1}' from account;
+        insert into audit_event (account_id, action, detail)
+        select id, 'password_changed', '{"sessions_revoked": 0}' from account;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to insert legacy events");
+    migrate_around(&pool, "20261019150000", false).await;
+
+    let models = Models::new(pool);
+    assert_eq!(
+        models
+            .chain_legacy_audit_events()
+            .await
+            .expect("Query failed"),
+        2
+    );
+    assert_eq!(
+        models
+            .chain_legacy_audit_events()
+            .await
+            .expect("Query failed"),
+        0
+    );
+    models
+        .record_audit_event(NewAuditEvent {
+            account_id: None,
+            username: Some("alice@example.com"),
+            action: "login_succeeded",
+            detail: &json!({}),
+        })
+        .await
+        .expect("Failed to record event");
+
+    let events = models
+        .audit()
+        .list_after(0, 10)
+        .await
+        .expect("Query failed");
+    assert_eq!(events[0].username.as_deref(), Some("alice@example.com"));
+    let summary = verify_chain(&PostgresStorage::new(models))
+        .await
+        .expect("Chain does not verify");
+    assert_eq!(summary.events_checked, 3);
+}
@@ -0,0 +1,56 @@
+mod utils;
+
+use reqwest::{Client, StatusCode};
+
+#[tokio::test]
+async fn metrics_endpoint_exposes_request_counters() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    let response = client
+        .get(format!("{}/auth/session", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .get(format!("{}/metrics", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.text().await.expect("Failed to read response");
+    assert!(body.contains("http_requests_total"));
+    assert!(body.contains("route=\"/auth/session\""));
+    assert!(body.contains("http_request_duration_seconds_bucket"));
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn unmatched_paths_are_not_used_as_labels() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    client
+        .get(format!("{}/users/alice@example.com", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    let body = client
+        .get(format!("{}/metrics", base_url))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .text()
+        .await
+        .expect("Failed to read response");
+
+    assert!(!body.contains("alice@example.com"));
+    assert!(body.contains("route=\"unmatched\""));
+
+    server_handle.abort();
+}
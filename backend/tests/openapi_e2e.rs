@@ -0,0 +1,103 @@
+mod utils;
+
+use std::path::PathBuf;
+
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+
+/// Set to rewrite `openapi.json` from the running server instead of
+/// comparing against it.
+const UPDATE_ENV: &str = "UPDATE_OPENAPI";
+
+fn committed_spec_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("openapi.json")
+}
+
+async fn served_spec(base_url: &str, client: &Client) -> Value {
+    let response = client
+        .get(format!("{}/openapi.json", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/json");
+    response.json().await.expect("Body is not JSON")
+}
+
+#[tokio::test]
+async fn committed_spec_matches_the_routers() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let spec = served_spec(&base_url, &Client::new()).await;
+    server_handle.abort();
+
+    let path = committed_spec_path();
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        let mut contents = serde_json::to_string_pretty(&spec).unwrap();
+        contents.push('\n');
+        std::fs::write(&path, contents).expect("Failed to write openapi.json");
+        return;
+    }
+
+    let committed: Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).expect("Failed to read openapi.json"))
+            .expect("openapi.json is not JSON");
+    assert!(
+        committed == spec,
+        "backend/openapi.json is out of date, regenerate it with \
+         `{}=1 cargo test -p backend --test openapi_e2e`",
+        UPDATE_ENV
+    );
+}
+
+#[tokio::test]
+async fn spec_documents_the_error_envelope_and_security() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let spec = served_spec(&base_url, &Client::new()).await;
+
+    assert_eq!(spec["openapi"], "3.1.0");
+    let scheme = &spec["components"]["securitySchemes"]["request_mac"];
+    assert_eq!(scheme["type"], "apiKey");
+    assert_eq!(scheme["in"], "header");
+    assert_eq!(scheme["name"], "x-session-id");
+
+    let codes = spec["components"]["schemas"]["ErrorCode"]["enum"]
+        .as_array()
+        .expect("ErrorCode is an enum");
+    assert!(codes.contains(&Value::from("invalid_fields")));
+    assert!(codes.contains(&Value::from("invalid_cbor")));
+
+    let login_finish = &spec["paths"]["/v1/auth/login/finish"]["post"];
+    assert_eq!(
+        login_finish["responses"]["default"]["$ref"],
+        "#/components/responses/Error"
+    );
+    let content = &login_finish["requestBody"]["content"];
+    assert_eq!(
+        content["application/json"]["schema"],
+        content["application/cbor"]["schema"]
+    );
+    assert!(login_finish.get("security").is_none());
+    assert_eq!(
+        spec["paths"]["/v1/items/{id}"]["put"]["security"][0]["request_mac"],
+        Value::Array(Vec::new())
+    );
+
+    server_handle.abort();
+}
+
+#[cfg(feature = "openapi-viewer")]
+#[tokio::test]
+async fn viewer_is_served() {
+    let (base_url, server_handle) = utils::setup_server().await;
+
+    let response = Client::new()
+        .get(format!("{}/docs", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await.expect("Body is not text");
+    assert!(body.contains("/v1/auth/login/finish"));
+
+    server_handle.abort();
+}
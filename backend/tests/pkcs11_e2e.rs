@@ -0,0 +1,78 @@
+//! Runs against a real PKCS#11 module when `PKCS11_MODULE`, `PKCS11_TOKEN_LABEL`
+//! and `PKCS11_PIN` are set, for example with SoftHSM:
+//!
+//! ```sh
+//! softhsm2-util --init-token --free --label salauskilke --pin 1234 --so-pin 4321
+//! PKCS11_MODULE=/usr/lib/softhsm/libsofthsm2.so PKCS11_TOKEN_LABEL=salauskilke \
+//!     PKCS11_PIN=1234 cargo test --features pkcs11 --test pkcs11_e2e
+//! ```
+#![cfg(feature = "pkcs11")]
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use backend::controllers::key_provider::{pkcs11::Pkcs11Provider, KeyProvider, KeyProviderError};
+use backend::controllers::signing::SigningKey;
+use ring::signature::{UnparsedPublicKey, ED25519};
+
+fn open_token() -> Option<Pkcs11Provider> {
+    let module = std::env::var("PKCS11_MODULE").ok()?;
+    let label = std::env::var("PKCS11_TOKEN_LABEL").unwrap_or_else(|_| "salauskilke".to_string());
+    let pin = std::env::var("PKCS11_PIN").unwrap_or_else(|_| "1234".to_string());
+    Some(Pkcs11Provider::open(&module, &label, &pin).expect("Failed to open the token"))
+}
+
+/// Names unique to this run, the token outlives it
+fn unique(name: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Clock before 1970")
+        .as_nanos();
+    format!("{}-{}", name, nanos)
+}
+
+#[test]
+fn token_keeps_secrets_and_keys() {
+    let Some(token) = open_token() else {
+        eprintln!("PKCS11_MODULE is not set, skipping");
+        return;
+    };
+
+    let secret = unique("secret");
+    assert!(token.secret(&secret).unwrap().is_none());
+    token.store_secret(&secret, b"first").unwrap();
+    token.store_secret(&secret, b"second").unwrap();
+    assert_eq!(token.secret(&secret).unwrap().unwrap().expose(), b"second");
+
+    let kek = unique("kek");
+    let wrapping_key = token.generate_wrapping_key(&kek).unwrap();
+    let wrapped = wrapping_key.wrap(b"alice", b"data key").unwrap();
+    let reloaded = token.wrapping_key(&kek).unwrap().unwrap();
+    assert_eq!(
+        reloaded.unwrap(b"alice", &wrapped).unwrap().expose(),
+        b"data key"
+    );
+    assert!(matches!(
+        reloaded.unwrap(b"bob", &wrapped),
+        Err(KeyProviderError::Decrypt)
+    ));
+
+    let name = unique("signing-key");
+    let generated = token.generate_signing_key(&name).unwrap();
+    let signature = generated.sign(b"document").unwrap();
+    UnparsedPublicKey::new(&ED25519, generated.public_key())
+        .verify(b"document", &signature)
+        .unwrap();
+
+    // Importing a newer key replaces the generated one for later lookups
+    let software = SigningKey::generate().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    token
+        .import_signing_key(&name, software.pkcs8().unwrap())
+        .unwrap();
+    let loaded = token.signing_key(&name).unwrap().unwrap();
+    assert_eq!(loaded.public_key(), software.public_key());
+    assert!(loaded.pkcs8().is_none());
+    UnparsedPublicKey::new(&ED25519, software.public_key())
+        .verify(b"document", &loaded.sign(b"document").unwrap())
+        .unwrap();
+}
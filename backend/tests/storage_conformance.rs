@@ -0,0 +1,702 @@
+//! Runs the same cases against every storage backend, so they all behave the
+//! same way behind the `Storage` trait.
+
+use backend::models::Models;
+use backend::storage::{
+    memory::MemoryStorage, postgres::PostgresStorage, AccountEncryption, NewAccount, NewAuditEvent,
+    RecoveryKey, Role, Storage, StorageError,
+};
+use serde_json::json;
+use sqlx::{types::chrono::Utc, PgPool};
+use std::time::Duration;
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+async fn create_account(storage: &dyn Storage, username: &str) -> i32 {
+    storage
+        .create_account(NewAccount {
+            username,
+            credential_id: username.as_bytes(),
+            client_identity: username.as_bytes(),
+            registration_record: b"record",
+            opaque_key_id: 1,
+            kek_id: None,
+            wrapped_dek: None,
+            password_wrapped_master_key: None,
+        })
+        .await
+        .expect("Failed to create account")
+        .id
+}
+
+async fn account_roundtrip(storage: &dyn Storage) {
+    let id = create_account(storage, "alice@example.com").await;
+
+    let account = storage
+        .find_account_by_username("alice@example.com")
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    assert_eq!(account.id, id);
+    assert_eq!(account.credential_id, b"alice@example.com");
+    assert_eq!(account.registration_record, b"record");
+
+    assert_eq!(account.opaque_key_id, 1);
+
+    storage
+        .update_registration_record(id, b"new record", 2)
+        .await
+        .expect("Failed to update record");
+    let account = storage
+        .find_account_by_username("alice@example.com")
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    assert_eq!(account.registration_record, b"new record");
+    assert_eq!(account.opaque_key_id, 2);
+
+    assert!(storage
+        .find_account_by_username("bob@example.com")
+        .await
+        .expect("Query failed")
+        .is_none());
+}
+
+async fn duplicate_username_conflicts(storage: &dyn Storage) {
+    create_account(storage, "alice@example.com").await;
+
+    let result = storage
+        .create_account(NewAccount {
+            username: "alice@example.com",
+            credential_id: b"other",
+            client_identity: b"other",
+            registration_record: b"other",
+            opaque_key_id: 1,
+            kek_id: None,
+            wrapped_dek: None,
+            password_wrapped_master_key: None,
+        })
+        .await;
+    assert!(matches!(result, Err(StorageError::Conflict)));
+}
+
+async fn updating_missing_account_fails(storage: &dyn Storage) {
+    let result = storage.update_registration_record(404, b"record", 1).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+
+    let result = storage
+        .change_password(404, b"record", 1, None, b"session")
+        .await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+}
+
+async fn accounts_are_searched_and_administered(storage: &dyn Storage) {
+    let alice = create_account(storage, "alice@example.com").await;
+    let bob = create_account(storage, "bob@example.com").await;
+    create_account(storage, "carol@example.org").await;
+
+    let usernames = |accounts: Vec<backend::storage::Account>| {
+        accounts
+            .into_iter()
+            .map(|account| account.username)
+            .collect::<Vec<_>>()
+    };
+    let found = storage
+        .search_accounts(Some("example.com"), None, 10)
+        .await
+        .expect("Search failed");
+    assert_eq!(usernames(found), ["alice@example.com", "bob@example.com"]);
+    let found = storage
+        .search_accounts(None, Some(alice), 1)
+        .await
+        .expect("Search failed");
+    assert_eq!(usernames(found), ["bob@example.com"]);
+    // Wildcards are matched literally
+    let found = storage
+        .search_accounts(Some("%"), None, 10)
+        .await
+        .expect("Search failed");
+    assert!(found.is_empty());
+
+    let account = storage
+        .find_account_by_id(bob)
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    assert_eq!(account.role, Role::User);
+    assert_eq!(account.locked_at, None);
+
+    let locked_at = Utc::now();
+    storage
+        .set_account_role(bob, Role::Admin)
+        .await
+        .expect("Failed to set role");
+    storage
+        .set_account_locked(bob, Some(locked_at))
+        .await
+        .expect("Failed to lock account");
+    let account = storage
+        .find_account_by_username("bob@example.com")
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    assert_eq!(account.role, Role::Admin);
+    assert_eq!(
+        account.locked_at.map(|locked_at| locked_at.timestamp()),
+        Some(locked_at.timestamp())
+    );
+
+    storage
+        .set_account_locked(bob, None)
+        .await
+        .expect("Failed to unlock account");
+    let account = storage
+        .find_account_by_id(bob)
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    assert_eq!(account.locked_at, None);
+
+    assert!(matches!(
+        storage.set_account_role(404, Role::Admin).await,
+        Err(StorageError::NotFound)
+    ));
+    assert!(matches!(
+        storage.set_account_locked(404, None).await,
+        Err(StorageError::NotFound)
+    ));
+}
+
+async fn sessions_expire_and_revoke(storage: &dyn Storage) {
+    let id = create_account(storage, "alice@example.com").await;
+    let now = Utc::now();
+    storage
+        .create_session(b"active", id, now + HOUR)
+        .await
+        .expect("Failed to create session");
+    storage
+        .create_session(b"expired", id, now - HOUR)
+        .await
+        .expect("Failed to create session");
+
+    let session = storage
+        .find_active_session(b"active", now)
+        .await
+        .expect("Query failed")
+        .expect("Session not found");
+    assert_eq!(session.account_id, id);
+    assert!(session.revoked_at.is_none());
+    assert!(storage
+        .find_active_session(b"expired", now)
+        .await
+        .expect("Query failed")
+        .is_none());
+
+    assert!(storage
+        .revoke_session(b"active")
+        .await
+        .expect("Query failed"));
+    assert!(!storage
+        .revoke_session(b"active")
+        .await
+        .expect("Query failed"));
+    assert!(storage
+        .find_active_session(b"active", now)
+        .await
+        .expect("Query failed")
+        .is_none());
+
+    let deleted = storage
+        .delete_expired_sessions(now)
+        .await
+        .expect("Failed to delete expired sessions");
+    assert_eq!(deleted, 1);
+}
+
+async fn password_change_revokes_sessions(storage: &dyn Storage) {
+    let alice = create_account(storage, "alice@example.com").await;
+    let bob = create_account(storage, "bob@example.com").await;
+    let now = Utc::now();
+    let expires_at = now + HOUR;
+    for (id_hash, account_id) in [
+        (&b"alice-1"[..], alice),
+        (b"alice-2", alice),
+        (b"bob-1", bob),
+    ] {
+        storage
+            .create_session(id_hash, account_id, expires_at)
+            .await
+            .expect("Failed to create session");
+    }
+
+    let revoked = storage
+        .change_password(alice, b"new record", 1, Some(b"rewrapped"), b"alice-1")
+        .await
+        .expect("Failed to change password");
+    assert_eq!(revoked, 1);
+
+    // The session the change was made from stays
+    assert!(storage
+        .find_active_session(b"alice-1", now)
+        .await
+        .expect("Query failed")
+        .is_some());
+    assert!(storage
+        .find_active_session(b"alice-2", now)
+        .await
+        .expect("Query failed")
+        .is_none());
+    assert!(storage
+        .find_active_session(b"bob-1", now)
+        .await
+        .expect("Query failed")
+        .is_some());
+    let account = storage
+        .find_account_by_username("alice@example.com")
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    assert_eq!(account.registration_record, b"new record");
+    assert_eq!(
+        account.password_wrapped_master_key.as_deref(),
+        Some(&b"rewrapped"[..])
+    );
+}
+
+async fn items_are_scoped_to_account(storage: &dyn Storage) {
+    let alice = create_account(storage, "alice@example.com").await;
+    let bob = create_account(storage, "bob@example.com").await;
+
+    let item = storage
+        .create_item(alice, b"ciphertext")
+        .await
+        .expect("Failed to create item");
+    assert_eq!(item.account_id, alice);
+
+    assert!(storage
+        .find_item(bob, item.id)
+        .await
+        .expect("Query failed")
+        .is_none());
+    assert!(storage
+        .update_item(bob, item.id, b"stolen")
+        .await
+        .expect("Query failed")
+        .is_none());
+    assert!(!storage
+        .delete_item(bob, item.id)
+        .await
+        .expect("Query failed"));
+    assert!(storage
+        .list_items(bob)
+        .await
+        .expect("Query failed")
+        .is_empty());
+
+    let updated = storage
+        .update_item(alice, item.id, b"new ciphertext")
+        .await
+        .expect("Query failed")
+        .expect("Item not found");
+    assert_eq!(updated.ciphertext, b"new ciphertext");
+    assert_eq!(
+        storage.list_items(alice).await.expect("Query failed"),
+        vec![updated]
+    );
+
+    assert!(storage
+        .delete_item(alice, item.id)
+        .await
+        .expect("Query failed"));
+    assert!(storage
+        .find_item(alice, item.id)
+        .await
+        .expect("Query failed")
+        .is_none());
+}
+
+async fn deleting_account_cascades(storage: &dyn Storage) {
+    let id = create_account(storage, "alice@example.com").await;
+    let now = Utc::now();
+    storage
+        .create_session(b"session", id, now + HOUR)
+        .await
+        .expect("Failed to create session");
+    let item = storage
+        .create_item(id, b"ciphertext")
+        .await
+        .expect("Failed to create item");
+
+    assert!(storage.delete_account(id).await.expect("Query failed"));
+    assert!(!storage.delete_account(id).await.expect("Query failed"));
+
+    assert!(storage
+        .find_account_by_username("alice@example.com")
+        .await
+        .expect("Query failed")
+        .is_none());
+    assert!(storage
+        .find_active_session(b"session", now)
+        .await
+        .expect("Query failed")
+        .is_none());
+    assert!(storage
+        .find_item(id, item.id)
+        .await
+        .expect("Query failed")
+        .is_none());
+}
+
+async fn key_log_is_append_only(storage: &dyn Storage) {
+    for (index, username) in ["alice@example.com", "bob@example.com", "alice@example.com"]
+        .into_iter()
+        .enumerate()
+    {
+        let entry = storage
+            .append_key_log_entry(index as u64, username, format!("leaf {}", index).as_bytes())
+            .await
+            .expect("Failed to append entry");
+        assert_eq!(entry.leaf_index, index as i64);
+    }
+
+    let result = storage
+        .append_key_log_entry(1, "mallory@example.com", b"rewritten")
+        .await;
+    assert!(matches!(result, Err(StorageError::Conflict)));
+
+    let entries = storage.list_key_log_entries().await.expect("Query failed");
+    assert_eq!(
+        entries.iter().map(|e| e.leaf_index).collect::<Vec<_>>(),
+        vec![0, 1, 2]
+    );
+    assert_eq!(entries[1].leaf, b"leaf 1");
+
+    let alice = storage
+        .list_key_log_entries_for_user("alice@example.com")
+        .await
+        .expect("Query failed");
+    assert_eq!(
+        alice.iter().map(|e| e.leaf_index).collect::<Vec<_>>(),
+        vec![0, 2]
+    );
+    let latest = storage
+        .latest_key_log_entry_for_user("alice@example.com")
+        .await
+        .expect("Query failed")
+        .expect("Entry not found");
+    assert_eq!(latest.leaf, b"leaf 2");
+    assert!(storage
+        .latest_key_log_entry_for_user("mallory@example.com")
+        .await
+        .expect("Query failed")
+        .is_none());
+}
+
+async fn audit_events_are_chained(storage: &dyn Storage) {
+    let alice = create_account(storage, "alice@example.com").await;
+    let bob = create_account(storage, "bob@example.com").await;
+    for (account_id, username, action) in [
+        (Some(alice), "alice@example.com", "login_succeeded"),
+        (Some(bob), "bob@example.com", "login_succeeded"),
+        (None, "mallory@example.com", "login_failed"),
+    ] {
+        storage
+            .record_audit_event(NewAuditEvent {
+                account_id,
+                username: Some(username),
+                action,
+                detail: &json!({ "from": username }),
+            })
+            .await
+            .expect("Failed to record event");
+    }
+    storage
+        .change_password(alice, b"new record", 1, None, b"session")
+        .await
+        .expect("Failed to change password");
+
+    let chain = storage
+        .list_audit_events_after(0, 10)
+        .await
+        .expect("Query failed");
+    assert_eq!(chain.len(), 4);
+    assert_eq!(chain[0].prev_hash, vec![0; 32]);
+    for pair in chain.windows(2) {
+        assert_eq!(pair[1].prev_hash, pair[0].hash);
+    }
+    for event in &chain {
+        assert_eq!(event.hash, event.expected_hash());
+    }
+    assert_eq!(chain[0].detail, json!({ "from": "alice@example.com" }));
+
+    let alice_events = storage
+        .list_audit_events(Some(alice), None, 10)
+        .await
+        .expect("Query failed");
+    assert_eq!(
+        alice_events
+            .iter()
+            .map(|e| e.action.as_str())
+            .collect::<Vec<_>>(),
+        vec!["password_changed", "login_succeeded"]
+    );
+    assert_eq!(
+        alice_events[0].username.as_deref(),
+        Some("alice@example.com")
+    );
+
+    let page = storage
+        .list_audit_events(None, Some(chain[2].id), 1)
+        .await
+        .expect("Query failed");
+    assert_eq!(page, vec![chain[1].clone()]);
+
+    assert!(storage.delete_account(bob).await.expect("Query failed"));
+    let bob_event = storage
+        .list_audit_events_after(chain[0].id, 1)
+        .await
+        .expect("Query failed");
+    assert_eq!(bob_event[0].account_id, None);
+    assert_eq!(bob_event[0].hash, bob_event[0].expected_hash());
+
+    let deleted = storage
+        .delete_audit_events_before(Utc::now() + HOUR)
+        .await
+        .expect("Failed to delete events");
+    assert_eq!(deleted, 4);
+}
+
+async fn accounts_are_counted_by_opaque_key_id(storage: &dyn Storage) {
+    assert!(storage
+        .count_accounts_by_opaque_key_id()
+        .await
+        .expect("Query failed")
+        .is_empty());
+
+    let alice = create_account(storage, "alice@example.com").await;
+    create_account(storage, "bob@example.com").await;
+    create_account(storage, "carol@example.com").await;
+    storage
+        .change_password(alice, b"new record", 3, None, b"session")
+        .await
+        .expect("Password change failed");
+
+    assert_eq!(
+        storage
+            .count_accounts_by_opaque_key_id()
+            .await
+            .expect("Query failed"),
+        vec![(1, 2), (3, 1)]
+    );
+}
+
+async fn account_keys_are_encrypted_and_rewrapped_once(storage: &dyn Storage) {
+    let id = create_account(storage, "alice@example.com").await;
+    let pending = storage
+        .list_accounts_not_under_kek(1, 10)
+        .await
+        .expect("Query failed");
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].kek_id, None);
+    assert!(!storage
+        .rewrap_account_key(id, 1, 2, b"data key")
+        .await
+        .expect("Rewrap failed"));
+
+    let encryption = AccountEncryption {
+        kek_id: 1,
+        wrapped_dek: b"data key",
+        credential_id: b"sealed credential id",
+        client_identity: b"sealed client identity",
+        registration_record: b"sealed record",
+        recovery_record: None,
+    };
+    assert!(storage
+        .encrypt_account(id, encryption)
+        .await
+        .expect("Encryption failed"));
+    let encryption = AccountEncryption {
+        kek_id: 1,
+        wrapped_dek: b"other data key",
+        credential_id: b"other",
+        client_identity: b"other",
+        registration_record: b"other",
+        recovery_record: None,
+    };
+    assert!(!storage
+        .encrypt_account(id, encryption)
+        .await
+        .expect("Encryption failed"));
+    let account = storage
+        .find_account_by_id(id)
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    assert_eq!(account.kek_id, Some(1));
+    assert_eq!(account.wrapped_dek.as_deref(), Some(&b"data key"[..]));
+    assert_eq!(account.registration_record, b"sealed record");
+    assert!(storage
+        .list_accounts_not_under_kek(1, 10)
+        .await
+        .expect("Query failed")
+        .is_empty());
+
+    assert!(!storage
+        .rewrap_account_key(id, 2, 3, b"new data key")
+        .await
+        .expect("Rewrap failed"));
+    assert!(storage
+        .rewrap_account_key(id, 1, 2, b"new data key")
+        .await
+        .expect("Rewrap failed"));
+    let account = storage
+        .find_account_by_id(id)
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    assert_eq!(account.kek_id, Some(2));
+    assert_eq!(account.wrapped_dek.as_deref(), Some(&b"new data key"[..]));
+    assert_eq!(account.registration_record, b"sealed record");
+    assert_eq!(
+        storage
+            .list_accounts_not_under_kek(1, 10)
+            .await
+            .expect("Query failed")
+            .len(),
+        1
+    );
+}
+
+async fn recovery_keys_are_set_and_removed(storage: &dyn Storage) {
+    let id = create_account(storage, "alice@example.com").await;
+    let other = create_account(storage, "bob@example.com").await;
+    assert!(matches!(
+        storage
+            .set_recovery_key(
+                id + other,
+                RecoveryKey {
+                    registration_record: b"recovery record",
+                    opaque_key_id: 2,
+                    public_key: b"public key",
+                    wrapped_master_key: b"wrapped",
+                },
+            )
+            .await,
+        Err(StorageError::NotFound)
+    ));
+
+    storage
+        .set_recovery_key(
+            id,
+            RecoveryKey {
+                registration_record: b"recovery record",
+                opaque_key_id: 2,
+                public_key: b"public key",
+                wrapped_master_key: b"wrapped",
+            },
+        )
+        .await
+        .expect("Failed to set recovery key");
+    let account = storage
+        .find_account_by_id(id)
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    assert_eq!(
+        account.recovery_record.as_deref(),
+        Some(&b"recovery record"[..])
+    );
+    assert_eq!(account.recovery_opaque_key_id, Some(2));
+    assert_eq!(
+        account.recovery_public_key.as_deref(),
+        Some(&b"public key"[..])
+    );
+    assert_eq!(account.wrapped_master_key.as_deref(), Some(&b"wrapped"[..]));
+    assert_eq!(account.registration_record, b"record");
+
+    // The recovery record keeps its generation in use
+    assert_eq!(
+        storage
+            .count_accounts_by_opaque_key_id()
+            .await
+            .expect("Query failed"),
+        vec![(1, 2), (2, 1)]
+    );
+
+    assert!(storage
+        .remove_recovery_key(id)
+        .await
+        .expect("Removal failed"));
+    assert!(!storage
+        .remove_recovery_key(id)
+        .await
+        .expect("Removal failed"));
+    let account = storage
+        .find_account_by_id(id)
+        .await
+        .expect("Query failed")
+        .expect("Account not found");
+    assert_eq!(account.recovery_record, None);
+    assert_eq!(account.recovery_opaque_key_id, None);
+    assert_eq!(account.recovery_public_key, None);
+    assert_eq!(account.wrapped_master_key, None);
+}
+
+/// Generates one test per case and backend. Postgres tests get a fresh
+/// database from `sqlx::test`, so they need `DATABASE_URL` to be set.
+macro_rules! conformance_tests {
+    ($($case:ident),* $(,)?) => {
+        mod memory {
+            use super::*;
+
+            $(
+                #[tokio::test]
+                async fn $case() {
+                    super::$case(&MemoryStorage::default()).await;
+                }
+            )*
+        }
+
+        #[cfg(feature = "sqlite")]
+        mod sqlite {
+            use backend::storage::sqlite::SqliteStorage;
+
+            $(
+                #[tokio::test]
+                async fn $case() {
+                    let storage = SqliteStorage::connect("sqlite::memory:", 1)
+                        .await
+                        .expect("Failed to open SQLite database");
+                    super::$case(&storage).await;
+                }
+            )*
+        }
+
+        mod postgres {
+            use super::*;
+
+            $(
+                #[sqlx::test(migrations = "db/migrations")]
+                async fn $case(pool: PgPool) {
+                    super::$case(&PostgresStorage::new(Models::new(pool))).await;
+                }
+            )*
+        }
+    };
+}
+
+conformance_tests!(
+    account_roundtrip,
+    duplicate_username_conflicts,
+    updating_missing_account_fails,
+    accounts_are_searched_and_administered,
+    accounts_are_counted_by_opaque_key_id,
+    account_keys_are_encrypted_and_rewrapped_once,
+    recovery_keys_are_set_and_removed,
+    sessions_expire_and_revoke,
+    password_change_revokes_sessions,
+    items_are_scoped_to_account,
+    deleting_account_cascades,
+    key_log_is_append_only,
+    audit_events_are_chained,
+);
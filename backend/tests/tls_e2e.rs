@@ -0,0 +1,188 @@
+mod utils;
+
+use std::{net::SocketAddr, path::Path, time::Duration};
+
+use rcgen::{
+    BasicConstraints, CertificateParams, CertifiedKey, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+};
+use reqwest::{header::STRICT_TRANSPORT_SECURITY, Certificate, Client, Identity, StatusCode};
+use tempfile::TempDir;
+use tokio::task::JoinHandle;
+
+fn self_signed() -> CertifiedKey {
+    rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+        .expect("Failed to generate certificate")
+}
+
+fn write_server_certificate(dir: &Path, certified: &CertifiedKey) {
+    std::fs::write(dir.join("cert.pem"), certified.cert.pem()).expect("Failed to write cert");
+    std::fs::write(dir.join("key.pem"), certified.key_pair.serialize_pem())
+        .expect("Failed to write key");
+}
+
+async fn setup_tls_server(
+    dir: &Path,
+    client_ca: Option<&str>,
+) -> (String, SocketAddr, JoinHandle<()>) {
+    let (_, listener) = utils::bind().await;
+    let addr = listener.local_addr().expect("Failed to get local address");
+
+    let mut config = utils::test_config();
+    config.tls_cert_path = Some(dir.join("cert.pem").display().to_string());
+    config.tls_key_path = Some(dir.join("key.pem").display().to_string());
+    config.tls_client_ca_path = client_ca.map(str::to_string);
+    config.tls_reload_interval_secs = 1;
+    config.hsts_max_age_secs = 600;
+    let state = utils::test_state(config).await;
+
+    let server_handle = tokio::spawn(async move {
+        backend::http::serve_until(listener, state, std::future::pending())
+            .await
+            .expect("Server error");
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    (
+        format!("https://localhost:{}", addr.port()),
+        addr,
+        server_handle,
+    )
+}
+
+fn client_trusting(addr: SocketAddr, certified: &CertifiedKey) -> reqwest::ClientBuilder {
+    Client::builder()
+        .use_rustls_tls()
+        .tls_built_in_root_certs(false)
+        .add_root_certificate(
+            Certificate::from_pem(certified.cert.pem().as_bytes()).expect("Invalid certificate"),
+        )
+        .resolve("localhost", addr)
+}
+
+#[tokio::test]
+async fn serves_https_with_hsts() {
+    let dir = TempDir::new().expect("Failed to create temp dir");
+    let certified = self_signed();
+    write_server_certificate(dir.path(), &certified);
+    let (base_url, addr, server_handle) = setup_tls_server(dir.path(), None).await;
+
+    let client = client_trusting(addr, &certified)
+        .build()
+        .expect("Failed to build client");
+    let response = client
+        .get(format!("{}/healthz", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[STRICT_TRANSPORT_SECURITY],
+        "max-age=600; includeSubDomains"
+    );
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn reloads_certificate_when_files_change() {
+    let dir = TempDir::new().expect("Failed to create temp dir");
+    let original = self_signed();
+    write_server_certificate(dir.path(), &original);
+    let (base_url, addr, server_handle) = setup_tls_server(dir.path(), None).await;
+
+    // Modification times may have a one second resolution
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let replacement = self_signed();
+    write_server_certificate(dir.path(), &replacement);
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+
+    let trusts_original = client_trusting(addr, &original)
+        .build()
+        .expect("Failed to build client");
+    assert!(trusts_original
+        .get(format!("{}/healthz", base_url))
+        .send()
+        .await
+        .is_err());
+
+    let trusts_replacement = client_trusting(addr, &replacement)
+        .build()
+        .expect("Failed to build client");
+    let response = trusts_replacement
+        .get(format!("{}/healthz", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn client_certificates_are_optional_at_the_tls_layer() {
+    let dir = TempDir::new().expect("Failed to create temp dir");
+    let server_certificate = self_signed();
+    write_server_certificate(dir.path(), &server_certificate);
+
+    let mut ca_params = CertificateParams::new(Vec::new()).expect("Invalid CA params");
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca_key = KeyPair::generate().expect("Failed to generate CA key");
+    let ca = ca_params
+        .self_signed(&ca_key)
+        .expect("Failed to sign CA certificate");
+    let ca_path = dir.path().join("client-ca.pem");
+    std::fs::write(&ca_path, ca.pem()).expect("Failed to write CA");
+
+    let mut client_params =
+        CertificateParams::new(vec!["admin".to_string()]).expect("Invalid client params");
+    client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let client_key = KeyPair::generate().expect("Failed to generate client key");
+    let client_certificate = client_params
+        .signed_by(&client_key, &ca, &ca_key)
+        .expect("Failed to sign client certificate");
+    let identity = Identity::from_pem(
+        format!("{}{}", client_certificate.pem(), client_key.serialize_pem()).as_bytes(),
+    )
+    .expect("Invalid identity");
+
+    let (base_url, addr, server_handle) = setup_tls_server(dir.path(), ca_path.to_str()).await;
+
+    let with_certificate = client_trusting(addr, &server_certificate)
+        .identity(identity)
+        .build()
+        .expect("Failed to build client");
+    let response = with_certificate
+        .get(format!("{}/healthz", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let without_certificate = client_trusting(addr, &server_certificate)
+        .build()
+        .expect("Failed to build client");
+    let response = without_certificate
+        .get(format!("{}/healthz", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Admin routes demand the certificate before the session
+    let response = with_certificate
+        .get(format!("{}/admin/audit-events/verify", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = without_certificate
+        .get(format!("{}/admin/audit-events/verify", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    server_handle.abort();
+}
@@ -0,0 +1,317 @@
+mod utils;
+
+use backend::controllers::{
+    session::{derive_mac_key, unix_timestamp, RequestToSign},
+    transparency::{leaf_hash, verify_consistency, verify_inclusion, Hash},
+};
+use backend::utils::base64::Base64String;
+use opaque_ke::rand::rngs::OsRng;
+use reqwest::{Client, StatusCode};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde_json::{json, Value};
+
+fn decode(value: &Value) -> Vec<u8> {
+    Base64String::from(value.as_str().expect("Not a string").to_string())
+        .decode_bytes()
+        .expect("Invalid base64")
+}
+
+fn decode_hash(value: &Value) -> Hash {
+    decode(value).try_into().expect("Not a SHA-256 hash")
+}
+
+fn decode_proof(value: &Value) -> Vec<Hash> {
+    value
+        .as_array()
+        .expect("Not an array")
+        .iter()
+        .map(decode_hash)
+        .collect()
+}
+
+async fn signing_public_key(base_url: &str, client: &Client) -> Vec<u8> {
+    let document: Value = client
+        .get(format!("{}/.well-known/salauskilke", base_url))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Body is not JSON");
+    let payload: Value =
+        serde_json::from_slice(&decode(&document["payload"])).expect("Payload is not JSON");
+    decode(&payload["signing_public_key"])
+}
+
+/// Verifies the signature of a tree head and returns its size and root.
+fn verify_tree_head(signed: &Value, public_key: &[u8]) -> (u64, Hash) {
+    let payload = decode(&signed["payload"]);
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(&payload, &decode(&signed["signature"]))
+        .expect("Invalid tree head signature");
+    let head: Value = serde_json::from_slice(&payload).expect("Payload is not JSON");
+    (
+        head["tree_size"].as_u64().expect("Missing tree size"),
+        decode_hash(&head["root_hash"]),
+    )
+}
+
+async fn put_keys(
+    username: &str,
+    keys: Value,
+    nonce: &str,
+    session_id: &str,
+    session_key: &[u8],
+    base_url: &str,
+    client: &Client,
+) -> reqwest::Response {
+    let path = format!("/users/{}/public-keys", username);
+    let body = serde_json::to_vec(&json!({ "keys": keys })).expect("Failed to encode body");
+    let timestamp = unix_timestamp();
+    let signature = RequestToSign {
+        method: "PUT",
+        path: &path,
+        timestamp,
+        nonce,
+        body: &body,
+    }
+    .sign(&derive_mac_key(session_key));
+
+    client
+        .put(format!("{}{}", base_url, path))
+        .header("content-type", "application/json")
+        .header("x-session-id", session_id)
+        .header("x-timestamp", timestamp.to_string())
+        .header("x-nonce", nonce)
+        .header("x-signature", Base64String::encode(&signature).to_string())
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+fn key(algorithm: &str, bytes: &[u8]) -> Value {
+    json!({ "algorithm": algorithm, "key": Base64String::encode_bytes(bytes) })
+}
+
+#[tokio::test]
+async fn key_lookups_are_proven_against_signed_tree_heads() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+    let mut rng = OsRng;
+    let public_key = signing_public_key(&base_url, &client).await;
+
+    utils::register("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+    let (alice, alice_session) =
+        utils::login("alice@example.com", "a$$word", &base_url, &client, &mut rng).await;
+    utils::register("bob@example.com", "secret", &base_url, &client, &mut rng).await;
+    let (bob, bob_session) =
+        utils::login("bob@example.com", "secret", &base_url, &client, &mut rng).await;
+
+    let response = client
+        .get(format!("{}/users/alice@example.com/public-keys", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = put_keys(
+        "alice@example.com",
+        json!([key("x25519", b"alice key 1")]),
+        "nonce-1",
+        &alice_session,
+        &alice.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let first: Value = response.json().await.expect("Body is not JSON");
+    let (first_size, first_root) = verify_tree_head(&first["tree_head"], &public_key);
+    assert_eq!(first_size, 1);
+
+    for (i, bytes) in [&b"bob key"[..], b"alice key 2"].into_iter().enumerate() {
+        let (username, session_id, session_key) = if i == 0 {
+            ("bob@example.com", &bob_session, &bob.session_key)
+        } else {
+            ("alice@example.com", &alice_session, &alice.session_key)
+        };
+        let response = put_keys(
+            username,
+            json!([key("x25519", bytes)]),
+            &format!("nonce-{}", i + 2),
+            session_id,
+            session_key,
+            &base_url,
+            &client,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let lookup: Value = client
+        .get(format!("{}/users/alice@example.com/public-keys", base_url))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Body is not JSON");
+    assert_eq!(
+        lookup["keys"][0]["key"],
+        key("x25519", b"alice key 2")["key"]
+    );
+    let (size, root) = verify_tree_head(&lookup["tree_head"], &public_key);
+    assert_eq!(size, 3);
+    assert_eq!(lookup["leaf_index"], 2);
+
+    let leaf = decode(&lookup["leaf"]);
+    let binding: Value = serde_json::from_slice(&leaf).expect("Leaf is not JSON");
+    assert_eq!(binding["username"], "alice@example.com");
+    assert_eq!(binding["keys"], lookup["keys"]);
+    assert!(verify_inclusion(
+        &leaf_hash(&leaf),
+        2,
+        size,
+        &decode_proof(&lookup["inclusion_proof"]),
+        &root
+    ));
+
+    // The first tree head the client saw is a prefix of the current one
+    let consistency: Value = client
+        .get(format!(
+            "{}/transparency/consistency?first={}&second={}",
+            base_url, first_size, size
+        ))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Body is not JSON");
+    assert!(verify_consistency(
+        first_size,
+        size,
+        &first_root,
+        &root,
+        &decode_proof(&consistency["proof"])
+    ));
+
+    // The audit history shows both of alice's bindings
+    let history: Value = client
+        .get(format!("{}/transparency/users/alice@example.com", base_url))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Body is not JSON");
+    let (size, root) = verify_tree_head(&history["tree_head"], &public_key);
+    let entries = history["entries"].as_array().expect("Not an array");
+    assert_eq!(entries.len(), 2);
+    for entry in entries {
+        assert!(verify_inclusion(
+            &leaf_hash(&decode(&entry["leaf"])),
+            entry["leaf_index"].as_u64().expect("Missing leaf index"),
+            size,
+            &decode_proof(&entry["inclusion_proof"]),
+            &root
+        ));
+    }
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn users_can_only_publish_their_own_keys() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+    let mut rng = OsRng;
+
+    utils::register("carol@example.com", "pa55", &base_url, &client, &mut rng).await;
+    let (carol, session_id) =
+        utils::login("carol@example.com", "pa55", &base_url, &client, &mut rng).await;
+
+    let response = put_keys(
+        "dave@example.com",
+        json!([key("x25519", b"not carol's")]),
+        "nonce-1",
+        &session_id,
+        &carol.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Keys are decoded with the body, before the other checks run
+    let response = put_keys(
+        "carol@example.com",
+        json!([{ "algorithm": "", "key": "not base64!" }]),
+        "nonce-2",
+        &session_id,
+        &carol.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["code"], "invalid_fields");
+    assert_eq!(body["error"]["details"][0]["field"], "keys[0].key");
+    assert_eq!(body["error"]["details"][0]["code"], "decode_invalid_base64");
+
+    let response = put_keys(
+        "carol@example.com",
+        json!([{ "algorithm": "", "key": "AAAA" }, key("x25519", b"")]),
+        "nonce-3",
+        &session_id,
+        &carol.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["details"][0]["field"], "keys[0].algorithm");
+    assert_eq!(body["error"]["details"][1]["field"], "keys[1].key");
+    assert_eq!(body["error"]["details"][1]["code"], "invalid_value");
+
+    let response = client
+        .put(format!("{}/users/carol@example.com/public-keys", base_url))
+        .json(&json!({ "keys": [key("x25519", b"unsigned")] }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn invalid_consistency_ranges_are_rejected() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    for query in ["first=2&second=1", "first=0&second=5", "first=x&second=1"] {
+        let response = client
+            .get(format!("{}/transparency/consistency?{}", base_url, query))
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+
+    let head: Value = client
+        .get(format!("{}/transparency/tree-head", base_url))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Body is not JSON");
+    let public_key = signing_public_key(&base_url, &client).await;
+    assert_eq!(verify_tree_head(&head, &public_key).0, 0);
+
+    server_handle.abort();
+}
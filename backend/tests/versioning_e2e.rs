@@ -0,0 +1,158 @@
+mod utils;
+
+use opaque_ke::rand::rngs::OsRng;
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+
+#[tokio::test]
+async fn versioned_and_unversioned_routes_are_served() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+    let mut rng = OsRng;
+
+    utils::register("frank@example.com", "hunter2", &base_url, &client, &mut rng).await;
+    let (login_finish, session_id) =
+        utils::login("frank@example.com", "hunter2", &base_url, &client, &mut rng).await;
+
+    let response = utils::signed_get(
+        "/v1/auth/session",
+        "nonce-1",
+        &session_id,
+        &login_finish.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-api-version"], "1");
+    assert!(response.headers().get("deprecation").is_none());
+    assert!(response.headers().get("sunset").is_none());
+
+    // Clients from before /v1 keep working, and are told where to go
+    let response = utils::signed_get(
+        "/auth/session?verbose=1",
+        "nonce-2",
+        &session_id,
+        &login_finish.session_key,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["deprecation"], "@1792368000");
+    assert_eq!(
+        response.headers()["sunset"],
+        "Tue, 19 Oct 2027 00:00:00 GMT"
+    );
+    assert_eq!(
+        response.headers()["link"],
+        "</v1/auth/session?verbose=1>; rel=\"successor-version\""
+    );
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["username"], "frank@example.com");
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn errors_on_unversioned_routes_are_marked_deprecated() {
+    let (base_url, server_handle) = utils::setup_server().await;
+
+    let response = Client::new()
+        .get(format!("{}/items", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().contains_key("deprecation"));
+
+    // Unversioned endpoints outside the API are not deprecated
+    let response = Client::new()
+        .get(format!("{}/.well-known/salauskilke", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key("deprecation"));
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn unknown_api_versions_are_rejected() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    let response = client
+        .get(format!("{}/healthz", base_url))
+        .header("x-api-version", "1")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .get(format!("{}/healthz", base_url))
+        .header("x-api-version", "2")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.headers()["x-api-version"], "1");
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["code"], "unsupported_api_version");
+    assert!(body["error"]["request_id"].is_string());
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn clients_below_the_minimum_version_must_upgrade() {
+    let (base_url, server_handle) = utils::setup_server_with(backend::utils::config::Config {
+        min_client_version: Some("1.4.0".to_string()),
+        ..utils::test_config()
+    })
+    .await;
+    let client = Client::new();
+    let url = format!("{}/.well-known/salauskilke", base_url);
+
+    let response = client
+        .get(&url)
+        .header("x-client-version", "1.3.9")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["code"], "client_version_unsupported");
+
+    let response = client
+        .get(&url)
+        .header("x-client-version", "not a version")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["code"], "invalid_header");
+
+    for version in ["1.4.0", "2.0.0-beta.1"] {
+        let response = client
+            .get(&url)
+            .header("x-client-version", version)
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(response.status(), StatusCode::OK, "{}", version);
+    }
+
+    // Clients that do not say which release they are stay served
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    server_handle.abort();
+}
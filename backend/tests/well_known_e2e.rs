@@ -0,0 +1,79 @@
+mod utils;
+
+use backend::utils::base64::Base64String;
+use reqwest::{Client, StatusCode};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+async fn fetch_document(base_url: &str, client: &Client) -> Value {
+    let response = client
+        .get(format!("{}/.well-known/salauskilke", base_url))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.expect("Body is not JSON")
+}
+
+fn decode(value: &Value) -> Vec<u8> {
+    Base64String::from(value.as_str().expect("Not a string").to_string())
+        .decode_bytes()
+        .expect("Invalid base64")
+}
+
+#[tokio::test]
+async fn well_known_document_is_signed() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    let body = fetch_document(&base_url, &client).await;
+    let payload_bytes = decode(&body["payload"]);
+    let signature = decode(&body["signature"]);
+    let payload: Value = serde_json::from_slice(&payload_bytes).expect("Payload is not JSON");
+
+    let signing_public_key = decode(&payload["signing_public_key"]);
+    UnparsedPublicKey::new(&ED25519, &signing_public_key)
+        .verify(&payload_bytes, &signature)
+        .expect("Invalid signature");
+    assert_eq!(
+        body["signing_key_fingerprint"],
+        hex::encode(Sha256::digest(&signing_public_key))
+    );
+
+    assert_eq!(payload["protocol_version"], 1);
+    assert_eq!(
+        payload["ciphersuites"][0],
+        "opaque-3dh-ristretto255-sha512-argon2id"
+    );
+    assert_eq!(payload["ksf"]["algorithm"], "argon2id");
+    assert_eq!(payload["server_identity"], utils::SERVER_IDENTITY);
+    assert_eq!(
+        payload["opaque_public_key_fingerprint"],
+        hex::encode(Sha256::digest(decode(&payload["opaque_public_key"])))
+    );
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn well_known_keys_are_stable() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    let first = fetch_document(&base_url, &client).await;
+    let second = fetch_document(&base_url, &client).await;
+
+    assert_eq!(
+        first["signing_key_fingerprint"],
+        second["signing_key_fingerprint"]
+    );
+    let first: Value = serde_json::from_slice(&decode(&first["payload"])).expect("Not JSON");
+    let second: Value = serde_json::from_slice(&decode(&second["payload"])).expect("Not JSON");
+    assert_eq!(
+        first["opaque_public_key_fingerprint"],
+        second["opaque_public_key_fingerprint"]
+    );
+
+    server_handle.abort();
+}
@@ -0,0 +1,354 @@
+mod utils;
+
+use backend::{
+    controllers::{
+        opaque::CS,
+        session::{derive_mac_key, unix_timestamp, RequestToSign},
+    },
+    utils::base64::Base64String,
+};
+use generic_array::GenericArray;
+use opaque_ke::{
+    rand::rngs::OsRng, ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+    ClientRegistrationFinishParameters, CredentialResponse, CredentialResponseLen,
+    RegistrationResponse, RegistrationResponseLen,
+};
+use reqwest::{Client, Method, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+const CBOR: &str = "application/cbor";
+
+fn to_cbor<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    ciborium::into_writer(value, &mut bytes).expect("Failed to encode CBOR");
+    bytes
+}
+
+async fn from_cbor<T: DeserializeOwned>(response: reqwest::Response) -> T {
+    assert_eq!(response.headers()["content-type"], CBOR);
+    let bytes = response.bytes().await.expect("Failed to read body");
+    ciborium::from_reader(bytes.as_ref()).expect("Body is not CBOR")
+}
+
+async fn post_cbor<T: Serialize>(client: &Client, url: String, body: &T) -> reqwest::Response {
+    client
+        .post(url)
+        .header("content-type", CBOR)
+        .header("accept", CBOR)
+        .body(to_cbor(body))
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+#[derive(Serialize)]
+struct RegisterInit<'a> {
+    username: &'a str,
+    registration_request: Base64String,
+}
+
+#[derive(Deserialize)]
+struct RegistrationInit {
+    registration_response: Base64String,
+    registration_ticket: Base64String,
+}
+
+#[derive(Serialize)]
+struct RegisterFinish<'a> {
+    username: &'a str,
+    registration_ticket: Base64String,
+    registration_request: Base64String,
+    registration_finish: Base64String,
+}
+
+#[derive(Serialize)]
+struct LoginInit<'a> {
+    username: &'a str,
+    credential_request: Base64String,
+}
+
+#[derive(Serialize)]
+struct LoginFinish<'a> {
+    username: &'a str,
+    credential_finish: Base64String,
+}
+
+#[derive(Deserialize)]
+struct LoginFinished {
+    session_id: String,
+}
+
+#[derive(Serialize)]
+struct ItemRequest {
+    ciphertext: Base64String,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct Item {
+    id: String,
+    ciphertext: Base64String,
+    created_at: i64,
+    updated_at: i64,
+}
+
+/// Registers and logs in speaking only CBOR, returns the session id and key.
+async fn cbor_login(
+    username: &str,
+    password: &str,
+    base_url: &str,
+    client: &Client,
+) -> (String, Vec<u8>) {
+    let mut rng = OsRng;
+    let registration_start =
+        ClientRegistration::<CS>::start(&mut rng, password.as_bytes()).unwrap();
+    let registration_request = Base64String::encode(&registration_start.message.serialize());
+    let response = post_cbor(
+        client,
+        format!("{}/auth/register/init", base_url),
+        &RegisterInit {
+            username,
+            registration_request: registration_request.clone(),
+        },
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let init: RegistrationInit = from_cbor(response).await;
+    let registration_response: Zeroizing<GenericArray<u8, RegistrationResponseLen<CS>>> =
+        init.registration_response.decode().unwrap();
+    let registration_finish = registration_start
+        .state
+        .finish(
+            &mut rng,
+            password.as_bytes(),
+            RegistrationResponse::deserialize(&registration_response).unwrap(),
+            ClientRegistrationFinishParameters::new(utils::identifiers(), None),
+        )
+        .unwrap()
+        .message
+        .serialize();
+    let response = post_cbor(
+        client,
+        format!("{}/auth/register/finish", base_url),
+        &RegisterFinish {
+            username,
+            registration_ticket: init.registration_ticket,
+            registration_request,
+            registration_finish: Base64String::encode(&registration_finish),
+        },
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let login_start = ClientLogin::<CS>::start(&mut rng, password.as_bytes()).unwrap();
+    let response = post_cbor(
+        client,
+        format!("{}/auth/login/init", base_url),
+        &LoginInit {
+            username,
+            credential_request: Base64String::encode(&login_start.message.serialize()),
+        },
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let credential_response: Base64String = from_cbor(response).await;
+    let credential_response: Zeroizing<GenericArray<u8, CredentialResponseLen<CS>>> =
+        credential_response.decode().unwrap();
+    let login_finish = login_start
+        .state
+        .finish(
+            password.as_bytes(),
+            CredentialResponse::deserialize(&credential_response).unwrap(),
+            ClientLoginFinishParameters::new(None, utils::identifiers(), None),
+        )
+        .unwrap();
+    let response = post_cbor(
+        client,
+        format!("{}/auth/login/finish", base_url),
+        &LoginFinish {
+            username,
+            credential_finish: Base64String::encode(&login_finish.message.serialize()),
+        },
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let finished: LoginFinished = from_cbor(response).await;
+
+    (finished.session_id, login_finish.session_key.to_vec())
+}
+
+/// Sends `body` signed with the session's MAC key, asking for `accept` back.
+#[allow(clippy::too_many_arguments)]
+async fn signed(
+    method: Method,
+    path: &str,
+    nonce: &str,
+    body: (&str, Vec<u8>),
+    accept: &str,
+    (session_id, session_key): &(String, Vec<u8>),
+    base_url: &str,
+    client: &Client,
+) -> reqwest::Response {
+    let (content_type, body) = body;
+    let timestamp = unix_timestamp();
+    let signature = RequestToSign {
+        method: method.as_str(),
+        path,
+        timestamp,
+        nonce,
+        body: &body,
+    }
+    .sign(&derive_mac_key(session_key));
+
+    let mut request = client
+        .request(method, format!("{}{}", base_url, path))
+        .header("accept", accept)
+        .header("x-session-id", session_id)
+        .header("x-timestamp", timestamp.to_string())
+        .header("x-nonce", nonce)
+        .header("x-signature", Base64String::encode(&signature).to_string());
+    if !body.is_empty() {
+        request = request.header("content-type", content_type);
+    }
+    request
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to send request")
+}
+
+#[tokio::test]
+async fn json_and_cbor_round_trip_identically_e2e() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+    let session = cbor_login("ada@example.com", "hunter2", &base_url, &client).await;
+
+    let ciphertext = [0x5a_u8; 48];
+    let response = signed(
+        Method::POST,
+        "/items",
+        "nonce-1",
+        (
+            CBOR,
+            to_cbor(&ItemRequest {
+                ciphertext: Base64String::encode_bytes(&ciphertext),
+            }),
+        ),
+        CBOR,
+        &session,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: Item = from_cbor(response).await;
+    assert_eq!(created.ciphertext.decode_bytes().unwrap(), ciphertext);
+
+    let path = format!("/items/{}", created.id);
+    let as_cbor: Item = from_cbor(
+        signed(
+            Method::GET,
+            &path,
+            "nonce-2",
+            ("", Vec::new()),
+            CBOR,
+            &session,
+            &base_url,
+            &client,
+        )
+        .await,
+    )
+    .await;
+    let as_json: Item = signed(
+        Method::GET,
+        &path,
+        "nonce-3",
+        ("", Vec::new()),
+        "application/json",
+        &session,
+        &base_url,
+        &client,
+    )
+    .await
+    .json()
+    .await
+    .expect("Body is not JSON");
+    assert_eq!(as_cbor, created);
+    assert_eq!(as_json, created);
+
+    // A JSON update reads back the same over CBOR
+    let response = signed(
+        Method::PUT,
+        &path,
+        "nonce-4",
+        (
+            "application/json",
+            serde_json::to_vec(&ItemRequest {
+                ciphertext: Base64String::encode_bytes(b"updated"),
+            })
+            .unwrap(),
+        ),
+        CBOR,
+        &session,
+        &base_url,
+        &client,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let updated: Item = from_cbor(response).await;
+    assert_eq!(updated.ciphertext.decode_bytes().unwrap(), b"updated");
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn cbor_errors_use_the_error_envelope_e2e() {
+    let (base_url, server_handle) = utils::setup_server().await;
+    let client = Client::new();
+
+    let response = client
+        .post(format!("{}/auth/login/init", base_url))
+        .header("content-type", CBOR)
+        .body(vec![0xff, 0x00])
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let request_id = response.headers()["x-request-id"].clone();
+    let body: ciborium::Value = from_cbor(response).await;
+    let error = body.as_map().unwrap()[0].1.as_map().unwrap().clone();
+    let field = |name: &str| {
+        error
+            .iter()
+            .find(|(key, _)| key.as_text() == Some(name))
+            .and_then(|(_, value)| value.as_text().map(str::to_string))
+    };
+    assert_eq!(field("code").as_deref(), Some("invalid_cbor"));
+    assert_eq!(field("request_id").as_deref(), request_id.to_str().ok());
+
+    let response = post_cbor(
+        &client,
+        format!("{}/auth/login/init", base_url),
+        &LoginInit {
+            username: "nobody@example.com",
+            credential_request: Base64String::encode_bytes(b"short"),
+        },
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: ciborium::Value = from_cbor(response).await;
+    assert!(format!("{:?}", body).contains("decode_length_mismatch"));
+
+    let response = client
+        .post(format!("{}/auth/login/init", base_url))
+        .header("content-type", "text/plain")
+        .body("hello")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let body: serde_json::Value = response.json().await.expect("Body is not JSON");
+    assert_eq!(body["error"]["code"], "unsupported_media_type");
+
+    server_handle.abort();
+}
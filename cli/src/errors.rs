@@ -0,0 +1,50 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+use salauskilke_client::{encoding::DecodeError, ClientError};
+
+#[derive(Debug)]
+pub enum CliError {
+    Client(ClientError),
+    Io(PathBuf, io::Error),
+    Json(serde_json::Error),
+    NotLoggedIn,
+    Usage(String),
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::Client(ClientError::NotLoggedIn) | CliError::NotLoggedIn => {
+                write!(f, "Not logged in, run the login command first")
+            }
+            CliError::Client(err) => write!(f, "{}", err),
+            CliError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            CliError::Json(err) => write!(f, "Invalid JSON: {}", err),
+            CliError::Usage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl From<ClientError> for CliError {
+    fn from(err: ClientError) -> Self {
+        CliError::Client(err)
+    }
+}
+
+impl From<DecodeError> for CliError {
+    fn from(err: DecodeError) -> Self {
+        CliError::Client(ClientError::Decode(err))
+    }
+}
+
+impl From<serde_json::Error> for CliError {
+    fn from(err: serde_json::Error) -> Self {
+        CliError::Json(err)
+    }
+}
+
+pub type CliResult<T> = Result<T, CliError>;
@@ -0,0 +1,10 @@
+#![deny(clippy::unwrap_used)]
+#![deny(clippy::expect_used)]
+#![deny(clippy::panic)]
+#![deny(unused_must_use)]
+
+//! Command-line front end of `salauskilke-client`, caching the session in a
+//! file between invocations.
+
+pub mod errors;
+pub mod session;
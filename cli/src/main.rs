@@ -0,0 +1,280 @@
+#![deny(clippy::unwrap_used)]
+#![deny(clippy::expect_used)]
+#![deny(clippy::panic)]
+#![deny(unused_must_use)]
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+use salauskilke_client::vault::{ItemPayload, SharedItem};
+use salauskilke_client::{Client, ReqwestTransport};
+
+use salauskilke_cli::errors::{CliError, CliResult};
+use salauskilke_cli::session::{self, SessionCache};
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Base URL of the server to register with or log in to
+    #[arg(
+        long,
+        env = "SALAUSKILKE_SERVER",
+        default_value = "http://localhost:8000",
+        global = true
+    )]
+    server: String,
+
+    /// Where the session is cached [default: <config dir>/salauskilke/session.json]
+    #[arg(long, env = "SALAUSKILKE_SESSION_FILE", global = true)]
+    session_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create an account. The password is read from SALAUSKILKE_PASSWORD or
+    /// prompted for
+    Register { username: String },
+    /// Log in and cache the session
+    Login { username: String },
+    /// End the cached session
+    Logout,
+    /// Set a new password with the recovery code, read from
+    /// SALAUSKILKE_RECOVERY_CODE or prompted for, and print the code that
+    /// replaces it
+    Recover { username: String },
+    #[command(flatten)]
+    Session(SessionCommand),
+}
+
+/// Commands that run with the cached session.
+#[derive(Subcommand)]
+enum SessionCommand {
+    /// Show who the cached session belongs to
+    Whoami,
+    /// Set a new password, read from SALAUSKILKE_NEW_PASSWORD or prompted
+    /// for. Other sessions are logged out
+    ChangePassword,
+    /// Print a new recovery code, which resets the password without losing
+    /// items. It replaces any earlier code
+    EnableRecovery,
+    /// Remove the recovery code
+    DisableRecovery,
+    /// Publish the identity key others share items to
+    PublishKey,
+    /// Encrypt a file, or standard input with `-`, and store it
+    Put {
+        file: PathBuf,
+        /// Name to store instead of the file name
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Decrypt an item to standard output or a file
+    Get {
+        id: String,
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// List item ids and names
+    List,
+    /// Delete an item
+    Delete { id: String },
+    /// Encrypt an item to another user's identity key
+    Share {
+        id: String,
+        recipient: String,
+        /// Where to write the shared item [default: standard output]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Store an item someone shared with you
+    Receive { file: PathBuf },
+}
+
+fn password(variable: &str, confirm: bool) -> CliResult<String> {
+    if let Ok(password) = std::env::var(variable) {
+        return Ok(password);
+    }
+    let prompt_error = |e| CliError::Io(PathBuf::from("<terminal>"), e);
+    let password = rpassword::prompt_password("Password: ").map_err(prompt_error)?;
+    if confirm && rpassword::prompt_password("Repeat password: ").map_err(prompt_error)? != password
+    {
+        return Err(CliError::Usage("Passwords do not match".to_string()));
+    }
+    Ok(password)
+}
+
+fn recovery_code() -> CliResult<String> {
+    if let Ok(code) = std::env::var("SALAUSKILKE_RECOVERY_CODE") {
+        return Ok(code);
+    }
+    rpassword::prompt_password("Recovery code: ")
+        .map_err(|e| CliError::Io(PathBuf::from("<terminal>"), e))
+}
+
+fn read_input(path: &Path) -> CliResult<Vec<u8>> {
+    if path == Path::new("-") {
+        let mut data = Vec::new();
+        io::stdin()
+            .read_to_end(&mut data)
+            .map_err(|e| CliError::Io(PathBuf::from("<stdin>"), e))?;
+        return Ok(data);
+    }
+    fs::read(path).map_err(|e| CliError::Io(path.to_path_buf(), e))
+}
+
+fn write_output(path: Option<&Path>, data: &[u8]) -> CliResult<()> {
+    match path {
+        Some(path) => fs::write(path, data).map_err(|e| CliError::Io(path.to_path_buf(), e)),
+        None => io::stdout()
+            .write_all(data)
+            .map_err(|e| CliError::Io(PathBuf::from("<stdout>"), e)),
+    }
+}
+
+fn open_session(path: &Path) -> CliResult<Client<ReqwestTransport>> {
+    let cache = SessionCache::load(path)?.ok_or(CliError::NotLoggedIn)?;
+    let mut client = Client::new(&cache.server);
+    client.resume(cache.session);
+    Ok(client)
+}
+
+/// Writes the session back, or removes it once the client has dropped it.
+fn save_session(path: &Path, client: &Client<ReqwestTransport>) -> CliResult<()> {
+    match client.session() {
+        Some(session) => SessionCache {
+            server: client.server().to_string(),
+            session: session.clone(),
+        }
+        .save(path),
+        None => SessionCache::remove(path).map(|_| ()),
+    }
+}
+
+async fn run_with_session(
+    client: &mut Client<ReqwestTransport>,
+    command: SessionCommand,
+) -> CliResult<()> {
+    match command {
+        SessionCommand::Whoami => println!("{}", client.whoami().await?),
+        SessionCommand::ChangePassword => {
+            let new_password = password("SALAUSKILKE_NEW_PASSWORD", true)?;
+            client.change_password(&new_password).await?;
+            eprintln!("Password changed");
+        }
+        SessionCommand::EnableRecovery => {
+            println!("{}", client.enable_recovery().await?);
+            eprintln!("Keep this recovery code somewhere safe, it is not shown again");
+        }
+        SessionCommand::DisableRecovery => client.disable_recovery().await?,
+        SessionCommand::PublishKey => client.publish_identity_key().await?,
+        SessionCommand::Put { file, name } => {
+            let name = name
+                .or_else(|| {
+                    file.file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                })
+                .unwrap_or_default();
+            let item = client
+                .put(&ItemPayload::new(&name, &read_input(&file)?))
+                .await?;
+            println!("{}", item.id);
+        }
+        SessionCommand::Get { id, output } => {
+            let payload = client.get(&id).await?;
+            write_output(output.as_deref(), &payload.data.decode_bytes()?)?;
+        }
+        SessionCommand::List => {
+            for item in client.list_items().await? {
+                // Items that no longer decrypt are listed rather than hidden
+                match client.decrypt(&item) {
+                    Ok(payload) => println!("{}\t{}", item.id, payload.name),
+                    Err(err) => println!("{}\t<{}>", item.id, err),
+                }
+            }
+        }
+        SessionCommand::Delete { id } => client.delete_item(&id).await?,
+        SessionCommand::Share {
+            id,
+            recipient,
+            output,
+        } => {
+            let shared = client.share(&id, &recipient).await?;
+            let mut json = serde_json::to_vec_pretty(&shared)?;
+            json.push(b'\n');
+            write_output(output.as_deref(), &json)?;
+        }
+        SessionCommand::Receive { file } => {
+            let shared: SharedItem = serde_json::from_slice(&read_input(&file)?)?;
+            println!("{}", client.receive(&shared).await?.id);
+        }
+    }
+    Ok(())
+}
+
+async fn run(cli: Cli) -> CliResult<()> {
+    let session_file = cli.session_file.unwrap_or_else(session::default_path);
+
+    match cli.command {
+        Command::Register { username } => {
+            Client::new(&cli.server)
+                .register(&username, &password("SALAUSKILKE_PASSWORD", true)?)
+                .await?;
+            eprintln!("Registered {}", username);
+        }
+        Command::Login { username } => {
+            let mut client = Client::new(&cli.server);
+            client
+                .login(&username, &password("SALAUSKILKE_PASSWORD", false)?)
+                .await?;
+            save_session(&session_file, &client)?;
+            eprintln!("Logged in as {}", username);
+            if client
+                .session()
+                .is_some_and(|session| session.reregistration_required)
+            {
+                eprintln!(
+                    "The server has rotated its OPAQUE key, run change-password to register again"
+                );
+            }
+        }
+        Command::Recover { username } => {
+            let code = recovery_code()?;
+            let new_password = password("SALAUSKILKE_NEW_PASSWORD", true)?;
+            let mut client = Client::new(&cli.server);
+            let result = client.recover(&username, &code, &new_password).await;
+            // The password may be changed even if rotating the code failed
+            save_session(&session_file, &client)?;
+            println!("{}", result?);
+            eprintln!("Password changed, the recovery code above replaces the one used");
+        }
+        Command::Logout => {
+            let mut client = open_session(&session_file)?;
+            let result = client.logout().await;
+            // Forget the session even if the server could not be reached
+            SessionCache::remove(&session_file)?;
+            result?;
+        }
+        Command::Session(command) => {
+            let mut client = open_session(&session_file)?;
+            let result = run_with_session(&mut client, command).await;
+            // Every sealed request advances the sequence number, so the
+            // session is saved whether the command succeeded or not
+            save_session(&session_file, &client)?;
+            result?;
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(err) = run(Cli::parse()).await {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
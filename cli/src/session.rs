@@ -0,0 +1,125 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use salauskilke_client::Session;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{CliError, CliResult};
+
+/// `<config dir>/salauskilke/session.json`, or the working directory where
+/// the platform has no config directory.
+pub fn default_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_default()
+        .join("salauskilke")
+        .join("session.json")
+}
+
+/// The session of a [`salauskilke_client::Client`] and the server it
+/// belongs to. It holds the session and vault keys, so it is only ever
+/// written readable by its owner.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SessionCache {
+    pub server: String,
+    #[serde(flatten)]
+    pub session: Session,
+}
+
+impl SessionCache {
+    /// Reads the cache, or `None` when there is no session.
+    pub fn load(path: &Path) -> CliResult<Option<Self>> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(CliError::Io(path.to_path_buf(), err)),
+        }
+    }
+
+    /// Replaces the cache atomically, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> CliResult<()> {
+        let io_error = |e| CliError::Io(path.to_path_buf(), e);
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(dir).map_err(io_error)?;
+
+        let temp_path = path.with_extension("tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&temp_path).map_err(io_error)?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)
+            .map_err(io_error)?;
+        file.sync_all().map_err(io_error)?;
+        fs::rename(&temp_path, path).map_err(io_error)?;
+        // The rename is only durable once the directory is synced too
+        #[cfg(unix)]
+        fs::File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(io_error)?;
+        Ok(())
+    }
+
+    /// Forgets the session. Returns whether there was one.
+    pub fn remove(path: &Path) -> CliResult<bool> {
+        match fs::remove_file(path) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(CliError::Io(path.to_path_buf(), err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+    use salauskilke_client::encoding::Base64String;
+
+    fn cache() -> SessionCache {
+        SessionCache {
+            server: "http://localhost:8000".to_string(),
+            session: Session {
+                username: "alice".to_string(),
+                session_id: "session".to_string(),
+                session_key: Base64String::encode_bytes(&[1u8; 64]),
+                item_key: Base64String::encode_bytes(&[2u8; 32]),
+                identity_key: Base64String::encode_bytes(&[3u8; 32]),
+                next_sequence: 3,
+                signing_public_key: Base64String::encode_bytes(&[4u8; 32]),
+                reregistration_required: false,
+            },
+        }
+    }
+
+    #[test]
+    fn saved_cache_loads_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("session.json");
+        assert!(SessionCache::load(&path).unwrap().is_none());
+
+        cache().save(&path).unwrap();
+        let loaded = SessionCache::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.session.next_sequence, 3);
+        assert_eq!(loaded.session.item_key, cache().session.item_key);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        assert!(SessionCache::remove(&path).unwrap());
+        assert!(!SessionCache::remove(&path).unwrap());
+    }
+}
@@ -0,0 +1,833 @@
+use generic_array::GenericArray;
+use opaque_ke::{
+    ClientLogin, ClientLoginFinishParameters, ClientLoginFinishResult, ClientRegistration,
+    ClientRegistrationFinishParameters, ClientRegistrationFinishResult, CredentialResponse,
+    CredentialResponseLen, Identifiers, RegistrationResponse, RegistrationResponseLen,
+};
+use rand_core::{OsRng, RngCore};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+
+use crate::encoding::Base64String;
+use crate::errors::{ClientError, ClientResult, ErrorCode};
+use crate::protocol::{
+    derive_mac_key, unix_timestamp, ChannelKeys, Direction, RequestToSign, SignedJson,
+    CIPHERSUITE_ID, CLIENT_VERSION, CLIENT_VERSION_HEADER, CS, NONCE_HEADER, PROTOCOL_VERSION,
+    SEALED_CONTENT_TYPE, SESSION_ID_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+};
+use crate::session::Session;
+use crate::transparency::{leaf_hash, verify_inclusion, TreeHead};
+use crate::transport::{HttpRequest, HttpResponse, Transport};
+use crate::vault::{
+    generate_recovery_code, normalize_recovery_code, ItemPayload, RecoveryKey, SharedItem,
+    VaultKeys, IDENTITY_KEY_ALGORITHM,
+};
+
+const NO_CONTENT: u16 = 204;
+
+#[derive(Deserialize)]
+struct ErrorEnvelope {
+    error: ErrorBody,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    code: ErrorCode,
+    message: String,
+    request_id: Option<String>,
+}
+
+/// Turns an error response into [`ClientError::Api`], keeping whatever the
+/// body was when it is not an error envelope.
+fn api_error(status: u16, body: &[u8]) -> ClientError {
+    match serde_json::from_slice::<ErrorEnvelope>(body) {
+        Ok(envelope) => ClientError::Api {
+            status,
+            code: envelope.error.code,
+            message: envelope.error.message,
+            request_id: envelope.error.request_id,
+        },
+        Err(_) => ClientError::Api {
+            status,
+            code: ErrorCode::Unknown,
+            message: String::from_utf8_lossy(body).into_owned(),
+            request_id: None,
+        },
+    }
+}
+
+fn check(response: HttpResponse) -> ClientResult<HttpResponse> {
+    if (200..300).contains(&response.status) {
+        Ok(response)
+    } else {
+        Err(api_error(response.status, &response.body))
+    }
+}
+
+/// Percent-encodes a path segment, so that the path the request is signed
+/// over is the path the server sees.
+fn segment(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The fields of the server's `/.well-known/salauskilke` document the client
+/// relies on.
+#[derive(Deserialize)]
+struct ServerDocument {
+    protocol_version: u32,
+    ciphersuites: Vec<String>,
+    server_identity: String,
+    opaque_public_key: Base64String,
+    #[serde(default)]
+    previous_opaque_public_keys: Vec<PreviousOpaqueKey>,
+    signing_public_key: Base64String,
+}
+
+#[derive(Deserialize)]
+struct PreviousOpaqueKey {
+    public_key: Base64String,
+}
+
+impl ServerDocument {
+    /// The server's identity, which OPAQUE binds into registrations and
+    /// logins alongside its key.
+    fn identifiers(&self) -> Identifiers<'_> {
+        Identifiers {
+            client: None,
+            server: Some(self.server_identity.as_bytes()),
+        }
+    }
+
+    /// The OPAQUE exchange proves the server holds the key it announced.
+    /// Registrations must use the current key.
+    fn check_server_key(&self, server_key: &[u8]) -> ClientResult<()> {
+        if self.opaque_public_key.decode_bytes()? != server_key {
+            return Err(ClientError::Verification(
+                "The server's OPAQUE key does not match its signed document".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Logins may also use a key the server has since rotated away from.
+    fn check_login_key(&self, server_key: &[u8]) -> ClientResult<()> {
+        for previous in &self.previous_opaque_public_keys {
+            if previous.public_key.decode_bytes()? == server_key {
+                return Ok(());
+            }
+        }
+        self.check_server_key(server_key)
+    }
+}
+
+/// An encrypted item as the server stores it.
+#[derive(Clone, Debug, Deserialize)]
+pub struct StoredItem {
+    pub id: String,
+    pub ciphertext: Base64String,
+    /// Unix seconds
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Deserialize)]
+struct RegistrationInitResponse {
+    registration_response: Base64String,
+    registration_ticket: Base64String,
+}
+
+#[derive(Deserialize)]
+struct LoginFinishResponse {
+    session_id: String,
+    #[serde(default)]
+    reregistration_required: bool,
+    password_wrapped_master_key: Option<Base64String>,
+}
+
+#[derive(Deserialize)]
+struct ItemList {
+    items: Vec<StoredItem>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct PublicKey {
+    algorithm: String,
+    key: Base64String,
+}
+
+/// The transparency log leaf binding a user to their keys.
+#[derive(Deserialize)]
+struct KeyBinding {
+    username: String,
+    keys: Vec<PublicKey>,
+}
+
+#[derive(Deserialize)]
+struct PublicKeysResponse {
+    leaf_index: u64,
+    leaf: Base64String,
+    inclusion_proof: Vec<Base64String>,
+    tree_head: SignedJson,
+}
+
+/// A salauskilke client. After [`Client::login`] it keeps the session and
+/// signs and seals every authenticated request with it. Persist
+/// [`Client::session`] after each call to resume it later with
+/// [`Client::resume`], as the sealed channel's sequence number advances
+/// with every request.
+pub struct Client<T: Transport> {
+    transport: T,
+    server: String,
+    session: Option<Session>,
+}
+
+#[cfg(feature = "reqwest")]
+impl Client<crate::transport::ReqwestTransport> {
+    pub fn new(server: &str) -> Self {
+        Self::with_transport(server, crate::transport::ReqwestTransport::default())
+    }
+}
+
+impl<T: Transport> Client<T> {
+    pub fn with_transport(server: &str, transport: T) -> Self {
+        Self {
+            transport,
+            server: server.trim_end_matches('/').to_string(),
+            session: None,
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    /// Continues a session saved from [`Client::session`].
+    pub fn resume(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+
+    fn current_session(&self) -> ClientResult<&Session> {
+        self.session.as_ref().ok_or(ClientError::NotLoggedIn)
+    }
+
+    async fn send(&self, mut request: HttpRequest) -> ClientResult<HttpResponse> {
+        request
+            .headers
+            .push((CLIENT_VERSION_HEADER, CLIENT_VERSION.to_string()));
+        self.transport.send(request).await
+    }
+
+    async fn get_json<R: DeserializeOwned>(&self, path: &str) -> ClientResult<R> {
+        let response = check(
+            self.send(HttpRequest {
+                method: "GET",
+                url: format!("{}{}", self.server, path),
+                headers: Vec::new(),
+                body: Vec::new(),
+            })
+            .await?,
+        )?;
+        Ok(serde_json::from_slice(&response.body)?)
+    }
+
+    /// Posts JSON without a session, as the OPAQUE messages are.
+    async fn post_json(&self, path: &str, body: serde_json::Value) -> ClientResult<Vec<u8>> {
+        let response = check(
+            self.send(HttpRequest {
+                method: "POST",
+                url: format!("{}{}", self.server, path),
+                headers: vec![("content-type", "application/json".to_string())],
+                body: serde_json::to_vec(&body)?,
+            })
+            .await?,
+        )?;
+        Ok(response.body)
+    }
+
+    /// Fetches the server document, which is signed by the key it
+    /// announces.
+    async fn server_document(&self) -> ClientResult<ServerDocument> {
+        let signed: SignedJson = self.get_json("/.well-known/salauskilke").await?;
+        let unverified: ServerDocument = serde_json::from_slice(&signed.payload.decode_bytes()?)?;
+        let document: ServerDocument =
+            signed.verify(&unverified.signing_public_key.decode_array()?)?;
+
+        if document.protocol_version != PROTOCOL_VERSION
+            || !document.ciphersuites.iter().any(|c| c == CIPHERSUITE_ID)
+        {
+            return Err(ClientError::Verification(format!(
+                "The server does not speak protocol version {} with {}",
+                PROTOCOL_VERSION, CIPHERSUITE_ID
+            )));
+        }
+        Ok(document)
+    }
+
+    /// Runs the client side of an OPAQUE registration against `init_path`
+    /// and returns the result to upload, with the ticket and the request the
+    /// finish must repeat.
+    async fn start_registration(
+        &mut self,
+        document: &ServerDocument,
+        init_path: &str,
+        username: Option<&str>,
+        password: &str,
+    ) -> ClientResult<(
+        ClientRegistrationFinishResult<CS>,
+        Base64String,
+        Base64String,
+    )> {
+        let mut rng = OsRng;
+        let start = ClientRegistration::<CS>::start(&mut rng, password.as_bytes())?;
+        let registration_request = Base64String::encode(&start.message.serialize());
+
+        let response = match username {
+            Some(username) => {
+                self.post_json(
+                    init_path,
+                    json!({ "username": username, "registration_request": &registration_request }),
+                )
+                .await?
+            }
+            None => {
+                self.send_authenticated(
+                    "POST",
+                    init_path,
+                    Some(json!({ "registration_request": &registration_request })),
+                )
+                .await?
+            }
+        };
+        let response: RegistrationInitResponse = serde_json::from_slice(&response)?;
+        let registration_response: GenericArray<u8, RegistrationResponseLen<CS>> =
+            response.registration_response.decode()?;
+
+        let finish = start.state.finish(
+            &mut rng,
+            password.as_bytes(),
+            RegistrationResponse::deserialize(&registration_response)?,
+            ClientRegistrationFinishParameters::new(document.identifiers(), None),
+        )?;
+        document.check_server_key(&finish.server_s_pk.serialize())?;
+        Ok((finish, response.registration_ticket, registration_request))
+    }
+
+    /// Registers `username` with fresh vault keys, which the server keeps
+    /// wrapped to the password's export key.
+    pub async fn register(&mut self, username: &str, password: &str) -> ClientResult<()> {
+        let keys = VaultKeys::generate();
+        let document = self.server_document().await?;
+        let (finish, ticket, request) = self
+            .start_registration(
+                &document,
+                "/v1/auth/register/init",
+                Some(username),
+                password,
+            )
+            .await?;
+
+        self.post_json(
+            "/v1/auth/register/finish",
+            json!({
+                "username": username,
+                "registration_ticket": ticket,
+                "registration_request": request,
+                "registration_finish": Base64String::encode(&finish.message.serialize()),
+                "password_wrapped_master_key":
+                    Base64String::encode_bytes(&keys.wrap_with_export_key(&finish.export_key)?),
+            }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Runs the client side of an OPAQUE login against `path`'s `init` and
+    /// `finish` endpoints and returns the result with the finish response.
+    async fn start_login(
+        &self,
+        document: &ServerDocument,
+        path: &str,
+        username: &str,
+        password: &str,
+    ) -> ClientResult<(ClientLoginFinishResult<CS>, Vec<u8>)> {
+        let mut rng = OsRng;
+        let start = ClientLogin::<CS>::start(&mut rng, password.as_bytes())?;
+
+        let response = self
+            .post_json(
+                &format!("{}/init", path),
+                json!({
+                    "username": username,
+                    "credential_request": Base64String::encode(&start.message.serialize()),
+                }),
+            )
+            .await?;
+        let response: GenericArray<u8, CredentialResponseLen<CS>> =
+            Base64String::from(String::from_utf8_lossy(&response).into_owned()).decode()?;
+
+        let finish = start.state.finish(
+            password.as_bytes(),
+            CredentialResponse::deserialize(&response)?,
+            ClientLoginFinishParameters::new(None, document.identifiers(), None),
+        )?;
+        document.check_login_key(&finish.server_s_pk.serialize())?;
+
+        let response = self
+            .post_json(
+                &format!("{}/finish", path),
+                json!({
+                    "username": username,
+                    "credential_finish": Base64String::encode(&finish.message.serialize()),
+                }),
+            )
+            .await?;
+        Ok((finish, response))
+    }
+
+    fn start_session(
+        &mut self,
+        username: &str,
+        document: ServerDocument,
+        session_key: &[u8],
+        response: LoginFinishResponse,
+        keys: &VaultKeys,
+    ) {
+        let mut session = Session {
+            username: username.to_string(),
+            session_id: response.session_id,
+            session_key: Base64String::encode_bytes(session_key),
+            item_key: Base64String::encode_bytes(&[]),
+            identity_key: Base64String::encode_bytes(&[]),
+            next_sequence: 0,
+            signing_public_key: document.signing_public_key,
+            reregistration_required: response.reregistration_required,
+        };
+        session.set_vault_keys(keys);
+        self.session = Some(session);
+    }
+
+    /// Logs in, opens the vault keys with the export key and pins the
+    /// server's signing key for the session.
+    pub async fn login(&mut self, username: &str, password: &str) -> ClientResult<()> {
+        let document = self.server_document().await?;
+        let (finish, response) = self
+            .start_login(&document, "/v1/auth/login", username, password)
+            .await?;
+        let response: LoginFinishResponse = serde_json::from_slice(&response)?;
+
+        let keys = match &response.password_wrapped_master_key {
+            Some(wrapped) => {
+                VaultKeys::unwrap_with_export_key(&finish.export_key, &wrapped.decode_bytes()?)?
+            }
+            None => VaultKeys::derive(&finish.export_key)?,
+        };
+        self.start_session(username, document, &finish.session_key, response, &keys);
+        Ok(())
+    }
+
+    /// Logs in with a recovery code from [`Client::enable_recovery`] and
+    /// sets `new_password`, keeping the vault readable. The code is used up:
+    /// the new one that replaces it is returned.
+    pub async fn recover(
+        &mut self,
+        username: &str,
+        recovery_code: &str,
+        new_password: &str,
+    ) -> ClientResult<String> {
+        #[derive(Deserialize)]
+        struct RecoveryLoginFinishResponse {
+            #[serde(flatten)]
+            login: LoginFinishResponse,
+            wrapped_master_key: Base64String,
+        }
+
+        let document = self.server_document().await?;
+        let (finish, response) = self
+            .start_login(
+                &document,
+                "/v1/auth/recovery/login",
+                username,
+                &normalize_recovery_code(recovery_code),
+            )
+            .await?;
+        let response: RecoveryLoginFinishResponse = serde_json::from_slice(&response)?;
+
+        let keys = VaultKeys::unwrap(
+            &RecoveryKey::derive(&finish.export_key)?,
+            &response.wrapped_master_key.decode_bytes()?,
+        )?;
+        self.start_session(
+            username,
+            document,
+            &finish.session_key,
+            response.login,
+            &keys,
+        );
+        self.change_password(new_password).await?;
+        self.enable_recovery().await
+    }
+
+    /// Ends the session on the server and forgets it.
+    pub async fn logout(&mut self) -> ClientResult<()> {
+        let result = self
+            .signed_request("POST", "/v1/auth/logout", Vec::new(), None)
+            .await;
+        self.session = None;
+        check(result?)?;
+        Ok(())
+    }
+
+    /// Signs `body` with the session's MAC key and sends it.
+    async fn signed_request(
+        &self,
+        method: &'static str,
+        path: &str,
+        body: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> ClientResult<HttpResponse> {
+        let session = self.current_session()?;
+        let mac_key = derive_mac_key(&session.session_key.decode_bytes()?)?;
+        let mut nonce = [0u8; 16];
+        OsRng.fill_bytes(&mut nonce);
+        let nonce = Base64String::encode_bytes(&nonce).to_string();
+        let timestamp = unix_timestamp();
+        let signature = RequestToSign {
+            method,
+            path,
+            timestamp,
+            nonce: &nonce,
+            body: &body,
+        }
+        .sign(&mac_key)?;
+
+        let mut headers = vec![
+            (SESSION_ID_HEADER, session.session_id.clone()),
+            (TIMESTAMP_HEADER, timestamp.to_string()),
+            (NONCE_HEADER, nonce),
+            (
+                SIGNATURE_HEADER,
+                Base64String::encode_bytes(&signature).to_string(),
+            ),
+        ];
+        if let Some(content_type) = content_type {
+            headers.push(("content-type", content_type.to_string()));
+        }
+
+        self.send(HttpRequest {
+            method,
+            url: format!("{}{}", self.server, path),
+            headers,
+            body,
+        })
+        .await
+    }
+
+    /// Sends `body` sealed and signed and returns the opened response body.
+    /// A session the server no longer knows is forgotten.
+    async fn send_authenticated(
+        &mut self,
+        method: &'static str,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> ClientResult<Vec<u8>> {
+        let session = self.current_session()?;
+        let channel_keys = ChannelKeys::derive(&session.session_key.decode_bytes()?)?;
+        let sequence = session.next_sequence;
+        let plaintext = match body {
+            Some(body) => serde_json::to_vec(&body)?,
+            None => Vec::new(),
+        };
+        let sealed = channel_keys.seal(Direction::ClientToServer, sequence, &plaintext)?;
+
+        let response = self
+            .signed_request(method, path, sealed, Some(SEALED_CONTENT_TYPE))
+            .await?;
+
+        // Only a sealed response means the server consumed the sequence number
+        let is_sealed = response.content_type.as_deref() == Some(SEALED_CONTENT_TYPE);
+        let body = if is_sealed {
+            if let Some(session) = self.session.as_mut() {
+                session.next_sequence += 1;
+            }
+            // No Content responses lose their sealed body on the way
+            if response.status == NO_CONTENT {
+                return Ok(Vec::new());
+            }
+            let (response_sequence, plaintext) =
+                channel_keys.open(Direction::ServerToClient, &response.body)?;
+            if response_sequence != sequence {
+                return Err(ClientError::Verification(
+                    "Response does not answer this request".to_string(),
+                ));
+            }
+            plaintext
+        } else {
+            response.body
+        };
+
+        let response = check(HttpResponse {
+            status: response.status,
+            content_type: None,
+            body,
+        });
+        if let Err(err) = &response {
+            if err.code() == Some(ErrorCode::SessionExpired) {
+                self.session = None;
+            }
+        }
+        Ok(response?.body)
+    }
+
+    async fn send_json<R: DeserializeOwned>(
+        &mut self,
+        method: &'static str,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> ClientResult<R> {
+        Ok(serde_json::from_slice(
+            &self.send_authenticated(method, path, body).await?,
+        )?)
+    }
+
+    /// Registers `new_password` for the logged in user. The server stores
+    /// the vault keys wrapped to the new export key with the new
+    /// registration record, so items, shares and the recovery code stay as
+    /// they are. Other sessions of the user are revoked by the server.
+    pub async fn change_password(&mut self, new_password: &str) -> ClientResult<()> {
+        let keys = self.current_session()?.vault_keys()?;
+        let document = self.server_document().await?;
+        let (finish, ticket, request) = self
+            .start_registration(&document, "/v1/auth/password/init", None, new_password)
+            .await?;
+        self.send_authenticated(
+            "POST",
+            "/v1/auth/password/finish",
+            Some(json!({
+                "registration_ticket": ticket,
+                "registration_request": request,
+                "registration_finish": Base64String::encode(&finish.message.serialize()),
+                "password_wrapped_master_key":
+                    Base64String::encode_bytes(&keys.wrap_with_export_key(&finish.export_key)?),
+            })),
+        )
+        .await?;
+
+        if let Some(session) = self.session.as_mut() {
+            session.reregistration_required = false;
+        }
+        Ok(())
+    }
+
+    /// Registers a new recovery code for the logged in user, replacing any
+    /// earlier one, and returns it to be shown to the user once. The vault
+    /// keys are wrapped to a key derived from the code, so
+    /// [`Client::recover`] can open them after the password is lost.
+    pub async fn enable_recovery(&mut self) -> ClientResult<String> {
+        let keys = self.current_session()?.vault_keys()?;
+        let code = generate_recovery_code();
+
+        let document = self.server_document().await?;
+        let (finish, ticket, request) = self
+            .start_registration(
+                &document,
+                "/v1/auth/recovery/init",
+                None,
+                &normalize_recovery_code(&code),
+            )
+            .await?;
+        let recovery_key = RecoveryKey::derive(&finish.export_key)?;
+        self.send_authenticated(
+            "POST",
+            "/v1/auth/recovery/finish",
+            Some(json!({
+                "registration_ticket": ticket,
+                "registration_request": request,
+                "registration_finish": Base64String::encode(&finish.message.serialize()),
+                "public_key": Base64String::encode_bytes(&recovery_key.public_key()),
+                "wrapped_master_key": Base64String::encode_bytes(&keys.wrap(recovery_key.public_key())?),
+            })),
+        )
+        .await?;
+        Ok(code)
+    }
+
+    /// Removes the recovery code of the logged in user.
+    pub async fn disable_recovery(&mut self) -> ClientResult<()> {
+        self.send_authenticated("DELETE", "/v1/auth/recovery", None)
+            .await?;
+        Ok(())
+    }
+
+    /// The username the server has for this session.
+    pub async fn whoami(&mut self) -> ClientResult<String> {
+        #[derive(Deserialize)]
+        struct SessionResponse {
+            username: String,
+        }
+        let session: SessionResponse = self.send_json("GET", "/v1/auth/session", None).await?;
+        Ok(session.username)
+    }
+
+    pub async fn list_items(&mut self) -> ClientResult<Vec<StoredItem>> {
+        let list: ItemList = self.send_json("GET", "/v1/items", None).await?;
+        Ok(list.items)
+    }
+
+    pub async fn get_item(&mut self, id: &str) -> ClientResult<StoredItem> {
+        self.send_json("GET", &format!("/v1/items/{}", segment(id)), None)
+            .await
+    }
+
+    pub async fn delete_item(&mut self, id: &str) -> ClientResult<()> {
+        self.send_authenticated("DELETE", &format!("/v1/items/{}", segment(id)), None)
+            .await?;
+        Ok(())
+    }
+
+    /// Encrypts `payload` and stores it as a new item.
+    pub async fn put(&mut self, payload: &ItemPayload) -> ClientResult<StoredItem> {
+        let ciphertext = payload.encrypt(&self.current_session()?.vault_keys()?)?;
+        self.send_json(
+            "POST",
+            "/v1/items",
+            Some(json!({ "ciphertext": Base64String::encode_bytes(&ciphertext) })),
+        )
+        .await
+    }
+
+    /// Encrypts `payload` and replaces item `id` with it.
+    pub async fn update(&mut self, id: &str, payload: &ItemPayload) -> ClientResult<StoredItem> {
+        let ciphertext = payload.encrypt(&self.current_session()?.vault_keys()?)?;
+        self.send_json(
+            "PUT",
+            &format!("/v1/items/{}", segment(id)),
+            Some(json!({ "ciphertext": Base64String::encode_bytes(&ciphertext) })),
+        )
+        .await
+    }
+
+    pub fn decrypt(&self, item: &StoredItem) -> ClientResult<ItemPayload> {
+        ItemPayload::decrypt(
+            &self.current_session()?.vault_keys()?,
+            &item.ciphertext.decode_bytes()?,
+        )
+    }
+
+    pub async fn get(&mut self, id: &str) -> ClientResult<ItemPayload> {
+        let item = self.get_item(id).await?;
+        self.decrypt(&item)
+    }
+
+    /// Publishes the identity key others encrypt shared items to. This
+    /// replaces any keys published before.
+    pub async fn publish_identity_key(&mut self) -> ClientResult<()> {
+        let session = self.current_session()?;
+        let public_key = session.vault_keys()?.identity_public_key();
+        let path = format!("/v1/users/{}/public-keys", segment(&session.username));
+        self.send_authenticated(
+            "PUT",
+            &path,
+            Some(json!({
+                "keys": [PublicKey {
+                    algorithm: IDENTITY_KEY_ALGORITHM.to_string(),
+                    key: Base64String::encode_bytes(&public_key),
+                }],
+            })),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Looks up the identity key of `username` and checks that it is the
+    /// binding logged in a tree head signed by the key pinned at login.
+    pub async fn fetch_identity_key(&self, username: &str) -> ClientResult<[u8; 32]> {
+        let signing_public_key = self.current_session()?.signing_public_key.decode_array()?;
+        let response: PublicKeysResponse = self
+            .get_json(&format!("/v1/users/{}/public-keys", segment(username)))
+            .await?;
+
+        let tree_head: TreeHead = response.tree_head.verify(&signing_public_key).map_err(|_| {
+            ClientError::Verification(
+                "The tree head is not signed by the key pinned at login, log in again if the server rotated it"
+                    .to_string(),
+            )
+        })?;
+        let leaf = response.leaf.decode_bytes()?;
+        let proof = response
+            .inclusion_proof
+            .iter()
+            .map(|hash| hash.decode_array())
+            .collect::<Result<Vec<_>, _>>()?;
+        if !verify_inclusion(
+            &leaf_hash(&leaf),
+            response.leaf_index,
+            tree_head.tree_size,
+            &proof,
+            &tree_head.root_hash.decode_array()?,
+        ) {
+            return Err(ClientError::Verification(
+                "The key binding is not in the signed tree".to_string(),
+            ));
+        }
+
+        // Trust only what was hashed into the tree
+        let binding: KeyBinding = serde_json::from_slice(&leaf)?;
+        if binding.username != username {
+            return Err(ClientError::Verification(format!(
+                "The logged binding belongs to {}",
+                binding.username
+            )));
+        }
+        let key = binding
+            .keys
+            .iter()
+            .find(|key| key.algorithm == IDENTITY_KEY_ALGORITHM)
+            .ok_or_else(|| ClientError::MissingIdentityKey(username.to_string()))?;
+        Ok(key.key.decode_array()?)
+    }
+
+    /// Re-encrypts item `id` to the verified identity key of `recipient`, and
+    /// records the grant in this user's audit trail.
+    pub async fn share(&mut self, id: &str, recipient: &str) -> ClientResult<SharedItem> {
+        let payload = self.get(id).await?;
+        let recipient_key = self.fetch_identity_key(recipient).await?;
+        let username = self.current_session()?.username.clone();
+        let shared = SharedItem::seal(&payload, &username, recipient, recipient_key)?;
+        self.send_authenticated(
+            "POST",
+            &format!("/v1/users/{}/shares", segment(&username)),
+            Some(json!({ "recipient": recipient })),
+        )
+        .await?;
+        Ok(shared)
+    }
+
+    /// Opens an item shared with this user and stores it in the vault.
+    pub async fn receive(&mut self, shared: &SharedItem) -> ClientResult<StoredItem> {
+        let session = self.current_session()?;
+        if shared.recipient != session.username {
+            return Err(ClientError::Verification(format!(
+                "This item was shared with {}",
+                shared.recipient
+            )));
+        }
+        let payload = shared.open(&session.vault_keys()?)?;
+        self.put(&payload).await
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt::Display;
+
+use base64::Engine;
+use generic_array::{ArrayLength, GenericArray};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Base64Error(base64::DecodeError),
+    LengthMismatch { expected: usize, actual: usize },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Base64Error(err) => write!(f, "Base64 decoding error: {}", err),
+            DecodeError::LengthMismatch { expected, actual } => write!(
+                f,
+                "Incorrect length: expected {} bytes but got {}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// URL-safe base64 with padding, the encoding of every binary field on the
+/// wire.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Base64String(String);
+
+impl Base64String {
+    pub fn encode<N: ArrayLength<u8>>(data: &GenericArray<u8, N>) -> Self {
+        Self::encode_bytes(data)
+    }
+
+    pub fn encode_bytes(data: &[u8]) -> Self {
+        Base64String(base64::engine::general_purpose::URL_SAFE.encode(data))
+    }
+
+    pub fn decode<N: ArrayLength<u8>>(&self) -> Result<GenericArray<u8, N>, DecodeError> {
+        let bytes = self.decode_bytes()?;
+        let actual = bytes.len();
+        GenericArray::from_exact_iter(bytes).ok_or(DecodeError::LengthMismatch {
+            expected: N::to_usize(),
+            actual,
+        })
+    }
+
+    pub fn decode_bytes(&self) -> Result<Vec<u8>, DecodeError> {
+        base64::engine::general_purpose::URL_SAFE
+            .decode(&self.0)
+            .map_err(DecodeError::Base64Error)
+    }
+
+    /// Decodes exactly `N` bytes, as for keys and hashes.
+    pub fn decode_array<const N: usize>(&self) -> Result<[u8; N], DecodeError> {
+        let bytes = self.decode_bytes()?;
+        let actual = bytes.len();
+        bytes.try_into().map_err(|_| DecodeError::LengthMismatch {
+            expected: N,
+            actual,
+        })
+    }
+}
+
+impl From<String> for Base64String {
+    fn from(value: String) -> Self {
+        Base64String(value)
+    }
+}
+
+impl Display for Base64String {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
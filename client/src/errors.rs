@@ -0,0 +1,147 @@
+use std::fmt::{Display, Formatter};
+
+use opaque_ke::errors::ProtocolError;
+use serde::Deserialize;
+
+use crate::encoding::DecodeError;
+
+/// The server's machine-readable error codes. Codes added to the server
+/// later deserialize as [`ErrorCode::Unknown`].
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidJson,
+    InvalidCbor,
+    UnsupportedMediaType,
+    InvalidBody,
+    InvalidHeader,
+    InvalidFields,
+    DecodeInvalidBase64,
+    DecodeLengthMismatch,
+    InvalidCredentials,
+    LoginSessionExpired,
+    RegistrationTicketExpired,
+    Unauthenticated,
+    SessionExpired,
+    InvalidRequestSignature,
+    RequestTimestampOutOfWindow,
+    ReplayedNonce,
+    InvalidSealedMessage,
+    SealedSequenceMismatch,
+    SealedChannelRequired,
+    UsernameTaken,
+    Forbidden,
+    NotFound,
+    RateLimited,
+    InvalidQuery,
+    InvalidValue,
+    InvalidTreeSize,
+    AuditChainBroken,
+    AccountLocked,
+    OpaqueKeyInUse,
+    UnsupportedApiVersion,
+    ClientVersionUnsupported,
+    InternalError,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// No response was received
+    Transport(String),
+    /// The server answered with an error envelope
+    Api {
+        status: u16,
+        code: ErrorCode,
+        message: String,
+        request_id: Option<String>,
+    },
+    Json(serde_json::Error),
+    Decode(DecodeError),
+    Protocol(ProtocolError),
+    /// The OPAQUE exchange failed on the client, so the password is wrong or
+    /// the account does not exist
+    InvalidCredentials,
+    /// Encryption or decryption failed, the data was tampered with or the key
+    /// is wrong
+    Crypto(String),
+    /// A signature, proof or key from the server did not check out
+    Verification(String),
+    NotLoggedIn,
+    /// The user has not published a key to share items with
+    MissingIdentityKey(String),
+}
+
+impl ClientError {
+    /// The server's error code, if the server answered with one.
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            ClientError::Api { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Transport(err) => write!(f, "Request failed: {}", err),
+            ClientError::Api {
+                status, message, ..
+            } => write!(f, "Server returned {}: {}", status, message),
+            ClientError::Json(err) => write!(f, "Invalid JSON: {}", err),
+            ClientError::Decode(err) => write!(f, "{}", err),
+            ClientError::Protocol(err) => write!(f, "OPAQUE protocol error: {}", err),
+            ClientError::InvalidCredentials => write!(f, "Wrong username or password"),
+            ClientError::Crypto(err) => write!(f, "{}", err),
+            ClientError::Verification(err) => write!(f, "Verification failed: {}", err),
+            ClientError::NotLoggedIn => write!(f, "Not logged in"),
+            ClientError::MissingIdentityKey(username) => {
+                write!(f, "{} has not published an identity key", username)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(err: serde_json::Error) -> Self {
+        ClientError::Json(err)
+    }
+}
+
+impl From<DecodeError> for ClientError {
+    fn from(err: DecodeError) -> Self {
+        ClientError::Decode(err)
+    }
+}
+
+impl From<ProtocolError> for ClientError {
+    fn from(err: ProtocolError) -> Self {
+        match err {
+            ProtocolError::InvalidLoginError => ClientError::InvalidCredentials,
+            err => ClientError::Protocol(err),
+        }
+    }
+}
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+
+    #[test]
+    fn unknown_codes_still_deserialize() {
+        let known: ErrorCode = serde_json::from_str("\"session_expired\"").unwrap();
+        let unknown: ErrorCode = serde_json::from_str("\"added_later\"").unwrap();
+
+        assert_eq!(known, ErrorCode::SessionExpired);
+        assert_eq!(unknown, ErrorCode::Unknown);
+    }
+}
@@ -0,0 +1,28 @@
+#![deny(clippy::unwrap_used)]
+#![deny(clippy::expect_used)]
+#![deny(clippy::panic)]
+#![deny(unused_must_use)]
+
+//! Client for the salauskilke protocol: OPAQUE registration and login,
+//! signed and sealed requests, and an end-to-end encrypted vault whose keys
+//! the server never sees.
+//!
+//! The crate builds for `wasm32-unknown-unknown`. HTTP goes through a
+//! [`Transport`], with a `reqwest` based one behind the default `reqwest`
+//! feature.
+
+pub mod client;
+pub mod encoding;
+pub mod errors;
+pub mod protocol;
+pub mod session;
+pub mod transparency;
+pub mod transport;
+pub mod vault;
+
+pub use client::Client;
+pub use errors::{ClientError, ClientResult, ErrorCode};
+pub use session::Session;
+#[cfg(feature = "reqwest")]
+pub use transport::ReqwestTransport;
+pub use transport::{HttpRequest, HttpResponse, Transport};
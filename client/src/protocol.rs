@@ -0,0 +1,237 @@
+//! The client halves of the server's protocol primitives. They must stay in
+//! step with the backend, which `tests/compatibility.rs` checks.
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::{Signature, VerifyingKey};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use opaque_ke::ciphersuite::CipherSuite;
+use serde::{de::DeserializeOwned, Deserialize};
+use sha2::{Digest, Sha256};
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::encoding::Base64String;
+use crate::errors::{ClientError, ClientResult};
+
+pub struct CS;
+
+/// Identifies [`CS`] in the server document.
+pub const CIPHERSUITE_ID: &str = "opaque-3dh-ristretto255-sha512-argon2id";
+
+impl CipherSuite for CS {
+    type OprfCs = opaque_ke::Ristretto255;
+    type KeGroup = opaque_ke::Ristretto255;
+    type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDh;
+    type Ksf = Argon2<'static>;
+}
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const SEALED_CONTENT_TYPE: &str = "application/salauskilke-sealed";
+
+pub const SESSION_ID_HEADER: &str = "x-session-id";
+pub const TIMESTAMP_HEADER: &str = "x-timestamp";
+pub const NONCE_HEADER: &str = "x-nonce";
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Sent with every request so the server can refuse releases it no longer
+/// supports.
+pub const CLIENT_VERSION_HEADER: &str = "x-client-version";
+pub const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const MAC_KEY_INFO: &[u8] = b"salauskilke request mac v1";
+const CLIENT_TO_SERVER_INFO: &[u8] = b"salauskilke sealed channel client to server v1";
+const SERVER_TO_CLIENT_INFO: &[u8] = b"salauskilke sealed channel server to client v1";
+const SEQUENCE_LEN: usize = 8;
+
+fn expand(session_key: &[u8], info: &[u8]) -> ClientResult<[u8; 32]> {
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, session_key)
+        .expand(info, &mut key)
+        .map_err(|e| ClientError::Crypto(e.to_string()))?;
+    Ok(key)
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// The request signing key, derived from the OPAQUE session key.
+pub fn derive_mac_key(session_key: &[u8]) -> ClientResult<[u8; 32]> {
+    expand(session_key, MAC_KEY_INFO)
+}
+
+/// The parts of an HTTP request covered by the request MAC.
+pub struct RequestToSign<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub timestamp: u64,
+    pub nonce: &'a str,
+    pub body: &'a [u8],
+}
+
+impl RequestToSign<'_> {
+    /// `METHOD \n path \n timestamp \n nonce \n hex(sha256(body))`
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        format!(
+            "{}\n{}\n{}\n{}\n{}",
+            self.method,
+            self.path,
+            self.timestamp,
+            self.nonce,
+            hex::encode(Sha256::digest(self.body))
+        )
+        .into_bytes()
+    }
+
+    pub fn sign(&self, mac_key: &[u8; 32]) -> ClientResult<[u8; 32]> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(mac_key)
+            .map_err(|e| ClientError::Crypto(e.to_string()))?;
+        mac.update(&self.canonical_bytes());
+        Ok(mac.finalize().into_bytes().into())
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+impl Direction {
+    fn aad(&self) -> &'static [u8] {
+        match self {
+            Direction::ClientToServer => b"c2s",
+            Direction::ServerToClient => b"s2c",
+        }
+    }
+}
+
+/// Separate AEAD keys for each direction of the sealed channel.
+#[derive(Clone)]
+pub struct ChannelKeys {
+    client_to_server: [u8; 32],
+    server_to_client: [u8; 32],
+}
+
+fn nonce(sequence: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&sequence.to_be_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+impl ChannelKeys {
+    pub fn derive(session_key: &[u8]) -> ClientResult<Self> {
+        Ok(Self {
+            client_to_server: expand(session_key, CLIENT_TO_SERVER_INFO)?,
+            server_to_client: expand(session_key, SERVER_TO_CLIENT_INFO)?,
+        })
+    }
+
+    fn cipher(&self, direction: Direction) -> ChaCha20Poly1305 {
+        let key = match direction {
+            Direction::ClientToServer => &self.client_to_server,
+            Direction::ServerToClient => &self.server_to_client,
+        };
+        ChaCha20Poly1305::new(Key::from_slice(key))
+    }
+
+    /// Encrypts `plaintext` into `sequence (8 bytes, big endian) || ciphertext`.
+    pub fn seal(
+        &self,
+        direction: Direction,
+        sequence: u64,
+        plaintext: &[u8],
+    ) -> ClientResult<Vec<u8>> {
+        let ciphertext = self
+            .cipher(direction)
+            .encrypt(
+                &nonce(sequence),
+                Payload {
+                    msg: plaintext,
+                    aad: direction.aad(),
+                },
+            )
+            .map_err(|_| ClientError::Crypto("Failed to seal message".to_string()))?;
+
+        let mut sealed = sequence.to_be_bytes().to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypts a sealed message and returns its sequence number and
+    /// plaintext.
+    pub fn open(&self, direction: Direction, sealed: &[u8]) -> ClientResult<(u64, Vec<u8>)> {
+        if sealed.len() < SEQUENCE_LEN {
+            return Err(ClientError::Verification(
+                "Sealed message is too short".to_string(),
+            ));
+        }
+        let (sequence_bytes, ciphertext) = sealed.split_at(SEQUENCE_LEN);
+        let mut sequence = [0u8; SEQUENCE_LEN];
+        sequence.copy_from_slice(sequence_bytes);
+        let sequence = u64::from_be_bytes(sequence);
+
+        let plaintext = self
+            .cipher(direction)
+            .decrypt(
+                &nonce(sequence),
+                Payload {
+                    msg: ciphertext,
+                    aad: direction.aad(),
+                },
+            )
+            .map_err(|_| {
+                ClientError::Verification("Sealed message could not be opened".to_string())
+            })?;
+
+        Ok((sequence, plaintext))
+    }
+}
+
+/// A JSON document signed by the server's Ed25519 signing key.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SignedJson {
+    pub payload: Base64String,
+    pub signature: Base64String,
+    pub signing_key_fingerprint: String,
+}
+
+impl SignedJson {
+    /// Checks the signature over the payload bytes with `public_key` and
+    /// parses the payload.
+    pub fn verify<T: DeserializeOwned>(&self, public_key: &[u8; 32]) -> ClientResult<T> {
+        let payload = self.payload.decode_bytes()?;
+        let key = VerifyingKey::from_bytes(public_key)
+            .map_err(|_| ClientError::Verification("Invalid signing key".to_string()))?;
+        let signature = Signature::from_bytes(&self.signature.decode_array()?);
+        key.verify_strict(&payload, &signature)
+            .map_err(|_| ClientError::Verification("Bad signature".to_string()))?;
+        Ok(serde_json::from_slice(&payload)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+
+    #[test]
+    fn sealed_messages_open_only_in_their_direction() {
+        let keys = ChannelKeys::derive(&[7u8; 64]).unwrap();
+        let sealed = keys.seal(Direction::ClientToServer, 5, b"hello").unwrap();
+
+        assert_eq!(
+            keys.open(Direction::ClientToServer, &sealed).unwrap(),
+            (5, b"hello".to_vec())
+        );
+        assert!(keys.open(Direction::ServerToClient, &sealed).is_err());
+    }
+}
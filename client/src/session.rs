@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+use x25519_dalek::StaticSecret;
+
+use crate::encoding::Base64String;
+use crate::errors::ClientResult;
+use crate::vault::VaultKeys;
+
+/// Everything a [`Client`](crate::Client) needs to keep using a login. It
+/// holds the session and vault keys, so wherever it is persisted must be
+/// private to the user.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub username: String,
+    pub session_id: String,
+    pub session_key: Base64String,
+    pub item_key: Base64String,
+    pub identity_key: Base64String,
+    /// The sequence number of the next sealed request
+    pub next_sequence: u64,
+    /// Pinned at login, tree heads must be signed by it
+    pub signing_public_key: Base64String,
+    /// The server has rotated the OPAQUE key the account was registered
+    /// under. Changing the password, even to the same one, registers the
+    /// account under the current key.
+    #[serde(default)]
+    pub reregistration_required: bool,
+}
+
+impl Session {
+    pub fn vault_keys(&self) -> ClientResult<VaultKeys> {
+        Ok(VaultKeys {
+            item_key: self.item_key.decode_array()?,
+            identity: StaticSecret::from(self.identity_key.decode_array::<32>()?),
+        })
+    }
+
+    pub(crate) fn set_vault_keys(&mut self, keys: &VaultKeys) {
+        self.item_key = Base64String::encode_bytes(&keys.item_key);
+        self.identity_key = Base64String::encode_bytes(keys.identity.as_bytes());
+    }
+}
@@ -0,0 +1,104 @@
+//! Verification of the server's key transparency log proofs, following
+//! RFC 9162.
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::encoding::Base64String;
+
+pub type Hash = [u8; 32];
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// The signed statement that the log held `tree_size` leaves with
+/// `root_hash`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TreeHead {
+    pub tree_size: u64,
+    pub root_hash: Base64String,
+    pub timestamp: u64,
+}
+
+/// `SHA-256(0x00 || data)`
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    Sha256::new()
+        .chain_update([LEAF_PREFIX])
+        .chain_update(data)
+        .finalize()
+        .into()
+}
+
+/// `SHA-256(0x01 || left || right)`
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new()
+        .chain_update([NODE_PREFIX])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+/// Checks an inclusion proof, following RFC 9162 section 2.1.3.2.
+pub fn verify_inclusion(
+    leaf_hash: &Hash,
+    index: u64,
+    size: u64,
+    proof: &[Hash],
+    root: &Hash,
+) -> bool {
+    if index >= size {
+        return false;
+    }
+    let (mut fnode, mut snode) = (index, size - 1);
+    let mut hash = *leaf_hash;
+    for sibling in proof {
+        if snode == 0 {
+            return false;
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            hash = node_hash(sibling, &hash);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            hash = node_hash(&hash, sibling);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    snode == 0 && hash == *root
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+
+    #[test]
+    fn verifies_a_three_leaf_tree() {
+        let leaves: Vec<Hash> = [b"a", b"b", b"c"].iter().map(|l| leaf_hash(*l)).collect();
+        let left = node_hash(&leaves[0], &leaves[1]);
+        let root = node_hash(&left, &leaves[2]);
+
+        assert!(verify_inclusion(&leaves[2], 2, 3, &[left], &root));
+        assert!(verify_inclusion(
+            &leaves[0],
+            0,
+            3,
+            &[leaves[1], leaves[2]],
+            &root
+        ));
+        assert!(!verify_inclusion(
+            &leaves[1],
+            0,
+            3,
+            &[leaves[1], leaves[2]],
+            &root
+        ));
+        assert!(!verify_inclusion(&leaves[2], 3, 3, &[left], &root));
+    }
+}
@@ -0,0 +1,82 @@
+use std::future::Future;
+
+use crate::errors::ClientResult;
+
+pub struct HttpRequest {
+    pub method: &'static str,
+    pub url: String,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// Sends HTTP requests for a [`Client`](crate::Client). Error statuses are
+/// responses like any other, only failing to get a response is an error.
+///
+/// The futures are not required to be `Send`, so that transports built on
+/// the browser's `fetch` can implement this too.
+pub trait Transport {
+    fn send(&self, request: HttpRequest) -> impl Future<Output = ClientResult<HttpResponse>>;
+}
+
+#[cfg(feature = "reqwest")]
+mod reqwest_transport {
+    use reqwest::header::CONTENT_TYPE;
+    use reqwest::Method;
+
+    use super::{HttpRequest, HttpResponse, Transport};
+    use crate::errors::{ClientError, ClientResult};
+
+    /// Sends requests with `reqwest`, which uses `fetch` on wasm.
+    #[derive(Clone, Default)]
+    pub struct ReqwestTransport {
+        client: reqwest::Client,
+    }
+
+    impl ReqwestTransport {
+        pub fn new(client: reqwest::Client) -> Self {
+            Self { client }
+        }
+    }
+
+    fn transport_error(err: impl std::fmt::Display) -> ClientError {
+        ClientError::Transport(err.to_string())
+    }
+
+    impl Transport for ReqwestTransport {
+        async fn send(&self, request: HttpRequest) -> ClientResult<HttpResponse> {
+            let method = Method::from_bytes(request.method.as_bytes()).map_err(transport_error)?;
+            let mut builder = self.client.request(method, &request.url);
+            for (name, value) in request.headers {
+                builder = builder.header(name, value);
+            }
+            let response = builder
+                .body(request.body)
+                .send()
+                .await
+                .map_err(transport_error)?;
+
+            let status = response.status().as_u16();
+            let content_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let body = response.bytes().await.map_err(transport_error)?.to_vec();
+
+            Ok(HttpResponse {
+                status,
+                content_type,
+                body,
+            })
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+pub use reqwest_transport::ReqwestTransport;
@@ -0,0 +1,461 @@
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::encoding::Base64String;
+use crate::errors::{ClientError, ClientResult};
+
+const ITEM_KEY_INFO: &[u8] = b"salauskilke vault item key v1";
+const IDENTITY_KEY_INFO: &[u8] = b"salauskilke vault identity key v1";
+const SHARE_KEY_INFO: &[u8] = b"salauskilke share v1";
+const RECOVERY_KEY_INFO: &[u8] = b"salauskilke recovery key v1";
+const RECOVERY_WRAP_INFO: &[u8] = b"salauskilke recovery wrap v1";
+const RECOVERY_AAD: &[u8] = b"salauskilke recovery master key v1";
+const PASSWORD_WRAP_INFO: &[u8] = b"salauskilke password wrap v1";
+const PASSWORD_AAD: &[u8] = b"salauskilke password master key v1";
+const RECOVERY_CODE_BYTES: usize = 16;
+const ITEM_AAD: &[u8] = b"salauskilke vault item v1";
+const NONCE_LEN: usize = 24;
+
+/// Name of the public key algorithm published for sharing.
+pub const IDENTITY_KEY_ALGORITHM: &str = "x25519";
+
+/// The vault master key. It is random and wrapped to the OPAQUE export key,
+/// which only a client that knows the password can compute, so a password
+/// change only wraps it again. Accounts registered before the wrapped key
+/// existed derive it from the export key instead.
+#[derive(Clone)]
+pub struct VaultKeys {
+    /// Encrypts the account's own items
+    pub item_key: [u8; 32],
+    /// Receives items shared by other users
+    pub identity: StaticSecret,
+}
+
+fn expand(hkdf: &Hkdf<Sha256>, info: &[u8]) -> ClientResult<[u8; 32]> {
+    let mut key = [0u8; 32];
+    hkdf.expand(info, &mut key)
+        .map_err(|e| ClientError::Crypto(e.to_string()))?;
+    Ok(key)
+}
+
+impl VaultKeys {
+    pub fn generate() -> Self {
+        let mut item_key = [0u8; 32];
+        OsRng.fill_bytes(&mut item_key);
+        Self {
+            item_key,
+            identity: StaticSecret::random_from_rng(OsRng),
+        }
+    }
+
+    pub fn derive(export_key: &[u8]) -> ClientResult<Self> {
+        let hkdf = Hkdf::<Sha256>::new(None, export_key);
+        Ok(Self {
+            item_key: expand(&hkdf, ITEM_KEY_INFO)?,
+            identity: StaticSecret::from(expand(&hkdf, IDENTITY_KEY_INFO)?),
+        })
+    }
+
+    /// Seals the keys with a key derived from `export_key`, as
+    /// `nonce || ciphertext`.
+    pub fn wrap_with_export_key(&self, export_key: &[u8]) -> ClientResult<Vec<u8>> {
+        let key = expand(&Hkdf::<Sha256>::new(None, export_key), PASSWORD_WRAP_INFO)?;
+        let mut master_key = self.to_bytes();
+        let sealed = seal(&key, PASSWORD_AAD, &master_key);
+        master_key.fill(0);
+        sealed
+    }
+
+    /// Opens keys wrapped with [`VaultKeys::wrap_with_export_key`].
+    pub fn unwrap_with_export_key(export_key: &[u8], wrapped: &[u8]) -> ClientResult<Self> {
+        let key = expand(&Hkdf::<Sha256>::new(None, export_key), PASSWORD_WRAP_INFO)?;
+        let mut master_key = open(&key, PASSWORD_AAD, wrapped)?;
+        let keys = Self::from_bytes(&master_key);
+        master_key.fill(0);
+        keys
+    }
+
+    /// `item key || identity secret`, the plaintext of both wraps.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut master_key = self.item_key.to_vec();
+        master_key.extend_from_slice(self.identity.as_bytes());
+        master_key
+    }
+
+    fn from_bytes(master_key: &[u8]) -> ClientResult<Self> {
+        match master_key.split_at_checked(32) {
+            Some((item_key, identity)) if identity.len() == 32 => {
+                let mut identity_bytes = [0u8; 32];
+                identity_bytes.copy_from_slice(identity);
+                let mut keys = Self {
+                    item_key: [0u8; 32],
+                    identity: StaticSecret::from(identity_bytes),
+                };
+                keys.item_key.copy_from_slice(item_key);
+                Ok(keys)
+            }
+            _ => Err(ClientError::Crypto("Invalid wrapped key".to_string())),
+        }
+    }
+
+    pub fn identity_public_key(&self) -> [u8; 32] {
+        PublicKey::from(&self.identity).to_bytes()
+    }
+
+    /// Encrypts the keys to a recovery key as
+    /// `ephemeral public key (32 bytes) || nonce || ciphertext`.
+    pub fn wrap(&self, recovery_public_key: [u8; 32]) -> ClientResult<Vec<u8>> {
+        let recovery_public_key = PublicKey::from(recovery_public_key);
+        let ephemeral = StaticSecret::random_from_rng(OsRng);
+        let ephemeral_public_key = PublicKey::from(&ephemeral);
+        let key = share_key(
+            &ephemeral,
+            &recovery_public_key,
+            &ephemeral_public_key,
+            &recovery_public_key,
+            RECOVERY_WRAP_INFO,
+        )?;
+
+        let mut master_key = self.to_bytes();
+        let sealed = seal(&key, RECOVERY_AAD, &master_key);
+        master_key.fill(0);
+
+        let mut wrapped = ephemeral_public_key.to_bytes().to_vec();
+        wrapped.extend_from_slice(&sealed?);
+        Ok(wrapped)
+    }
+
+    /// Opens keys wrapped with [`VaultKeys::wrap`].
+    pub fn unwrap(recovery: &RecoveryKey, wrapped: &[u8]) -> ClientResult<Self> {
+        if wrapped.len() < 32 {
+            return Err(ClientError::Crypto("Wrapped key is too short".to_string()));
+        }
+        let (ephemeral_public_key, sealed) = wrapped.split_at(32);
+        let ephemeral_public_key = PublicKey::from(
+            <[u8; 32]>::try_from(ephemeral_public_key)
+                .map_err(|_| ClientError::Crypto("Invalid ephemeral public key".to_string()))?,
+        );
+        let key = share_key(
+            &recovery.secret,
+            &ephemeral_public_key,
+            &ephemeral_public_key,
+            &PublicKey::from(recovery.public_key()),
+            RECOVERY_WRAP_INFO,
+        )?;
+
+        let mut master_key = open(&key, RECOVERY_AAD, sealed)?;
+        let keys = Self::from_bytes(&master_key);
+        master_key.fill(0);
+        keys
+    }
+}
+
+/// A recovery code, which logs in like a second password and opens the
+/// vault keys wrapped to its [`RecoveryKey`]. Shown to the user once, as
+/// groups of hex digits.
+pub fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    let digits = hex::encode(bytes);
+    bytes.fill(0);
+    digits
+        .as_bytes()
+        .chunks(4)
+        .map(|group| String::from_utf8_lossy(group).into_owned())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// The form of a recovery code that is registered, so that it may be typed
+/// back with any case, spacing or dashes.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The key the vault keys are wrapped to, derived from the export key of
+/// the recovery code's OPAQUE registration.
+pub struct RecoveryKey {
+    secret: StaticSecret,
+}
+
+impl RecoveryKey {
+    pub fn derive(export_key: &[u8]) -> ClientResult<Self> {
+        Ok(Self {
+            secret: StaticSecret::from(expand(
+                &Hkdf::<Sha256>::new(None, export_key),
+                RECOVERY_KEY_INFO,
+            )?),
+        })
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        PublicKey::from(&self.secret).to_bytes()
+    }
+}
+
+/// Encrypts into `nonce (24 bytes) || ciphertext` with XChaCha20-Poly1305,
+/// whose nonces are long enough to pick at random.
+fn seal(key: &[u8; 32], aad: &[u8], plaintext: &[u8]) -> ClientResult<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = XChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| ClientError::Crypto("Encryption failed".to_string()))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+fn open(key: &[u8; 32], aad: &[u8], sealed: &[u8]) -> ClientResult<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return Err(ClientError::Crypto("Ciphertext is too short".to_string()));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| ClientError::Crypto("Decryption failed".to_string()))
+}
+
+/// What an item decrypts to. Files keep their name.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ItemPayload {
+    pub name: String,
+    pub data: Base64String,
+}
+
+impl ItemPayload {
+    pub fn new(name: &str, data: &[u8]) -> Self {
+        Self {
+            name: name.to_string(),
+            data: Base64String::encode_bytes(data),
+        }
+    }
+
+    pub fn encrypt(&self, keys: &VaultKeys) -> ClientResult<Vec<u8>> {
+        seal(&keys.item_key, ITEM_AAD, &serde_json::to_vec(self)?)
+    }
+
+    pub fn decrypt(keys: &VaultKeys, ciphertext: &[u8]) -> ClientResult<Self> {
+        Ok(serde_json::from_slice(&open(
+            &keys.item_key,
+            ITEM_AAD,
+            ciphertext,
+        )?)?)
+    }
+}
+
+/// An item encrypted to another user's identity key. It travels out of band,
+/// the server only vouches for the recipient's public key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SharedItem {
+    pub sender: String,
+    pub recipient: String,
+    pub ephemeral_public_key: Base64String,
+    pub ciphertext: Base64String,
+}
+
+/// Binds the ciphertext to who sent it to whom.
+fn share_aad(sender: &str, recipient: &str) -> Vec<u8> {
+    let mut aad = Vec::new();
+    for field in [sender, recipient] {
+        aad.extend_from_slice(&(field.len() as u64).to_be_bytes());
+        aad.extend_from_slice(field.as_bytes());
+    }
+    aad
+}
+
+fn share_key(
+    secret: &StaticSecret,
+    public_key: &PublicKey,
+    ephemeral_public_key: &PublicKey,
+    recipient_public_key: &PublicKey,
+    info: &[u8],
+) -> ClientResult<[u8; 32]> {
+    let shared = secret.diffie_hellman(public_key);
+    if !shared.was_contributory() {
+        return Err(ClientError::Crypto("Invalid public key".to_string()));
+    }
+    let mut salt = ephemeral_public_key.to_bytes().to_vec();
+    salt.extend_from_slice(recipient_public_key.as_bytes());
+    expand(&Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes()), info)
+}
+
+impl SharedItem {
+    /// Encrypts `payload` with a key agreed between a fresh ephemeral key and
+    /// the recipient's identity key.
+    pub fn seal(
+        payload: &ItemPayload,
+        sender: &str,
+        recipient: &str,
+        recipient_public_key: [u8; 32],
+    ) -> ClientResult<Self> {
+        let recipient_public_key = PublicKey::from(recipient_public_key);
+        let ephemeral = StaticSecret::random_from_rng(OsRng);
+        let ephemeral_public_key = PublicKey::from(&ephemeral);
+        let key = share_key(
+            &ephemeral,
+            &recipient_public_key,
+            &ephemeral_public_key,
+            &recipient_public_key,
+            SHARE_KEY_INFO,
+        )?;
+
+        Ok(Self {
+            sender: sender.to_string(),
+            recipient: recipient.to_string(),
+            ephemeral_public_key: Base64String::encode_bytes(ephemeral_public_key.as_bytes()),
+            ciphertext: Base64String::encode_bytes(&seal(
+                &key,
+                &share_aad(sender, recipient),
+                &serde_json::to_vec(payload)?,
+            )?),
+        })
+    }
+
+    pub fn open(&self, keys: &VaultKeys) -> ClientResult<ItemPayload> {
+        let ephemeral_public_key: [u8; 32] =
+            self.ephemeral_public_key
+                .decode_bytes()?
+                .try_into()
+                .map_err(|_| ClientError::Crypto("Invalid ephemeral public key".to_string()))?;
+        let ephemeral_public_key = PublicKey::from(ephemeral_public_key);
+        let key = share_key(
+            &keys.identity,
+            &ephemeral_public_key,
+            &ephemeral_public_key,
+            &PublicKey::from(keys.identity_public_key()),
+            SHARE_KEY_INFO,
+        )?;
+
+        let plaintext = open(
+            &key,
+            &share_aad(&self.sender, &self.recipient),
+            &self.ciphertext.decode_bytes()?,
+        )?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::expect_used)]
+
+    use super::*;
+
+    fn keys(export_key: &[u8]) -> VaultKeys {
+        VaultKeys::derive(export_key).unwrap()
+    }
+
+    #[test]
+    fn keys_are_deterministic() {
+        let first = keys(b"export key");
+        let second = keys(b"export key");
+
+        assert_eq!(first.item_key, second.item_key);
+        assert_eq!(first.identity_public_key(), second.identity_public_key());
+        assert_ne!(first.item_key, keys(b"other key").item_key);
+    }
+
+    #[test]
+    fn items_roundtrip_and_detect_tampering() {
+        let keys = keys(b"export key");
+        let payload = ItemPayload::new("notes.txt", b"secret notes");
+        let mut ciphertext = payload.encrypt(&keys).unwrap();
+
+        let decrypted = ItemPayload::decrypt(&keys, &ciphertext).unwrap();
+        assert_eq!(decrypted.name, "notes.txt");
+        assert_eq!(decrypted.data.decode_bytes().unwrap(), b"secret notes");
+        let last = ciphertext.len() - 1;
+        ciphertext[last] ^= 1;
+        assert!(ItemPayload::decrypt(&keys, &ciphertext).is_err());
+    }
+
+    #[test]
+    fn shares_open_only_for_the_recipient() {
+        let alice = keys(b"alice");
+        let bob = keys(b"bob");
+        let payload = ItemPayload::new("notes.txt", b"for bob");
+
+        let share = SharedItem::seal(&payload, "alice", "bob", bob.identity_public_key()).unwrap();
+        assert_eq!(
+            share.open(&bob).unwrap().data.decode_bytes().unwrap(),
+            b"for bob"
+        );
+        assert!(share.open(&alice).is_err());
+
+        let mut forged = share.clone();
+        forged.sender = "mallory".to_string();
+        assert!(forged.open(&bob).is_err());
+    }
+
+    #[test]
+    fn wrapped_keys_open_only_with_the_recovery_key() {
+        let keys = keys(b"export key");
+        let recovery = RecoveryKey::derive(b"recovery export key").unwrap();
+        let wrapped = keys.wrap(recovery.public_key()).unwrap();
+
+        let unwrapped = VaultKeys::unwrap(&recovery, &wrapped).unwrap();
+        assert_eq!(unwrapped.item_key, keys.item_key);
+        assert_eq!(unwrapped.identity_public_key(), keys.identity_public_key());
+
+        let other = RecoveryKey::derive(b"other export key").unwrap();
+        assert!(VaultKeys::unwrap(&other, &wrapped).is_err());
+        assert!(VaultKeys::unwrap(&recovery, &wrapped[..40]).is_err());
+    }
+
+    #[test]
+    fn generated_keys_rewrap_to_a_new_export_key() {
+        let keys = VaultKeys::generate();
+        assert_ne!(keys.item_key, VaultKeys::generate().item_key);
+        let wrapped = keys.wrap_with_export_key(b"old export key").unwrap();
+        let rewrapped = VaultKeys::unwrap_with_export_key(b"old export key", &wrapped)
+            .unwrap()
+            .wrap_with_export_key(b"new export key")
+            .unwrap();
+
+        let unwrapped = VaultKeys::unwrap_with_export_key(b"new export key", &rewrapped).unwrap();
+        assert_eq!(unwrapped.item_key, keys.item_key);
+        assert_eq!(unwrapped.identity_public_key(), keys.identity_public_key());
+        assert!(VaultKeys::unwrap_with_export_key(b"old export key", &rewrapped).is_err());
+
+        // A recovery wrap is not a password wrap
+        let recovery = RecoveryKey::derive(b"old export key").unwrap();
+        assert!(VaultKeys::unwrap(&recovery, &wrapped).is_err());
+    }
+
+    #[test]
+    fn recovery_codes_normalize_to_what_was_generated() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 39);
+        assert_ne!(code, generate_recovery_code());
+
+        let normalized = normalize_recovery_code(&code);
+        assert_eq!(normalized.len(), 32);
+        assert_eq!(
+            normalize_recovery_code(&format!(" {} ", code.to_uppercase().replace('-', " "))),
+            normalized
+        );
+    }
+}
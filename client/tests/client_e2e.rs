@@ -0,0 +1,386 @@
+use std::cell::Cell;
+use std::sync::Arc;
+
+use backend::http::initialize_app_state;
+use backend::storage::{memory::MemoryStorage, Storage};
+use backend::utils::config::Config;
+use salauskilke_client::vault::ItemPayload;
+use salauskilke_client::{
+    Client, ClientError, ClientResult, ErrorCode, HttpRequest, HttpResponse, ReqwestTransport,
+    Transport,
+};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpListener;
+
+fn test_config() -> Config {
+    Config {
+        port: 0,
+        database_url: "memory://".to_string(),
+        shutdown_timeout_secs: 5,
+        rate_limit_burst: 100,
+        // The client must take the identity from the server document
+        server_identity: "client-e2e.test".to_string(),
+        ..Config::default()
+    }
+}
+
+async fn setup_server() -> String {
+    setup_server_with(test_config()).await
+}
+
+async fn setup_server_with(config: Config) -> String {
+    setup_server_with_storage(config, Arc::new(MemoryStorage::default())).await
+}
+
+/// Serves `storage`, for tests that check what the server stored.
+async fn setup_server_with_storage(config: Config, storage: Arc<dyn Storage>) -> String {
+    let state = initialize_app_state(config, storage, None)
+        .await
+        .expect("Failed to initialize app state");
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind");
+    let addr = listener.local_addr().expect("Failed to get local address");
+
+    tokio::spawn(async move {
+        backend::http::serve_until(listener, state, std::future::pending())
+            .await
+            .expect("Server error");
+    });
+
+    format!("http://{}", addr)
+}
+
+async fn logged_in(server: &str, username: &str) -> Client<ReqwestTransport> {
+    let mut client = Client::new(server);
+    client.register(username, "password").await.unwrap();
+    client.login(username, "password").await.unwrap();
+    client
+}
+
+fn assert_code<T>(result: ClientResult<T>, expected: ErrorCode) {
+    match result {
+        Err(err) => assert_eq!(err.code(), Some(expected), "{}", err),
+        Ok(_) => panic!("expected {:?}", expected),
+    }
+}
+
+#[tokio::test]
+async fn items_are_stored_encrypted_and_shared() {
+    let server = setup_server().await;
+    let mut alice = logged_in(&server, "alice").await;
+    let mut bob = logged_in(&server, "bob").await;
+
+    assert_eq!(alice.whoami().await.unwrap(), "alice");
+    let item = alice
+        .put(&ItemPayload::new("notes.txt", b"secret notes"))
+        .await
+        .unwrap();
+    assert_ne!(
+        item.ciphertext.decode_bytes().unwrap(),
+        b"secret notes".to_vec()
+    );
+
+    // A resumed session carries on with the sequence number
+    let mut resumed = Client::new(&server);
+    resumed.resume(alice.session().unwrap().clone());
+    let payload = resumed.get(&item.id).await.unwrap();
+    assert_eq!(payload.name, "notes.txt");
+    assert_eq!(payload.data.decode_bytes().unwrap(), b"secret notes");
+    assert_eq!(resumed.list_items().await.unwrap().len(), 1);
+    let mut alice = resumed;
+
+    assert_code(bob.get_item(&item.id).await, ErrorCode::NotFound);
+    assert_code(alice.share(&item.id, "bob").await, ErrorCode::NotFound);
+    bob.publish_identity_key().await.unwrap();
+    let shared = alice.share(&item.id, "bob").await.unwrap();
+    assert!(alice.receive(&shared).await.is_err());
+
+    let received = bob.receive(&shared).await.unwrap();
+    assert_eq!(
+        bob.get(&received.id)
+            .await
+            .unwrap()
+            .data
+            .decode_bytes()
+            .unwrap(),
+        b"secret notes"
+    );
+
+    alice.delete_item(&item.id).await.unwrap();
+    assert!(alice.list_items().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn logout_ends_the_session() {
+    let server = setup_server().await;
+    let mut alice = logged_in(&server, "alice").await;
+    let session = alice.session().unwrap().clone();
+
+    alice.logout().await.unwrap();
+    assert!(alice.session().is_none());
+    assert!(matches!(
+        alice.whoami().await,
+        Err(ClientError::NotLoggedIn)
+    ));
+
+    // The server forgot it too, and so does a client still holding it
+    let mut stale = Client::new(&server);
+    stale.resume(session);
+    assert_code(stale.whoami().await, ErrorCode::SessionExpired);
+    assert!(stale.session().is_none());
+}
+
+#[tokio::test]
+async fn outdated_clients_are_refused() {
+    let server = setup_server_with(Config {
+        min_client_version: Some("99.0.0".to_string()),
+        ..test_config()
+    })
+    .await;
+
+    let mut client = Client::new(&server);
+    assert_code(
+        client.register("alice", "password").await,
+        ErrorCode::ClientVersionUnsupported,
+    );
+}
+
+#[tokio::test]
+async fn changing_the_password_keeps_the_vault_readable() {
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::default());
+    let server = setup_server_with_storage(test_config(), storage.clone()).await;
+    let mut alice = logged_in(&server, "alice").await;
+    let mut other = Client::new(&server);
+    other.login("alice", "password").await.unwrap();
+    let item = alice
+        .put(&ItemPayload::new("notes.txt", b"secret notes"))
+        .await
+        .unwrap();
+    alice.publish_identity_key().await.unwrap();
+    let old_key = alice.fetch_identity_key("alice").await.unwrap();
+
+    let kept = alice.session().unwrap().session_id.clone();
+    let revoked = other.session().unwrap().session_id.clone();
+
+    alice.change_password("new password").await.unwrap();
+
+    // The stored sessions agree with the live ones: only the session the
+    // change was made from stays
+    alice.whoami().await.unwrap();
+    assert_code(other.whoami().await, ErrorCode::SessionExpired);
+    for (session_id, active) in [(kept, true), (revoked, false)] {
+        let stored = storage
+            .find_active_session(&Sha256::digest(session_id), chrono::Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(stored.is_some(), active);
+    }
+    assert!(matches!(
+        Client::new(&server).login("alice", "password").await,
+        Err(ClientError::InvalidCredentials)
+    ));
+    let mut relogin = Client::new(&server);
+    relogin.login("alice", "new password").await.unwrap();
+    assert_eq!(
+        relogin
+            .get(&item.id)
+            .await
+            .unwrap()
+            .data
+            .decode_bytes()
+            .unwrap(),
+        b"secret notes"
+    );
+    // The vault keys only got wrapped again
+    assert_eq!(relogin.fetch_identity_key("alice").await.unwrap(), old_key);
+}
+
+/// Loses the response to requests for `path` after the server handled them.
+struct LosingTransport {
+    inner: ReqwestTransport,
+    path: &'static str,
+}
+
+impl Transport for LosingTransport {
+    async fn send(&self, request: HttpRequest) -> ClientResult<HttpResponse> {
+        let lost = request.url.ends_with(self.path);
+        let response = self.inner.send(request).await?;
+        if lost {
+            return Err(ClientError::Transport("connection reset".to_string()));
+        }
+        Ok(response)
+    }
+}
+
+#[tokio::test]
+async fn a_password_change_that_fails_halfway_keeps_the_vault_readable() {
+    let server = setup_server().await;
+    let mut alice = logged_in(&server, "alice").await;
+    let mut bob = Client::with_transport(
+        &server,
+        LosingTransport {
+            inner: ReqwestTransport::default(),
+            path: "/v1/auth/password/finish",
+        },
+    );
+    bob.register("bob", "password").await.unwrap();
+    bob.login("bob", "password").await.unwrap();
+    let item = bob
+        .put(&ItemPayload::new("notes.txt", b"bob's notes"))
+        .await
+        .unwrap();
+    bob.publish_identity_key().await.unwrap();
+    let alice_item = alice
+        .put(&ItemPayload::new("shared.txt", b"for bob"))
+        .await
+        .unwrap();
+    let shared = alice.share(&alice_item.id, "bob").await.unwrap();
+
+    // The server stored the new password but the client never heard back
+    assert!(matches!(
+        bob.change_password("new password").await,
+        Err(ClientError::Transport(_))
+    ));
+
+    let mut relogin = Client::new(&server);
+    relogin.login("bob", "new password").await.unwrap();
+    assert_eq!(
+        relogin
+            .get(&item.id)
+            .await
+            .unwrap()
+            .data
+            .decode_bytes()
+            .unwrap(),
+        b"bob's notes"
+    );
+    let received = relogin.receive(&shared).await.unwrap();
+    assert_eq!(
+        relogin
+            .get(&received.id)
+            .await
+            .unwrap()
+            .data
+            .decode_bytes()
+            .unwrap(),
+        b"for bob"
+    );
+}
+
+#[tokio::test]
+async fn recovery_codes_reset_the_password_and_keep_the_vault() {
+    let server = setup_server().await;
+    let mut alice = logged_in(&server, "alice").await;
+    let item = alice
+        .put(&ItemPayload::new("notes.txt", b"secret notes"))
+        .await
+        .unwrap();
+    let code = alice.enable_recovery().await.unwrap();
+    // The wrapped keys follow the password
+    alice.change_password("forgotten password").await.unwrap();
+
+    let mut recovered = Client::new(&server);
+    let new_code = recovered
+        .recover(
+            "alice",
+            &code.to_uppercase().replace('-', " "),
+            "new password",
+        )
+        .await
+        .unwrap();
+    assert_ne!(new_code, code);
+    assert_eq!(
+        recovered
+            .get(&item.id)
+            .await
+            .unwrap()
+            .data
+            .decode_bytes()
+            .unwrap(),
+        b"secret notes"
+    );
+
+    let mut relogin = Client::new(&server);
+    relogin.login("alice", "new password").await.unwrap();
+    assert_eq!(relogin.list_items().await.unwrap().len(), 1);
+    assert!(matches!(
+        Client::new(&server)
+            .recover("alice", &code, "other password")
+            .await,
+        Err(ClientError::InvalidCredentials)
+    ));
+
+    relogin.disable_recovery().await.unwrap();
+    assert!(matches!(
+        Client::new(&server)
+            .recover("alice", &new_code, "other password")
+            .await,
+        Err(ClientError::InvalidCredentials)
+    ));
+    Client::new(&server)
+        .login("alice", "new password")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn a_recovery_login_uses_up_the_code() {
+    let server = setup_server().await;
+    let mut alice = logged_in(&server, "alice").await;
+    let code = alice.enable_recovery().await.unwrap();
+
+    // The client never learns of the login, the server forgets the code anyway
+    let mut lost = Client::with_transport(
+        &server,
+        LosingTransport {
+            inner: ReqwestTransport::default(),
+            path: "/v1/auth/recovery/login/finish",
+        },
+    );
+    assert!(matches!(
+        lost.recover("alice", &code, "new password").await,
+        Err(ClientError::Transport(_))
+    ));
+    assert!(matches!(
+        Client::new(&server)
+            .recover("alice", &code, "new password")
+            .await,
+        Err(ClientError::InvalidCredentials)
+    ));
+    Client::new(&server)
+        .login("alice", "password")
+        .await
+        .unwrap();
+}
+
+/// Counts requests on top of the default transport.
+#[derive(Default)]
+struct CountingTransport {
+    inner: ReqwestTransport,
+    requests: Cell<usize>,
+}
+
+impl Transport for CountingTransport {
+    async fn send(&self, request: HttpRequest) -> ClientResult<HttpResponse> {
+        self.requests.set(self.requests.get() + 1);
+        self.inner.send(request).await
+    }
+}
+
+#[tokio::test]
+async fn requests_go_through_the_transport() {
+    let server = setup_server().await;
+    let mut client = Client::with_transport(&server, CountingTransport::default());
+
+    client.register("alice", "password").await.unwrap();
+    assert!(matches!(
+        client.login("alice", "wrong").await,
+        Err(ClientError::InvalidCredentials)
+    ));
+    client.login("alice", "password").await.unwrap();
+    client.whoami().await.unwrap();
+
+    // Each of register and the two logins fetch the server document first
+    assert_eq!(client.transport().requests.get(), 3 + 2 + 3 + 1);
+}
@@ -0,0 +1,114 @@
+//! The client reimplements the server's protocol primitives so that it can
+//! build for wasm. These check that both sides still agree.
+
+use backend::controllers::{opaque, sealed_channel, session, signing::SigningKey, transparency};
+use salauskilke_client::ErrorCode;
+use salauskilke_client::protocol::{
+    derive_mac_key, ChannelKeys, Direction, RequestToSign, SignedJson, CIPHERSUITE_ID,
+};
+use salauskilke_client::transparency::{leaf_hash, verify_inclusion};
+use serde_json::json;
+
+const SESSION_KEY: [u8; 64] = [7u8; 64];
+
+#[test]
+fn ciphersuites_match() {
+    assert_eq!(CIPHERSUITE_ID, opaque::CIPHERSUITE_ID);
+}
+
+/// The server's codes as documented in its OpenAPI document, which
+/// `openapi_e2e` keeps in step with the server.
+#[test]
+fn error_codes_match() {
+    let document: serde_json::Value =
+        serde_json::from_str(include_str!("../../backend/openapi.json")).unwrap();
+    let codes = document["components"]["schemas"]["ErrorCode"]["enum"]
+        .as_array()
+        .unwrap();
+
+    assert!(!codes.is_empty());
+    for code in codes {
+        let parsed: ErrorCode = serde_json::from_value(code.clone()).unwrap();
+        assert_ne!(parsed, ErrorCode::Unknown, "client lacks {}", code);
+    }
+}
+
+#[test]
+fn sealed_channels_interoperate() {
+    let client = ChannelKeys::derive(&SESSION_KEY).unwrap();
+    let server = sealed_channel::ChannelKeys::derive(&SESSION_KEY);
+
+    let request = client
+        .seal(Direction::ClientToServer, 3, b"request")
+        .unwrap();
+    assert_eq!(
+        server
+            .open(sealed_channel::Direction::ClientToServer, &request)
+            .unwrap(),
+        (3, b"request".to_vec())
+    );
+
+    let response = server
+        .seal(sealed_channel::Direction::ServerToClient, 3, b"response")
+        .unwrap();
+    assert_eq!(
+        client.open(Direction::ServerToClient, &response).unwrap(),
+        (3, b"response".to_vec())
+    );
+}
+
+#[test]
+fn request_signatures_match() {
+    let client = RequestToSign {
+        method: "PUT",
+        path: "/items/1?x=y",
+        timestamp: 1_700_000_000,
+        nonce: "nonce",
+        body: b"body",
+    };
+    let server = session::RequestToSign {
+        method: client.method,
+        path: client.path,
+        timestamp: client.timestamp,
+        nonce: client.nonce,
+        body: client.body,
+    };
+
+    assert_eq!(
+        client
+            .sign(&derive_mac_key(&SESSION_KEY).unwrap())
+            .unwrap()
+            .as_slice(),
+        server
+            .sign(&session::derive_mac_key(&SESSION_KEY))
+            .as_slice()
+    );
+}
+
+#[test]
+fn server_signatures_verify() {
+    let key = SigningKey::generate().unwrap();
+    let signed = serde_json::to_value(key.sign_json(&json!({ "tree_size": 1 })).unwrap()).unwrap();
+    let signed: SignedJson = serde_json::from_value(signed).unwrap();
+    let public_key: [u8; 32] = key.public_key().try_into().unwrap();
+
+    let payload: serde_json::Value = signed.verify(&public_key).unwrap();
+    assert_eq!(payload["tree_size"], 1);
+    assert!(signed.verify::<serde_json::Value>(&[1u8; 32]).is_err());
+}
+
+#[test]
+fn server_inclusion_proofs_verify() {
+    let leaves: Vec<Vec<u8>> = (0..7u8).map(|i| vec![i]).collect();
+    let log = transparency::MerkleLog::from_leaves(leaves.iter().map(Vec::as_slice));
+
+    for size in 1..=7u64 {
+        let root = log.root(size).unwrap();
+        for index in 0..size {
+            let proof = log.inclusion_proof(index, size).unwrap();
+            let leaf = leaf_hash(&leaves[index as usize]);
+            assert_eq!(leaf, transparency::leaf_hash(&leaves[index as usize]));
+            assert!(verify_inclusion(&leaf, index, size, &proof, &root));
+        }
+    }
+}